@@ -17,7 +17,8 @@
 //! the SEPolicy. Notably, it provides wrappers for:
 //!  * getcon
 //!  * selinux_check_access
-//!  * selabel_lookup for the keystore2_key backend.
+//!  * selabel_lookup for the keystore2_key backend and other label backends.
+//!  * selinux_android_restorecon.
 //!
 //! And it provides an owning wrapper around context strings `Context`.
 
@@ -27,15 +28,18 @@
 use anyhow::Context as AnyhowContext;
 use anyhow::{anyhow, Result};
 pub use selinux::pid_t;
-use selinux::SELABEL_CTX_ANDROID_KEYSTORE2_KEY;
+pub use selinux::SELABEL_CTX_ANDROID_KEYSTORE2_KEY;
+pub use selinux::SELABEL_CTX_FILE;
 use selinux::SELINUX_CB_LOG;
 use selinux_bindgen as selinux;
+use std::collections::HashMap;
 use std::ffi::{CStr, CString};
 use std::fmt;
 use std::io;
 use std::marker::{Send, Sync};
 pub use std::ops::Deref;
 use std::os::raw::c_char;
+use std::os::unix::io::RawFd;
 use std::ptr;
 use std::sync;
 
@@ -144,68 +148,126 @@ impl Context {
                 .with_context(|| format!("Failed to create Context with \"{}\"", con))?,
         ))
     }
+
+    /// Safe wrapper around libselinux `security_canonicalize_context`. Returns the canonical
+    /// form of this context according to the loaded policy, e.g., with the categories in
+    /// canonical order, so that equivalent contexts compare equal.
+    ///
+    /// ## Return
+    ///  * Ok(Context::Raw()) if successful.
+    ///  * Err(Error::sys()) if the call succeeded but returned a NULL pointer.
+    ///  * Err(io::Error::last_os_error()) if the context could not be canonicalized.
+    pub fn canonicalize(&self) -> Result<Context> {
+        init_logger_once();
+        let _lock = LIB_SELINUX_LOCK.lock().unwrap();
+
+        let mut con: *mut c_char = ptr::null_mut();
+        match unsafe { selinux::security_canonicalize_context(self.as_ptr(), &mut con) } {
+            0 => {
+                if !con.is_null() {
+                    Ok(Context::Raw(con))
+                } else {
+                    Err(anyhow!(Error::sys(
+                        "security_canonicalize_context returned a NULL context"
+                    )))
+                }
+            }
+            _ => Err(anyhow!(io::Error::last_os_error()))
+                .with_context(|| format!("security_canonicalize_context failed for \"{}\"", self)),
+        }
+    }
+
+    /// Returns true if both contexts have the same user, role, and type, i.e., if they are
+    /// equal up to the MLS level. Unlike `==`, this treats `u:r:shell:s0` and
+    /// `u:r:shell:s0:c512` as equal.
+    pub fn eq_ignoring_level(&self, other: &Context) -> bool {
+        // The level may itself contain colons, so only the first three components are split
+        // off and compared.
+        let is_colon = |b: &u8| *b == b':';
+        self.to_bytes().splitn(4, is_colon).take(3).eq(other.to_bytes().splitn(4, is_colon).take(3))
+    }
 }
 
 /// The backend trait provides a uniform interface to all libselinux context backends.
-/// Currently, we only implement the KeystoreKeyBackend though.
+/// It is implemented by `SeLabelBackend` for any libselinux label backend type.
 pub trait Backend {
     /// Implementers use libselinux `selabel_lookup` to lookup the context for the given `key`.
     fn lookup(&self, key: &str) -> Result<Context>;
+
+    /// Looks up the context for the given `key` and checks if `source` has the permission
+    /// `perm` of class `tclass` on it. Implementers may override this to perform both steps
+    /// while acquiring the libselinux lock only once.
+    fn lookup_and_check_access(
+        &self,
+        source: &CStr,
+        key: &str,
+        tclass: &str,
+        perm: &str,
+    ) -> Result<()> {
+        let target = self.lookup(key)?;
+        check_access(source, &target, tclass, perm)
+    }
 }
 
-/// Keystore key backend takes onwnership of the SELinux context handle returned by
-/// `selinux_android_keystore2_key_context_handle` and uses `selabel_close` to free
-/// the handle when dropped.
-/// It implements `Backend` to provide keystore_key label lookup functionality.
-pub struct KeystoreKeyBackend {
+/// A label backend takes ownership of an SELinux context handle for one libselinux backend
+/// type, e.g., `SELABEL_CTX_FILE`, and uses `selabel_close` to free the handle when dropped.
+/// It implements `Backend` to provide label lookup functionality for that type.
+pub struct SeLabelBackend {
     handle: *mut selinux::selabel_handle,
+    backend_type: i32,
 }
 
-// SAFETY: KeystoreKeyBackend is Sync because selabel_lookup is thread safe.
-unsafe impl Sync for KeystoreKeyBackend {}
-// SAFETY: KeystoreKeyBackend is Send because selabel_lookup is thread safe.
-unsafe impl Send for KeystoreKeyBackend {}
+// SAFETY: SeLabelBackend is Sync because selabel_lookup is thread safe.
+unsafe impl Sync for SeLabelBackend {}
+// SAFETY: SeLabelBackend is Send because selabel_lookup is thread safe.
+unsafe impl Send for SeLabelBackend {}
 
-impl KeystoreKeyBackend {
-    const BACKEND_TYPE: i32 = SELABEL_CTX_ANDROID_KEYSTORE2_KEY as i32;
-
-    /// Creates a new instance representing an SELinux context handle as returned by
-    /// `selinux_android_keystore2_key_context_handle`.
-    pub fn new() -> Result<Self> {
+impl SeLabelBackend {
+    /// Opens the context handle for the given backend type. The keystore2_key and file
+    /// backends are opened with the Android specific handle functions, which know where the
+    /// contexts files live. All other types are opened with `selabel_open` and no options.
+    pub fn new(backend_type: i32) -> Result<Self> {
         init_logger_once();
         let _lock = LIB_SELINUX_LOCK.lock().unwrap();
 
-        let handle = unsafe { selinux::selinux_android_keystore2_key_context_handle() };
+        let handle = match u32::try_from(backend_type) {
+            Ok(SELABEL_CTX_ANDROID_KEYSTORE2_KEY) => unsafe {
+                selinux::selinux_android_keystore2_key_context_handle()
+            },
+            Ok(SELABEL_CTX_FILE) => unsafe { selinux::selinux_android_file_context_handle() },
+            Ok(backend) => unsafe { selinux::selabel_open(backend, ptr::null(), 0) },
+            Err(_) => ptr::null_mut(),
+        };
         if handle.is_null() {
-            return Err(anyhow!(Error::sys("Failed to open KeystoreKeyBackend")));
+            return Err(anyhow!(Error::sys(format!(
+                "Failed to open label backend of type {}",
+                backend_type
+            ))));
         }
-        Ok(KeystoreKeyBackend { handle })
+        Ok(SeLabelBackend { handle, backend_type })
     }
 }
 
-impl Drop for KeystoreKeyBackend {
+impl Drop for SeLabelBackend {
     fn drop(&mut self) {
         // No need to initialize the logger here because it cannot be called unless
-        // KeystoreKeyBackend::new has run.
+        // SeLabelBackend::new has run.
         unsafe { selinux::selabel_close(self.handle) };
     }
 }
 
-// Because KeystoreKeyBackend is Sync and Send, member function must never call
+// Because SeLabelBackend is Sync and Send, member function must never call
 // non thread safe libselinux functions. As of this writing no non thread safe
 // functions exist that could be called on a label backend handle.
-impl Backend for KeystoreKeyBackend {
-    fn lookup(&self, key: &str) -> Result<Context> {
+impl SeLabelBackend {
+    /// Performs the lookup. The caller must hold `LIB_SELINUX_LOCK`.
+    fn lookup_with_lock_held(&self, key: &str) -> Result<Context> {
         let mut con: *mut c_char = ptr::null_mut();
         let c_key = CString::new(key).with_context(|| {
             format!("selabel_lookup: Failed to convert key \"{}\" to CString.", key)
         })?;
         match unsafe {
-            // No need to initialize the logger here because it cannot run unless
-            // KeystoreKeyBackend::new has run.
-            let _lock = LIB_SELINUX_LOCK.lock().unwrap();
-
-            selinux::selabel_lookup(self.handle, &mut con, c_key.as_ptr(), Self::BACKEND_TYPE)
+            selinux::selabel_lookup(self.handle, &mut con, c_key.as_ptr(), self.backend_type)
         } {
             0 => {
                 if !con.is_null() {
@@ -223,6 +285,193 @@ impl Backend for KeystoreKeyBackend {
     }
 }
 
+impl Backend for SeLabelBackend {
+    fn lookup(&self, key: &str) -> Result<Context> {
+        // No need to initialize the logger here because it cannot run unless
+        // SeLabelBackend::new has run.
+        let _lock = LIB_SELINUX_LOCK.lock().unwrap();
+        self.lookup_with_lock_held(key)
+    }
+
+    fn lookup_and_check_access(
+        &self,
+        source: &CStr,
+        key: &str,
+        tclass: &str,
+        perm: &str,
+    ) -> Result<()> {
+        let _lock = LIB_SELINUX_LOCK.lock().unwrap();
+        let target = self.lookup_with_lock_held(key)?;
+        check_access_with_lock_held(source, &target, tclass, perm)
+    }
+}
+
+/// Keystore key backend is the `SeLabelBackend` for the keystore2_key contexts, whose handle
+/// is returned by `selinux_android_keystore2_key_context_handle`.
+/// It implements `Backend` to provide keystore_key label lookup functionality.
+/// Keystore resolves the same few namespaces over and over, so successful lookups are cached
+/// and served without calling into libselinux. The handle does not pick up policy reloads
+/// either, so the cache never holds anything the handle would not have returned.
+pub struct KeystoreKeyBackend {
+    backend: SeLabelBackend,
+    // `Context::Raw` is neither `Clone` nor `Send`, so the cache holds a copy of the context
+    // string instead.
+    cache: sync::RwLock<HashMap<String, CString>>,
+    #[cfg(test)]
+    selinux_lookups: sync::atomic::AtomicUsize,
+}
+
+impl KeystoreKeyBackend {
+    const BACKEND_TYPE: i32 = SELABEL_CTX_ANDROID_KEYSTORE2_KEY as i32;
+
+    /// Creates a new instance representing an SELinux context handle as returned by
+    /// `selinux_android_keystore2_key_context_handle`.
+    pub fn new() -> Result<Self> {
+        let backend = SeLabelBackend::new(Self::BACKEND_TYPE)
+            .map_err(|_| anyhow!(Error::sys("Failed to open KeystoreKeyBackend")))?;
+        Ok(KeystoreKeyBackend {
+            backend,
+            cache: Default::default(),
+            #[cfg(test)]
+            selinux_lookups: Default::default(),
+        })
+    }
+
+    /// Drops all cached lookup results, so that the next lookup of each key is served by
+    /// libselinux again.
+    pub fn clear_cache(&self) {
+        self.cache.write().unwrap().clear();
+    }
+
+    fn cached(&self, key: &str) -> Option<Context> {
+        self.cache.read().unwrap().get(key).map(|con| Context::CString(con.clone()))
+    }
+
+    /// Performs the lookup in libselinux and caches the result. The caller must hold
+    /// `LIB_SELINUX_LOCK`.
+    fn lookup_and_cache_with_lock_held(&self, key: &str) -> Result<Context> {
+        #[cfg(test)]
+        self.selinux_lookups.fetch_add(1, sync::atomic::Ordering::Relaxed);
+        let con = self.backend.lookup_with_lock_held(key)?;
+        self.cache.write().unwrap().insert(key.to_string(), (*con).to_owned());
+        Ok(con)
+    }
+}
+
+impl Backend for KeystoreKeyBackend {
+    fn lookup(&self, key: &str) -> Result<Context> {
+        if let Some(con) = self.cached(key) {
+            return Ok(con);
+        }
+        let _lock = LIB_SELINUX_LOCK.lock().unwrap();
+        self.lookup_and_cache_with_lock_held(key)
+    }
+
+    fn lookup_and_check_access(
+        &self,
+        source: &CStr,
+        key: &str,
+        tclass: &str,
+        perm: &str,
+    ) -> Result<()> {
+        if let Some(target) = self.cached(key) {
+            return check_access(source, &target, tclass, perm);
+        }
+        let _lock = LIB_SELINUX_LOCK.lock().unwrap();
+        let target = self.lookup_and_cache_with_lock_held(key)?;
+        check_access_with_lock_held(source, &target, tclass, perm)
+    }
+}
+
+/// Identifies the kinds of label backends that can be held by a `BackendRegistry`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BackendType {
+    /// The keystore2_key backend, see `KeystoreKeyBackend`.
+    KeystoreKey,
+}
+
+/// Opens a new backend handle of a given type.
+pub type BackendOpener = fn() -> Result<Box<dyn Backend + Send + Sync>>;
+
+struct BackendSlot {
+    open: BackendOpener,
+    backend: sync::OnceLock<Box<dyn Backend + Send + Sync>>,
+    open_lock: sync::Mutex<()>,
+}
+
+/// The backend registry owns one lazily opened handle per backend type and dispatches lookups
+/// to it. Once a handle is open, lookups do not take any locks beyond the ones taken by the
+/// backend itself, and the handle is reused for all subsequent lookups.
+pub struct BackendRegistry {
+    slots: HashMap<BackendType, BackendSlot>,
+}
+
+impl Default for BackendRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BackendRegistry {
+    /// Creates a registry with the default openers for all backend types. No backend is opened
+    /// until it is first used.
+    pub fn new() -> Self {
+        let mut registry = Self { slots: HashMap::new() };
+        registry.register(BackendType::KeystoreKey, || Ok(Box::new(KeystoreKeyBackend::new()?)));
+        registry
+    }
+
+    /// Sets the function used to open the backend of the given type, replacing any previously
+    /// registered opener and dropping any backend that was already opened.
+    pub fn register(&mut self, backend_type: BackendType, open: BackendOpener) {
+        self.slots.insert(
+            backend_type,
+            BackendSlot { open, backend: sync::OnceLock::new(), open_lock: sync::Mutex::new(()) },
+        );
+    }
+
+    /// Returns the backend of the given type, opening it if this is the first use.
+    pub fn get(&self, backend_type: BackendType) -> Result<&(dyn Backend + Send + Sync)> {
+        let slot = self
+            .slots
+            .get(&backend_type)
+            .ok_or_else(|| anyhow!(Error::sys(format!("No backend for {:?}", backend_type))))?;
+        if let Some(backend) = slot.backend.get() {
+            return Ok(backend.as_ref());
+        }
+        // Serialize opening, so that concurrent first users do not open redundant handles.
+        let _lock = slot.open_lock.lock().unwrap();
+        if let Some(backend) = slot.backend.get() {
+            return Ok(backend.as_ref());
+        }
+        let backend =
+            (slot.open)().with_context(|| format!("Failed to open backend {:?}", backend_type))?;
+        Ok(slot.backend.get_or_init(|| backend).as_ref())
+    }
+
+    /// Looks up the context for `key` in the backend of the given type.
+    pub fn lookup(&self, backend_type: BackendType, key: &str) -> Result<Context> {
+        self.get(backend_type)?.lookup(key)
+    }
+
+    /// Checks if `caller_ctx` has the permission `perm` on the keystore2_key `namespace`.
+    /// The namespace lookup and the access check are performed in a single critical section
+    /// of `LIB_SELINUX_LOCK`.
+    pub fn check_keystore_key_perm<T: ClassPermission>(
+        &self,
+        caller_ctx: &CStr,
+        namespace: &str,
+        perm: T,
+    ) -> Result<()> {
+        self.get(BackendType::KeystoreKey)?.lookup_and_check_access(
+            caller_ctx,
+            namespace,
+            perm.class_name(),
+            perm.name(),
+        )
+    }
+}
+
 /// Safe wrapper around libselinux `getcon`. It initializes the `Context::Raw` variant of the
 /// returned `Context`.
 ///
@@ -247,6 +496,62 @@ pub fn getcon() -> Result<Context> {
     }
 }
 
+/// Safe wrapper around libselinux `getpidcon`. It initializes the `Context::Raw` variant of the
+/// returned `Context`.
+///
+/// ## Return
+///  * Ok(Context::Raw()) if successful.
+///  * Err(Error::sys()) if getpidcon succeeded but returned a NULL pointer.
+///  * Err(io::Error::last_os_error()) if getpidcon failed.
+pub fn getpidcon(pid: pid_t) -> Result<Context> {
+    init_logger_once();
+    let _lock = LIB_SELINUX_LOCK.lock().unwrap();
+
+    let mut con: *mut c_char = ptr::null_mut();
+    match unsafe { selinux::getpidcon(pid, &mut con) } {
+        0 => {
+            if !con.is_null() {
+                Ok(Context::Raw(con))
+            } else {
+                Err(anyhow!(Error::sys(format!(
+                    "getpidcon returned a NULL context for pid {}",
+                    pid
+                ))))
+            }
+        }
+        _ => Err(anyhow!(io::Error::last_os_error()))
+            .with_context(|| format!("getpidcon failed for pid {}", pid)),
+    }
+}
+
+/// Safe wrapper around libselinux `getpeercon`. It returns the context of the peer of the Unix
+/// socket `fd` and initializes the `Context::Raw` variant of the returned `Context`.
+///
+/// ## Return
+///  * Ok(Context::Raw()) if successful.
+///  * Err(Error::sys()) if getpeercon succeeded but returned a NULL pointer.
+///  * Err(io::Error::last_os_error()) if getpeercon failed.
+pub fn getpeercon(fd: RawFd) -> Result<Context> {
+    init_logger_once();
+    let _lock = LIB_SELINUX_LOCK.lock().unwrap();
+
+    let mut con: *mut c_char = ptr::null_mut();
+    match unsafe { selinux::getpeercon(fd, &mut con) } {
+        0 => {
+            if !con.is_null() {
+                Ok(Context::Raw(con))
+            } else {
+                Err(anyhow!(Error::sys(format!(
+                    "getpeercon returned a NULL context for fd {}",
+                    fd
+                ))))
+            }
+        }
+        _ => Err(anyhow!(io::Error::last_os_error()))
+            .with_context(|| format!("getpeercon failed for fd {}", fd)),
+    }
+}
+
 /// Safe wrapper around selinux_check_access.
 ///
 /// ## Return
@@ -255,8 +560,36 @@ pub fn getcon() -> Result<Context> {
 ///  * Err(anyhow!(ioError::last_os_error())) if any other error occurred while performing
 ///            the access check.
 pub fn check_access(source: &CStr, target: &CStr, tclass: &str, perm: &str) -> Result<()> {
+    check_access_multi(source, target, tclass, &[perm])
+}
+
+/// Like `check_access`, but checks all of `perms` of class `tclass` for the same `source` and
+/// `target` while taking `LIB_SELINUX_LOCK` only once. The checks stop at the first permission
+/// that is not granted; the context of the returned error names that permission.
+///
+/// ## Return
+///  * Ok(()) iff all requested permissions were granted.
+///  * Err(anyhow!(Error::perm()))) if one of the permissions was denied.
+///  * Err(anyhow!(ioError::last_os_error())) if any other error occurred while performing
+///            the access checks.
+pub fn check_access_multi(
+    source: &CStr,
+    target: &CStr,
+    tclass: &str,
+    perms: &[&str],
+) -> Result<()> {
     init_logger_once();
+    let _lock = LIB_SELINUX_LOCK.lock().unwrap();
+    perms.iter().try_for_each(|perm| check_access_with_lock_held(source, target, tclass, perm))
+}
 
+/// Performs the access check of `check_access`. The caller must hold `LIB_SELINUX_LOCK`.
+fn check_access_with_lock_held(
+    source: &CStr,
+    target: &CStr,
+    tclass: &str,
+    perm: &str,
+) -> Result<()> {
     let c_tclass = CString::new(tclass).with_context(|| {
         format!("check_access: Failed to convert tclass \"{}\" to CString.", tclass)
     })?;
@@ -265,8 +598,6 @@ pub fn check_access(source: &CStr, target: &CStr, tclass: &str, perm: &str) -> R
     })?;
 
     match unsafe {
-        let _lock = LIB_SELINUX_LOCK.lock().unwrap();
-
         selinux::selinux_check_access(
             source.as_ptr(),
             target.as_ptr(),
@@ -307,6 +638,106 @@ pub fn setcon(target: &CStr) -> std::io::Result<()> {
     }
 }
 
+/// Restores the context that was current when it was created via `setcon_scoped` when dropped.
+#[must_use = "the previous context is restored as soon as the guard is dropped"]
+pub struct ScopedCon {
+    previous: Context,
+}
+
+impl Drop for ScopedCon {
+    fn drop(&mut self) {
+        if let Err(e) = setcon(&self.previous) {
+            log::error!("ScopedCon: Failed to restore context {}: {:?}", self.previous, e);
+        }
+    }
+}
+
+/// Like `setcon` but returns a guard that restores the current context when dropped. This
+/// is meant for tests and transient operations that must not leave the context changed.
+pub fn setcon_scoped(target: &CStr) -> std::io::Result<ScopedCon> {
+    let previous = getcon().map_err(|e| io::Error::other(format!("{:?}", e)))?;
+    setcon(target)?;
+    Ok(ScopedCon { previous })
+}
+
+/// The SELinux mode of the system as reported by `get_enforce`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeLinuxMode {
+    /// Policy violations are denied.
+    Enforcing,
+    /// Policy violations are logged but not denied.
+    Permissive,
+    /// SELinux is not enabled.
+    Disabled,
+}
+
+/// Safe wrapper around libselinux `security_getenforce`.
+///
+/// ## Return
+///  * Ok(SeLinuxMode) if successful.
+///  * Err(io::Error::last_os_error()) if the mode could not be determined.
+pub fn get_enforce() -> Result<SeLinuxMode> {
+    init_logger_once();
+    let _lock = LIB_SELINUX_LOCK.lock().unwrap();
+
+    // SAFETY: Neither function takes any arguments.
+    if unsafe { selinux::is_selinux_enabled() } == 0 {
+        return Ok(SeLinuxMode::Disabled);
+    }
+    match unsafe { selinux::security_getenforce() } {
+        1 => Ok(SeLinuxMode::Enforcing),
+        0 => Ok(SeLinuxMode::Permissive),
+        _ => Err(anyhow!(io::Error::last_os_error())).context("security_getenforce failed"),
+    }
+}
+
+/// Safe wrapper around libselinux `security_setenforce`. SELinux cannot be disabled at runtime,
+/// so only `Enforcing` and `Permissive` are accepted.
+///
+/// ## Return
+///  * Ok(()) if the mode was set.
+///  * Err(Error::sys()) if `mode` is `SeLinuxMode::Disabled`.
+///  * Err(io::Error::last_os_error()) if the mode could not be set, e.g., EPERM if the caller
+///    lacks the permission to do so.
+pub fn set_enforce(mode: SeLinuxMode) -> Result<()> {
+    let value = match mode {
+        SeLinuxMode::Enforcing => 1,
+        SeLinuxMode::Permissive => 0,
+        SeLinuxMode::Disabled => {
+            return Err(anyhow!(Error::sys("set_enforce: SELinux cannot be disabled")))
+        }
+    };
+    init_logger_once();
+    let _lock = LIB_SELINUX_LOCK.lock().unwrap();
+
+    // SAFETY: `security_setenforce` only takes an integer argument.
+    match unsafe { selinux::security_setenforce(value) } {
+        0 => Ok(()),
+        _ => Err(anyhow!(io::Error::last_os_error()))
+            .with_context(|| format!("security_setenforce failed for {:?}", mode)),
+    }
+}
+
+/// Safe wrapper around libselinux `selinux_android_restorecon`. Resets the SELinux label of the
+/// file or directory at `path` to the context that the file_contexts of the policy assign to it.
+/// Only the given path is relabeled, not the contents of a directory.
+///
+/// ## Return
+///  * Ok(()) if the label was restored or was already correct.
+///  * Err(io::Error::last_os_error()) if the context could not be looked up or set.
+pub fn restore_file_context(path: &CStr) -> Result<()> {
+    init_logger_once();
+    let _lock = LIB_SELINUX_LOCK.lock().unwrap();
+
+    // SAFETY: `selinux_android_restorecon` only reads the nul terminated string `path` and
+    // does not retain a pointer to it. `path` outlives the call.
+    match unsafe { selinux::selinux_android_restorecon(path.as_ptr(), 0) } {
+        0 => Ok(()),
+        _ => Err(anyhow!(io::Error::last_os_error()))
+            .with_context(|| format!("restore_file_context: Failed to relabel {:?}.", path)),
+    }
+}
+
 /// Represents an SEPolicy permission belonging to a specific class.
 pub trait ClassPermission {
     /// The permission string of the given instance as specified in the class vector.
@@ -324,6 +755,9 @@ pub trait ClassPermission {
 ///    of `0` and a string representation of `"none"`.
 ///  * Specifying a value is optional. If the value is omitted it is set to the value of the
 ///    previous variant left shifted by 1.
+///  * All values must be positive and distinct. This is checked at compile time, so that a
+///    class that outgrows the 31 usable bits of an i32 fails to build instead of silently
+///    producing overflowed or duplicate values.
 ///
 /// ## Example
 /// ```
@@ -573,6 +1007,28 @@ macro_rules! implement_class {
             )*
         }
 
+        // Left shifting the implicit values past the sign bit of an i32 yields negative values
+        // or 0, so checking that all values are positive and distinct catches overflows.
+        const _: () = {
+            let values: &[i32] = &[$($vval,)*];
+            let mut i = 0;
+            while i < values.len() {
+                assert!(
+                    values[i] > 0,
+                    concat!("Permission value overflow in ", stringify!($enum_name), ".")
+                );
+                let mut j = i + 1;
+                while j < values.len() {
+                    assert!(
+                        values[i] != values[j],
+                        concat!("Duplicate permission value in ", stringify!($enum_name), ".")
+                    );
+                    j += 1;
+                }
+                i += 1;
+            }
+        };
+
         impl From<i32> for $enum_name {
             #[allow(non_upper_case_globals)]
             fn from (p: i32) -> Self {
@@ -636,12 +1092,179 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_eq_ignoring_level() -> Result<()> {
+        let shell = Context::new("u:r:shell:s0")?;
+        let shell_with_category = Context::new("u:r:shell:s0:c512")?;
+        assert_ne!(shell, shell_with_category);
+        assert!(shell.eq_ignoring_level(&shell_with_category));
+        assert!(shell_with_category.eq_ignoring_level(&shell));
+
+        assert!(!shell.eq_ignoring_level(&Context::new("u:r:su:s0")?));
+        assert!(!shell.eq_ignoring_level(&Context::new("u:object_r:shell:s0")?));
+        Ok(())
+    }
+
+    struct CountingBackend;
+
+    static COUNTING_BACKEND_OPENS: sync::atomic::AtomicUsize = sync::atomic::AtomicUsize::new(0);
+
+    impl Backend for CountingBackend {
+        fn lookup(&self, key: &str) -> Result<Context> {
+            Context::new(&format!("u:object_r:{}:s0", key))
+        }
+    }
+
+    #[test]
+    fn test_backend_registry_reuses_handle() -> Result<()> {
+        let mut registry = BackendRegistry::new();
+        registry.register(BackendType::KeystoreKey, || {
+            COUNTING_BACKEND_OPENS.fetch_add(1, sync::atomic::Ordering::SeqCst);
+            Ok(Box::new(CountingBackend))
+        });
+
+        let first = registry.get(BackendType::KeystoreKey)? as *const _ as *const u8;
+        assert_eq!(
+            registry.lookup(BackendType::KeystoreKey, "test_key")?,
+            Context::new("u:object_r:test_key:s0")?
+        );
+        registry.lookup(BackendType::KeystoreKey, "other_key")?;
+        let second = registry.get(BackendType::KeystoreKey)? as *const _ as *const u8;
+
+        assert_eq!(first, second);
+        assert_eq!(COUNTING_BACKEND_OPENS.load(sync::atomic::Ordering::SeqCst), 1);
+        Ok(())
+    }
+
+    implement_class!(
+        /// Permission class whose implicit values reach the highest usable bit of an i32.
+        /// Adding another implicit variant makes the compile time overflow check fail.
+        #[selinux(class_name = test_class)]
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        enum HighBitPerm {
+            #[selinux(name = low)]
+            Low = 1,
+            #[selinux(name = high)]
+            High = 1 << 28,
+            #[selinux(name = higher)]
+            Higher,
+            #[selinux(name = highest)]
+            Highest,
+        }
+    );
+
+    #[test]
+    fn test_implement_class_high_bits() {
+        assert_eq!(HighBitPerm::Higher as i32, 1 << 29);
+        assert_eq!(HighBitPerm::Highest as i32, 1 << 30);
+        assert_eq!(HighBitPerm::from(1 << 30), HighBitPerm::Highest);
+        assert_eq!(HighBitPerm::from(i32::MIN), HighBitPerm::None);
+        assert_eq!(HighBitPerm::Highest.name(), "highest");
+        assert_eq!(HighBitPerm::Low.class_name(), "test_class");
+    }
+
+    #[test]
+    fn test_canonicalize() -> Result<()> {
+        let a = Context::new("u:object_r:app_data_file:s0:c1,c2")?.canonicalize()?;
+        let b = Context::new("u:object_r:app_data_file:s0:c2,c1")?.canonicalize()?;
+        assert_eq!(a, b);
+        assert!(Context::new("not a context")?.canonicalize().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_restore_file_context() -> Result<()> {
+        check_context()?;
+        let name = format!("keystore2_selinux_restorecon_{}", std::process::id());
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, b"test")?;
+        let c_path = CString::new(path.to_str().unwrap())?;
+        let result = restore_file_context(&c_path);
+        std::fs::remove_file(&path)?;
+        result
+    }
+
     #[test]
     fn test_getcon() -> Result<()> {
         check_context()?;
         Ok(())
     }
 
+    #[test]
+    fn test_keystore_key_backend_caches_lookups() -> Result<()> {
+        let (context, namespace, _) = check_context()?;
+        let backend = KeystoreKeyBackend::new()?;
+        let lookups = || backend.selinux_lookups.load(sync::atomic::Ordering::Relaxed);
+
+        let first = backend.lookup(namespace)?;
+        let second = backend.lookup(namespace)?;
+        assert_eq!(first, second);
+        assert_eq!(lookups(), 1);
+        // The outcome of the access check does not matter here, only that the namespace is
+        // served from the cache.
+        let _ = backend.lookup_and_check_access(&context, namespace, "keystore2_key", "use");
+        assert_eq!(lookups(), 1);
+
+        // A miss in `lookup_and_check_access` fills the cache as well.
+        let backend = KeystoreKeyBackend::new()?;
+        let lookups = || backend.selinux_lookups.load(sync::atomic::Ordering::Relaxed);
+        let _ = backend.lookup_and_check_access(&context, namespace, "keystore2_key", "use");
+        assert_eq!(backend.lookup(namespace)?, first);
+        assert_eq!(lookups(), 1);
+
+        // After clearing the cache, the next lookup calls into libselinux again.
+        backend.clear_cache();
+        assert_eq!(backend.lookup(namespace)?, first);
+        assert_eq!(lookups(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_enforce() -> Result<()> {
+        // The mode depends on the device, so only check that it can be read.
+        let mode = get_enforce()?;
+        assert!(matches!(
+            mode,
+            SeLinuxMode::Enforcing | SeLinuxMode::Permissive | SeLinuxMode::Disabled
+        ));
+        assert!(set_enforce(SeLinuxMode::Disabled).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_setcon_scoped() -> Result<()> {
+        let (context, _, is_su) = check_context()?;
+        // Only su may change its own context. Stay in the su domain and change only the level
+        // so that the original context can be restored.
+        if !is_su {
+            return Ok(());
+        }
+        let target = CString::new("u:r:su:s0:c512")?;
+        {
+            let _guard = setcon_scoped(&target)?;
+            assert_eq!(getcon()?.to_str(), Ok("u:r:su:s0:c512"));
+        }
+        assert_eq!(getcon()?, context);
+        Ok(())
+    }
+
+    #[test]
+    fn test_getpidcon() -> Result<()> {
+        let pid = std::process::id() as pid_t;
+        assert_eq!(getpidcon(pid)?, getcon()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_getpeercon() -> Result<()> {
+        use std::os::unix::io::AsRawFd;
+        let (a, b) = std::os::unix::net::UnixStream::pair()?;
+        let own = getcon()?;
+        assert_eq!(getpeercon(a.as_raw_fd())?, own);
+        assert_eq!(getpeercon(b.as_raw_fd())?, own);
+        Ok(())
+    }
+
     #[test]
     fn test_label_lookup() -> Result<()> {
         let (_context, namespace, is_su) = check_context()?;
@@ -655,6 +1278,14 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_file_context_lookup() -> Result<()> {
+        let backend = SeLabelBackend::new(SELABEL_CTX_FILE as i32)?;
+        let context = backend.lookup("/system/bin/keystore2")?;
+        assert_eq!(context.to_str(), Ok("u:object_r:keystore_exec:s0"));
+        Ok(())
+    }
+
     #[test]
     fn context_from_string() -> Result<()> {
         let tctx = Context::new("u:object_r:keystore:s0").unwrap();
@@ -724,6 +1355,25 @@ mod tests {
             };
         }
 
+        #[test]
+        fn check_access_multi_stops_at_first_denial() -> Result<()> {
+            let scontext = Context::new("u:r:shell:s0")?;
+            let backend = KeystoreKeyBackend::new()?;
+            let tcontext = backend.lookup(SHELL_KEY_NAMESPACE)?;
+
+            check_access_multi(&scontext, &tcontext, "keystore2_key", &["delete", "get_info"])?;
+            let e = check_access_multi(
+                &scontext,
+                &tcontext,
+                "keystore2_key",
+                &["delete", "grant", "manage_blob"],
+            )
+            .unwrap_err();
+            assert_eq!(Some(&Error::perm()), e.root_cause().downcast_ref::<Error>());
+            assert!(e.to_string().contains("perm: \"grant\""), "{:?}", e);
+            Ok(())
+        }
+
         check_key_perm!(manage_blob, true);
         check_key_perm!(delete, false);
         check_key_perm!(use_dev_id, true);
@@ -735,6 +1385,65 @@ mod tests {
         check_key_perm!(update, false);
         check_key_perm!(use, false);
 
+        /// Mirrors `check_key_perm` using `BackendRegistry::check_keystore_key_perm`, which
+        /// performs the namespace lookup and the access check under a single lock.
+        mod batched {
+            use super::*;
+
+            struct KeystoreKeyPerm(&'static str);
+
+            impl ClassPermission for KeystoreKeyPerm {
+                fn name(&self) -> &'static str {
+                    self.0
+                }
+                fn class_name(&self) -> &'static str {
+                    "keystore2_key"
+                }
+            }
+
+            macro_rules! check_key_perm_batched {
+                (use, $privileged:expr) => {
+                    check_key_perm_batched!(use_, $privileged, "use");
+                };
+                ($perm:ident, $privileged:expr) => {
+                    check_key_perm_batched!($perm, $privileged, stringify!($perm));
+                };
+                ($perm:ident, $privileged:expr, $p_str:expr) => {
+                    #[test]
+                    fn $perm() -> Result<()> {
+                        let scontext = Context::new("u:r:shell:s0")?;
+                        let registry = BackendRegistry::new();
+                        let result = registry.check_keystore_key_perm(
+                            &scontext,
+                            SHELL_KEY_NAMESPACE,
+                            KeystoreKeyPerm($p_str),
+                        );
+
+                        if $privileged {
+                            assert_eq!(
+                                Some(&Error::perm()),
+                                result.err().unwrap().root_cause().downcast_ref::<Error>()
+                            );
+                        } else {
+                            assert!(result.is_ok());
+                        }
+                        Ok(())
+                    }
+                };
+            }
+
+            check_key_perm_batched!(manage_blob, true);
+            check_key_perm_batched!(delete, false);
+            check_key_perm_batched!(use_dev_id, true);
+            check_key_perm_batched!(req_forced_op, true);
+            check_key_perm_batched!(gen_unique_id, true);
+            check_key_perm_batched!(grant, true);
+            check_key_perm_batched!(get_info, false);
+            check_key_perm_batched!(rebind, false);
+            check_key_perm_batched!(update, false);
+            check_key_perm_batched!(use, false);
+        }
+
         macro_rules! check_keystore_perm {
             ($perm:ident) => {
                 #[test]
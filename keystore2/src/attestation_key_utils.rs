@@ -17,13 +17,15 @@
 
 use crate::database::{BlobMetaData, KeyEntryLoadBits, KeyType};
 use crate::database::{KeyIdGuard, KeystoreDB};
+use crate::enforcements::Enforcements;
 use crate::error::{Error, ErrorCode};
+use crate::key_parameter::{KeyParameter as KsKeyParameter, KeyParameterValue};
 use crate::ks_err;
 use crate::permission::KeyPerm;
 use crate::remote_provisioning::RemProvState;
-use crate::utils::check_key_permission;
+use crate::utils::{check_key_permission, KeyMintCapabilities};
 use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
-    AttestationKey::AttestationKey, KeyParameter::KeyParameter, Tag::Tag,
+    AttestationKey::AttestationKey, KeyParameter::KeyParameter, KeyPurpose::KeyPurpose, Tag::Tag,
 };
 use android_system_keystore2::aidl::android::system::keystore2::{
     Domain::Domain, KeyDescriptor::KeyDescriptor, ResponseCode::ResponseCode,
@@ -50,13 +52,15 @@ pub enum AttestationKeyInfo {
 
 /// This function loads and, optionally, assigns the caller's remote provisioned
 /// attestation key if a challenge is present. Alternatively, if `attest_key_descriptor` is given,
-/// it loads the user generated attestation key from the database.
+/// it loads the user generated attestation key from the database. User generated attestation keys
+/// are rejected with `UNSUPPORTED_PURPOSE` if the device does not support them.
 pub fn get_attest_key_info(
     key: &KeyDescriptor,
     caller_uid: u32,
     attest_key_descriptor: Option<&KeyDescriptor>,
     params: &[KeyParameter],
     rem_prov_state: &RemProvState,
+    capabilities: &KeyMintCapabilities,
     db: &mut KeystoreDB,
 ) -> Result<Option<AttestationKeyInfo>> {
     let challenge_present = params.iter().any(|kp| kp.tag == Tag::ATTESTATION_CHALLENGE);
@@ -73,6 +77,10 @@ pub fn get_attest_key_info(
                 })
             }),
         None => Ok(None),
+        Some(_) if !capabilities.supports_attest_key() => {
+            Err(Error::Km(ErrorCode::UNSUPPORTED_PURPOSE))
+                .context(ks_err!("The KeyMint device does not support ATTEST_KEY."))
+        }
         Some(attest_key) => get_user_generated_attestation_key(attest_key, caller_uid, db)
             .context(ks_err!("Trying to load attest key"))
             .map(Some),
@@ -121,7 +129,91 @@ fn load_attest_key_blob_and_cert(
                 .take_cert()
                 .ok_or(Error::Rc(ResponseCode::INVALID_ARGUMENT))
                 .context(ks_err!("Successfully loaded key entry, but cert was missing"))?;
+            check_attest_key_parameters(&key_entry.into_key_parameters())
+                .context(ks_err!("Key is not usable as attestation key."))?;
             Ok((key_id_guard, blob, cert, blob_metadata))
         }
     }
 }
+
+/// Checks that the characteristics of a user generated attestation key allow it to sign
+/// attestations, i.e., that it has the `ATTEST_KEY` purpose and that it has not expired.
+/// Attestation signatures are originated by the key, so `ORIGINATION_EXPIRE_DATETIME` applies.
+/// Catching this here gives the caller a meaningful error instead of whatever the KeyMint device
+/// reports when it is asked to use the key.
+fn check_attest_key_parameters(key_params: &[KsKeyParameter]) -> Result<()> {
+    if !key_params.iter().any(|kp| {
+        kp.key_parameter_value() == &KeyParameterValue::KeyPurpose(KeyPurpose::ATTEST_KEY)
+    }) {
+        return Err(Error::Km(ErrorCode::INCOMPATIBLE_PURPOSE))
+            .context(ks_err!("Attestation key lacks the ATTEST_KEY purpose."));
+    }
+    // ORIGINATION_EXPIRE_DATETIME is in milliseconds since the epoch, so it must be compared
+    // with the wall clock the same way `authorize_create` does.
+    let expired = key_params.iter().any(|kp| match kp.key_parameter_value() {
+        KeyParameterValue::OriginationExpireDateTime(expiry) => {
+            Enforcements::is_given_time_passed(*expiry, false)
+        }
+        _ => false,
+    });
+    if expired {
+        return Err(Error::Km(ErrorCode::KEY_EXPIRED)).context(ks_err!("Attestation key expired."));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use android_hardware_security_keymint::aidl::android::hardware::security::keymint::SecurityLevel::SecurityLevel;
+
+    fn check(values: Vec<KeyParameterValue>) -> Result<()> {
+        let key_params: Vec<KsKeyParameter> = values
+            .into_iter()
+            .map(|v| KsKeyParameter::new(v, SecurityLevel::TRUSTED_ENVIRONMENT))
+            .collect();
+        check_attest_key_parameters(&key_params)
+    }
+
+    fn fails_with(result: Result<()>, ec: ErrorCode) -> bool {
+        result.is_err_and(|e| e.root_cause().downcast_ref::<Error>() == Some(&Error::Km(ec)))
+    }
+
+    #[test]
+    fn test_check_attest_key_parameters() {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as i64;
+        let attest_key = KeyParameterValue::KeyPurpose(KeyPurpose::ATTEST_KEY);
+
+        assert!(check(vec![attest_key.clone()]).is_ok());
+        assert!(check(vec![
+            KeyParameterValue::KeyPurpose(KeyPurpose::SIGN),
+            attest_key.clone(),
+            KeyParameterValue::OriginationExpireDateTime(now + 3_600_000),
+        ])
+        .is_ok());
+
+        // A signing key without the ATTEST_KEY purpose must be rejected.
+        assert!(fails_with(
+            check(vec![KeyParameterValue::KeyPurpose(KeyPurpose::SIGN)]),
+            ErrorCode::INCOMPATIBLE_PURPOSE
+        ));
+        assert!(fails_with(check(vec![]), ErrorCode::INCOMPATIBLE_PURPOSE));
+
+        assert!(fails_with(
+            check(vec![
+                attest_key.clone(),
+                KeyParameterValue::OriginationExpireDateTime(now - 3_600_000)
+            ]),
+            ErrorCode::KEY_EXPIRED
+        ));
+        // An expiry that lies in the past only by the wall clock, e.g., one hour after boot,
+        // must be rejected, too.
+        assert!(fails_with(
+            check(vec![attest_key, KeyParameterValue::OriginationExpireDateTime(3_600_000)]),
+            ErrorCode::KEY_EXPIRED
+        ));
+    }
+}
@@ -13,7 +13,8 @@
 // limitations under the License.
 
 //! This module implements functions to log audit events to binary security log buffer for NIAP
-//! compliance.
+//! compliance. Audit events are kept apart from the regular keystore2 logs, which go to the
+//! system buffer, so that they get the retention and access control of the security buffer.
 
 use crate::globals::LOGS_HANDLER;
 use android_system_keystore2::aidl::android::system::keystore2::{
@@ -29,6 +30,25 @@ const TAG_KEY_INTEGRITY_VIOLATION: u32 = 210032;
 
 const FLAG_NAMESPACE: i64 = 0x80000000;
 
+/// A single audit event. `success` is not recorded for integrity violations.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct AuditEvent {
+    tag: u32,
+    success: Option<bool>,
+    alias: String,
+    owner: i32,
+}
+
+fn write_to_security_log(event: AuditEvent) {
+    let AuditEvent { tag, success, alias, owner } = event;
+    let _result = match success {
+        Some(success) => {
+            structured_log!(log_id: LOG_ID_SECURITY, tag, i32::from(success), alias, owner)
+        }
+        None => structured_log!(log_id: LOG_ID_SECURITY, tag, alias, owner),
+    };
+}
+
 /// Encode key owner as either uid or namespace with a flag.
 fn key_owner(domain: Domain, nspace: i64, uid: i32) -> i32 {
     match domain {
@@ -43,34 +63,88 @@ fn key_owner(domain: Domain, nspace: i64, uid: i32) -> i32 {
 
 /// Logs key generation event to NIAP audit log.
 pub fn log_key_generated(key: &KeyDescriptor, calling_app: uid_t, success: bool) {
-    log_key_event(TAG_KEY_GENERATED, key, calling_app, success);
+    log_key_event(TAG_KEY_GENERATED, key, calling_app, success, write_to_security_log);
 }
 
 /// Logs key import event to NIAP audit log.
 pub fn log_key_imported(key: &KeyDescriptor, calling_app: uid_t, success: bool) {
-    log_key_event(TAG_KEY_IMPORTED, key, calling_app, success);
+    log_key_event(TAG_KEY_IMPORTED, key, calling_app, success, write_to_security_log);
 }
 
 /// Logs key deletion event to NIAP audit log.
 pub fn log_key_deleted(key: &KeyDescriptor, calling_app: uid_t, success: bool) {
-    log_key_event(TAG_KEY_DESTROYED, key, calling_app, success);
+    log_key_event(TAG_KEY_DESTROYED, key, calling_app, success, write_to_security_log);
 }
 
 /// Logs key integrity violation to NIAP audit log.
 pub fn log_key_integrity_violation(key: &KeyDescriptor) {
+    log_integrity_violation(key, write_to_security_log);
+}
+
+fn log_integrity_violation(key: &KeyDescriptor, sink: impl FnOnce(AuditEvent) + Send + 'static) {
     let owner = key_owner(key.domain, key.nspace, key.nspace as i32);
-    let alias = String::from(key.alias.as_ref().map_or("none", String::as_str));
-    LOGS_HANDLER.queue_lo(move |_| {
-        let _result =
-            structured_log!(log_id: LOG_ID_SECURITY, TAG_KEY_INTEGRITY_VIOLATION, alias, owner);
-    });
+    queue_audit_event(TAG_KEY_INTEGRITY_VIOLATION, None, key, owner, sink);
 }
 
-fn log_key_event(tag: u32, key: &KeyDescriptor, calling_app: uid_t, success: bool) {
+fn log_key_event(
+    tag: u32,
+    key: &KeyDescriptor,
+    calling_app: uid_t,
+    success: bool,
+    sink: impl FnOnce(AuditEvent) + Send + 'static,
+) {
     let owner = key_owner(key.domain, key.nspace, calling_app as i32);
+    queue_audit_event(tag, Some(success), key, owner, sink);
+}
+
+/// Hands the event to `sink` on the low priority logging thread. Outside of tests, the sink is
+/// always `write_to_security_log`.
+fn queue_audit_event(
+    tag: u32,
+    success: Option<bool>,
+    key: &KeyDescriptor,
+    owner: i32,
+    sink: impl FnOnce(AuditEvent) + Send + 'static,
+) {
     let alias = String::from(key.alias.as_ref().map_or("none", String::as_str));
-    LOGS_HANDLER.queue_lo(move |_| {
-        let _result =
-            structured_log!(log_id: LOG_ID_SECURITY, tag, i32::from(success), alias, owner);
-    });
+    let event = AuditEvent { tag, success, alias, owner };
+    LOGS_HANDLER.queue_lo(move |_| sink(event));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn test_audit_events_route_to_audit_sink() {
+        let (sender, receiver) = channel();
+        let sink = move |event: AuditEvent| sender.send(event).unwrap();
+        let key = KeyDescriptor {
+            domain: Domain::APP,
+            nspace: 0,
+            alias: Some("audit_test_key".to_string()),
+            blob: None,
+        };
+        log_key_event(TAG_KEY_GENERATED, &key, 10001, true, sink.clone());
+        log_key_event(TAG_KEY_DESTROYED, &key, 10001, false, sink.clone());
+        log_integrity_violation(&KeyDescriptor { domain: Domain::SELINUX, nspace: 7, ..key }, sink);
+
+        // Low priority jobs run in order, so the events arrive in the order they were logged.
+        let event = |tag, success, owner| AuditEvent {
+            tag,
+            success,
+            alias: "audit_test_key".to_string(),
+            owner,
+        };
+        let captured: Vec<AuditEvent> = receiver.iter().take(3).collect();
+        assert_eq!(
+            captured,
+            vec![
+                event(TAG_KEY_GENERATED, Some(true), 10001),
+                event(TAG_KEY_DESTROYED, Some(false), 10001),
+                event(TAG_KEY_INTEGRITY_VIOLATION, None, (7 | FLAG_NAMESPACE) as i32),
+            ]
+        );
+    }
 }
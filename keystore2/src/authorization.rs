@@ -19,6 +19,7 @@ use crate::error::Error as KeystoreError;
 use crate::globals::{DB, ENFORCEMENTS, LEGACY_IMPORTER, SUPER_KEY};
 use crate::ks_err;
 use crate::permission::KeystorePerm;
+use crate::super_key::SuperKeyManager;
 use crate::utils::{check_keystore_permission, watchdog as wd};
 use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
     HardwareAuthToken::HardwareAuthToken, HardwareAuthenticatorType::HardwareAuthenticatorType,
@@ -132,16 +133,15 @@ impl AuthorizationManager {
             .context(ks_err!("caller missing Unlock permissions"))?;
         ENFORCEMENTS.set_device_locked(user_id, false);
 
-        let mut skm = SUPER_KEY.write().unwrap();
-        if let Some(password) = password {
-            DB.with(|db| {
-                skm.unlock_user(&mut db.borrow_mut(), &LEGACY_IMPORTER, user_id as u32, &password)
-            })
-            .context(ks_err!("Unlock with password."))
-        } else {
-            DB.with(|db| skm.try_unlock_user_with_biometric(&mut db.borrow_mut(), user_id as u32))
-                .context(ks_err!("try_unlock_user_with_biometric failed user_id={user_id}"))
-        }
+        DB.with(|db| {
+            SuperKeyManager::unlock_user_and_notify(
+                &SUPER_KEY,
+                &mut db.borrow_mut(),
+                &LEGACY_IMPORTER,
+                user_id as u32,
+                password.as_ref(),
+            )
+        })
     }
 
     fn on_device_locked(
@@ -159,14 +159,14 @@ impl AuthorizationManager {
         check_keystore_permission(KeystorePerm::Lock)
             .context(ks_err!("caller missing Lock permission"))?;
         ENFORCEMENTS.set_device_locked(user_id, true);
-        let mut skm = SUPER_KEY.write().unwrap();
         DB.with(|db| {
-            skm.lock_unlocked_device_required_keys(
+            SuperKeyManager::lock_user_and_notify(
+                &SUPER_KEY,
                 &mut db.borrow_mut(),
                 user_id as u32,
                 unlocking_sids,
                 weak_unlock_enabled,
-            );
+            )
         });
         Ok(())
     }
@@ -20,14 +20,19 @@
 
 #include <assert.h>
 #include <log/log.h>
+#include <openssl/aead.h>
 #include <openssl/aes.h>
+#include <openssl/bn.h>
+#include <openssl/bytestring.h>
 #include <openssl/ec.h>
 #include <openssl/ec_key.h>
 #include <openssl/ecdh.h>
+#include <openssl/ecdsa.h>
 #include <openssl/evp.h>
 #include <openssl/hkdf.h>
 #include <openssl/hmac.h>
 #include <openssl/rand.h>
+#include <openssl/rsa.h>
 #include <openssl/x509.h>
 
 #include <vector>
@@ -36,6 +41,7 @@
 
 constexpr size_t kGcmTagLength = 128 / 8;
 constexpr size_t kAes128KeySizeBytes = 128 / 8;
+constexpr size_t kAesKcvLength = 3;
 
 // Copied from system/security/keystore/blob.cpp.
 
@@ -70,12 +76,68 @@ const EVP_CIPHER* getAesCipherForKey(size_t key_size) {
 
 bool hmacSha256(const uint8_t* key, size_t key_size, const uint8_t* msg, size_t msg_size,
                 uint8_t* out, size_t out_size) {
-    const EVP_MD* digest = EVP_sha256();
+    return hmacDigest(NID_sha256, key, key_size, msg, msg_size, out, out_size);
+}
+
+bool hmacDigest(int digest_nid, const uint8_t* key, size_t key_size, const uint8_t* msg,
+                size_t msg_size, uint8_t* out, size_t out_size) {
+    const EVP_MD* digest;
+    switch (digest_nid) {
+    case NID_sha1:
+        digest = EVP_sha1();
+        break;
+    case NID_sha256:
+        digest = EVP_sha256();
+        break;
+    case NID_sha512:
+        digest = EVP_sha512();
+        break;
+    default:
+        return false;
+    }
+    if (out_size < EVP_MD_size(digest)) {
+        return false;
+    }
     unsigned int actual_out_size = out_size;
     uint8_t* p = HMAC(digest, key, key_size, msg, msg_size, out, &actual_out_size);
     return (p != nullptr);
 }
 
+/*
+ * Create an HMAC-SHA256 context keyed with 'key'. Returns nullptr on failure. The context must be
+ * released with hmacSha256_ctx_free.
+ */
+void* hmacSha256_ctx_new(const uint8_t* key, size_t key_size) {
+    bssl::UniquePtr<HMAC_CTX> ctx(HMAC_CTX_new());
+    if (!ctx || !HMAC_Init_ex(ctx.get(), key, key_size, EVP_sha256(), nullptr /* engine */)) {
+        return nullptr;
+    }
+    return ctx.release();
+}
+
+/*
+ * Feed the next 'len' bytes at 'data' into the HMAC context 'ctx'.
+ */
+bool hmacSha256_ctx_update(void* ctx, const uint8_t* data, size_t len) {
+    return HMAC_Update(static_cast<HMAC_CTX*>(ctx), data, len);
+}
+
+/*
+ * Write the 32-byte tag of all data fed into 'ctx' to 'out'.
+ */
+bool hmacSha256_ctx_final(void* ctx, uint8_t* out) {
+    unsigned int out_len;
+    return HMAC_Final(static_cast<HMAC_CTX*>(ctx), out, &out_len) &&
+           out_len == SHA256_DIGEST_LENGTH;
+}
+
+/*
+ * Release a context created by hmacSha256_ctx_new, erasing the key.
+ */
+void hmacSha256_ctx_free(void* ctx) {
+    HMAC_CTX_free(static_cast<HMAC_CTX*>(ctx));
+}
+
 bool randomBytes(uint8_t* out, size_t len) {
     return RAND_bytes(out, len);
 }
@@ -87,6 +149,16 @@ bool randomBytes(uint8_t* out, size_t len) {
  */
 bool AES_gcm_encrypt(const uint8_t* in, uint8_t* out, size_t len, const uint8_t* key,
                      size_t key_size, const uint8_t* iv, uint8_t* tag) {
+    return AES_gcm_encrypt_aad(in, out, len, nullptr, 0, key, key_size, iv, tag);
+}
+
+/*
+ * Like AES_gcm_encrypt, but additionally authenticates 'aad_len' additional data at 'aad'. With
+ * an empty 'aad' the output is identical to that of AES_gcm_encrypt.
+ */
+bool AES_gcm_encrypt_aad(const uint8_t* in, uint8_t* out, size_t len, const uint8_t* aad,
+                         size_t aad_len, const uint8_t* key, size_t key_size, const uint8_t* iv,
+                         uint8_t* tag) {
 
     // There can be 128-bit and 256-bit keys
     const EVP_CIPHER* cipher = getAesCipherForKey(key_size);
@@ -96,9 +168,13 @@ bool AES_gcm_encrypt(const uint8_t* in, uint8_t* out, size_t len, const uint8_t*
     EVP_EncryptInit_ex(ctx.get(), cipher, nullptr /* engine */, key, iv);
     EVP_CIPHER_CTX_set_padding(ctx.get(), 0 /* no padding needed with GCM */);
 
+    int out_len;
+    if (aad_len > 0 && !EVP_EncryptUpdate(ctx.get(), nullptr, &out_len, aad, aad_len)) {
+        return false;
+    }
+
     std::vector<uint8_t> out_tmp(len);
     uint8_t* out_pos = out_tmp.data();
-    int out_len;
 
     EVP_EncryptUpdate(ctx.get(), out_pos, &out_len, in, len);
     out_pos += out_len;
@@ -123,6 +199,16 @@ bool AES_gcm_encrypt(const uint8_t* in, uint8_t* out, size_t len, const uint8_t*
  */
 bool AES_gcm_decrypt(const uint8_t* in, uint8_t* out, size_t len, const uint8_t* key,
                      size_t key_size, const uint8_t* iv, const uint8_t* tag) {
+    return AES_gcm_decrypt_aad(in, out, len, nullptr, 0, key, key_size, iv, tag);
+}
+
+/*
+ * Like AES_gcm_decrypt, but additionally authenticates 'aad_len' additional data at 'aad'.
+ * Decryption fails unless 'aad' matches the additional data used for encryption exactly.
+ */
+bool AES_gcm_decrypt_aad(const uint8_t* in, uint8_t* out, size_t len, const uint8_t* aad,
+                         size_t aad_len, const uint8_t* key, size_t key_size, const uint8_t* iv,
+                         const uint8_t* tag) {
 
     // There can be 128-bit and 256-bit keys
     const EVP_CIPHER* cipher = getAesCipherForKey(key_size);
@@ -133,10 +219,14 @@ bool AES_gcm_decrypt(const uint8_t* in, uint8_t* out, size_t len, const uint8_t*
     EVP_CIPHER_CTX_set_padding(ctx.get(), 0 /* no padding needed with GCM */);
     EVP_CIPHER_CTX_ctrl(ctx.get(), EVP_CTRL_GCM_SET_TAG, kGcmTagLength, const_cast<uint8_t*>(tag));
 
+    int out_len;
+    if (aad_len > 0 && !EVP_DecryptUpdate(ctx.get(), nullptr, &out_len, aad, aad_len)) {
+        return false;
+    }
+
     std::vector<uint8_t> out_tmp(len);
     ArrayEraser out_eraser(out_tmp.data(), len);
     uint8_t* out_pos = out_tmp.data();
-    int out_len;
 
     EVP_DecryptUpdate(ctx.get(), out_pos, &out_len, in, len);
     out_pos += out_len;
@@ -157,6 +247,183 @@ bool AES_gcm_decrypt(const uint8_t* in, uint8_t* out, size_t len, const uint8_t*
     return true;
 }
 
+/*
+ * Authenticate 'len' data at 'in' and 'aad_len' additional data at 'aad' with AES-GCM, using
+ * 128-bit or 256-bit key at 'key', 96-bit IV at 'iv' and 128-bit tag at 'tag'. The plaintext is
+ * decrypted into a scratch buffer that is erased before returning.
+ */
+bool AES_gcm_verify(const uint8_t* in, size_t len, const uint8_t* aad, size_t aad_len,
+                    const uint8_t* key, size_t key_size, const uint8_t* iv, const uint8_t* tag) {
+
+    // There can be 128-bit and 256-bit keys
+    const EVP_CIPHER* cipher = getAesCipherForKey(key_size);
+
+    bssl::UniquePtr<EVP_CIPHER_CTX> ctx(EVP_CIPHER_CTX_new());
+
+    EVP_DecryptInit_ex(ctx.get(), cipher, nullptr /* engine */, key, iv);
+    EVP_CIPHER_CTX_set_padding(ctx.get(), 0 /* no padding needed with GCM */);
+    EVP_CIPHER_CTX_ctrl(ctx.get(), EVP_CTRL_GCM_SET_TAG, kGcmTagLength, const_cast<uint8_t*>(tag));
+
+    int out_len;
+    if (aad_len > 0 && !EVP_DecryptUpdate(ctx.get(), nullptr, &out_len, aad, aad_len)) {
+        return false;
+    }
+
+    std::vector<uint8_t> out_tmp(len);
+    ArrayEraser out_eraser(out_tmp.data(), len);
+    uint8_t* out_pos = out_tmp.data();
+
+    EVP_DecryptUpdate(ctx.get(), out_pos, &out_len, in, len);
+    out_pos += out_len;
+    return EVP_DecryptFinal_ex(ctx.get(), out_pos, &out_len);
+}
+
+/*
+ * Create a context for a streaming AES-GCM encryption ('encrypt' true) or decryption ('encrypt'
+ * false), using 128-bit or 256-bit key at 'key' and 96-bit IV at 'iv'. Returns nullptr on
+ * failure. The context must be released with AES_gcm_ctx_free.
+ */
+void* AES_gcm_ctx_new(bool encrypt, const uint8_t* key, size_t key_size, const uint8_t* iv) {
+    // There can be 128-bit and 256-bit keys
+    const EVP_CIPHER* cipher = getAesCipherForKey(key_size);
+
+    bssl::UniquePtr<EVP_CIPHER_CTX> ctx(EVP_CIPHER_CTX_new());
+    if (!ctx || !EVP_CipherInit_ex(ctx.get(), cipher, nullptr /* engine */, key, iv, encrypt)) {
+        return nullptr;
+    }
+    EVP_CIPHER_CTX_set_padding(ctx.get(), 0 /* no padding needed with GCM */);
+    return ctx.release();
+}
+
+/*
+ * Encrypt or decrypt the next 'len' bytes at 'in' with the streaming context 'ctx' and write the
+ * same number of bytes to 'out'.
+ */
+bool AES_gcm_ctx_update(void* ctx, const uint8_t* in, uint8_t* out, size_t len) {
+    auto cipher_ctx = static_cast<EVP_CIPHER_CTX*>(ctx);
+    int out_len;
+    if (!EVP_CipherUpdate(cipher_ctx, out, &out_len, in, len)) {
+        return false;
+    }
+    // GCM is a stream cipher mode, so no input is held back.
+    return out_len == static_cast<int>(len);
+}
+
+/*
+ * Finish the streaming encryption 'ctx' and write the 128-bit tag to 'tag'.
+ */
+bool AES_gcm_ctx_encrypt_final(void* ctx, uint8_t* tag) {
+    auto cipher_ctx = static_cast<EVP_CIPHER_CTX*>(ctx);
+    int out_len;
+    if (!EVP_EncryptFinal_ex(cipher_ctx, nullptr, &out_len) || out_len != 0) {
+        return false;
+    }
+    return EVP_CIPHER_CTX_ctrl(cipher_ctx, EVP_CTRL_GCM_GET_TAG, kGcmTagLength, tag);
+}
+
+/*
+ * Finish the streaming decryption 'ctx', checking the 128-bit tag at 'tag'.
+ */
+bool AES_gcm_ctx_decrypt_final(void* ctx, const uint8_t* tag) {
+    auto cipher_ctx = static_cast<EVP_CIPHER_CTX*>(ctx);
+    if (!EVP_CIPHER_CTX_ctrl(cipher_ctx, EVP_CTRL_GCM_SET_TAG, kGcmTagLength,
+                             const_cast<uint8_t*>(tag))) {
+        return false;
+    }
+    int out_len;
+    return EVP_DecryptFinal_ex(cipher_ctx, nullptr, &out_len) && out_len == 0;
+}
+
+/*
+ * Release a context created by AES_gcm_ctx_new, erasing the key schedule.
+ */
+void AES_gcm_ctx_free(void* ctx) {
+    EVP_CIPHER_CTX_free(static_cast<EVP_CIPHER_CTX*>(ctx));
+}
+
+/*
+ * Encrypt 'len' data at 'in' with ChaCha20-Poly1305, using 256-bit key at 'key' and 96-bit nonce
+ * at 'nonce', and write output to 'out' and 128-bit tag to 'tag'.
+ */
+bool ChaCha20Poly1305_encrypt(const uint8_t* in, uint8_t* out, size_t len, const uint8_t* key,
+                              const uint8_t* nonce, uint8_t* tag) {
+    const EVP_AEAD* aead = EVP_aead_chacha20_poly1305();
+    bssl::ScopedEVP_AEAD_CTX ctx;
+    if (!EVP_AEAD_CTX_init(ctx.get(), aead, key, EVP_AEAD_key_length(aead), kGcmTagLength,
+                           nullptr /* engine */)) {
+        return false;
+    }
+    size_t tag_len;
+    return EVP_AEAD_CTX_seal_scatter(ctx.get(), out, tag, &tag_len, kGcmTagLength, nonce,
+                                     EVP_AEAD_nonce_length(aead), in, len, nullptr, 0, nullptr,
+                                     0) &&
+           tag_len == kGcmTagLength;
+}
+
+/*
+ * Decrypt 'len' data at 'in' with ChaCha20-Poly1305, using 256-bit key at 'key' and 96-bit nonce
+ * at 'nonce', checking 128-bit tag at 'tag' and writing plaintext to 'out'.
+ */
+bool ChaCha20Poly1305_decrypt(const uint8_t* in, uint8_t* out, size_t len, const uint8_t* key,
+                              const uint8_t* nonce, const uint8_t* tag) {
+    const EVP_AEAD* aead = EVP_aead_chacha20_poly1305();
+    bssl::ScopedEVP_AEAD_CTX ctx;
+    if (!EVP_AEAD_CTX_init(ctx.get(), aead, key, EVP_AEAD_key_length(aead), kGcmTagLength,
+                           nullptr /* engine */)) {
+        return false;
+    }
+    return EVP_AEAD_CTX_open_gather(ctx.get(), out, nonce, EVP_AEAD_nonce_length(aead), in, len,
+                                    tag, kGcmTagLength, nullptr, 0);
+}
+
+/*
+ * Compute the key check value of the 128-bit or 256-bit AES key at 'key' by encrypting a single
+ * all-zero block with AES-ECB and writing the first 3 bytes of the result to 'kcv'.
+ */
+bool AES_kcv(const uint8_t* key, size_t key_size, uint8_t* kcv) {
+    AES_KEY aes_key;
+    if (AES_set_encrypt_key(key, key_size * 8, &aes_key) != 0) {
+        return false;
+    }
+    ArrayEraser key_eraser(reinterpret_cast<uint8_t*>(&aes_key), sizeof(aes_key));
+
+    const uint8_t zero_block[AES_BLOCK_SIZE] = {};
+    uint8_t out[AES_BLOCK_SIZE];
+    AES_encrypt(zero_block, out, &aes_key);
+    memcpy(kcv, out, kAesKcvLength);
+    return true;
+}
+
+/*
+ * Wrap the 'in_len' bytes of key material at 'in' under the 128-bit or 256-bit key encryption key
+ * at 'kek' as specified in RFC 3394 and write the 'in_len' + 8 bytes of output to 'out'.
+ */
+bool AES_key_wrap(const uint8_t* kek, size_t kek_size, const uint8_t* in, size_t in_len,
+                  uint8_t* out) {
+    AES_KEY aes_key;
+    if (AES_set_encrypt_key(kek, kek_size * 8, &aes_key) != 0) {
+        return false;
+    }
+    ArrayEraser key_eraser(reinterpret_cast<uint8_t*>(&aes_key), sizeof(aes_key));
+    return AES_wrap_key(&aes_key, nullptr /* default iv */, out, in, in_len) ==
+           static_cast<int>(in_len + 8);
+}
+
+/*
+ * Unwrap the 'in_len' bytes at 'in' produced by AES_key_wrap and write the 'in_len' - 8 bytes of
+ * key material to 'out'. Returns false if the integrity check fails.
+ */
+bool AES_key_unwrap(const uint8_t* kek, size_t kek_size, const uint8_t* in, size_t in_len,
+                    uint8_t* out) {
+    AES_KEY aes_key;
+    if (AES_set_decrypt_key(kek, kek_size * 8, &aes_key) != 0) {
+        return false;
+    }
+    ArrayEraser key_eraser(reinterpret_cast<uint8_t*>(&aes_key), sizeof(aes_key));
+    return AES_unwrap_key(&aes_key, nullptr /* default iv */, out, in, in_len) ==
+           static_cast<int>(in_len - 8);
+}
+
 // Copied from system/security/keystore/keymaster_enforcement.cpp.
 
 class EvpMdCtx {
@@ -193,6 +460,11 @@ static constexpr size_t SALT_SIZE = 16;
 // Copied from system/security/keystore/user_state.cpp.
 
 void PBKDF2(uint8_t* key, size_t key_len, const char* pw, size_t pw_len, const uint8_t* salt) {
+    PBKDF2_iters(key, key_len, pw, pw_len, salt, 8192);
+}
+
+bool PBKDF2_iters(uint8_t* key, size_t key_len, const char* pw, size_t pw_len,
+                  const uint8_t* salt, uint32_t iterations) {
     const EVP_MD* digest = EVP_sha256();
 
     // SHA1 was used prior to increasing the key size
@@ -200,7 +472,7 @@ void PBKDF2(uint8_t* key, size_t key_len, const char* pw, size_t pw_len, const u
         digest = EVP_sha1();
     }
 
-    PKCS5_PBKDF2_HMAC(pw, pw_len, salt, SALT_SIZE, 8192, digest, key_len, key);
+    return PKCS5_PBKDF2_HMAC(pw, pw_len, salt, SALT_SIZE, iterations, digest, key_len, key);
 }
 
 // New code.
@@ -224,19 +496,29 @@ int ECDHComputeKey(void* out, const EC_POINT* pub_key, const EC_KEY* priv_key) {
 }
 
 EC_KEY* ECKEYGenerateKey() {
-    EC_KEY* key = EC_KEY_new();
-    EC_GROUP* group = EC_GROUP_new_by_curve_name(NID_secp521r1);
-    EC_KEY_set_group(key, group);
-    auto result = EC_KEY_generate_key(key);
-    if (result == 0) {
-        EC_GROUP_free(group);
-        EC_KEY_free(key);
+    return ECKEYGenerateKeyForCurve(NID_secp521r1);
+}
+
+EC_KEY* ECKEYGenerateKeyForCurve(int curve_nid) {
+    bssl::UniquePtr<EC_KEY> key(EC_KEY_new_by_curve_name(curve_nid));
+    if (!key || !EC_KEY_generate_key(key.get())) {
         return nullptr;
     }
-    return key;
+    return key.release();
 }
 
 size_t ECKEYMarshalPrivateKey(const EC_KEY* priv_key, uint8_t* buf, size_t len) {
+    if (buf == nullptr) {
+        // Marshal into a growable buffer to learn the required length. CBB_cleanup
+        // zeroes the buffer before freeing it.
+        bssl::ScopedCBB cbb;
+        if (!CBB_init(cbb.get(), 0) ||
+            !EC_KEY_marshal_private_key(cbb.get(), priv_key,
+                                        EC_PKEY_NO_PARAMETERS | EC_PKEY_NO_PUBKEY)) {
+            return 0;
+        }
+        return CBB_len(cbb.get());
+    }
     CBB cbb;
     size_t out_len;
     if (!CBB_init_fixed(&cbb, buf, len) ||
@@ -249,9 +531,13 @@ size_t ECKEYMarshalPrivateKey(const EC_KEY* priv_key, uint8_t* buf, size_t len)
 }
 
 EC_KEY* ECKEYParsePrivateKey(const uint8_t* buf, size_t len) {
+    return ECKEYParsePrivateKeyForCurve(NID_secp521r1, buf, len);
+}
+
+EC_KEY* ECKEYParsePrivateKeyForCurve(int curve_nid, const uint8_t* buf, size_t len) {
     CBS cbs;
     CBS_init(&cbs, buf, len);
-    EC_GROUP* group = EC_GROUP_new_by_curve_name(NID_secp521r1);
+    EC_GROUP* group = EC_GROUP_new_by_curve_name(curve_nid);
     auto result = EC_KEY_parse_private_key(&cbs, group);
     EC_GROUP_free(group);
     if (result != nullptr && CBS_len(&cbs) != 0) {
@@ -261,16 +547,78 @@ EC_KEY* ECKEYParsePrivateKey(const uint8_t* buf, size_t len) {
     return result;
 }
 
+EC_KEY* ECKEYFromScalar(int curve_nid, const uint8_t* scalar, size_t len) {
+    bssl::UniquePtr<EC_KEY> key(EC_KEY_new_by_curve_name(curve_nid));
+    bssl::UniquePtr<BIGNUM> priv_key(BN_bin2bn(scalar, len, nullptr));
+    if (!key || !priv_key) {
+        return nullptr;
+    }
+    const EC_GROUP* group = EC_KEY_get0_group(key.get());
+    if (BN_is_zero(priv_key.get()) || BN_cmp(priv_key.get(), EC_GROUP_get0_order(group)) >= 0) {
+        return nullptr;
+    }
+    bssl::UniquePtr<EC_POINT> pub_key(EC_POINT_new(group));
+    if (!pub_key ||
+        !EC_POINT_mul(group, pub_key.get(), priv_key.get(), nullptr, nullptr, nullptr) ||
+        !EC_KEY_set_private_key(key.get(), priv_key.get()) ||
+        !EC_KEY_set_public_key(key.get(), pub_key.get())) {
+        return nullptr;
+    }
+    return key.release();
+}
+
+size_t ECDSASign(const EC_KEY* key, const uint8_t* digest, size_t digest_len, uint8_t* sig,
+                 size_t sig_len) {
+    if (ECDSA_size(key) > sig_len) {
+        return 0;
+    }
+    unsigned int out_len = 0;
+    if (!ECDSA_sign(0 /* type */, digest, digest_len, sig, &out_len, key)) {
+        return 0;
+    }
+    return out_len;
+}
+
+int ECDSAVerify(const EC_POINT* pub_key, const uint8_t* digest, size_t digest_len,
+                const uint8_t* sig, size_t sig_len) {
+    return ECDSAVerifyForCurve(NID_secp521r1, pub_key, digest, digest_len, sig, sig_len);
+}
+
+int ECDSAVerifyForCurve(int curve_nid, const EC_POINT* pub_key, const uint8_t* digest,
+                        size_t digest_len, const uint8_t* sig, size_t sig_len) {
+    bssl::UniquePtr<EC_KEY> key(EC_KEY_new_by_curve_name(curve_nid));
+    if (!key || !EC_KEY_set_public_key(key.get(), pub_key)) {
+        return -1;
+    }
+    return ECDSA_verify(0 /* type */, digest, digest_len, sig, sig_len, key.get());
+}
+
 size_t ECPOINTPoint2Oct(const EC_POINT* point, uint8_t* buf, size_t len) {
-    EC_GROUP* group = EC_GROUP_new_by_curve_name(NID_secp521r1);
-    point_conversion_form_t form = POINT_CONVERSION_UNCOMPRESSED;
+    return ECPOINTPoint2OctForCurve(NID_secp521r1, point, false /* compressed */, buf, len);
+}
+
+size_t ECPOINTPoint2OctForCurve(int curve_nid, const EC_POINT* point, bool compressed,
+                                uint8_t* buf, size_t len) {
+    EC_GROUP* group = EC_GROUP_new_by_curve_name(curve_nid);
+    if (!group) {
+        return 0;
+    }
+    point_conversion_form_t form =
+        compressed ? POINT_CONVERSION_COMPRESSED : POINT_CONVERSION_UNCOMPRESSED;
     auto result = EC_POINT_point2oct(group, point, form, buf, len, nullptr);
     EC_GROUP_free(group);
     return result;
 }
 
 EC_POINT* ECPOINTOct2Point(const uint8_t* buf, size_t len) {
-    EC_GROUP* group = EC_GROUP_new_by_curve_name(NID_secp521r1);
+    return ECPOINTOct2PointForCurve(NID_secp521r1, buf, len);
+}
+
+EC_POINT* ECPOINTOct2PointForCurve(int curve_nid, const uint8_t* buf, size_t len) {
+    EC_GROUP* group = EC_GROUP_new_by_curve_name(curve_nid);
+    if (!group) {
+        return nullptr;
+    }
     EC_POINT* point = EC_POINT_new(group);
     auto result = EC_POINT_oct2point(group, point, buf, len, nullptr);
     EC_GROUP_free(group);
@@ -281,6 +629,33 @@ EC_POINT* ECPOINTOct2Point(const uint8_t* buf, size_t len) {
     return point;
 }
 
+RSA* RSAGenerateKey(unsigned bits) {
+    bssl::UniquePtr<RSA> key(RSA_new());
+    bssl::UniquePtr<BIGNUM> e(BN_new());
+    if (!key || !e || !BN_set_word(e.get(), RSA_F4) ||
+        !RSA_generate_key_ex(key.get(), bits, e.get(), nullptr)) {
+        return nullptr;
+    }
+    return key.release();
+}
+
+size_t RSASignPKCS1(RSA* key, int hash_nid, const uint8_t* digest, size_t digest_len,
+                    uint8_t* sig, size_t sig_len) {
+    if (RSA_size(key) > sig_len) {
+        return 0;
+    }
+    unsigned int out_len = 0;
+    if (!RSA_sign(hash_nid, digest, digest_len, sig, &out_len, key)) {
+        return 0;
+    }
+    return out_len;
+}
+
+int RSAVerifyPKCS1(RSA* key, int hash_nid, const uint8_t* digest, size_t digest_len,
+                   const uint8_t* sig, size_t sig_len) {
+    return RSA_verify(hash_nid, digest, digest_len, sig, sig_len, key);
+}
+
 int extractSubjectFromCertificate(const uint8_t* cert_buf, size_t cert_len, uint8_t* subject_buf,
                                   size_t subject_buf_len) {
     if (!cert_buf || !subject_buf) {
@@ -319,3 +694,153 @@ int extractSubjectFromCertificate(const uint8_t* cert_buf, size_t cert_len, uint
     uint8_t* tmp = subject_buf;
     return i2d_X509_NAME(subject, &tmp);
 }
+
+int isSelfSignedCertificate(const uint8_t* cert_buf, size_t cert_len) {
+    if (!cert_buf) {
+        ALOGE("isSelfSignedCertificate: received null pointer");
+        return -1;
+    }
+
+    const uint8_t* p = cert_buf;
+    bssl::UniquePtr<X509> cert(d2i_X509(nullptr /* Allocate X509 struct */, &p, cert_len));
+    if (!cert || p != cert_buf + cert_len) {
+        ALOGE("isSelfSignedCertificate: failed to parse certificate");
+        return -1;
+    }
+
+    if (X509_check_issued(cert.get(), cert.get()) != X509_V_OK) {
+        return 0;
+    }
+    EVP_PKEY* public_key = X509_get0_pubkey(cert.get());
+    return public_key && X509_verify(cert.get(), public_key) == 1 ? 1 : 0;
+}
+
+int extractAttestationChallenge(const uint8_t* cert_buf, size_t cert_len,
+                                uint8_t* challenge_buf, size_t challenge_buf_len) {
+    if (!cert_buf || !challenge_buf) {
+        ALOGE("extractAttestationChallenge: received null pointer");
+        return -1;
+    }
+
+    const uint8_t* p = cert_buf;
+    bssl::UniquePtr<X509> cert(d2i_X509(nullptr /* Allocate X509 struct */, &p, cert_len));
+    if (!cert) {
+        ALOGE("extractAttestationChallenge: failed to parse certificate");
+        return -1;
+    }
+
+    bssl::UniquePtr<ASN1_OBJECT> oid(
+        OBJ_txt2obj("1.3.6.1.4.1.11129.2.1.17", 1 /* dotted decimal only */));
+    if (!oid) {
+        ALOGE("extractAttestationChallenge: failed to create attestation extension OID");
+        return -1;
+    }
+    int location = X509_get_ext_by_OBJ(cert.get(), oid.get(), -1 /* search from the start */);
+    if (location < 0) {
+        return -2;
+    }
+    ASN1_OCTET_STRING* ext_data = X509_EXTENSION_get_data(X509_get_ext(cert.get(), location));
+    if (!ext_data) {
+        ALOGE("extractAttestationChallenge: failed to retrieve attestation extension");
+        return -1;
+    }
+
+    // KeyDescription ::= SEQUENCE {
+    //     attestationVersion INTEGER,
+    //     attestationSecurityLevel SecurityLevel,
+    //     keyMintVersion INTEGER,
+    //     keyMintSecurityLevel SecurityLevel,
+    //     attestationChallenge OCTET_STRING,
+    //     ...
+    // }
+    CBS ext, key_description, challenge;
+    CBS_init(&ext, ASN1_STRING_get0_data(ext_data), ASN1_STRING_length(ext_data));
+    if (!CBS_get_asn1(&ext, &key_description, CBS_ASN1_SEQUENCE) ||
+        !CBS_get_asn1(&key_description, nullptr, CBS_ASN1_INTEGER) ||
+        !CBS_get_asn1(&key_description, nullptr, CBS_ASN1_ENUMERATED) ||
+        !CBS_get_asn1(&key_description, nullptr, CBS_ASN1_INTEGER) ||
+        !CBS_get_asn1(&key_description, nullptr, CBS_ASN1_ENUMERATED) ||
+        !CBS_get_asn1(&key_description, &challenge, CBS_ASN1_OCTETSTRING)) {
+        ALOGE("extractAttestationChallenge: malformed attestation extension");
+        return -1;
+    }
+
+    if (CBS_len(&challenge) > challenge_buf_len) {
+        ALOGE("extractAttestationChallenge: needed %zu bytes for challenge, caller provided %zu",
+              CBS_len(&challenge), challenge_buf_len);
+        return -1;
+    }
+    memcpy(challenge_buf, CBS_data(&challenge), CBS_len(&challenge));
+    return CBS_len(&challenge);
+}
+
+bool extractCertificateFields(const uint8_t* cert_buf, size_t cert_len, uint8_t* issuer_buf,
+                              size_t* issuer_len, uint8_t* serial_buf, size_t* serial_len,
+                              int64_t* not_before, int64_t* not_after) {
+    if (!cert_buf || !issuer_buf || !issuer_len || !serial_buf || !serial_len || !not_before ||
+        !not_after) {
+        ALOGE("extractCertificateFields: received null pointer");
+        return false;
+    }
+
+    const uint8_t* p = cert_buf;
+    bssl::UniquePtr<X509> cert(d2i_X509(nullptr /* Allocate X509 struct */, &p, cert_len));
+    if (!cert) {
+        ALOGE("extractCertificateFields: failed to parse certificate");
+        return false;
+    }
+
+    X509_NAME* issuer = X509_get_issuer_name(cert.get());
+    int encoded_issuer_len = issuer ? i2d_X509_NAME(issuer, nullptr) : -1;
+    if (encoded_issuer_len < 0 || static_cast<size_t>(encoded_issuer_len) > *issuer_len) {
+        ALOGE("extractCertificateFields: failed to encode issuer name");
+        return false;
+    }
+    uint8_t* tmp = issuer_buf;
+    *issuer_len = i2d_X509_NAME(issuer, &tmp);
+
+    const ASN1_INTEGER* serial = X509_get0_serialNumber(cert.get());
+    if (!serial || static_cast<size_t>(ASN1_STRING_length(serial)) > *serial_len) {
+        ALOGE("extractCertificateFields: failed to retrieve serial number");
+        return false;
+    }
+    *serial_len = ASN1_STRING_length(serial);
+    memcpy(serial_buf, ASN1_STRING_get0_data(serial), *serial_len);
+
+    if (!ASN1_TIME_to_posix(X509_get0_notBefore(cert.get()), not_before) ||
+        !ASN1_TIME_to_posix(X509_get0_notAfter(cert.get()), not_after)) {
+        ALOGE("extractCertificateFields: failed to convert validity period");
+        return false;
+    }
+    return true;
+}
+
+int verifyCertChain(const uint8_t* const* certs, const size_t* cert_lens, size_t count) {
+    if (!certs || !cert_lens || count == 0) {
+        ALOGE("verifyCertChain: received empty chain");
+        return 0;
+    }
+
+    std::vector<bssl::UniquePtr<X509>> chain;
+    for (size_t i = 0; i < count; ++i) {
+        const uint8_t* p = certs[i];
+        bssl::UniquePtr<X509> cert;
+        if (p) {
+            cert.reset(d2i_X509(nullptr /* Allocate X509 struct */, &p, cert_lens[i]));
+        }
+        if (!cert || p != certs[i] + cert_lens[i]) {
+            ALOGE("verifyCertChain: failed to parse certificate %zu", i);
+            return i;
+        }
+        chain.push_back(std::move(cert));
+    }
+
+    for (size_t i = 0; i + 1 < count; ++i) {
+        EVP_PKEY* issuer_key = X509_get0_pubkey(chain[i + 1].get());
+        if (!issuer_key || X509_verify(chain[i].get(), issuer_key) != 1) {
+            ALOGE("verifyCertChain: signature of certificate %zu does not verify", i);
+            return i;
+        }
+    }
+    return -1;
+}
@@ -24,12 +24,53 @@
 extern "C" {
   bool hmacSha256(const uint8_t* key, size_t key_size, const uint8_t* msg, size_t msg_size,
                   uint8_t* out, size_t out_size);
+  // Like hmacSha256, but with the digest given by digest_nid, which must be one of NID_sha1,
+  // NID_sha256 and NID_sha512. Fails if out_size is smaller than the digest size.
+  bool hmacDigest(int digest_nid, const uint8_t* key, size_t key_size, const uint8_t* msg,
+                  size_t msg_size, uint8_t* out, size_t out_size);
+  // Incremental HMAC-SHA256. The context returned by hmacSha256_ctx_new is
+  // opaque and must be released with hmacSha256_ctx_free. The out parameter of
+  // hmacSha256_ctx_final must point to a buffer of at least 32 bytes.
+  void* hmacSha256_ctx_new(const uint8_t* key, size_t key_size);
+  bool hmacSha256_ctx_update(void* ctx, const uint8_t* data, size_t len);
+  bool hmacSha256_ctx_final(void* ctx, uint8_t* out);
+  void hmacSha256_ctx_free(void* ctx);
   bool randomBytes(uint8_t* out, size_t len);
   bool AES_gcm_encrypt(const uint8_t* in, uint8_t* out, size_t len,
                        const uint8_t* key, size_t key_size, const uint8_t* iv, uint8_t* tag);
   bool AES_gcm_decrypt(const uint8_t* in, uint8_t* out, size_t len,
                        const uint8_t* key, size_t key_size, const uint8_t* iv,
                        const uint8_t* tag);
+  bool AES_gcm_encrypt_aad(const uint8_t* in, uint8_t* out, size_t len,
+                           const uint8_t* aad, size_t aad_len, const uint8_t* key,
+                           size_t key_size, const uint8_t* iv, uint8_t* tag);
+  bool AES_gcm_decrypt_aad(const uint8_t* in, uint8_t* out, size_t len,
+                           const uint8_t* aad, size_t aad_len, const uint8_t* key,
+                           size_t key_size, const uint8_t* iv, const uint8_t* tag);
+  bool AES_gcm_verify(const uint8_t* in, size_t len, const uint8_t* aad, size_t aad_len,
+                      const uint8_t* key, size_t key_size, const uint8_t* iv,
+                      const uint8_t* tag);
+  // Streaming AES-GCM. The context returned by AES_gcm_ctx_new is opaque and
+  // must be released with AES_gcm_ctx_free.
+  void* AES_gcm_ctx_new(bool encrypt, const uint8_t* key, size_t key_size, const uint8_t* iv);
+  bool AES_gcm_ctx_update(void* ctx, const uint8_t* in, uint8_t* out, size_t len);
+  bool AES_gcm_ctx_encrypt_final(void* ctx, uint8_t* tag);
+  bool AES_gcm_ctx_decrypt_final(void* ctx, const uint8_t* tag);
+  void AES_gcm_ctx_free(void* ctx);
+  // The key must be 32 bytes, the nonce 12 bytes and the tag 16 bytes.
+  bool ChaCha20Poly1305_encrypt(const uint8_t* in, uint8_t* out, size_t len,
+                                const uint8_t* key, const uint8_t* nonce, uint8_t* tag);
+  bool ChaCha20Poly1305_decrypt(const uint8_t* in, uint8_t* out, size_t len,
+                                const uint8_t* key, const uint8_t* nonce, const uint8_t* tag);
+  // The kcv parameter must point to a buffer of at least 3 bytes.
+  bool AES_kcv(const uint8_t* key, size_t key_size, uint8_t* kcv);
+  // RFC 3394 AES key wrap with the default IV. The in_len of AES_key_wrap must be a multiple of
+  // 8 and at least 16, and out must have room for in_len + 8 bytes. AES_key_unwrap writes
+  // in_len - 8 bytes to out and returns false if the integrity check fails.
+  bool AES_key_wrap(const uint8_t* kek, size_t kek_size, const uint8_t* in, size_t in_len,
+                    uint8_t* out);
+  bool AES_key_unwrap(const uint8_t* kek, size_t kek_size, const uint8_t* in, size_t in_len,
+                      uint8_t* out);
 
   // Copied from system/security/keystore/keymaster_enforcement.h.
   typedef uint64_t km_id_t;
@@ -38,9 +79,17 @@ extern "C" {
 
   // The salt parameter must be non-nullptr and point to 16 bytes of data.
   void PBKDF2(uint8_t* key, size_t key_len, const char* pw, size_t pw_len, const uint8_t* salt);
+  // Like PBKDF2, but with the given number of iterations instead of 8192.
+  bool PBKDF2_iters(uint8_t* key, size_t key_len, const char* pw, size_t pw_len,
+                    const uint8_t* salt, uint32_t iterations);
 
+  #include "openssl/crypto.h"
   #include "openssl/digest.h"
   #include "openssl/ec_key.h"
+  #include "openssl/mem.h"
+  #include "openssl/nid.h"
+  #include "openssl/rsa.h"
+  #include "openssl/sha.h"
 
   bool HKDFExtract(uint8_t *out_key, size_t *out_len,
                    const uint8_t *secret, size_t secret_len,
@@ -57,14 +106,69 @@ extern "C" {
 
   EC_KEY* ECKEYGenerateKey();
 
+  // Generates a new EC_KEY on the curve curve_nid. Returns nullptr on failure.
+  EC_KEY* ECKEYGenerateKeyForCurve(int curve_nid);
+
+  // Marshals priv_key into buf and returns the number of bytes written, or 0 on
+  // failure. If buf is null, returns the number of bytes required instead.
   size_t ECKEYMarshalPrivateKey(const EC_KEY *priv_key, uint8_t *buf, size_t len);
 
   EC_KEY* ECKEYParsePrivateKey(const uint8_t *buf, size_t len);
 
+  // Parses a private key on the curve curve_nid as marshaled by
+  // ECKEYMarshalPrivateKey. Returns nullptr on failure.
+  EC_KEY* ECKEYParsePrivateKeyForCurve(int curve_nid, const uint8_t *buf, size_t len);
+
+  // Creates an EC_KEY on the curve curve_nid from the big-endian private scalar
+  // and computes its public point. Returns nullptr if the scalar is not in
+  // [1, order - 1].
+  EC_KEY* ECKEYFromScalar(int curve_nid, const uint8_t *scalar, size_t len);
+
+  // Signs the pre-hashed digest with key and writes the DER-encoded signature
+  // to sig. Returns the length of the signature or 0 on failure.
+  size_t ECDSASign(const EC_KEY *key, const uint8_t *digest, size_t digest_len, uint8_t *sig,
+                   size_t sig_len);
+
+  // Verifies the DER-encoded signature sig over the pre-hashed digest with the
+  // P-521 public key pub_key. Returns 1 if the signature is valid, 0 if it is
+  // not and -1 if the public key could not be used.
+  int ECDSAVerify(const EC_POINT *pub_key, const uint8_t *digest, size_t digest_len,
+                  const uint8_t *sig, size_t sig_len);
+
+  // Like ECDSAVerify, but for a public key on the curve curve_nid. Returns -1
+  // if pub_key is not a point on that curve.
+  int ECDSAVerifyForCurve(int curve_nid, const EC_POINT *pub_key, const uint8_t *digest,
+                          size_t digest_len, const uint8_t *sig, size_t sig_len);
+
   size_t ECPOINTPoint2Oct(const EC_POINT *point, uint8_t *buf, size_t len);
 
+  // Encodes point on the curve curve_nid into buf, in compressed form if
+  // compressed is set. Returns the number of bytes written, or 0 on failure.
+  // If buf is null, returns the number of bytes required instead.
+  size_t ECPOINTPoint2OctForCurve(int curve_nid, const EC_POINT *point, bool compressed,
+                                  uint8_t *buf, size_t len);
+
   EC_POINT* ECPOINTOct2Point(const uint8_t *buf, size_t len);
 
+  // Decodes a point on the curve curve_nid in either compressed or
+  // uncompressed form. Returns nullptr on failure.
+  EC_POINT* ECPOINTOct2PointForCurve(int curve_nid, const uint8_t *buf, size_t len);
+
+  // Generates an RSA key of the given size with public exponent 65537.
+  // Returns nullptr on failure.
+  RSA* RSAGenerateKey(unsigned bits);
+
+  // Signs the pre-hashed digest with key using RSASSA-PKCS1-v1_5, where
+  // hash_nid identifies the hash function. Returns the length of the signature
+  // written to sig or 0 on failure.
+  size_t RSASignPKCS1(RSA *key, int hash_nid, const uint8_t *digest, size_t digest_len,
+                      uint8_t *sig, size_t sig_len);
+
+  // Verifies the RSASSA-PKCS1-v1_5 signature sig over the pre-hashed digest.
+  // Returns 1 if the signature is valid and 0 otherwise.
+  int RSAVerifyPKCS1(RSA *key, int hash_nid, const uint8_t *digest, size_t digest_len,
+                     const uint8_t *sig, size_t sig_len);
+
 }
 
 // Parse a DER-encoded X.509 certificate contained in cert_buf, with length
@@ -89,4 +193,51 @@ extern "C" {
 int extractSubjectFromCertificate(const uint8_t* cert_buf, size_t cert_len,
                                   uint8_t* subject_buf, size_t subject_buf_len);
 
+// Extracts the attestationChallenge field from the KeyMint attestation
+// extension (OID 1.3.6.1.4.1.11129.2.1.17) of the DER-encoded certificate in
+// cert_buf. The challenge is never larger than the certificate, so a
+// challenge_buf of cert_len bytes is always sufficient.
+//
+// If the return value >= 0 it specifies the number of bytes written into
+// challenge_buf; the operation was successful.
+//
+// If the return value == -1, the certificate or the extension could not be
+// parsed, or challenge_buf was too small.  The reason will be logged.
+//
+// If the return value == -2, the certificate has no attestation extension.
+int extractAttestationChallenge(const uint8_t* cert_buf, size_t cert_len,
+                                uint8_t* challenge_buf, size_t challenge_buf_len);
+
+// Checks whether the DER-encoded X.509 certificate contained in cert_buf, with
+// length cert_len, is self-signed, i.e., its issuer matches its subject and its
+// signature verifies with its own public key.
+//
+// Returns 1 if the certificate is self-signed and 0 if it is not. Returns -1
+// if cert_buf does not hold exactly one parseable certificate.
+int isSelfSignedCertificate(const uint8_t* cert_buf, size_t cert_len);
+
+// Extracts the DER-encoded issuer, the serial number and the validity period
+// from the DER-encoded X.509 certificate contained in cert_buf. The serial
+// number is written as its big-endian magnitude. On entry, *issuer_len and
+// *serial_len hold the capacity of issuer_buf and serial_buf; on success they
+// are set to the number of bytes written. Neither field is larger than the
+// certificate, so buffers of cert_len bytes are always sufficient. The validity
+// bounds are written in seconds since the epoch.
+//
+// Returns false if the certificate could not be parsed or a buffer was too
+// small.  The reason will be logged.
+bool extractCertificateFields(const uint8_t* cert_buf, size_t cert_len, uint8_t* issuer_buf,
+                              size_t* issuer_len, uint8_t* serial_buf, size_t* serial_len,
+                              int64_t* not_before, int64_t* not_after);
+
+// Verifies the chain of count DER-encoded X.509 certificates, ordered from the
+// leaf to the root, where certs[i] has length cert_lens[i]. The signature of
+// each certificate but the last is checked against the public key of the
+// certificate following it. The root is not checked, it is up to the caller to
+// decide whether to trust it.
+//
+// Returns -1 if the chain verifies. Otherwise, returns the index of the first
+// certificate that could not be parsed or whose signature does not verify.
+int verifyCertChain(const uint8_t* const* certs, const size_t* cert_lens, size_t count);
+
 #endif  //  __CRYPTO_H__
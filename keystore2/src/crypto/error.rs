@@ -30,6 +30,10 @@ pub enum Error {
     #[error("Invalid IV length.")]
     InvalidIvLength,
 
+    /// The nonce has the wrong length.
+    #[error("Invalid nonce length.")]
+    InvalidNonceLength,
+
     /// The aead tag has the wrong length.
     #[error("Invalid AEAD tag length.")]
     InvalidAeadTagLength,
@@ -42,10 +46,22 @@ pub enum Error {
     #[error("Invalid data length.")]
     InvalidDataLength,
 
+    /// The input is larger than a one-shot operation accepts.
+    #[error("Invalid input length.")]
+    InvalidInputLength,
+
     /// Invalid salt length.
     #[error("Invalid salt length.")]
     InvalidSaltLength,
 
+    /// The PBKDF2 iteration count is zero.
+    #[error("Invalid iteration count.")]
+    InvalidIterationCount,
+
+    /// This is returned if the C implementation of PBKDF2_iters returned false.
+    #[error("Failed to derive key with PBKDF2.")]
+    Pbkdf2Failed,
+
     /// Random number generation failed.
     #[error("Random number generation failed.")]
     RandomNumberGenerationFailed,
@@ -83,6 +99,30 @@ pub enum Error {
     #[error("Failed to parse private key.")]
     ECKEYParsePrivateKeyFailed,
 
+    /// This is returned if the C implementation of ECKEYFromScalar returned null.
+    #[error("Failed to create EC key from scalar.")]
+    ECKEYFromScalarFailed,
+
+    /// This is returned if the C implementation of ECDSASign returned 0.
+    #[error("Failed to compute ECDSA signature.")]
+    EcdsaSignFailed,
+
+    /// This is returned if the C implementation of ECDSAVerify could not use the public key.
+    #[error("Failed to verify ECDSA signature.")]
+    EcdsaVerifyFailed,
+
+    /// This is returned if rsa_generate_key is called with an unsupported key size.
+    #[error("Invalid key size.")]
+    InvalidKeySize,
+
+    /// This is returned if the C implementation of RSAGenerateKey returned null.
+    #[error("Failed to generate RSA key.")]
+    RsaGenerateKeyFailed,
+
+    /// This is returned if the C implementation of RSASignPKCS1 returned 0.
+    #[error("Failed to compute RSA signature.")]
+    RsaSignFailed,
+
     /// This is returned if the C implementation of ECPOINTPoint2Oct returned 0.
     #[error("Failed to convert point to oct.")]
     ECPoint2OctFailed,
@@ -95,10 +135,56 @@ pub enum Error {
     #[error("Failed to extract certificate subject.")]
     ExtractSubjectFailed,
 
+    /// This is returned if the C implementation of isSelfSignedCertificate could not parse the
+    /// certificate.
+    #[error("Failed to parse certificate.")]
+    CertificateParseFailed,
+
+    /// This is returned if the C implementation of extractAttestationChallenge failed.
+    #[error("Failed to extract attestation challenge.")]
+    ExtractAttestationChallengeFailed,
+
+    /// This is returned if the C implementation of extractCertificateFields failed.
+    #[error("Failed to extract certificate fields.")]
+    ExtractCertificateFieldsFailed,
+
+    /// The certificate at the given index of a chain, counted from the leaf, could not be parsed
+    /// or its signature does not verify with the public key of the next certificate.
+    #[error("Certificate chain verification failed at index {0}.")]
+    CertChainVerificationFailed(usize),
+
+    /// The certificate does not carry a KeyMint attestation extension.
+    #[error("Certificate has no attestation extension.")]
+    AttestationExtensionMissing,
+
+    /// This is returned if the integrity check of an AES key unwrap fails.
+    #[error("Failed to unwrap key.")]
+    KeyUnwrapFailed,
+
+    /// This is returned if the C implementation of hmacDigest failed.
+    #[error("Failed to calculate HMAC.")]
+    HmacFailed,
+
     /// This is returned if the C implementation of hmacSha256 failed.
     #[error("Failed to calculate HMAC-SHA256.")]
     HmacSha256Failed,
 
+    /// This is returned if the C implementation of AES_kcv returned false.
+    #[error("Failed to compute key check value.")]
+    KcvFailed,
+
+    /// A known answer test of the named primitive failed.
+    #[error("Self test failed: {0}.")]
+    SelfTestFailed(&'static str),
+
+    /// The requested MAC length is out of range.
+    #[error("Invalid MAC length.")]
+    InvalidMacLength,
+
+    /// A MAC tag did not match the expected value.
+    #[error("MAC verification failed.")]
+    MacVerificationFailed,
+
     /// Zvec error.
     #[error(transparent)]
     ZVec(#[from] zvec::Error),
@@ -14,18 +14,38 @@
 
 //! This module implements safe wrappers for some crypto operations required by
 //! Keystore 2.0.
+//!
+//! Outputs that are or are derived from secret key material are returned as `ZVec`, which is
+//! zeroized on drop: generated and derived keys, decrypted plaintexts, ECDH shared secrets and
+//! marshalled private keys. Public outputs are returned as plain `Vec<u8>`: salts, IVs, MAC
+//! tags, ciphertexts, encoded EC points and data extracted from certificates. Secret outputs are
+//! written by BoringSSL straight into the `ZVec`, so that they are never held in a buffer that
+//! is not zeroized. New functions must follow the same classification.
 
 mod error;
+pub mod self_test;
 pub mod zvec;
 pub use error::Error;
 use keystore2_crypto_bindgen::{
-    extractSubjectFromCertificate, hmacSha256, randomBytes, AES_gcm_decrypt, AES_gcm_encrypt,
-    ECDHComputeKey, ECKEYGenerateKey, ECKEYMarshalPrivateKey, ECKEYParsePrivateKey,
-    ECPOINTOct2Point, ECPOINTPoint2Oct, EC_KEY_free, EC_KEY_get0_public_key, EC_POINT_free,
-    HKDFExpand, HKDFExtract, EC_KEY, EC_MAX_BYTES, EC_POINT, EVP_MAX_MD_SIZE, PBKDF2,
+    extractAttestationChallenge, extractCertificateFields, extractSubjectFromCertificate,
+    hmacDigest, hmacSha256_ctx_final, hmacSha256_ctx_free, hmacSha256_ctx_new,
+    hmacSha256_ctx_update, isSelfSignedCertificate, randomBytes, verifyCertChain,
+    AES_gcm_ctx_decrypt_final, AES_gcm_ctx_encrypt_final, AES_gcm_ctx_free, AES_gcm_ctx_new,
+    AES_gcm_ctx_update, AES_gcm_decrypt_aad, AES_gcm_encrypt_aad, AES_gcm_verify, AES_kcv,
+    AES_key_unwrap, AES_key_wrap, CRYPTO_memcmp, ChaCha20Poly1305_decrypt,
+    ChaCha20Poly1305_encrypt, ECDHComputeKey, ECDSASign, ECDSAVerifyForCurve, ECKEYFromScalar,
+    ECKEYGenerateKey, ECKEYGenerateKeyForCurve, ECKEYMarshalPrivateKey, ECKEYParsePrivateKey,
+    ECKEYParsePrivateKeyForCurve, ECPOINTOct2PointForCurve, ECPOINTPoint2OctForCurve, EC_KEY_free,
+    EC_KEY_get0_public_key, EC_POINT_free, FIPS_mode, HKDFExpand, HKDFExtract,
+    NID_X9_62_prime256v1, NID_secp384r1, NID_secp521r1, NID_sha1, NID_sha256, NID_sha384,
+    NID_sha512, OpenSSL_version, PBKDF2_iters, RSAGenerateKey, RSASignPKCS1, RSAVerifyPKCS1,
+    RSA_free, EC_KEY, EC_MAX_BYTES, EC_POINT, EVP_MAX_MD_SIZE, OPENSSL_VERSION, RSA, SHA256,
+    SHA256_DIGEST_LENGTH, SHA512, SHA512_DIGEST_LENGTH,
 };
+pub use self_test::self_test;
 use std::convert::TryFrom;
 use std::convert::TryInto;
+use std::ffi::CStr;
 use std::marker::PhantomData;
 pub use zvec::ZVec;
 
@@ -37,10 +57,22 @@ pub const TAG_LENGTH: usize = 16;
 pub const AES_256_KEY_LENGTH: usize = 32;
 /// Length of an AES 128 key in bytes.
 pub const AES_128_KEY_LENGTH: usize = 16;
+/// Length of a ChaCha20-Poly1305 key in bytes.
+pub const CHACHA20_POLY1305_KEY_LENGTH: usize = 32;
+/// Length of a ChaCha20-Poly1305 nonce in bytes.
+pub const CHACHA20_POLY1305_NONCE_LENGTH: usize = 12;
+/// Number of PBKDF2 iterations used by `Password::derive_key_pbkdf2`.
+pub const PBKDF2_DEFAULT_ITERATIONS: u32 = 8192;
 /// Length of the expected salt for key from password generation.
 pub const SALT_LENGTH: usize = 16;
 /// Length of an HMAC-SHA256 tag in bytes.
 pub const HMAC_SHA256_LEN: usize = 32;
+/// Length of an AES key check value in bytes.
+pub const AES_KCV_LENGTH: usize = 3;
+
+/// Maximum length in bytes of the ciphertext accepted by a one-shot AES GCM decryption. This
+/// bounds the zeroizing buffer allocated for the plaintext; key blobs are far smaller.
+pub const MAX_ONE_SHOT_DECRYPT_LEN: usize = 1 << 20;
 
 /// Older versions of keystore produced IVs with four extra
 /// ignored zero bytes at the end; recognise and trim those.
@@ -58,6 +90,17 @@ pub fn generate_aes256_key() -> Result<ZVec, Error> {
     }
 }
 
+/// Returns the length of the strongest AES key that can be taken from `available` bytes of key
+/// material: `AES_256_KEY_LENGTH` for 32 bytes or more, `AES_128_KEY_LENGTH` for 16 bytes or
+/// more, and `None` if there is not enough material for any AES key.
+pub fn preferred_aes_key_len(available: usize) -> Option<usize> {
+    match available {
+        AES_256_KEY_LENGTH.. => Some(AES_256_KEY_LENGTH),
+        AES_128_KEY_LENGTH.. => Some(AES_128_KEY_LENGTH),
+        _ => None,
+    }
+}
+
 /// Generate a salt.
 pub fn generate_salt() -> Result<Vec<u8>, Error> {
     generate_random_data(SALT_LENGTH)
@@ -76,17 +119,232 @@ pub fn generate_random_data(size: usize) -> Result<Vec<u8>, Error> {
 
 /// Perform HMAC-SHA256.
 pub fn hmac_sha256(key: &[u8], msg: &[u8]) -> Result<Vec<u8>, Error> {
-    let mut tag = vec![0; HMAC_SHA256_LEN];
+    hmac(Digest::Sha256, key, msg).map_err(|_| Error::HmacSha256Failed)
+}
+
+/// Digests that can be used with `hmac`. SHA-1 is only meant for interoperability with legacy
+/// formats.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Digest {
+    /// SHA-1
+    Sha1,
+    /// SHA-256
+    Sha256,
+    /// SHA-512
+    Sha512,
+}
+
+impl Digest {
+    fn nid(self) -> i32 {
+        match self {
+            Digest::Sha1 => NID_sha1 as i32,
+            Digest::Sha256 => NID_sha256 as i32,
+            Digest::Sha512 => NID_sha512 as i32,
+        }
+    }
+
+    /// Returns the length of the digest in bytes, which is also the length of an HMAC tag
+    /// computed with it.
+    pub fn output_len(self) -> usize {
+        match self {
+            Digest::Sha1 => 20,
+            Digest::Sha256 => 32,
+            Digest::Sha512 => 64,
+        }
+    }
+}
+
+/// Perform HMAC with the given digest on a message. The tag has length `digest.output_len()`.
+pub fn hmac(digest: Digest, key: &[u8], msg: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut tag = vec![0; digest.output_len()];
+    hmac_into(digest, key, msg, &mut tag)?;
+    Ok(tag)
+}
+
+/// Like `hmac`, but writes the tag to `tag`, e.g., so that it can be kept in a `ZVec`. `tag`
+/// must be `digest.output_len()` bytes long.
+fn hmac_into(digest: Digest, key: &[u8], msg: &[u8], tag: &mut [u8]) -> Result<(), Error> {
+    if tag.len() != digest.output_len() {
+        return Err(Error::InvalidMacLength);
+    }
     // Safety: The first two pairs of arguments must point to const buffers with
     // size given by the second arg of the pair.  The final pair of arguments
     // must point to an output buffer with size given by the second arg of the
     // pair.
     match unsafe {
-        hmacSha256(key.as_ptr(), key.len(), msg.as_ptr(), msg.len(), tag.as_mut_ptr(), tag.len())
+        hmacDigest(
+            digest.nid(),
+            key.as_ptr(),
+            key.len(),
+            msg.as_ptr(),
+            msg.len(),
+            tag.as_mut_ptr(),
+            tag.len(),
+        )
+    } {
+        true => Ok(()),
+        false => Err(Error::HmacFailed),
+    }
+}
+
+/// Incremental HMAC-SHA256, for messages that consist of several parts. Feeding all parts to
+/// `update` and calling `finalize` yields the same tag as `hmac_sha256` on the concatenated
+/// message.
+pub struct HmacSha256(*mut std::ffi::c_void);
+
+impl HmacSha256 {
+    /// Starts computing a tag under `key`.
+    pub fn new(key: &[u8]) -> Result<Self, Error> {
+        // Safety: The key is passed along with its length.
+        let ctx = unsafe { hmacSha256_ctx_new(key.as_ptr(), key.len()) };
+        if ctx.is_null() {
+            Err(Error::HmacSha256Failed)
+        } else {
+            Ok(Self(ctx))
+        }
+    }
+
+    /// Feeds the next part of the message.
+    pub fn update(&mut self, data: &[u8]) -> Result<(), Error> {
+        // Safety: The context is valid and `data` is passed along with its length.
+        if unsafe { hmacSha256_ctx_update(self.0, data.as_ptr(), data.len()) } {
+            Ok(())
+        } else {
+            Err(Error::HmacSha256Failed)
+        }
+    }
+
+    /// Returns the tag of all parts fed to `update`.
+    pub fn finalize(self) -> Result<[u8; HMAC_SHA256_LEN], Error> {
+        let mut tag = [0; HMAC_SHA256_LEN];
+        // Safety: The context is valid and `tag` is HMAC_SHA256_LEN bytes long.
+        if unsafe { hmacSha256_ctx_final(self.0, tag.as_mut_ptr()) } {
+            Ok(tag)
+        } else {
+            Err(Error::HmacSha256Failed)
+        }
+    }
+}
+
+impl Drop for HmacSha256 {
+    fn drop(&mut self) {
+        // Safety: We only create HmacSha256 objects for valid contexts and they are the sole
+        // owners of those contexts.
+        unsafe { hmacSha256_ctx_free(self.0) };
+    }
+}
+
+// Safety: The HMAC context is owned exclusively by HmacSha256 and is not tied to the thread
+// that created it.
+unsafe impl Send for HmacSha256 {}
+
+/// Computes the key check value (KCV) of a 128 or 256-bit AES key, i.e., the first three bytes
+/// of the encryption of an all-zero block. The KCV can be used to check that two parties hold the
+/// same key without revealing meaningful information about the key.
+pub fn aes_kcv(key: &[u8]) -> Result<[u8; AES_KCV_LENGTH], Error> {
+    match key.len() {
+        AES_128_KEY_LENGTH | AES_256_KEY_LENGTH => {}
+        _ => return Err(Error::InvalidKeyLength),
+    }
+    let mut kcv = [0u8; AES_KCV_LENGTH];
+    // Safety: The key buffer is valid for reads of key.len() bytes and kcv is valid for
+    // writes of AES_KCV_LENGTH bytes, which is what AES_kcv writes.
+    if unsafe { AES_kcv(key.as_ptr(), key.len(), kcv.as_mut_ptr()) } {
+        Ok(kcv)
+    } else {
+        Err(Error::KcvFailed)
+    }
+}
+
+/// Length in bytes of the integrity check value that AES key wrap adds to the key material.
+pub const AES_KEY_WRAP_OVERHEAD: usize = 8;
+
+/// Checks that `key` can be wrapped with AES key wrap, i.e., that its length is a multiple of 8
+/// bytes and at least 16 bytes.
+fn check_key_wrap_input_length(key: &[u8]) -> Result<(), Error> {
+    if key.len() < 16 || key.len() % 8 != 0 {
+        return Err(Error::InvalidKeyLength);
+    }
+    Ok(())
+}
+
+/// Wraps `key` under the 128 or 256-bit AES key encryption key `kek` with the key wrap algorithm
+/// of RFC 3394. The output is `AES_KEY_WRAP_OVERHEAD` bytes longer than `key`. Unlike GCM, key
+/// wrap is deterministic and needs no IV, but it can only be used for key material.
+pub fn aes_key_wrap(kek: &[u8], key: &[u8]) -> Result<Vec<u8>, Error> {
+    check_aes_key_length(kek)?;
+    check_key_wrap_input_length(key)?;
+    let mut wrapped = vec![0; key.len() + AES_KEY_WRAP_OVERHEAD];
+    // Safety: The kek and key are passed along with their lengths, and `wrapped` has room for
+    // the key.len() + 8 bytes written by AES_key_wrap.
+    if unsafe {
+        AES_key_wrap(kek.as_ptr(), kek.len(), key.as_ptr(), key.len(), wrapped.as_mut_ptr())
+    } {
+        Ok(wrapped)
+    } else {
+        Err(Error::EncryptionFailed)
+    }
+}
+
+/// Unwraps key material wrapped by `aes_key_wrap` under `kek`. Returns `Error::KeyUnwrapFailed`
+/// if `wrapped` was not produced with `kek` or was modified.
+pub fn aes_key_unwrap(kek: &[u8], wrapped: &[u8]) -> Result<ZVec, Error> {
+    check_aes_key_length(kek)?;
+    if wrapped.len() < AES_KEY_WRAP_OVERHEAD
+        || check_key_wrap_input_length(&wrapped[AES_KEY_WRAP_OVERHEAD..]).is_err()
+    {
+        return Err(Error::KeyUnwrapFailed);
+    }
+    let mut key = ZVec::new(wrapped.len() - AES_KEY_WRAP_OVERHEAD)?;
+    // Safety: The kek and wrapped key are passed along with their lengths, and `key` has room
+    // for the wrapped.len() - 8 bytes written by AES_key_unwrap.
+    if unsafe {
+        AES_key_unwrap(kek.as_ptr(), kek.len(), wrapped.as_ptr(), wrapped.len(), key.as_mut_ptr())
     } {
-        true => Ok(tag),
-        false => Err(Error::HmacSha256Failed),
+        Ok(key)
+    } else {
+        Err(Error::KeyUnwrapFailed)
+    }
+}
+
+/// Perform HMAC-SHA256 and truncate the tag to its first `out_len` bytes, as used by
+/// constructions such as HMAC-SHA256-128. `out_len` must be in the range 1..=HMAC_SHA256_LEN.
+pub fn hmac_sha256_truncated(key: &[u8], msg: &[u8], out_len: usize) -> Result<Vec<u8>, Error> {
+    if !(1..=HMAC_SHA256_LEN).contains(&out_len) {
+        return Err(Error::InvalidMacLength);
+    }
+    let mut tag = hmac_sha256(key, msg)?;
+    tag.truncate(out_len);
+    Ok(tag)
+}
+
+/// Verifies a truncated HMAC-SHA256 tag as produced by `hmac_sha256_truncated`. The length of
+/// `tag` determines the truncation length. The comparison is performed in constant time.
+pub fn hmac_sha256_truncated_verify(key: &[u8], msg: &[u8], tag: &[u8]) -> Result<bool, Error> {
+    let expected = hmac_sha256_truncated(key, msg, tag.len())?;
+    Ok(constant_time_eq(&expected, tag))
+}
+
+/// Computes the HMAC-SHA256 tag of `msg` under `key` and compares it to `expected_tag` in
+/// constant time. Returns `Error::MacVerificationFailed` if the tags do not match, including
+/// if `expected_tag` does not have length `HMAC_SHA256_LEN`.
+pub fn verify_hmac_sha256(key: &[u8], msg: &[u8], expected_tag: &[u8]) -> Result<(), Error> {
+    let tag = hmac_sha256(key, msg)?;
+    if constant_time_eq(&tag, expected_tag) {
+        Ok(())
+    } else {
+        Err(Error::MacVerificationFailed)
+    }
+}
+
+/// Compares two byte slices in constant time with respect to their content. Slices of different
+/// lengths compare unequal; the length itself is not considered secret.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
     }
+    // Safety: Both buffers are valid for reads of `a.len()` bytes.
+    unsafe { CRYPTO_memcmp(a.as_ptr() as *const _, b.as_ptr() as *const _, a.len()) == 0 }
 }
 
 /// Uses AES GCM to decipher a message given an initialization vector, aead tag, and key.
@@ -96,14 +354,70 @@ pub fn hmac_sha256(key: &[u8], msg: &[u8]) -> Result<Vec<u8>, Error> {
 /// it contains sensitive information that should be zeroed from memory before its buffer is
 /// freed. Input key is taken as a slice for flexibility, but it is recommended that it is held
 /// in a ZVec as well.
+/// Ciphertexts longer than `MAX_ONE_SHOT_DECRYPT_LEN` are rejected with
+/// `Error::InvalidInputLength` before any buffer is allocated.
 pub fn aes_gcm_decrypt(data: &[u8], iv: &[u8], tag: &[u8], key: &[u8]) -> Result<ZVec, Error> {
-    // Old versions of aes_gcm_encrypt produced 16 byte IVs, but the last four bytes were ignored
-    // so trim these to the correct size.
-    let iv = match iv.len() {
-        GCM_IV_LENGTH => iv,
-        LEGACY_IV_LENGTH => &iv[..GCM_IV_LENGTH],
-        _ => return Err(Error::InvalidIvLength),
-    };
+    aes_gcm_decrypt_aad(data, iv, tag, &[], key)
+}
+
+/// Like `aes_gcm_decrypt`, but additionally authenticates the additional data `aad`, e.g., a
+/// header that is stored in the clear next to the ciphertext. Decryption fails with
+/// `Error::DecryptionFailed` unless `aad` matches the additional data used for encryption
+/// exactly. An empty `aad` is equivalent to `aes_gcm_decrypt`.
+pub fn aes_gcm_decrypt_aad(
+    data: &[u8],
+    iv: &[u8],
+    tag: &[u8],
+    aad: &[u8],
+    key: &[u8],
+) -> Result<ZVec, Error> {
+    let iv = check_gcm_decrypt_params(data, iv, tag, key)?;
+
+    let mut result = ZVec::new(data.len())?;
+
+    // Safety: The first two arguments must point to buffers with a size given by the third
+    // argument. `aad` is passed along with its length, and so is the key.
+    // The `iv` buffer must be 12 bytes and the `tag` buffer 16, which we check above.
+    match unsafe {
+        AES_gcm_decrypt_aad(
+            data.as_ptr(),
+            result.as_mut_ptr(),
+            data.len(),
+            aad.as_ptr(),
+            aad.len(),
+            key.as_ptr(),
+            key.len(),
+            iv.as_ptr(),
+            tag.as_ptr(),
+        )
+    } {
+        true => Ok(result),
+        false => Err(Error::DecryptionFailed),
+    }
+}
+
+/// Returns the IV to use for an AES GCM decryption. Old versions of aes_gcm_encrypt produced
+/// 16 byte IVs, but the last four bytes were ignored, so these are trimmed to the correct size.
+/// Every GCM decryption path must go through this function so that legacy blobs stay readable.
+pub fn normalize_gcm_iv(iv: &[u8]) -> Result<&[u8], Error> {
+    match iv.len() {
+        GCM_IV_LENGTH => Ok(iv),
+        LEGACY_IV_LENGTH => Ok(&iv[..GCM_IV_LENGTH]),
+        _ => Err(Error::InvalidIvLength),
+    }
+}
+
+/// Validates the lengths of the parameters of an AES GCM decryption and returns the IV to use.
+fn check_gcm_decrypt_params<'a>(
+    data: &[u8],
+    iv: &'a [u8],
+    tag: &[u8],
+    key: &[u8],
+) -> Result<&'a [u8], Error> {
+    if data.len() > MAX_ONE_SHOT_DECRYPT_LEN {
+        return Err(Error::InvalidInputLength);
+    }
+    let iv = normalize_gcm_iv(iv)?;
     if tag.len() != TAG_LENGTH {
         return Err(Error::InvalidAeadTagLength);
     }
@@ -112,24 +426,37 @@ pub fn aes_gcm_decrypt(data: &[u8], iv: &[u8], tag: &[u8], key: &[u8]) -> Result
         AES_128_KEY_LENGTH | AES_256_KEY_LENGTH => {}
         _ => return Err(Error::InvalidKeyLength),
     }
+    Ok(iv)
+}
 
-    let mut result = ZVec::new(data.len())?;
+/// Checks the AES GCM tag of a message and the additional authenticated data `aad` without
+/// returning the plaintext. The message is decrypted into a scratch buffer that is zeroed
+/// before this function returns. Returns `Ok(())` if and only if the tag verifies.
+/// The parameters are validated the same way as by `aes_gcm_decrypt`.
+pub fn aes_gcm_verify(
+    data: &[u8],
+    iv: &[u8],
+    tag: &[u8],
+    aad: &[u8],
+    key: &[u8],
+) -> Result<(), Error> {
+    let iv = check_gcm_decrypt_params(data, iv, tag, key)?;
 
-    // Safety: The first two arguments must point to buffers with a size given by the third
-    // argument. We pass the length of the key buffer along with the key.
+    // Safety: `data` and `aad` are passed along with their lengths, and so is the key.
     // The `iv` buffer must be 12 bytes and the `tag` buffer 16, which we check above.
     match unsafe {
-        AES_gcm_decrypt(
+        AES_gcm_verify(
             data.as_ptr(),
-            result.as_mut_ptr(),
             data.len(),
+            aad.as_ptr(),
+            aad.len(),
             key.as_ptr(),
             key.len(),
             iv.as_ptr(),
             tag.as_ptr(),
         )
     } {
-        true => Ok(result),
+        true => Ok(()),
         false => Err(Error::DecryptionFailed),
     }
 }
@@ -139,39 +466,298 @@ pub fn aes_gcm_decrypt(data: &[u8], iv: &[u8], tag: &[u8], key: &[u8]) -> Result
 /// the key length. The function generates an initialization vector. The return value is a tuple
 /// of `(ciphertext, iv, tag)`.
 pub fn aes_gcm_encrypt(plaintext: &[u8], key: &[u8]) -> Result<(Vec<u8>, Vec<u8>, Vec<u8>), Error> {
-    let mut iv = vec![0; GCM_IV_LENGTH];
+    let (ciphertext, iv, tag) = aes_gcm_encrypt_arrays(plaintext, key)?;
+    Ok((ciphertext, iv.to_vec(), tag.to_vec()))
+}
+
+/// Like `aes_gcm_encrypt`, but returns the IV and tag as fixed-size arrays, so that callers can
+/// store them without checking their lengths.
+pub fn aes_gcm_encrypt_arrays(
+    plaintext: &[u8],
+    key: &[u8],
+) -> Result<(Vec<u8>, [u8; GCM_IV_LENGTH], [u8; TAG_LENGTH]), Error> {
+    gcm_encrypt_random_iv(plaintext, &[], key)
+}
+
+/// Like `aes_gcm_encrypt`, but additionally authenticates the additional data `aad` without
+/// encrypting it. The same `aad` must be passed to `aes_gcm_decrypt_aad`. With an empty `aad`
+/// the output is interchangeable with that of `aes_gcm_encrypt`.
+pub fn aes_gcm_encrypt_aad(
+    plaintext: &[u8],
+    aad: &[u8],
+    key: &[u8],
+) -> Result<(Vec<u8>, Vec<u8>, Vec<u8>), Error> {
+    let (ciphertext, iv, tag) = gcm_encrypt_random_iv(plaintext, aad, key)?;
+    Ok((ciphertext, iv.to_vec(), tag.to_vec()))
+}
+
+/// Like `aes_gcm_encrypt`, but uses the caller supplied `iv` instead of generating one. The IV
+/// must be exactly `GCM_IV_LENGTH` bytes long, and it must never be reused with the same key.
+/// The return value is a tuple of `(ciphertext, tag)`.
+pub fn aes_gcm_encrypt_with_iv(
+    plaintext: &[u8],
+    iv: &[u8],
+    key: &[u8],
+) -> Result<(Vec<u8>, Vec<u8>), Error> {
+    let (ciphertext, tag) = gcm_encrypt(plaintext, &[], iv, key)?;
+    Ok((ciphertext, tag.to_vec()))
+}
+
+/// Encrypts `plaintext` with AES GCM under `key` and a freshly generated IV, authenticating
+/// `aad` along with it. Returns `(ciphertext, iv, tag)`.
+fn gcm_encrypt_random_iv(
+    plaintext: &[u8],
+    aad: &[u8],
+    key: &[u8],
+) -> Result<(Vec<u8>, [u8; GCM_IV_LENGTH], [u8; TAG_LENGTH]), Error> {
+    let mut iv = [0; GCM_IV_LENGTH];
     // Safety: iv is GCM_IV_LENGTH bytes long.
     if !unsafe { randomBytes(iv.as_mut_ptr(), GCM_IV_LENGTH) } {
         return Err(Error::RandomNumberGenerationFailed);
     }
 
-    match key.len() {
-        AES_128_KEY_LENGTH | AES_256_KEY_LENGTH => {}
-        _ => return Err(Error::InvalidKeyLength),
+    let (ciphertext, tag) = gcm_encrypt(plaintext, aad, &iv, key)?;
+    Ok((ciphertext, iv, tag))
+}
+
+/// Encrypts `plaintext` with AES GCM under `key` and `iv`, authenticating `aad` along with it.
+fn gcm_encrypt(
+    plaintext: &[u8],
+    aad: &[u8],
+    iv: &[u8],
+    key: &[u8],
+) -> Result<(Vec<u8>, [u8; TAG_LENGTH]), Error> {
+    if iv.len() != GCM_IV_LENGTH {
+        return Err(Error::InvalidIvLength);
     }
+    check_aes_key_length(key)?;
 
     let mut ciphertext: Vec<u8> = vec![0; plaintext.len()];
-    let mut tag: Vec<u8> = vec![0; TAG_LENGTH];
+    let mut tag = [0; TAG_LENGTH];
     // Safety: The first two arguments must point to buffers with a size given by the third
-    // argument. We pass the length of the key buffer along with the key.
+    // argument. `aad` is passed along with its length, and so is the key.
     // The `iv` buffer must be 12 bytes and the `tag` buffer 16, which we check above.
     if unsafe {
-        AES_gcm_encrypt(
+        AES_gcm_encrypt_aad(
             plaintext.as_ptr(),
             ciphertext.as_mut_ptr(),
             plaintext.len(),
+            aad.as_ptr(),
+            aad.len(),
             key.as_ptr(),
             key.len(),
             iv.as_ptr(),
             tag.as_mut_ptr(),
         )
     } {
-        Ok((ciphertext, iv, tag))
+        Ok((ciphertext, tag))
+    } else {
+        Err(Error::EncryptionFailed)
+    }
+}
+
+/// Uses ChaCha20-Poly1305 to encrypt a message given a 256-bit key. This is an alternative to
+/// `aes_gcm_encrypt` for devices without AES hardware. The function generates a nonce. The
+/// return value is a tuple of `(ciphertext, nonce, tag)`.
+pub fn chacha20_poly1305_encrypt(
+    plaintext: &[u8],
+    key: &[u8],
+) -> Result<(Vec<u8>, Vec<u8>, Vec<u8>), Error> {
+    if key.len() != CHACHA20_POLY1305_KEY_LENGTH {
+        return Err(Error::InvalidKeyLength);
+    }
+    let nonce = generate_random_data(CHACHA20_POLY1305_NONCE_LENGTH)?;
+
+    let mut ciphertext: Vec<u8> = vec![0; plaintext.len()];
+    let mut tag: Vec<u8> = vec![0; TAG_LENGTH];
+    // Safety: The first two arguments must point to buffers with a size given by the third
+    // argument. The key must be 32 bytes, the `nonce` 12 bytes and the `tag` buffer 16 bytes,
+    // which we check above.
+    if unsafe {
+        ChaCha20Poly1305_encrypt(
+            plaintext.as_ptr(),
+            ciphertext.as_mut_ptr(),
+            plaintext.len(),
+            key.as_ptr(),
+            nonce.as_ptr(),
+            tag.as_mut_ptr(),
+        )
+    } {
+        Ok((ciphertext, nonce, tag))
     } else {
         Err(Error::EncryptionFailed)
     }
 }
 
+/// Uses ChaCha20-Poly1305 to decrypt a message given a 256-bit key, the nonce and the tag
+/// produced by `chacha20_poly1305_encrypt`. The plaintext is returned in a `ZVec`.
+pub fn chacha20_poly1305_decrypt(
+    data: &[u8],
+    nonce: &[u8],
+    tag: &[u8],
+    key: &[u8],
+) -> Result<ZVec, Error> {
+    if data.len() > MAX_ONE_SHOT_DECRYPT_LEN {
+        return Err(Error::InvalidInputLength);
+    }
+    if nonce.len() != CHACHA20_POLY1305_NONCE_LENGTH {
+        return Err(Error::InvalidNonceLength);
+    }
+    if tag.len() != TAG_LENGTH {
+        return Err(Error::InvalidAeadTagLength);
+    }
+    if key.len() != CHACHA20_POLY1305_KEY_LENGTH {
+        return Err(Error::InvalidKeyLength);
+    }
+
+    let mut result = ZVec::new(data.len())?;
+
+    // Safety: The first two arguments must point to buffers with a size given by the third
+    // argument. The key must be 32 bytes, the `nonce` 12 bytes and the `tag` 16 bytes, which we
+    // check above.
+    match unsafe {
+        ChaCha20Poly1305_decrypt(
+            data.as_ptr(),
+            result.as_mut_ptr(),
+            data.len(),
+            key.as_ptr(),
+            nonce.as_ptr(),
+            tag.as_ptr(),
+        )
+    } {
+        true => Ok(result),
+        false => Err(Error::DecryptionFailed),
+    }
+}
+
+/// Owns a streaming AES GCM context of the C wrapper and frees it on drop.
+struct GcmContext(*mut std::ffi::c_void);
+
+impl GcmContext {
+    fn new(encrypt: bool, key: &[u8], iv: &[u8]) -> Option<Self> {
+        // Safety: The key is passed along with its length, and the caller checks that the key
+        // length is valid and that `iv` is 12 bytes.
+        let ctx = unsafe { AES_gcm_ctx_new(encrypt, key.as_ptr(), key.len(), iv.as_ptr()) };
+        if ctx.is_null() {
+            None
+        } else {
+            Some(Self(ctx))
+        }
+    }
+
+    fn update(&mut self, input: &[u8], output: &mut [u8]) -> bool {
+        assert_eq!(input.len(), output.len());
+        // Safety: The context is valid. `input` and `output` both have the length passed in.
+        unsafe { AES_gcm_ctx_update(self.0, input.as_ptr(), output.as_mut_ptr(), input.len()) }
+    }
+}
+
+impl Drop for GcmContext {
+    fn drop(&mut self) {
+        // Safety: We only create GcmContext objects for valid contexts and they are the sole
+        // owners of those contexts.
+        unsafe { AES_gcm_ctx_free(self.0) };
+    }
+}
+
+/// Checks that `key` is a 128 or 256-bit AES key.
+fn check_aes_key_length(key: &[u8]) -> Result<(), Error> {
+    match key.len() {
+        AES_128_KEY_LENGTH | AES_256_KEY_LENGTH => Ok(()),
+        _ => Err(Error::InvalidKeyLength),
+    }
+}
+
+/// Incremental AES GCM encryption, for messages that are too large to be held in memory at
+/// once. Feeding all chunks to `update` and calling `finalize` yields the same ciphertext and
+/// tag as `aes_gcm_encrypt_with_iv` on the concatenated plaintext.
+pub struct GcmEncryptContext {
+    ctx: GcmContext,
+}
+
+impl GcmEncryptContext {
+    /// Starts an encryption with a 128 or 256-bit `key` and an `iv` of `GCM_IV_LENGTH` bytes.
+    /// The IV must never be reused with the same key.
+    pub fn new(key: &[u8], iv: &[u8]) -> Result<Self, Error> {
+        check_aes_key_length(key)?;
+        if iv.len() != GCM_IV_LENGTH {
+            return Err(Error::InvalidIvLength);
+        }
+        let ctx = GcmContext::new(true, key, iv).ok_or(Error::EncryptionFailed)?;
+        Ok(Self { ctx })
+    }
+
+    /// Encrypts the next chunk of plaintext and returns the ciphertext for it, which always has
+    /// the same length as the chunk.
+    pub fn update(&mut self, chunk: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut ciphertext = vec![0; chunk.len()];
+        if self.ctx.update(chunk, &mut ciphertext) {
+            Ok(ciphertext)
+        } else {
+            Err(Error::EncryptionFailed)
+        }
+    }
+
+    /// Finishes the encryption and returns the remaining ciphertext and the tag. GCM does not
+    /// hold back any input, so the remaining ciphertext is always empty.
+    pub fn finalize(self) -> Result<(Vec<u8>, [u8; TAG_LENGTH]), Error> {
+        let mut tag = [0; TAG_LENGTH];
+        // Safety: The context is valid and `tag` is TAG_LENGTH bytes long.
+        if unsafe { AES_gcm_ctx_encrypt_final(self.ctx.0, tag.as_mut_ptr()) } {
+            Ok((Vec::new(), tag))
+        } else {
+            Err(Error::EncryptionFailed)
+        }
+    }
+}
+
+/// Incremental AES GCM decryption, the counterpart of `GcmEncryptContext`. The plaintext is only
+/// released by `finalize` after the tag has been verified. Until then it is buffered in `ZVec`s,
+/// so it is zeroized if the decryption fails or is abandoned.
+pub struct GcmDecryptContext {
+    ctx: GcmContext,
+    plaintext: Vec<ZVec>,
+}
+
+impl GcmDecryptContext {
+    /// Starts a decryption with a 128 or 256-bit `key` and the `iv` used for encryption.
+    pub fn new(key: &[u8], iv: &[u8]) -> Result<Self, Error> {
+        check_aes_key_length(key)?;
+        let iv = normalize_gcm_iv(iv)?;
+        let ctx = GcmContext::new(false, key, iv).ok_or(Error::DecryptionFailed)?;
+        Ok(Self { ctx, plaintext: Vec::new() })
+    }
+
+    /// Decrypts the next chunk of ciphertext. The plaintext is held back until `finalize`.
+    pub fn update(&mut self, chunk: &[u8]) -> Result<(), Error> {
+        let mut plaintext = ZVec::new(chunk.len())?;
+        if !self.ctx.update(chunk, &mut plaintext) {
+            return Err(Error::DecryptionFailed);
+        }
+        self.plaintext.push(plaintext);
+        Ok(())
+    }
+
+    /// Verifies `tag` and returns the complete plaintext. Fails with `Error::DecryptionFailed`
+    /// if the tag does not match.
+    pub fn finalize(self, tag: &[u8]) -> Result<ZVec, Error> {
+        if tag.len() != TAG_LENGTH {
+            return Err(Error::InvalidAeadTagLength);
+        }
+        // Safety: The context is valid and `tag` is TAG_LENGTH bytes long, as checked above.
+        if !unsafe { AES_gcm_ctx_decrypt_final(self.ctx.0, tag.as_ptr()) } {
+            return Err(Error::DecryptionFailed);
+        }
+        let mut result = ZVec::new(self.plaintext.iter().map(|chunk| chunk.len()).sum())?;
+        let mut pos = 0;
+        for chunk in &self.plaintext {
+            result[pos..pos + chunk.len()].copy_from_slice(chunk);
+            pos += chunk.len();
+        }
+        Ok(result)
+    }
+}
+
 /// A high-entropy synthetic password from which an AES key may be derived.
 pub enum Password<'a> {
     /// Borrow an existing byte array
@@ -201,6 +787,17 @@ impl<'a> Password<'a> {
     /// This function exists only for backwards compatibility reasons.  Keystore now receives only
     /// high-entropy synthetic passwords, which do not require key stretching.
     pub fn derive_key_pbkdf2(&self, salt: &[u8], out_len: usize) -> Result<ZVec, Error> {
+        self.derive_key_pbkdf2_iters(salt, PBKDF2_DEFAULT_ITERATIONS, out_len)
+    }
+
+    /// Like `derive_key_pbkdf2`, but with `iters` iterations, e.g., to read blobs that were
+    /// protected with a different iteration count. `iters` must not be zero.
+    pub fn derive_key_pbkdf2_iters(
+        &self,
+        salt: &[u8],
+        iters: u32,
+        out_len: usize,
+    ) -> Result<ZVec, Error> {
         if salt.len() != SALT_LENGTH {
             return Err(Error::InvalidSaltLength);
         }
@@ -208,30 +805,57 @@ impl<'a> Password<'a> {
             AES_128_KEY_LENGTH | AES_256_KEY_LENGTH => {}
             _ => return Err(Error::InvalidKeyLength),
         }
+        if iters == 0 {
+            return Err(Error::InvalidIterationCount);
+        }
 
         let pw = self.get_key();
         let mut result = ZVec::new(out_len)?;
 
         // Safety: We checked that the salt is exactly 16 bytes long. The other pointers are valid,
         // and have matching lengths.
-        unsafe {
-            PBKDF2(
+        let success = unsafe {
+            PBKDF2_iters(
                 result.as_mut_ptr(),
                 result.len(),
                 pw.as_ptr() as *const std::os::raw::c_char,
                 pw.len(),
                 salt.as_ptr(),
+                iters,
             )
         };
+        if !success {
+            return Err(Error::Pbkdf2Failed);
+        }
 
         Ok(result)
     }
 
+    /// Checks the password against a verifier that was produced by `derive_key_pbkdf2` with
+    /// the same salt. The derived key is compared to `stored_verifier` in constant time.
+    /// Returns `Error::InvalidKeyLength` if the verifier is neither 16 nor 32 bytes long.
+    ///
+    /// Like `derive_key_pbkdf2` this exists only for compatibility with legacy passwords.
+    pub fn verify_pbkdf2(&self, salt: &[u8], stored_verifier: &[u8]) -> Result<bool, Error> {
+        let derived = self.derive_key_pbkdf2(salt, stored_verifier.len())?;
+        Ok(constant_time_eq(&derived, stored_verifier))
+    }
+
     /// Derives a key from the given high-entropy synthetic password and salt, using HKDF.
     pub fn derive_key_hkdf(&self, salt: &[u8], out_len: usize) -> Result<ZVec, Error> {
+        self.derive_key_hkdf_with_info(salt, &[], out_len)
+    }
+
+    /// Like `derive_key_hkdf`, but passes `info` to the HKDF expand step, so that keys for
+    /// different purposes can be derived from the same password and salt.
+    pub fn derive_key_hkdf_with_info(
+        &self,
+        salt: &[u8],
+        info: &[u8],
+        out_len: usize,
+    ) -> Result<ZVec, Error> {
         let prk = hkdf_extract(self.get_key(), salt)?;
-        let info = [];
-        hkdf_expand(out_len, &prk, &info)
+        hkdf_expand(out_len, &prk, info)
     }
 
     /// Try to make another Password object with the same data.
@@ -284,6 +908,49 @@ pub fn hkdf_expand(out_len: usize, prk: &[u8], info: &[u8]) -> Result<ZVec, Erro
     Ok(buf)
 }
 
+/// Derives a pair of independent 256 bit keys, one for encryption and one for MAC
+/// computation, from a single secret. The PRK is extracted once and then expanded with the
+/// distinct `info` labels "enc" and "mac", so the two keys can safely be used together in an
+/// encrypt-then-MAC construction. Returns (encryption key, MAC key).
+pub fn derive_enc_mac_keys(secret: &[u8], salt: &[u8]) -> Result<(ZVec, ZVec), Error> {
+    let prk = hkdf_extract(secret, salt)?;
+    let enc_key = hkdf_expand(AES_256_KEY_LENGTH, &prk, b"enc")?;
+    let mac_key = hkdf_expand(HMAC_SHA256_LEN, &prk, b"mac")?;
+    Ok((enc_key, mac_key))
+}
+
+/// Derives `out_len` bytes of key material from `key` with the NIST SP 800-108 KDF in counter
+/// mode, using HMAC-SHA256 as the PRF. Each block is computed over the fixed input
+/// `[i]_32 || label || 0x00 || context || [L]_32`, where the counter `i` starts at 1 and `L` is
+/// the output length in bits, both big-endian. Unlike HKDF, a shorter output is not a prefix of a
+/// longer one, because `L` is part of every block.
+pub fn kdf_ctr_hmac_sha256(
+    key: &[u8],
+    label: &[u8],
+    context: &[u8],
+    out_len: usize,
+) -> Result<ZVec, Error> {
+    let out_bits = out_len
+        .checked_mul(8)
+        .and_then(|bits| u32::try_from(bits).ok())
+        .filter(|bits| *bits > 0)
+        .ok_or(Error::InvalidDataLength)?;
+    // The counter in the first four bytes is filled in for each block.
+    let mut fixed_input =
+        ZVec::try_concat(&[&[0u8; 4], label, &[0], context, &out_bits.to_be_bytes()])?;
+
+    let mut result = ZVec::new(out_len)?;
+    let mut block = ZVec::new(HMAC_SHA256_LEN)?;
+    for (i, chunk) in result.chunks_mut(HMAC_SHA256_LEN).enumerate() {
+        // The counter cannot overflow, because out_len * 8 fits in a u32.
+        fixed_input[..4].copy_from_slice(&(i as u32 + 1).to_be_bytes());
+        hmac_into(Digest::Sha256, key, &fixed_input, &mut block)
+            .map_err(|_| Error::HmacSha256Failed)?;
+        chunk.copy_from_slice(&block[..chunk.len()]);
+    }
+    Ok(result)
+}
+
 /// A wrapper around the boringssl EC_KEY type that frees it on drop.
 pub struct ECKey(*mut EC_KEY);
 
@@ -295,6 +962,10 @@ impl Drop for ECKey {
     }
 }
 
+// Safety: An ECKey is the sole owner of its EC_KEY, and boringssl does not tie EC_KEY objects to
+// the thread that created them, so ownership can be moved to another thread.
+unsafe impl Send for ECKey {}
+
 // Wrappers around the boringssl EC_POINT type.
 // The EC_POINT can either be owned (and therefore mutable) or a pointer to an
 // EC_POINT owned by someone else (and thus immutable).  The former are freed
@@ -355,10 +1026,16 @@ pub fn ecdh_compute_key(pub_key: &EC_POINT, priv_key: &ECKey) -> Result<ZVec, Er
     Ok(buf)
 }
 
-/// Calls the boringssl EC_KEY_generate_key function.
+/// Calls the boringssl EC_KEY_generate_key function. The key is on P-521, which is the curve
+/// expected by `ec_key_parse_private_key` and the EC_POINT functions of this crate.
 pub fn ec_key_generate_key() -> Result<ECKey, Error> {
+    ec_key_generate_key_for_curve(EcCurve::P521)
+}
+
+/// Calls the boringssl EC_KEY_generate_key function for a key on `curve`.
+pub fn ec_key_generate_key_for_curve(curve: EcCurve) -> Result<ECKey, Error> {
     // Safety: Creates a new key on its own.
-    let key = unsafe { ECKEYGenerateKey() };
+    let key = unsafe { ECKEYGenerateKeyForCurve(curve.nid()) };
     if key.is_null() {
         return Err(Error::ECKEYGenerateKeyFailed);
     }
@@ -367,7 +1044,12 @@ pub fn ec_key_generate_key() -> Result<ECKey, Error> {
 
 /// Calls the boringssl EC_KEY_marshal_private_key function.
 pub fn ec_key_marshal_private_key(key: &ECKey) -> Result<ZVec, Error> {
-    let len = 73; // Empirically observed length of private key
+    // Safety: the key is valid. With a null buffer, ECKEYMarshalPrivateKey only
+    // returns the required length.
+    let len = unsafe { ECKEYMarshalPrivateKey(key.0, std::ptr::null_mut(), 0) };
+    if len == 0 {
+        return Err(Error::ECKEYMarshalPrivateKeyFailed);
+    }
     let mut buf = ZVec::new(len)?;
     // Safety: the key is valid.
     // This will not write past the specified length of the buffer; if the
@@ -380,11 +1062,16 @@ pub fn ec_key_marshal_private_key(key: &ECKey) -> Result<ZVec, Error> {
     }
 }
 
-/// Calls the boringssl EC_KEY_parse_private_key function.
+/// Calls the boringssl EC_KEY_parse_private_key function for a P-521 key.
 pub fn ec_key_parse_private_key(buf: &[u8]) -> Result<ECKey, Error> {
+    ec_key_parse_private_key_for_curve(EcCurve::P521, buf)
+}
+
+/// Calls the boringssl EC_KEY_parse_private_key function for a key on `curve`.
+pub fn ec_key_parse_private_key_for_curve(curve: EcCurve, buf: &[u8]) -> Result<ECKey, Error> {
     // Safety: this will not read past the specified length of the buffer.
     // It fails if less than the whole buffer is consumed.
-    let key = unsafe { ECKEYParsePrivateKey(buf.as_ptr(), buf.len()) };
+    let key = unsafe { ECKEYParsePrivateKeyForCurve(curve.nid(), buf.as_ptr(), buf.len()) };
     if key.is_null() {
         Err(Error::ECKEYParsePrivateKeyFailed)
     } else {
@@ -392,6 +1079,40 @@ pub fn ec_key_parse_private_key(buf: &[u8]) -> Result<ECKey, Error> {
     }
 }
 
+/// Named elliptic curves supported by the EC functions of this crate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EcCurve {
+    /// NIST P-256, aka secp256r1.
+    P256,
+    /// NIST P-384, aka secp384r1.
+    P384,
+    /// NIST P-521, aka secp521r1.
+    P521,
+}
+
+impl EcCurve {
+    fn nid(self) -> i32 {
+        let nid = match self {
+            EcCurve::P256 => NID_X9_62_prime256v1,
+            EcCurve::P384 => NID_secp384r1,
+            EcCurve::P521 => NID_secp521r1,
+        };
+        nid as i32
+    }
+}
+
+/// Creates an EC key on `curve` from the big-endian private `scalar` alone, computing the public
+/// point. The scalar must be in the range [1, order - 1] of the curve.
+pub fn ec_key_from_scalar(curve: EcCurve, scalar: &[u8]) -> Result<ECKey, Error> {
+    // Safety: this will not read past the specified length of the scalar.
+    let key = unsafe { ECKEYFromScalar(curve.nid(), scalar.as_ptr(), scalar.len()) };
+    if key.is_null() {
+        Err(Error::ECKEYFromScalarFailed)
+    } else {
+        Ok(ECKey(key))
+    }
+}
+
 /// Calls the boringssl EC_KEY_get0_public_key function.
 pub fn ec_key_get0_public_key(key: &ECKey) -> BorrowedECPoint {
     // Safety: The key is valid.
@@ -399,36 +1120,215 @@ pub fn ec_key_get0_public_key(key: &ECKey) -> BorrowedECPoint {
     BorrowedECPoint { data: unsafe { EC_KEY_get0_public_key(key.0) }, phantom: PhantomData }
 }
 
-/// Calls the boringssl EC_POINT_point2oct.
-pub fn ec_point_point_to_oct(point: &EC_POINT) -> Result<Vec<u8>, Error> {
-    // We fix the length to 133 (1 + 2 * field_elem_size), as we get an error if it's too small.
-    let len = 133;
-    let mut buf = vec![0; len];
-    // Safety: EC_POINT_point2oct writes at most len bytes. The point is valid.
-    let result = unsafe { ECPOINTPoint2Oct(point, buf.as_mut_ptr(), len) };
-    if result == 0 {
-        return Err(Error::ECPoint2OctFailed);
-    }
-    // According to the boringssl API, this should never happen.
-    if result > len {
-        return Err(Error::ECPoint2OctFailed);
+/// Signs the pre-hashed `digest` with `key` using ECDSA and returns the DER-encoded signature.
+/// The caller chooses the hash function; the digest is used as is.
+pub fn ecdsa_sign(key: &ECKey, digest: &[u8]) -> Result<Vec<u8>, Error> {
+    // A DER-encoded P-521 signature is at most 3 + 2 * (2 + 67) bytes long.
+    let mut sig = vec![0; 141];
+    // Safety: The key is valid, digest is valid for reads of digest.len() bytes and ECDSASign
+    // writes at most sig.len() bytes to sig.
+    let len =
+        unsafe { ECDSASign(key.0, digest.as_ptr(), digest.len(), sig.as_mut_ptr(), sig.len()) };
+    if len == 0 || len > sig.len() {
+        return Err(Error::EcdsaSignFailed);
     }
-    buf.resize(result, 0);
-    Ok(buf)
+    sig.truncate(len);
+    Ok(sig)
 }
 
-/// Calls the boringssl EC_POINT_oct2point function.
-pub fn ec_point_oct_to_point(buf: &[u8]) -> Result<OwnedECPoint, Error> {
-    // Safety: The buffer is valid.
-    let result = unsafe { ECPOINTOct2Point(buf.as_ptr(), buf.len()) };
-    if result.is_null() {
-        return Err(Error::ECPoint2OctFailed);
-    }
+/// Verifies the DER-encoded ECDSA signature `sig` over the pre-hashed `digest` with the public
+/// key `point`, which must be a P-521 point like those handled by `ec_point_oct_to_point`.
+/// Returns `Ok(false)` if the signature does not verify.
+pub fn ecdsa_verify(point: &EC_POINT, digest: &[u8], sig: &[u8]) -> Result<bool, Error> {
+    ecdsa_verify_for_curve(EcCurve::P521, point, digest, sig)
+}
+
+/// Like `ecdsa_verify`, but for a public key `point` on `curve`. Fails if the point is not on
+/// that curve.
+pub fn ecdsa_verify_for_curve(
+    curve: EcCurve,
+    point: &EC_POINT,
+    digest: &[u8],
+    sig: &[u8],
+) -> Result<bool, Error> {
+    // Safety: The point is valid, and digest and sig are valid for reads of their lengths.
+    match unsafe {
+        ECDSAVerifyForCurve(
+            curve.nid(),
+            point,
+            digest.as_ptr(),
+            digest.len(),
+            sig.as_ptr(),
+            sig.len(),
+        )
+    } {
+        1 => Ok(true),
+        0 => Ok(false),
+        _ => Err(Error::EcdsaVerifyFailed),
+    }
+}
+
+/// The encoding of an EC point, see SEC 1, section 2.3.3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointConversionForm {
+    /// Only the x coordinate and the parity of the y coordinate.
+    Compressed,
+    /// Both coordinates.
+    Uncompressed,
+}
+
+/// Calls the boringssl EC_POINT_point2oct for a P-521 point in uncompressed form.
+pub fn ec_point_point_to_oct(point: &EC_POINT) -> Result<Vec<u8>, Error> {
+    ec_point_point_to_oct_for_curve(EcCurve::P521, point, PointConversionForm::Uncompressed)
+}
+
+/// Calls the boringssl EC_POINT_point2oct for a point on `curve` in the given `form`.
+pub fn ec_point_point_to_oct_for_curve(
+    curve: EcCurve,
+    point: &EC_POINT,
+    form: PointConversionForm,
+) -> Result<Vec<u8>, Error> {
+    let compressed = form == PointConversionForm::Compressed;
+    // Safety: The point is valid. With a null buffer, nothing is written and only the
+    // required length is returned.
+    let len = unsafe {
+        ECPOINTPoint2OctForCurve(curve.nid(), point, compressed, std::ptr::null_mut(), 0)
+    };
+    if len == 0 {
+        return Err(Error::ECPoint2OctFailed);
+    }
+    let mut buf = vec![0; len];
+    // Safety: EC_POINT_point2oct writes at most len bytes. The point is valid.
+    let result =
+        unsafe { ECPOINTPoint2OctForCurve(curve.nid(), point, compressed, buf.as_mut_ptr(), len) };
+    if result != len {
+        return Err(Error::ECPoint2OctFailed);
+    }
+    Ok(buf)
+}
+
+/// Calls the boringssl EC_POINT_oct2point function for a P-521 point.
+pub fn ec_point_oct_to_point(buf: &[u8]) -> Result<OwnedECPoint, Error> {
+    ec_point_oct_to_point_for_curve(EcCurve::P521, buf)
+}
+
+/// Calls the boringssl EC_POINT_oct2point function for a point on `curve`. Both compressed and
+/// uncompressed encodings are accepted.
+pub fn ec_point_oct_to_point_for_curve(curve: EcCurve, buf: &[u8]) -> Result<OwnedECPoint, Error> {
+    // Safety: The buffer is valid.
+    let result = unsafe { ECPOINTOct2PointForCurve(curve.nid(), buf.as_ptr(), buf.len()) };
+    if result.is_null() {
+        return Err(Error::ECPoint2OctFailed);
+    }
     // Our C wrapper creates a new EC_POINT, so we mark this mutable and free
     // it on drop.
     Ok(OwnedECPoint(result))
 }
 
+/// NID of SHA-256, for use as `hash_nid` of the RSA PKCS#1 functions.
+pub const RSA_HASH_NID_SHA256: i32 = NID_sha256 as i32;
+/// NID of SHA-384, for use as `hash_nid` of the RSA PKCS#1 functions.
+pub const RSA_HASH_NID_SHA384: i32 = NID_sha384 as i32;
+/// NID of SHA-512, for use as `hash_nid` of the RSA PKCS#1 functions.
+pub const RSA_HASH_NID_SHA512: i32 = NID_sha512 as i32;
+
+/// RSA key sizes in bits supported by `rsa_generate_key`.
+pub const RSA_KEY_SIZES: [u32; 3] = [2048, 3072, 4096];
+
+/// A wrapper around the boringssl RSA type that frees it on drop.
+pub struct RsaKey(*mut RSA);
+
+impl Drop for RsaKey {
+    fn drop(&mut self) {
+        // Safety: We only create RsaKey objects for valid RSA keys
+        // and they are the sole owners of those keys.
+        unsafe { RSA_free(self.0) };
+    }
+}
+
+// Safety: An RsaKey is the sole owner of its RSA key, and boringssl does not tie RSA objects to
+// the thread that created them, so ownership can be moved to another thread.
+unsafe impl Send for RsaKey {}
+
+/// Generates an RSA key of `bits` bits with public exponent 65537. `bits` must be one of
+/// `RSA_KEY_SIZES`.
+pub fn rsa_generate_key(bits: u32) -> Result<RsaKey, Error> {
+    if !RSA_KEY_SIZES.contains(&bits) {
+        return Err(Error::InvalidKeySize);
+    }
+    // Safety: Creates a new key on its own.
+    let key = unsafe { RSAGenerateKey(bits) };
+    if key.is_null() {
+        return Err(Error::RsaGenerateKeyFailed);
+    }
+    Ok(RsaKey(key))
+}
+
+/// Signs the pre-hashed `digest` with `key` using RSASSA-PKCS1-v1_5. `hash_nid` names the hash
+/// function that produced the digest, e.g., `RSA_HASH_NID_SHA256`.
+pub fn rsa_sign_pkcs1(key: &RsaKey, digest: &[u8], hash_nid: i32) -> Result<Vec<u8>, Error> {
+    // Large enough for the largest key in RSA_KEY_SIZES.
+    let mut sig = vec![0; 4096 / 8];
+    // Safety: The key is valid, digest is valid for reads of digest.len() bytes and RSASignPKCS1
+    // writes at most sig.len() bytes to sig.
+    let len = unsafe {
+        RSASignPKCS1(key.0, hash_nid, digest.as_ptr(), digest.len(), sig.as_mut_ptr(), sig.len())
+    };
+    if len == 0 || len > sig.len() {
+        return Err(Error::RsaSignFailed);
+    }
+    sig.truncate(len);
+    Ok(sig)
+}
+
+/// Verifies the RSASSA-PKCS1-v1_5 signature `sig` over the pre-hashed `digest` with `key`.
+/// Returns `Ok(false)` if the signature does not verify.
+pub fn rsa_verify_pkcs1(
+    key: &RsaKey,
+    digest: &[u8],
+    sig: &[u8],
+    hash_nid: i32,
+) -> Result<bool, Error> {
+    // Safety: The key is valid, and digest and sig are valid for reads of their lengths.
+    let result = unsafe {
+        RSAVerifyPKCS1(key.0, hash_nid, digest.as_ptr(), digest.len(), sig.as_ptr(), sig.len())
+    };
+    Ok(result == 1)
+}
+
+/// Computes the SHA-256 digest of `data`.
+pub fn sha256(data: &[u8]) -> [u8; SHA256_DIGEST_LENGTH as usize] {
+    let mut digest = [0; SHA256_DIGEST_LENGTH as usize];
+    // Safety: data is valid for reads of data.len() bytes and digest has room for the
+    // SHA256_DIGEST_LENGTH bytes written by SHA256, which cannot fail.
+    unsafe { SHA256(data.as_ptr(), data.len(), digest.as_mut_ptr()) };
+    digest
+}
+
+/// Computes the SHA-512 digest of `data`.
+pub fn sha512(data: &[u8]) -> [u8; SHA512_DIGEST_LENGTH as usize] {
+    let mut digest = [0; SHA512_DIGEST_LENGTH as usize];
+    // Safety: data is valid for reads of data.len() bytes and digest has room for the
+    // SHA512_DIGEST_LENGTH bytes written by SHA512, which cannot fail.
+    unsafe { SHA512(data.as_ptr(), data.len(), digest.as_mut_ptr()) };
+    digest
+}
+
+/// Returns true if BoringSSL was built in FIPS mode.
+pub fn fips_mode() -> bool {
+    // Safety: FIPS_mode only reads a compile time constant.
+    unsafe { FIPS_mode() != 0 }
+}
+
+/// Describes the crypto library that keystore is linked against, i.e., the BoringSSL version
+/// string and whether it was built in FIPS mode. This is meant for logging, so that crypto
+/// behavior can be correlated with library versions across builds.
+pub fn library_version() -> String {
+    // Safety: OpenSSL_version returns a pointer to a static, nul terminated string.
+    let version = unsafe { CStr::from_ptr(OpenSSL_version(OPENSSL_VERSION as i32)) };
+    format!("{} (FIPS mode: {})", version.to_string_lossy(), if fips_mode() { "on" } else { "off" })
+}
+
 /// Uses BoringSSL to extract the DER-encoded subject from a DER-encoded X.509 certificate.
 pub fn parse_subject_from_certificate(cert_buf: &[u8]) -> Result<Vec<u8>, Error> {
     // Try with a 200-byte output buffer, should be enough in all but bizarre cases.
@@ -477,6 +1377,118 @@ pub fn parse_subject_from_certificate(cert_buf: &[u8]) -> Result<Vec<u8>, Error>
     Ok(retval)
 }
 
+/// Fields of an X.509 certificate, see [`parse_certificate_fields`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertFields {
+    /// The DER-encoded subject name.
+    pub subject: Vec<u8>,
+    /// The DER-encoded issuer name.
+    pub issuer: Vec<u8>,
+    /// The big-endian magnitude of the serial number.
+    pub serial: Vec<u8>,
+    /// The start of the validity period in milliseconds since the epoch.
+    pub not_before_ms: i64,
+    /// The end of the validity period in milliseconds since the epoch.
+    pub not_after_ms: i64,
+}
+
+/// Uses BoringSSL to extract the subject, issuer, serial number and validity period from a
+/// DER-encoded X.509 certificate.
+pub fn parse_certificate_fields(cert_buf: &[u8]) -> Result<CertFields, Error> {
+    let subject = parse_subject_from_certificate(cert_buf)?;
+
+    let mut issuer = vec![0; cert_buf.len()];
+    let mut issuer_len = issuer.len();
+    let mut serial = vec![0; cert_buf.len()];
+    let mut serial_len = serial.len();
+    let mut not_before: i64 = 0;
+    let mut not_after: i64 = 0;
+
+    // Safety: extractCertificateFields reads at most cert_buf.len() bytes from cert_buf, writes
+    // at most issuer_len bytes to issuer and serial_len bytes to serial, and writes the validity
+    // bounds to the provided i64 locations.
+    if !unsafe {
+        extractCertificateFields(
+            cert_buf.as_ptr(),
+            cert_buf.len(),
+            issuer.as_mut_ptr(),
+            &mut issuer_len,
+            serial.as_mut_ptr(),
+            &mut serial_len,
+            &mut not_before,
+            &mut not_after,
+        )
+    } {
+        return Err(Error::ExtractCertificateFieldsFailed);
+    }
+    issuer.truncate(issuer_len);
+    serial.truncate(serial_len);
+
+    let to_ms = |secs: i64| secs.checked_mul(1000).ok_or(Error::ExtractCertificateFieldsFailed);
+    Ok(CertFields {
+        subject,
+        issuer,
+        serial,
+        not_before_ms: to_ms(not_before)?,
+        not_after_ms: to_ms(not_after)?,
+    })
+}
+
+/// Returns whether the DER-encoded X.509 certificate `cert_der` is self-signed, i.e., its issuer
+/// matches its subject and its signature verifies with its own public key.
+pub fn is_self_signed_certificate(cert_der: &[u8]) -> Result<bool, Error> {
+    // Safety: isSelfSignedCertificate reads at most cert_der.len() bytes from cert_der.
+    match unsafe { isSelfSignedCertificate(cert_der.as_ptr(), cert_der.len()) } {
+        1 => Ok(true),
+        0 => Ok(false),
+        _ => Err(Error::CertificateParseFailed),
+    }
+}
+
+/// Verifies the chain of DER-encoded X.509 certificates `leaf_to_root`, ordered from the leaf to
+/// the root, by checking the signature of each certificate against the public key of the next
+/// one. The root itself is not checked; whether to trust it is up to the caller. On failure, the
+/// error carries the index of the first offending certificate.
+pub fn verify_cert_chain(leaf_to_root: &[&[u8]]) -> Result<(), Error> {
+    let certs: Vec<*const u8> = leaf_to_root.iter().map(|cert| cert.as_ptr()).collect();
+    let cert_lens: Vec<usize> = leaf_to_root.iter().map(|cert| cert.len()).collect();
+
+    // Safety: verifyCertChain reads leaf_to_root.len() entries from certs and cert_lens, and at
+    // most cert_lens[i] bytes from certs[i], all of which point into live slices.
+    match unsafe { verifyCertChain(certs.as_ptr(), cert_lens.as_ptr(), certs.len()) } {
+        -1 => Ok(()),
+        index => Err(Error::CertChainVerificationFailed(usize::try_from(index).unwrap_or(0))),
+    }
+}
+
+/// Extracts the attestation challenge from the KeyMint attestation extension of the DER-encoded
+/// leaf certificate `leaf_der`. Callers compare it against the challenge passed at key generation
+/// to check that the attestation is fresh.
+pub fn extract_attestation_challenge(leaf_der: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut challenge = vec![0; leaf_der.len()];
+
+    // Safety: extractAttestationChallenge reads at most leaf_der.len() bytes from leaf_der and
+    // writes at most challenge.len() bytes to challenge.
+    let size = unsafe {
+        extractAttestationChallenge(
+            leaf_der.as_ptr(),
+            leaf_der.len(),
+            challenge.as_mut_ptr(),
+            challenge.len(),
+        )
+    };
+
+    match size {
+        -2 => Err(Error::AttestationExtensionMissing),
+        size => {
+            let size =
+                usize::try_from(size).map_err(|_e| Error::ExtractAttestationChallengeFailed)?;
+            challenge.truncate(size);
+            Ok(challenge)
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -492,6 +1504,319 @@ mod tests {
         assert_eq!(message[..], message2[..])
     }
 
+    #[test]
+    fn test_preferred_aes_key_len() {
+        assert_eq!(preferred_aes_key_len(0), None);
+        assert_eq!(preferred_aes_key_len(15), None);
+        assert_eq!(preferred_aes_key_len(16), Some(AES_128_KEY_LENGTH));
+        assert_eq!(preferred_aes_key_len(31), Some(AES_128_KEY_LENGTH));
+        assert_eq!(preferred_aes_key_len(32), Some(AES_256_KEY_LENGTH));
+        assert_eq!(preferred_aes_key_len(usize::MAX), Some(AES_256_KEY_LENGTH));
+    }
+
+    #[test]
+    fn test_aes_gcm_verify() {
+        let key = generate_aes256_key().unwrap();
+        let (cipher_text, iv, mut tag) = aes_gcm_encrypt(b"authenticate me", &key).unwrap();
+        assert_eq!(aes_gcm_verify(&cipher_text, &iv, &tag, &[], &key), Ok(()));
+        tag[0] ^= 1;
+        assert_eq!(
+            aes_gcm_verify(&cipher_text, &iv, &tag, &[], &key),
+            Err(Error::DecryptionFailed)
+        );
+
+        // Test case 4 from "The Galois/Counter Mode of Operation (GCM)" by McGrew and Viega.
+        let key = [
+            0xfe, 0xff, 0xe9, 0x92, 0x86, 0x65, 0x73, 0x1c, 0x6d, 0x6a, 0x8f, 0x94, 0x67, 0x30,
+            0x83, 0x08,
+        ];
+        let iv = [0xca, 0xfe, 0xba, 0xbe, 0xfa, 0xce, 0xdb, 0xad, 0xde, 0xca, 0xf8, 0x88];
+        let aad = [
+            0xfe, 0xed, 0xfa, 0xce, 0xde, 0xad, 0xbe, 0xef, 0xfe, 0xed, 0xfa, 0xce, 0xde, 0xad,
+            0xbe, 0xef, 0xab, 0xad, 0xda, 0xd2,
+        ];
+        let cipher_text = [
+            0x42, 0x83, 0x1e, 0xc2, 0x21, 0x77, 0x74, 0x24, 0x4b, 0x72, 0x21, 0xb7, 0x84, 0xd0,
+            0xd4, 0x9c, 0xe3, 0xaa, 0x21, 0x2f, 0x2c, 0x02, 0xa4, 0xe0, 0x35, 0xc1, 0x7e, 0x23,
+            0x29, 0xac, 0xa1, 0x2e, 0x21, 0xd5, 0x14, 0xb2, 0x54, 0x66, 0x93, 0x1c, 0x7d, 0x8f,
+            0x6a, 0x5a, 0xac, 0x84, 0xaa, 0x05, 0x1b, 0xa3, 0x0b, 0x39, 0x6a, 0x0a, 0xac, 0x97,
+            0x3d, 0x58, 0xe0, 0x91,
+        ];
+        let tag = [
+            0x5b, 0xc9, 0x4f, 0xbc, 0x32, 0x21, 0xa5, 0xdb, 0x94, 0xfa, 0xe9, 0x5a, 0xe7, 0x12,
+            0x1a, 0x47,
+        ];
+        assert_eq!(aes_gcm_verify(&cipher_text, &iv, &tag, &aad, &key), Ok(()));
+        assert_eq!(
+            aes_gcm_verify(&cipher_text, &iv, &tag, &aad[1..], &key),
+            Err(Error::DecryptionFailed)
+        );
+        assert_eq!(
+            aes_gcm_verify(&cipher_text, &iv, &tag[1..], &aad, &key),
+            Err(Error::InvalidAeadTagLength)
+        );
+    }
+
+    #[test]
+    fn test_aes_gcm_aad() -> Result<(), Error> {
+        let key = generate_aes256_key()?;
+        let message = b"bound to a header";
+        let header = b"\x01 key id 42";
+
+        let (cipher_text, iv, tag) = aes_gcm_encrypt_aad(message, header, &key)?;
+        assert_eq!(&aes_gcm_decrypt_aad(&cipher_text, &iv, &tag, header, &key)?[..], message);
+        assert_eq!(aes_gcm_verify(&cipher_text, &iv, &tag, header, &key), Ok(()));
+
+        // Any change to the additional data, including leaving it out, fails decryption.
+        let mut tampered = header.to_vec();
+        tampered[0] ^= 1;
+        for aad in [&tampered[..], &header[1..], &[]] {
+            assert_eq!(
+                aes_gcm_decrypt_aad(&cipher_text, &iv, &tag, aad, &key),
+                Err(Error::DecryptionFailed)
+            );
+        }
+        assert_eq!(aes_gcm_decrypt(&cipher_text, &iv, &tag, &key), Err(Error::DecryptionFailed));
+
+        // An empty AAD is interchangeable with the functions without AAD, so existing blobs
+        // still decrypt.
+        let (cipher_text, iv, tag) = aes_gcm_encrypt(message, &key)?;
+        assert_eq!(&aes_gcm_decrypt_aad(&cipher_text, &iv, &tag, &[], &key)?[..], message);
+        let (cipher_text, iv, tag) = aes_gcm_encrypt_aad(message, &[], &key)?;
+        assert_eq!(&aes_gcm_decrypt(&cipher_text, &iv, &tag, &key)?[..], message);
+
+        // Test case 4 from "The Galois/Counter Mode of Operation (GCM)" by McGrew and Viega.
+        let key = [
+            0xfe, 0xff, 0xe9, 0x92, 0x86, 0x65, 0x73, 0x1c, 0x6d, 0x6a, 0x8f, 0x94, 0x67, 0x30,
+            0x83, 0x08,
+        ];
+        let iv = [0xca, 0xfe, 0xba, 0xbe, 0xfa, 0xce, 0xdb, 0xad, 0xde, 0xca, 0xf8, 0x88];
+        let aad = [
+            0xfe, 0xed, 0xfa, 0xce, 0xde, 0xad, 0xbe, 0xef, 0xfe, 0xed, 0xfa, 0xce, 0xde, 0xad,
+            0xbe, 0xef, 0xab, 0xad, 0xda, 0xd2,
+        ];
+        let plain_text = [
+            0xd9, 0x31, 0x32, 0x25, 0xf8, 0x84, 0x06, 0xe5, 0xa5, 0x59, 0x09, 0xc5, 0xaf, 0xf5,
+            0x26, 0x9a, 0x86, 0xa7, 0xa9, 0x53, 0x15, 0x34, 0xf7, 0xda, 0x2e, 0x4c, 0x30, 0x3d,
+            0x8a, 0x31, 0x8a, 0x72, 0x1c, 0x3c, 0x0c, 0x95, 0x95, 0x68, 0x09, 0x53, 0x2f, 0xcf,
+            0x0e, 0x24, 0x49, 0xa6, 0xb5, 0x25, 0xb1, 0x6a, 0xed, 0xf5, 0xaa, 0x0d, 0xe6, 0x57,
+            0xba, 0x63, 0x7b, 0x39,
+        ];
+        let cipher_text = [
+            0x42, 0x83, 0x1e, 0xc2, 0x21, 0x77, 0x74, 0x24, 0x4b, 0x72, 0x21, 0xb7, 0x84, 0xd0,
+            0xd4, 0x9c, 0xe3, 0xaa, 0x21, 0x2f, 0x2c, 0x02, 0xa4, 0xe0, 0x35, 0xc1, 0x7e, 0x23,
+            0x29, 0xac, 0xa1, 0x2e, 0x21, 0xd5, 0x14, 0xb2, 0x54, 0x66, 0x93, 0x1c, 0x7d, 0x8f,
+            0x6a, 0x5a, 0xac, 0x84, 0xaa, 0x05, 0x1b, 0xa3, 0x0b, 0x39, 0x6a, 0x0a, 0xac, 0x97,
+            0x3d, 0x58, 0xe0, 0x91,
+        ];
+        let tag = [
+            0x5b, 0xc9, 0x4f, 0xbc, 0x32, 0x21, 0xa5, 0xdb, 0x94, 0xfa, 0xe9, 0x5a, 0xe7, 0x12,
+            0x1a, 0x47,
+        ];
+        assert_eq!(&aes_gcm_decrypt_aad(&cipher_text, &iv, &tag, &aad, &key)?[..], &plain_text[..]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_aes_gcm_encrypt_arrays() -> Result<(), Error> {
+        let key = generate_aes256_key()?;
+        let message = b"totally awesome message";
+        let (cipher_text, iv, tag) = aes_gcm_encrypt_arrays(message, &key)?;
+        assert_eq!(&aes_gcm_decrypt(&cipher_text, &iv, &tag, &key)?[..], message);
+
+        // The IV is fresh for each encryption.
+        let (_, other_iv, _) = aes_gcm_encrypt_arrays(message, &key)?;
+        assert_ne!(iv, other_iv);
+
+        // The Vec returning variant is interchangeable.
+        let (cipher_text, iv, tag) = aes_gcm_encrypt(message, &key)?;
+        assert_eq!((iv.len(), tag.len()), (GCM_IV_LENGTH, TAG_LENGTH));
+        assert_eq!(&aes_gcm_decrypt(&cipher_text, &iv, &tag, &key)?[..], message);
+        Ok(())
+    }
+
+    #[test]
+    fn test_aes_gcm_encrypt_with_iv() -> Result<(), Error> {
+        // Test case 3 from "The Galois/Counter Mode of Operation (GCM)" by McGrew and Viega.
+        let key = [
+            0xfe, 0xff, 0xe9, 0x92, 0x86, 0x65, 0x73, 0x1c, 0x6d, 0x6a, 0x8f, 0x94, 0x67, 0x30,
+            0x83, 0x08,
+        ];
+        let iv = [0xca, 0xfe, 0xba, 0xbe, 0xfa, 0xce, 0xdb, 0xad, 0xde, 0xca, 0xf8, 0x88];
+        let plain_text = [
+            0xd9, 0x31, 0x32, 0x25, 0xf8, 0x84, 0x06, 0xe5, 0xa5, 0x59, 0x09, 0xc5, 0xaf, 0xf5,
+            0x26, 0x9a, 0x86, 0xa7, 0xa9, 0x53, 0x15, 0x34, 0xf7, 0xda, 0x2e, 0x4c, 0x30, 0x3d,
+            0x8a, 0x31, 0x8a, 0x72, 0x1c, 0x3c, 0x0c, 0x95, 0x95, 0x68, 0x09, 0x53, 0x2f, 0xcf,
+            0x0e, 0x24, 0x49, 0xa6, 0xb5, 0x25, 0xb1, 0x6a, 0xed, 0xf5, 0xaa, 0x0d, 0xe6, 0x57,
+            0xba, 0x63, 0x7b, 0x39, 0x1a, 0xaf, 0xd2, 0x55,
+        ];
+        let cipher_text = [
+            0x42, 0x83, 0x1e, 0xc2, 0x21, 0x77, 0x74, 0x24, 0x4b, 0x72, 0x21, 0xb7, 0x84, 0xd0,
+            0xd4, 0x9c, 0xe3, 0xaa, 0x21, 0x2f, 0x2c, 0x02, 0xa4, 0xe0, 0x35, 0xc1, 0x7e, 0x23,
+            0x29, 0xac, 0xa1, 0x2e, 0x21, 0xd5, 0x14, 0xb2, 0x54, 0x66, 0x93, 0x1c, 0x7d, 0x8f,
+            0x6a, 0x5a, 0xac, 0x84, 0xaa, 0x05, 0x1b, 0xa3, 0x0b, 0x39, 0x6a, 0x0a, 0xac, 0x97,
+            0x3d, 0x58, 0xe0, 0x91, 0x47, 0x3f, 0x59, 0x85,
+        ];
+        let tag = [
+            0x4d, 0x5c, 0x2a, 0xf3, 0x27, 0xcd, 0x64, 0xa6, 0x2c, 0xf3, 0x5a, 0xbd, 0x2b, 0xa6,
+            0xfa, 0xb4,
+        ];
+        assert_eq!(
+            aes_gcm_encrypt_with_iv(&plain_text, &iv, &key)?,
+            (cipher_text.to_vec(), tag.to_vec())
+        );
+        assert_eq!(&aes_gcm_decrypt(&cipher_text, &iv, &tag, &key)?[..], &plain_text[..]);
+
+        // Only IVs of exactly GCM_IV_LENGTH bytes are accepted, not even legacy 16 byte ones.
+        for iv_len in [0, GCM_IV_LENGTH - 1, GCM_IV_LENGTH + 1, LEGACY_IV_LENGTH] {
+            assert_eq!(
+                aes_gcm_encrypt_with_iv(&plain_text, &vec![0; iv_len], &key),
+                Err(Error::InvalidIvLength)
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_gcm_streaming() -> Result<(), Error> {
+        let key = generate_aes256_key()?;
+        let iv = generate_random_data(GCM_IV_LENGTH)?;
+        let message = generate_random_data(1000)?;
+        let (expected_cipher_text, expected_tag) = aes_gcm_encrypt_with_iv(&message, &iv, &key)?;
+
+        let mut ctx = GcmEncryptContext::new(&key, &iv)?;
+        let mut cipher_text = Vec::new();
+        for chunk in message.chunks(77) {
+            cipher_text.extend(ctx.update(chunk)?);
+        }
+        let (rest, tag) = ctx.finalize()?;
+        cipher_text.extend(rest);
+        assert_eq!(cipher_text, expected_cipher_text);
+        assert_eq!(tag.to_vec(), expected_tag);
+
+        let decrypt = |cipher_text: &[u8], tag: &[u8]| {
+            let mut ctx = GcmDecryptContext::new(&key, &iv)?;
+            for chunk in cipher_text.chunks(100) {
+                ctx.update(chunk)?;
+            }
+            ctx.finalize(tag)
+        };
+        assert_eq!(&decrypt(&cipher_text, &tag)?[..], &message[..]);
+
+        // Nothing is released if the ciphertext or the tag was tampered with.
+        let mut tampered = cipher_text.clone();
+        tampered[500] ^= 1;
+        assert_eq!(decrypt(&tampered, &tag), Err(Error::DecryptionFailed));
+        let mut tampered_tag = tag;
+        tampered_tag[0] ^= 1;
+        assert_eq!(decrypt(&cipher_text, &tampered_tag), Err(Error::DecryptionFailed));
+        assert_eq!(decrypt(&cipher_text, &tag[1..]), Err(Error::InvalidAeadTagLength));
+
+        assert!(matches!(GcmEncryptContext::new(&key[1..], &iv), Err(Error::InvalidKeyLength)));
+        assert!(matches!(GcmEncryptContext::new(&key, &iv[1..]), Err(Error::InvalidIvLength)));
+        assert!(matches!(GcmDecryptContext::new(&key, &iv[1..]), Err(Error::InvalidIvLength)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_chacha20_poly1305() -> Result<(), Error> {
+        let key = generate_aes256_key()?;
+        let message = b"backed up off device";
+
+        let (cipher_text, nonce, tag) = chacha20_poly1305_encrypt(message, &key)?;
+        assert_eq!(nonce.len(), CHACHA20_POLY1305_NONCE_LENGTH);
+        assert_eq!(&chacha20_poly1305_decrypt(&cipher_text, &nonce, &tag, &key)?[..], message);
+
+        let mut tampered = cipher_text.clone();
+        tampered[0] ^= 1;
+        assert_eq!(
+            chacha20_poly1305_decrypt(&tampered, &nonce, &tag, &key),
+            Err(Error::DecryptionFailed)
+        );
+        assert_eq!(
+            chacha20_poly1305_decrypt(&cipher_text, &nonce[1..], &tag, &key),
+            Err(Error::InvalidNonceLength)
+        );
+        assert_eq!(
+            chacha20_poly1305_decrypt(&cipher_text, &nonce, &tag[1..], &key),
+            Err(Error::InvalidAeadTagLength)
+        );
+
+        // A 128-bit AES GCM key is not a valid ChaCha20-Poly1305 key.
+        let aes_128_key = [0u8; AES_128_KEY_LENGTH];
+        assert!(aes_gcm_encrypt(message, &aes_128_key).is_ok());
+        assert_eq!(chacha20_poly1305_encrypt(message, &aes_128_key), Err(Error::InvalidKeyLength));
+        assert_eq!(
+            chacha20_poly1305_decrypt(&cipher_text, &nonce, &tag, &aes_128_key),
+            Err(Error::InvalidKeyLength)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_derive_key_hkdf_with_info() -> Result<(), Error> {
+        let password = Password::from(&b"synthetic password"[..]);
+        let salt = b"salt";
+
+        let file_key = password.derive_key_hkdf_with_info(salt, b"file encryption", 32)?;
+        let mac_key = password.derive_key_hkdf_with_info(salt, b"metadata MAC", 32)?;
+        assert_ne!(file_key, mac_key);
+        assert_eq!(password.derive_key_hkdf_with_info(salt, b"file encryption", 32)?, file_key);
+
+        // An empty info is what derive_key_hkdf uses.
+        assert_eq!(
+            password.derive_key_hkdf_with_info(salt, &[], 32)?,
+            password.derive_key_hkdf(salt, 32)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_one_shot_decrypt_length_bound() -> Result<(), Error> {
+        let key = generate_aes256_key()?;
+        let message = vec![0x5a; MAX_ONE_SHOT_DECRYPT_LEN];
+        let (ciphertext, iv, tag) = aes_gcm_encrypt(&message, &key)?;
+        assert_eq!(&aes_gcm_decrypt(&ciphertext, &iv, &tag, &key)?[..], &message[..]);
+        assert_eq!(aes_gcm_verify(&ciphertext, &iv, &tag, &[], &key), Ok(()));
+
+        let too_long = vec![0; MAX_ONE_SHOT_DECRYPT_LEN + 1];
+        assert_eq!(aes_gcm_decrypt(&too_long, &iv, &tag, &key), Err(Error::InvalidInputLength));
+        assert_eq!(aes_gcm_verify(&too_long, &iv, &tag, &[], &key), Err(Error::InvalidInputLength));
+        Ok(())
+    }
+
+    #[test]
+    fn test_legacy_iv_accepted_by_all_decrypt_paths() {
+        assert_eq!(normalize_gcm_iv(&[7; GCM_IV_LENGTH]), Ok(&[7; GCM_IV_LENGTH][..]));
+        assert_eq!(normalize_gcm_iv(&[7; LEGACY_IV_LENGTH]), Ok(&[7; GCM_IV_LENGTH][..]));
+        assert_eq!(normalize_gcm_iv(&[7; 8]), Err(Error::InvalidIvLength));
+
+        let key = generate_aes256_key().unwrap();
+        let message = b"written by an old version";
+        let (cipher_text, iv, tag) = aes_gcm_encrypt(message, &key).unwrap();
+        let mut legacy_iv = iv.clone();
+        legacy_iv.extend_from_slice(&[0xff; LEGACY_IV_LENGTH - GCM_IV_LENGTH]);
+
+        let plaintext = aes_gcm_decrypt(&cipher_text, &legacy_iv, &tag, &key).unwrap();
+        assert_eq!(plaintext[..], message[..]);
+        assert_eq!(aes_gcm_verify(&cipher_text, &legacy_iv, &tag, &[], &key), Ok(()));
+    }
+
+    #[test]
+    fn test_verify_pbkdf2() -> Result<(), Error> {
+        let salt = [3; SALT_LENGTH];
+        let pw: Password = (&b"correct horse"[..]).into();
+        let verifier = pw.derive_key_pbkdf2(&salt, AES_256_KEY_LENGTH)?;
+        assert!(pw.verify_pbkdf2(&salt, &verifier)?);
+
+        let wrong_pw: Password = (&b"battery staple"[..]).into();
+        assert!(!wrong_pw.verify_pbkdf2(&salt, &verifier)?);
+        assert!(!pw.verify_pbkdf2(&[4; SALT_LENGTH], &verifier)?);
+
+        assert_eq!(pw.verify_pbkdf2(&salt, &verifier[..20]), Err(Error::InvalidKeyLength));
+        Ok(())
+    }
+
     #[test]
     fn test_encrypt_decrypt() {
         let input = vec![0; 16];
@@ -556,6 +1881,36 @@ mod tests {
         assert_ne!(key, vec![0; 16]);
     }
 
+    #[test]
+    fn test_derive_key_pbkdf2_iters() -> Result<(), Error> {
+        let password = Password::from(&b"user password"[..]);
+        let salt = [7; SALT_LENGTH];
+
+        for out_len in [AES_128_KEY_LENGTH, AES_256_KEY_LENGTH] {
+            let legacy = password.derive_key_pbkdf2(&salt, out_len)?;
+            let mut raw = vec![0; out_len];
+            // SAFETY: The pointers are obtained from references so they are valid, the salt is
+            // the expected length, and the other lengths match the lengths of the arrays.
+            unsafe {
+                PBKDF2(
+                    raw.as_mut_ptr(),
+                    raw.len(),
+                    password.get_key().as_ptr() as *const std::os::raw::c_char,
+                    password.get_key().len(),
+                    salt.as_ptr(),
+                );
+            }
+            assert_eq!(&legacy[..], &raw[..]);
+            assert_eq!(password.derive_key_pbkdf2_iters(&salt, 8192, out_len)?, legacy);
+            assert_ne!(password.derive_key_pbkdf2_iters(&salt, 10000, out_len)?, legacy);
+        }
+        assert_eq!(
+            password.derive_key_pbkdf2_iters(&salt, 0, AES_256_KEY_LENGTH),
+            Err(Error::InvalidIterationCount)
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_hkdf() {
         let result = hkdf_extract(&[0; 16], &[0; 16]);
@@ -567,6 +1922,134 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_kdf_ctr_hmac_sha256() -> Result<(), Error> {
+        // Computed with an independent implementation of the construction, because the CAVP
+        // vectors do not use the label || 0x00 || context || L fixed input format.
+        let key: Vec<u8> = (0..32).collect();
+        let expected = [
+            0xb9, 0xcd, 0x5f, 0x63, 0x23, 0xf0, 0x1f, 0x46, 0x80, 0x65, 0x08, 0x55, 0xf1, 0xeb,
+            0xea, 0x9b, 0x4c, 0x54, 0xc0, 0x81, 0x31, 0xb5, 0x06, 0xfc, 0x28, 0xc8, 0x56, 0x36,
+            0x4a, 0x38, 0xa2, 0xf4, 0xfb, 0x68, 0x0c, 0x12, 0xea, 0x51, 0x69, 0x68, 0x87, 0xd9,
+        ];
+        let derived = kdf_ctr_hmac_sha256(&key, b"label", b"context", expected.len())?;
+        assert_eq!(&derived[..], &expected[..]);
+
+        // The output length is bound into every block, so a shorter output is not a prefix.
+        let short = kdf_ctr_hmac_sha256(&key, b"label", b"context", 16)?;
+        assert_ne!(&short[..], &expected[..16]);
+        assert_ne!(&kdf_ctr_hmac_sha256(&key, b"label", b"other", 16)?[..], &short[..]);
+
+        let result = kdf_ctr_hmac_sha256(&key, b"label", b"context", 0);
+        assert_eq!(result.unwrap_err(), Error::InvalidDataLength);
+        Ok(())
+    }
+
+    #[test]
+    fn test_self_test() -> Result<(), Error> {
+        self_test::aes_gcm_kat()?;
+        self_test::hmac_sha256_kat()?;
+        self_test::hkdf_kat()?;
+        self_test::ecdh_round_trip_test()?;
+        self_test()
+    }
+
+    #[test]
+    fn test_rng_health_check() -> Result<(), Error> {
+        self_test::rng_health_check()?;
+        self_test::rng_health_check_with(generate_random_data)?;
+
+        // Stuck and obviously patterned RNGs are flagged.
+        for byte in [0x00, 0xff, 0x55, 0x0f] {
+            assert!(matches!(
+                self_test::rng_health_check_with(|len| Ok(vec![byte; len])),
+                Err(Error::SelfTestFailed(_))
+            ));
+        }
+        assert!(matches!(
+            self_test::rng_health_check_with(|_| Ok(vec![])),
+            Err(Error::SelfTestFailed("RNG sample length"))
+        ));
+        assert_eq!(
+            self_test::rng_health_check_with(|_| Err(Error::RandomNumberGenerationFailed)),
+            Err(Error::RandomNumberGenerationFailed)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_aes_kcv() -> Result<(), Error> {
+        // AES-128 and AES-256 encryptions of the zero block under the all-zero key.
+        assert_eq!(aes_kcv(&[0; AES_128_KEY_LENGTH])?, [0x66, 0xe9, 0x4b]);
+        assert_eq!(aes_kcv(&[0; AES_256_KEY_LENGTH])?, [0xdc, 0x95, 0xc0]);
+        assert_eq!(aes_kcv(&[0; 24]), Err(Error::InvalidKeyLength));
+        assert_eq!(aes_kcv(&[]), Err(Error::InvalidKeyLength));
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_hmac_sha256() -> Result<(), Error> {
+        let key = b"key";
+        let msg = b"message";
+        let tag = hmac_sha256(key, msg)?;
+        assert_eq!(verify_hmac_sha256(key, msg, &tag), Ok(()));
+
+        let mut bad_tag = tag.clone();
+        bad_tag[HMAC_SHA256_LEN - 1] ^= 1;
+        assert_eq!(verify_hmac_sha256(key, msg, &bad_tag), Err(Error::MacVerificationFailed));
+        assert_eq!(verify_hmac_sha256(b"other key", msg, &tag), Err(Error::MacVerificationFailed));
+        assert_eq!(verify_hmac_sha256(key, msg, &tag[..16]), Err(Error::MacVerificationFailed));
+        assert_eq!(verify_hmac_sha256(key, msg, &[]), Err(Error::MacVerificationFailed));
+
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(constant_time_eq(&[], &[]));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"abcd"));
+        assert!(!constant_time_eq(b"", b"a"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_hmac_sha256_truncated() -> Result<(), Error> {
+        let key = b"key";
+        let msg = b"message";
+        let full = hmac_sha256(key, msg)?;
+        for out_len in [1, 16, HMAC_SHA256_LEN] {
+            let tag = hmac_sha256_truncated(key, msg, out_len)?;
+            assert_eq!(&tag[..], &full[..out_len]);
+            assert!(hmac_sha256_truncated_verify(key, msg, &tag)?);
+        }
+
+        let mut tag = hmac_sha256_truncated(key, msg, 16)?;
+        tag[0] ^= 1;
+        assert!(!hmac_sha256_truncated_verify(key, msg, &tag)?);
+        assert!(!hmac_sha256_truncated_verify(key, b"other message", &full[..16])?);
+
+        assert_eq!(hmac_sha256_truncated(key, msg, 0), Err(Error::InvalidMacLength));
+        assert_eq!(
+            hmac_sha256_truncated(key, msg, HMAC_SHA256_LEN + 1),
+            Err(Error::InvalidMacLength)
+        );
+        assert_eq!(hmac_sha256_truncated_verify(key, msg, &[]), Err(Error::InvalidMacLength));
+        Ok(())
+    }
+
+    #[test]
+    fn test_derive_enc_mac_keys() -> Result<(), Error> {
+        let (enc_key, mac_key) = derive_enc_mac_keys(&[1; 32], &[2; 16])?;
+        assert_eq!(enc_key.len(), AES_256_KEY_LENGTH);
+        assert_eq!(mac_key.len(), HMAC_SHA256_LEN);
+        assert_ne!(&enc_key[..], &mac_key[..]);
+
+        let (enc_key2, mac_key2) = derive_enc_mac_keys(&[1; 32], &[2; 16])?;
+        assert_eq!(&enc_key[..], &enc_key2[..]);
+        assert_eq!(&mac_key[..], &mac_key2[..]);
+
+        let (enc_key3, _) = derive_enc_mac_keys(&[1; 32], &[3; 16])?;
+        assert_ne!(&enc_key[..], &enc_key3[..]);
+        Ok(())
+    }
+
     #[test]
     fn test_ec() -> Result<(), Error> {
         let priv0 = ec_key_generate_key()?;
@@ -591,6 +2074,324 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_ec_key_generate_key_for_curve() -> Result<(), Error> {
+        let curves = [(EcCurve::P256, 39), (EcCurve::P384, 55), (EcCurve::P521, 73)];
+        for (curve, marshaled_len) in curves {
+            let key = ec_key_generate_key_for_curve(curve)?;
+            let marshaled = ec_key_marshal_private_key(&key)?;
+            assert_eq!(marshaled.len(), marshaled_len);
+            let other = ec_key_generate_key_for_curve(curve)?;
+            assert_ne!(&ec_key_marshal_private_key(&other)?[..], &marshaled[..]);
+            ecdsa_sign(&key, &[0x5a; 32])?;
+        }
+        // The default curve remains P-521, so that generated keys can be parsed again.
+        let key = ec_key_generate_key()?;
+        let marshaled = ec_key_marshal_private_key(&key)?;
+        assert_eq!(marshaled.len(), 73);
+        ec_key_parse_private_key(&marshaled)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_ec_key_marshal_round_trip() -> Result<(), Error> {
+        for curve in [EcCurve::P256, EcCurve::P384, EcCurve::P521] {
+            let key = ec_key_generate_key_for_curve(curve)?;
+            let marshaled = ec_key_marshal_private_key(&key)?;
+            let parsed = ec_key_parse_private_key_for_curve(curve, &marshaled)?;
+            assert_eq!(&ec_key_marshal_private_key(&parsed)?[..], &marshaled[..]);
+        }
+        assert!(ec_key_parse_private_key_for_curve(EcCurve::P256, &[]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_ec_point_compressed() -> Result<(), Error> {
+        for (curve, field_len) in [(EcCurve::P256, 32), (EcCurve::P384, 48), (EcCurve::P521, 66)] {
+            let key = ec_key_generate_key_for_curve(curve)?;
+            let point = ec_key_get0_public_key(&key);
+            let uncompressed = ec_point_point_to_oct_for_curve(
+                curve,
+                point.get_point(),
+                PointConversionForm::Uncompressed,
+            )?;
+            let compressed = ec_point_point_to_oct_for_curve(
+                curve,
+                point.get_point(),
+                PointConversionForm::Compressed,
+            )?;
+            assert_eq!(uncompressed.len(), 1 + 2 * field_len);
+            assert_eq!(compressed.len(), 1 + field_len);
+            assert_eq!(uncompressed[0], 0x04);
+            assert!(compressed[0] == 0x02 || compressed[0] == 0x03);
+            assert_eq!(&compressed[1..], &uncompressed[1..=field_len]);
+
+            // Both forms decode to the same point.
+            for encoded in [&uncompressed, &compressed] {
+                let decoded = ec_point_oct_to_point_for_curve(curve, encoded)?;
+                assert_eq!(
+                    ec_point_point_to_oct_for_curve(
+                        curve,
+                        decoded.get_point(),
+                        PointConversionForm::Uncompressed
+                    )?,
+                    uncompressed
+                );
+            }
+        }
+        assert!(ec_point_oct_to_point_for_curve(EcCurve::P256, &[0x02; 10]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rsa_pkcs1() -> Result<(), Error> {
+        let key = rsa_generate_key(2048)?;
+        let digest = [0x5a; 32];
+        let sig = rsa_sign_pkcs1(&key, &digest, RSA_HASH_NID_SHA256)?;
+        assert_eq!(sig.len(), 2048 / 8);
+        assert!(rsa_verify_pkcs1(&key, &digest, &sig, RSA_HASH_NID_SHA256)?);
+
+        assert!(!rsa_verify_pkcs1(&key, &[0xa5; 32], &sig, RSA_HASH_NID_SHA256)?);
+        assert!(!rsa_verify_pkcs1(&key, &digest, &sig, RSA_HASH_NID_SHA512)?);
+        let mut bad_sig = sig.clone();
+        bad_sig[0] ^= 1;
+        assert!(!rsa_verify_pkcs1(&key, &digest, &bad_sig, RSA_HASH_NID_SHA256)?);
+        let other = rsa_generate_key(2048)?;
+        assert!(!rsa_verify_pkcs1(&other, &digest, &sig, RSA_HASH_NID_SHA256)?);
+
+        // The digest must match the hash function.
+        assert_eq!(rsa_sign_pkcs1(&key, &[0; 20], RSA_HASH_NID_SHA256), Err(Error::RsaSignFailed));
+        for bits in [0, 1024, 2047, 8192] {
+            assert!(matches!(rsa_generate_key(bits), Err(Error::InvalidKeySize)));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_aes_key_wrap() -> Result<(), Error> {
+        // RFC 3394, section 4.1: wrap 128 bits of key data with a 128-bit KEK.
+        let kek: Vec<u8> = (0..16).collect();
+        let key: Vec<u8> = (0..16).map(|i| i * 0x11).collect();
+        const WRAPPED: [u8; 24] = [
+            0x1f, 0xa6, 0x8b, 0x0a, 0x81, 0x12, 0xb4, 0x47, 0xae, 0xf3, 0x4b, 0xd8, 0xfb, 0x5a,
+            0x7b, 0x82, 0x9d, 0x3e, 0x86, 0x23, 0x71, 0xd2, 0xcf, 0xe5,
+        ];
+        let wrapped = aes_key_wrap(&kek, &key)?;
+        assert_eq!(wrapped, WRAPPED);
+        assert_eq!(&aes_key_unwrap(&kek, &wrapped)?[..], &key[..]);
+
+        let kek = generate_aes256_key()?;
+        let key = generate_aes256_key()?;
+        let wrapped = aes_key_wrap(&kek, &key)?;
+        assert_eq!(wrapped.len(), key.len() + AES_KEY_WRAP_OVERHEAD);
+        assert_eq!(&aes_key_unwrap(&kek, &wrapped)?[..], &key[..]);
+
+        // Tampering with the wrapped key or unwrapping with another KEK fails the integrity
+        // check.
+        for i in [0, wrapped.len() / 2, wrapped.len() - 1] {
+            let mut tampered = wrapped.clone();
+            tampered[i] ^= 1;
+            assert_eq!(aes_key_unwrap(&kek, &tampered), Err(Error::KeyUnwrapFailed));
+        }
+        assert_eq!(aes_key_unwrap(&generate_aes256_key()?, &wrapped), Err(Error::KeyUnwrapFailed));
+        assert_eq!(aes_key_unwrap(&kek, &wrapped[..16]), Err(Error::KeyUnwrapFailed));
+        assert_eq!(aes_key_unwrap(&kek, &wrapped[..20]), Err(Error::KeyUnwrapFailed));
+
+        for len in [0, 8, 17, 20] {
+            assert_eq!(aes_key_wrap(&kek, &vec![0; len]), Err(Error::InvalidKeyLength));
+        }
+        assert_eq!(aes_key_wrap(&kek[..20], &key), Err(Error::InvalidKeyLength));
+        Ok(())
+    }
+
+    #[test]
+    fn test_hmac_digests() -> Result<(), Error> {
+        // RFC 2202 and RFC 4231, test case 2.
+        let key = b"Jefe";
+        let msg = b"what do ya want for nothing?";
+        const SHA1: [u8; 20] = [
+            0xef, 0xfc, 0xdf, 0x6a, 0xe5, 0xeb, 0x2f, 0xa2, 0xd2, 0x74, 0x16, 0xd5, 0xf1, 0x84,
+            0xdf, 0x9c, 0x25, 0x9a, 0x7c, 0x79,
+        ];
+        const SHA512_PREFIX: [u8; 8] = [0x16, 0x4b, 0x7a, 0x7b, 0xfc, 0xf8, 0x19, 0xe2];
+
+        for digest in [Digest::Sha1, Digest::Sha256, Digest::Sha512] {
+            assert_eq!(hmac(digest, key, msg)?.len(), digest.output_len());
+        }
+        assert_eq!(hmac(Digest::Sha1, key, msg)?, SHA1);
+        assert_eq!(hmac(Digest::Sha512, key, msg)?[..8], SHA512_PREFIX);
+        assert_eq!(hmac(Digest::Sha256, key, msg)?, hmac_sha256(key, msg)?);
+        assert_ne!(hmac(Digest::Sha512, key, msg)?[..HMAC_SHA256_LEN], hmac_sha256(key, msg)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_hmac_sha256_incremental() -> Result<(), Error> {
+        let key = b"incremental key";
+        let msg = b"a message that is fed in more than one part";
+        let expected = hmac_sha256(key, msg)?;
+
+        let mut hmac = HmacSha256::new(key)?;
+        hmac.update(&msg[..10])?;
+        hmac.update(&msg[10..])?;
+        assert_eq!(hmac.finalize()?.to_vec(), expected);
+
+        // Empty updates do not change the tag.
+        let mut hmac = HmacSha256::new(key)?;
+        hmac.update(&[])?;
+        hmac.update(msg)?;
+        hmac.update(&[])?;
+        assert_eq!(hmac.finalize()?.to_vec(), expected);
+
+        assert_eq!(HmacSha256::new(key)?.finalize()?.to_vec(), hmac_sha256(key, &[])?);
+        assert_ne!(HmacSha256::new(b"other key")?.finalize()?.to_vec(), hmac_sha256(key, &[])?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sha256() {
+        const EMPTY: [u8; 32] = [
+            0xe3, 0xb0, 0xc4, 0x42, 0x98, 0xfc, 0x1c, 0x14, 0x9a, 0xfb, 0xf4, 0xc8, 0x99, 0x6f,
+            0xb9, 0x24, 0x27, 0xae, 0x41, 0xe4, 0x64, 0x9b, 0x93, 0x4c, 0xa4, 0x95, 0x99, 0x1b,
+            0x78, 0x52, 0xb8, 0x55,
+        ];
+        const ABC: [u8; 32] = [
+            0xba, 0x78, 0x16, 0xbf, 0x8f, 0x01, 0xcf, 0xea, 0x41, 0x41, 0x40, 0xde, 0x5d, 0xae,
+            0x22, 0x23, 0xb0, 0x03, 0x61, 0xa3, 0x96, 0x17, 0x7a, 0x9c, 0xb4, 0x10, 0xff, 0x61,
+            0xf2, 0x00, 0x15, 0xad,
+        ];
+        assert_eq!(sha256(b""), EMPTY);
+        assert_eq!(sha256(b"abc"), ABC);
+    }
+
+    #[test]
+    fn test_sha512() {
+        const EMPTY: [u8; 64] = [
+            0xcf, 0x83, 0xe1, 0x35, 0x7e, 0xef, 0xb8, 0xbd, 0xf1, 0x54, 0x28, 0x50, 0xd6, 0x6d,
+            0x80, 0x07, 0xd6, 0x20, 0xe4, 0x05, 0x0b, 0x57, 0x15, 0xdc, 0x83, 0xf4, 0xa9, 0x21,
+            0xd3, 0x6c, 0xe9, 0xce, 0x47, 0xd0, 0xd1, 0x3c, 0x5d, 0x85, 0xf2, 0xb0, 0xff, 0x83,
+            0x18, 0xd2, 0x87, 0x7e, 0xec, 0x2f, 0x63, 0xb9, 0x31, 0xbd, 0x47, 0x41, 0x7a, 0x81,
+            0xa5, 0x38, 0x32, 0x7a, 0xf9, 0x27, 0xda, 0x3e,
+        ];
+        const ABC: [u8; 64] = [
+            0xdd, 0xaf, 0x35, 0xa1, 0x93, 0x61, 0x7a, 0xba, 0xcc, 0x41, 0x73, 0x49, 0xae, 0x20,
+            0x41, 0x31, 0x12, 0xe6, 0xfa, 0x4e, 0x89, 0xa9, 0x7e, 0xa2, 0x0a, 0x9e, 0xee, 0xe6,
+            0x4b, 0x55, 0xd3, 0x9a, 0x21, 0x92, 0x99, 0x2a, 0x27, 0x4f, 0xc1, 0xa8, 0x36, 0xba,
+            0x3c, 0x23, 0xa3, 0xfe, 0xeb, 0xbd, 0x45, 0x4d, 0x44, 0x23, 0x64, 0x3c, 0xe8, 0x0e,
+            0x2a, 0x9a, 0xc9, 0x4f, 0xa5, 0x4c, 0xa4, 0x9f,
+        ];
+        assert_eq!(sha512(b""), EMPTY);
+        assert_eq!(sha512(b"abc"), ABC);
+    }
+
+    #[test]
+    fn test_library_version() {
+        let version = library_version();
+        let fips = if fips_mode() { "on" } else { "off" };
+        assert!(version.ends_with(&format!(" (FIPS mode: {})", fips)), "{}", version);
+        assert!(!version.starts_with(" (FIPS"), "{}", version);
+    }
+
+    #[test]
+    fn test_ecdsa() -> Result<(), Error> {
+        let key = ec_key_generate_key()?;
+        let public = ec_key_get0_public_key(&key);
+        let digest = [0x5a; 32];
+
+        let sig = ecdsa_sign(&key, &digest)?;
+        assert!(ecdsa_verify(public.get_point(), &digest, &sig)?);
+
+        // The signature survives a round trip of the public key through its encoding.
+        let public = ec_point_oct_to_point(&ec_point_point_to_oct(public.get_point())?)?;
+        assert!(ecdsa_verify(public.get_point(), &digest, &sig)?);
+
+        assert!(!ecdsa_verify(public.get_point(), &[0xa5; 32], &sig)?);
+        let other = ec_key_generate_key()?;
+        assert!(!ecdsa_verify(ec_key_get0_public_key(&other).get_point(), &digest, &sig)?);
+        let mut bad_sig = sig.clone();
+        let last = bad_sig.len() - 1;
+        bad_sig[last] ^= 1;
+        assert!(!ecdsa_verify(public.get_point(), &digest, &bad_sig)?);
+        assert!(!ecdsa_verify(public.get_point(), &digest, &[])?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ecdsa_for_curve() -> Result<(), Error> {
+        let digest = [0x5a; 32];
+        for curve in [EcCurve::P256, EcCurve::P384, EcCurve::P521] {
+            let key = ec_key_generate_key_for_curve(curve)?;
+            let sig = ecdsa_sign(&key, &digest)?;
+            let public = ec_key_get0_public_key(&key);
+            assert!(ecdsa_verify_for_curve(curve, public.get_point(), &digest, &sig)?);
+
+            let encoded = ec_point_point_to_oct_for_curve(
+                curve,
+                public.get_point(),
+                PointConversionForm::Compressed,
+            )?;
+            let public = ec_point_oct_to_point_for_curve(curve, &encoded)?;
+            assert!(ecdsa_verify_for_curve(curve, public.get_point(), &digest, &sig)?);
+            assert!(!ecdsa_verify_for_curve(curve, public.get_point(), &[0xa5; 32], &sig)?);
+        }
+
+        // A point on one curve is not a public key on another.
+        let key = ec_key_generate_key_for_curve(EcCurve::P256)?;
+        let sig = ecdsa_sign(&key, &digest)?;
+        let public = ec_key_get0_public_key(&key);
+        assert!(ecdsa_verify_for_curve(EcCurve::P384, public.get_point(), &digest, &sig).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_secret_outputs_are_zvec() -> Result<(), Error> {
+        // These bindings only compile if the functions keep their return types.
+        let key: ZVec = generate_aes256_key()?;
+        let (cipher_text, iv, tag): (Vec<u8>, Vec<u8>, Vec<u8>) = aes_gcm_encrypt(b"msg", &key)?;
+        let _plain_text: ZVec = aes_gcm_decrypt(&cipher_text, &iv, &tag, &key)?;
+        let _mac: Vec<u8> = hmac_sha256(&key, b"msg")?;
+        let _salt: Vec<u8> = generate_salt()?;
+        let prk: ZVec = hkdf_extract(&key, b"salt")?;
+        let _okm: ZVec = hkdf_expand(AES_256_KEY_LENGTH, &prk, b"info")?;
+        let _derived: ZVec = Password::from(&b"password"[..]).derive_key_hkdf(b"salt", 32)?;
+
+        let priv0 = ec_key_generate_key()?;
+        let priv1 = ec_key_generate_key()?;
+        let _marshalled: ZVec = ec_key_marshal_private_key(&priv0)?;
+        let pub1: Vec<u8> = ec_point_point_to_oct(ec_key_get0_public_key(&priv1).get_point())?;
+        let _shared: ZVec = ecdh_compute_key(ec_point_oct_to_point(&pub1)?.get_point(), &priv0)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_ec_key_from_scalar() -> Result<(), Error> {
+        let key = ec_key_generate_key()?;
+        let marshalled = ec_key_marshal_private_key(&key)?;
+        // ECPrivateKey ::= SEQUENCE { version INTEGER, privateKey OCTET STRING (66 bytes) }
+        let scalar = &marshalled[7..];
+        assert_eq!(scalar.len(), 66);
+
+        let restored = ec_key_from_scalar(EcCurve::P521, scalar)?;
+        assert_eq!(ec_key_marshal_private_key(&restored)?, marshalled);
+        assert_eq!(
+            ec_point_point_to_oct(ec_key_get0_public_key(&restored).get_point())?,
+            ec_point_point_to_oct(ec_key_get0_public_key(&key).get_point())?
+        );
+
+        assert!(ec_key_from_scalar(EcCurve::P256, &[7; 32]).is_ok());
+        assert!(ec_key_from_scalar(EcCurve::P384, &[7; 48]).is_ok());
+        // Zero and scalars not below the order of the curve are rejected.
+        assert_eq!(
+            ec_key_from_scalar(EcCurve::P256, &[0; 32]).err(),
+            Some(Error::ECKEYFromScalarFailed)
+        );
+        assert_eq!(
+            ec_key_from_scalar(EcCurve::P256, &[0xff; 32]).err(),
+            Some(Error::ECKEYFromScalarFailed)
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_hmac_sha256() {
         let key = b"This is the key";
@@ -604,4 +2405,181 @@ mod tests {
         assert_eq!(tag2.len(), HMAC_SHA256_LEN);
         assert_ne!(tag1a, tag2);
     }
+
+    // Self-signed certificate with an attestation extension whose challenge is "challenge".
+    const ATTESTATION_CERT: &[u8] = &[
+        0x30, 0x82, 0x01, 0x1d, 0x30, 0x81, 0xd0, 0xa0, 0x03, 0x02, 0x01, 0x02, 0x02, 0x01, 0x01,
+        0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x30, 0x1f, 0x31, 0x1d, 0x30, 0x1b, 0x06, 0x03,
+        0x55, 0x04, 0x03, 0x0c, 0x14, 0x41, 0x6e, 0x64, 0x72, 0x6f, 0x69, 0x64, 0x20, 0x4b, 0x65,
+        0x79, 0x73, 0x74, 0x6f, 0x72, 0x65, 0x20, 0x4b, 0x65, 0x79, 0x30, 0x1e, 0x17, 0x0d, 0x32,
+        0x36, 0x31, 0x30, 0x31, 0x35, 0x30, 0x36, 0x30, 0x33, 0x32, 0x36, 0x5a, 0x17, 0x0d, 0x33,
+        0x36, 0x31, 0x30, 0x31, 0x32, 0x30, 0x36, 0x30, 0x33, 0x32, 0x36, 0x5a, 0x30, 0x1f, 0x31,
+        0x1d, 0x30, 0x1b, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0c, 0x14, 0x41, 0x6e, 0x64, 0x72, 0x6f,
+        0x69, 0x64, 0x20, 0x4b, 0x65, 0x79, 0x73, 0x74, 0x6f, 0x72, 0x65, 0x20, 0x4b, 0x65, 0x79,
+        0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00, 0x60, 0xab, 0x61,
+        0x48, 0x7e, 0x7c, 0x88, 0xaa, 0x69, 0xde, 0x29, 0x97, 0x48, 0x2e, 0x3a, 0x4f, 0x28, 0x93,
+        0x07, 0x19, 0x51, 0xfb, 0xce, 0x95, 0x74, 0x75, 0x94, 0x37, 0x5b, 0x7c, 0x48, 0xe4, 0xa3,
+        0x31, 0x30, 0x2f, 0x30, 0x2d, 0x06, 0x0a, 0x2b, 0x06, 0x01, 0x04, 0x01, 0xd6, 0x79, 0x02,
+        0x01, 0x11, 0x04, 0x1f, 0x30, 0x1d, 0x02, 0x01, 0x64, 0x0a, 0x01, 0x01, 0x02, 0x01, 0x64,
+        0x0a, 0x01, 0x01, 0x04, 0x09, 0x63, 0x68, 0x61, 0x6c, 0x6c, 0x65, 0x6e, 0x67, 0x65, 0x04,
+        0x00, 0x30, 0x00, 0x30, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x41, 0x00,
+        0xa4, 0x91, 0x8d, 0x95, 0x0e, 0xcf, 0xb5, 0xa4, 0xb6, 0x5d, 0x86, 0x31, 0xee, 0x59, 0xd2,
+        0x6b, 0x41, 0x4e, 0xe5, 0xb1, 0xe8, 0x7b, 0xcf, 0x06, 0x4c, 0x68, 0xcf, 0x03, 0x14, 0xc8,
+        0x0e, 0x26, 0xe1, 0x6c, 0x7d, 0xe0, 0xd3, 0xca, 0xdf, 0xb6, 0xe9, 0xe2, 0xf5, 0x94, 0x13,
+        0x41, 0x2e, 0x02, 0x2c, 0x69, 0x8b, 0x2d, 0x62, 0x87, 0xd0, 0x46, 0x30, 0x5f, 0xf2, 0x8b,
+        0x2a, 0x0c, 0xc9, 0x0d,
+    ];
+
+    // The same certificate without any extensions.
+    const PLAIN_CERT: &[u8] = &[
+        0x30, 0x81, 0xea, 0x30, 0x81, 0x9d, 0xa0, 0x03, 0x02, 0x01, 0x02, 0x02, 0x01, 0x02, 0x30,
+        0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x30, 0x1f, 0x31, 0x1d, 0x30, 0x1b, 0x06, 0x03, 0x55,
+        0x04, 0x03, 0x0c, 0x14, 0x41, 0x6e, 0x64, 0x72, 0x6f, 0x69, 0x64, 0x20, 0x4b, 0x65, 0x79,
+        0x73, 0x74, 0x6f, 0x72, 0x65, 0x20, 0x4b, 0x65, 0x79, 0x30, 0x1e, 0x17, 0x0d, 0x32, 0x36,
+        0x31, 0x30, 0x31, 0x35, 0x30, 0x36, 0x30, 0x33, 0x32, 0x36, 0x5a, 0x17, 0x0d, 0x33, 0x36,
+        0x31, 0x30, 0x31, 0x32, 0x30, 0x36, 0x30, 0x33, 0x32, 0x36, 0x5a, 0x30, 0x1f, 0x31, 0x1d,
+        0x30, 0x1b, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0c, 0x14, 0x41, 0x6e, 0x64, 0x72, 0x6f, 0x69,
+        0x64, 0x20, 0x4b, 0x65, 0x79, 0x73, 0x74, 0x6f, 0x72, 0x65, 0x20, 0x4b, 0x65, 0x79, 0x30,
+        0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00, 0x60, 0xab, 0x61, 0x48,
+        0x7e, 0x7c, 0x88, 0xaa, 0x69, 0xde, 0x29, 0x97, 0x48, 0x2e, 0x3a, 0x4f, 0x28, 0x93, 0x07,
+        0x19, 0x51, 0xfb, 0xce, 0x95, 0x74, 0x75, 0x94, 0x37, 0x5b, 0x7c, 0x48, 0xe4, 0x30, 0x05,
+        0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x41, 0x00, 0x44, 0xd4, 0xa8, 0x2c, 0x74, 0x3b, 0xe6,
+        0x53, 0xaa, 0x30, 0x2f, 0x93, 0x56, 0x16, 0xca, 0x7e, 0xb5, 0x51, 0x9b, 0x08, 0xe1, 0x15,
+        0x93, 0x4b, 0x07, 0xf5, 0x69, 0x89, 0x1f, 0x66, 0x3c, 0xd3, 0x92, 0x07, 0x39, 0x11, 0xbf,
+        0x5d, 0x82, 0x63, 0x15, 0x64, 0xbc, 0xf2, 0x9e, 0x99, 0xdd, 0x46, 0x5a, 0xd1, 0xe9, 0x85,
+        0x09, 0xa1, 0xf4, 0xa5, 0xe5, 0x55, 0xbf, 0x5d, 0x54, 0x7b, 0xda, 0x03,
+    ];
+
+    #[test]
+    fn test_is_self_signed_certificate() {
+        assert_eq!(is_self_signed_certificate(ATTESTATION_CERT), Ok(true));
+        assert_eq!(is_self_signed_certificate(PLAIN_CERT), Ok(true));
+        assert_eq!(
+            is_self_signed_certificate(&ATTESTATION_CERT[..100]),
+            Err(Error::CertificateParseFailed)
+        );
+        // Trailing data, e.g. a concatenated chain, is not a single certificate.
+        let concatenated = [ATTESTATION_CERT, PLAIN_CERT].concat();
+        assert_eq!(is_self_signed_certificate(&concatenated), Err(Error::CertificateParseFailed));
+    }
+
+    #[test]
+    fn test_parse_certificate_fields() {
+        // The name "CN=Android Keystore Key" as encoded in PLAIN_CERT.
+        let name = &PLAIN_CERT[21..54];
+        assert_eq!(
+            parse_certificate_fields(PLAIN_CERT),
+            Ok(CertFields {
+                subject: name.to_vec(),
+                issuer: name.to_vec(),
+                serial: vec![2],
+                // 2026-10-15T06:03:26Z
+                not_before_ms: 1792044206000,
+                // 2036-10-12T06:03:26Z
+                not_after_ms: 2107404206000,
+            })
+        );
+        assert_eq!(
+            parse_subject_from_certificate(PLAIN_CERT).unwrap(),
+            parse_certificate_fields(PLAIN_CERT).unwrap().subject
+        );
+        assert!(parse_certificate_fields(&PLAIN_CERT[..100]).is_err());
+    }
+
+    // A chain of Ed25519 certificates: "CN=Leaf", issued by "CN=Intermediate", issued by the
+    // self-signed "CN=Root".
+    const CHAIN_ROOT_CERT: &[u8] = &[
+        0x30, 0x81, 0xc9, 0x30, 0x7d, 0xa0, 0x03, 0x02, 0x01, 0x02, 0x02, 0x01, 0x01, 0x30, 0x05,
+        0x06, 0x03, 0x2b, 0x65, 0x70, 0x30, 0x0f, 0x31, 0x0d, 0x30, 0x0b, 0x06, 0x03, 0x55, 0x04,
+        0x03, 0x0c, 0x04, 0x52, 0x6f, 0x6f, 0x74, 0x30, 0x1e, 0x17, 0x0d, 0x32, 0x36, 0x31, 0x30,
+        0x31, 0x35, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x5a, 0x17, 0x0d, 0x33, 0x36, 0x31, 0x30,
+        0x31, 0x32, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x5a, 0x30, 0x0f, 0x31, 0x0d, 0x30, 0x0b,
+        0x06, 0x03, 0x55, 0x04, 0x03, 0x0c, 0x04, 0x52, 0x6f, 0x6f, 0x74, 0x30, 0x2a, 0x30, 0x05,
+        0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00, 0xc2, 0x5b, 0xc6, 0x29, 0x06, 0x68, 0xb6,
+        0x3d, 0x84, 0xbc, 0x1f, 0xed, 0x7e, 0x0e, 0x15, 0x1a, 0xd6, 0x60, 0xe5, 0x71, 0x34, 0xfd,
+        0x99, 0xe4, 0xaf, 0xb8, 0xc3, 0x3b, 0xc0, 0xf0, 0xc7, 0x4b, 0x30, 0x05, 0x06, 0x03, 0x2b,
+        0x65, 0x70, 0x03, 0x41, 0x00, 0x7d, 0x3e, 0x9c, 0x74, 0xf0, 0x4a, 0xd6, 0x38, 0xb5, 0x59,
+        0x89, 0x5c, 0x8c, 0x8c, 0x03, 0x1c, 0x09, 0x2f, 0x7f, 0x1c, 0x79, 0x61, 0x37, 0x0f, 0x98,
+        0x0b, 0xfb, 0x86, 0x79, 0xc3, 0xd3, 0x69, 0x04, 0x92, 0xb7, 0xc9, 0xe5, 0xc6, 0x97, 0xa0,
+        0x0a, 0x46, 0xc7, 0x3e, 0x4d, 0x9b, 0x38, 0x6f, 0xd6, 0x7a, 0x52, 0xa0, 0x31, 0xf7, 0x37,
+        0x33, 0xe5, 0x6b, 0x17, 0xe9, 0x75, 0x48, 0x7c, 0x02,
+    ];
+
+    const CHAIN_INTERMEDIATE_CERT: &[u8] = &[
+        0x30, 0x81, 0xd2, 0x30, 0x81, 0x85, 0xa0, 0x03, 0x02, 0x01, 0x02, 0x02, 0x01, 0x02, 0x30,
+        0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x30, 0x0f, 0x31, 0x0d, 0x30, 0x0b, 0x06, 0x03, 0x55,
+        0x04, 0x03, 0x0c, 0x04, 0x52, 0x6f, 0x6f, 0x74, 0x30, 0x1e, 0x17, 0x0d, 0x32, 0x36, 0x31,
+        0x30, 0x31, 0x35, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x5a, 0x17, 0x0d, 0x33, 0x36, 0x31,
+        0x30, 0x31, 0x32, 0x30, 0x30, 0x30, 0x30, 0x30, 0x30, 0x5a, 0x30, 0x17, 0x31, 0x15, 0x30,
+        0x13, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0c, 0x0c, 0x49, 0x6e, 0x74, 0x65, 0x72, 0x6d, 0x65,
+        0x64, 0x69, 0x61, 0x74, 0x65, 0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03,
+        0x21, 0x00, 0x1c, 0xbc, 0x65, 0xf2, 0x0a, 0x36, 0x5f, 0x56, 0x38, 0x0a, 0xb4, 0x60, 0xac,
+        0x08, 0xd4, 0x71, 0xac, 0xd8, 0x39, 0xef, 0x16, 0x9c, 0x99, 0xa9, 0x3b, 0x65, 0x61, 0xa8,
+        0x06, 0xce, 0x24, 0xaf, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x41, 0x00, 0xad,
+        0x74, 0x7e, 0xc8, 0xb0, 0xe0, 0x0b, 0x51, 0x55, 0xe1, 0x05, 0x80, 0x35, 0xa0, 0xee, 0xf8,
+        0xba, 0x68, 0x08, 0x59, 0x67, 0x10, 0x0d, 0xda, 0xc4, 0x16, 0xf5, 0xef, 0x4e, 0xb2, 0x27,
+        0x0b, 0x9a, 0xc9, 0x22, 0x2f, 0xd1, 0xc3, 0xa0, 0x62, 0x4a, 0xd6, 0x08, 0x99, 0x14, 0x98,
+        0x3b, 0x8f, 0x2b, 0xbf, 0xee, 0xc6, 0xd5, 0x21, 0xdf, 0x32, 0x23, 0x81, 0x9d, 0x7b, 0xb9,
+        0x1a, 0xfa, 0x00,
+    ];
+
+    const CHAIN_LEAF_CERT: &[u8] = &[
+        0x30, 0x81, 0xd2, 0x30, 0x81, 0x85, 0xa0, 0x03, 0x02, 0x01, 0x02, 0x02, 0x01, 0x03, 0x30,
+        0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x30, 0x17, 0x31, 0x15, 0x30, 0x13, 0x06, 0x03, 0x55,
+        0x04, 0x03, 0x0c, 0x0c, 0x49, 0x6e, 0x74, 0x65, 0x72, 0x6d, 0x65, 0x64, 0x69, 0x61, 0x74,
+        0x65, 0x30, 0x1e, 0x17, 0x0d, 0x32, 0x36, 0x31, 0x30, 0x31, 0x35, 0x30, 0x30, 0x30, 0x30,
+        0x30, 0x30, 0x5a, 0x17, 0x0d, 0x33, 0x36, 0x31, 0x30, 0x31, 0x32, 0x30, 0x30, 0x30, 0x30,
+        0x30, 0x30, 0x5a, 0x30, 0x0f, 0x31, 0x0d, 0x30, 0x0b, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0c,
+        0x04, 0x4c, 0x65, 0x61, 0x66, 0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03,
+        0x21, 0x00, 0x84, 0x94, 0x0f, 0x4f, 0x4c, 0x35, 0xc7, 0xea, 0x48, 0x6d, 0xe3, 0xd3, 0xef,
+        0xba, 0xae, 0x72, 0x8e, 0x7a, 0x45, 0x4d, 0x40, 0xe7, 0x90, 0x7c, 0xab, 0xc4, 0xf0, 0x73,
+        0xfd, 0xd1, 0xed, 0xdf, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x41, 0x00, 0x56,
+        0x2c, 0x7c, 0x36, 0x76, 0x98, 0xa2, 0x13, 0x76, 0x4b, 0x9b, 0xa2, 0x1c, 0xb1, 0x6a, 0xda,
+        0xbb, 0x9a, 0x27, 0xc6, 0xf6, 0xcd, 0x25, 0x47, 0x30, 0xf0, 0x6e, 0x8c, 0xbe, 0x87, 0x1f,
+        0x18, 0xca, 0x4e, 0x0a, 0x0c, 0x2a, 0x74, 0x39, 0xb8, 0xa9, 0x58, 0x92, 0xbd, 0x3e, 0x97,
+        0x37, 0x72, 0x5a, 0xa5, 0x63, 0x61, 0x8a, 0x26, 0x1e, 0x7d, 0xeb, 0xd9, 0xba, 0x25, 0x4a,
+        0xbe, 0x34, 0x04,
+    ];
+
+    #[test]
+    fn test_verify_cert_chain() {
+        assert_eq!(
+            verify_cert_chain(&[CHAIN_LEAF_CERT, CHAIN_INTERMEDIATE_CERT, CHAIN_ROOT_CERT]),
+            Ok(())
+        );
+        assert_eq!(verify_cert_chain(&[CHAIN_ROOT_CERT]), Ok(()));
+
+        // Tampering with the signature of the intermediate breaks its link to the root, but not
+        // the link of the leaf to the intermediate.
+        let mut tampered = CHAIN_INTERMEDIATE_CERT.to_vec();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert_eq!(
+            verify_cert_chain(&[CHAIN_LEAF_CERT, &tampered, CHAIN_ROOT_CERT]),
+            Err(Error::CertChainVerificationFailed(1))
+        );
+
+        // Out of order chains and unparseable certificates are rejected as well.
+        assert_eq!(
+            verify_cert_chain(&[CHAIN_LEAF_CERT, CHAIN_ROOT_CERT]),
+            Err(Error::CertChainVerificationFailed(0))
+        );
+        assert_eq!(
+            verify_cert_chain(&[CHAIN_LEAF_CERT, &CHAIN_INTERMEDIATE_CERT[..50]]),
+            Err(Error::CertChainVerificationFailed(1))
+        );
+        assert_eq!(verify_cert_chain(&[]), Err(Error::CertChainVerificationFailed(0)));
+    }
+
+    #[test]
+    fn test_extract_attestation_challenge() {
+        assert_eq!(extract_attestation_challenge(ATTESTATION_CERT), Ok(b"challenge".to_vec()));
+        assert_eq!(
+            extract_attestation_challenge(PLAIN_CERT),
+            Err(Error::AttestationExtensionMissing)
+        );
+        assert_eq!(
+            extract_attestation_challenge(&ATTESTATION_CERT[..100]),
+            Err(Error::ExtractAttestationChallengeFailed)
+        );
+    }
 }
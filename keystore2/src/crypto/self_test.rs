@@ -0,0 +1,199 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module implements known answer tests for the crypto primitives used by Keystore 2.0.
+//! They are meant to detect a broken or miscompiled crypto library before it is used on real
+//! keys.
+
+use crate::{
+    aes_gcm_decrypt, ec_key_generate_key, ec_key_get0_public_key, ecdh_compute_key,
+    generate_random_data, hkdf_expand, hkdf_extract, hmac_sha256, Error, AES_256_KEY_LENGTH,
+    GCM_IV_LENGTH, TAG_LENGTH,
+};
+use keystore2_crypto_bindgen::AES_gcm_encrypt;
+
+/// Runs all known answer tests and fails with the first error encountered.
+pub fn self_test() -> Result<(), Error> {
+    aes_gcm_kat()?;
+    hmac_sha256_kat()?;
+    hkdf_kat()?;
+    ecdh_round_trip_test()
+}
+
+/// AES-256-GCM known answer test (McGrew & Viega, test case 14).
+pub fn aes_gcm_kat() -> Result<(), Error> {
+    const KEY: [u8; AES_256_KEY_LENGTH] = [0; AES_256_KEY_LENGTH];
+    const IV: [u8; GCM_IV_LENGTH] = [0; GCM_IV_LENGTH];
+    const PLAINTEXT: [u8; 16] = [0; 16];
+    const CIPHERTEXT: [u8; 16] = [
+        0xce, 0xa7, 0x40, 0x3d, 0x4d, 0x60, 0x6b, 0x6e, 0x07, 0x4e, 0xc5, 0xd3, 0xba, 0xf3, 0x9d,
+        0x18,
+    ];
+    const TAG: [u8; TAG_LENGTH] = [
+        0xd0, 0xd1, 0xc8, 0xa7, 0x99, 0x99, 0x6b, 0xf0, 0x26, 0x5b, 0x98, 0xb5, 0xd4, 0x8a, 0xb9,
+        0x19,
+    ];
+
+    let mut ciphertext = [0u8; PLAINTEXT.len()];
+    let mut tag = [0u8; TAG_LENGTH];
+    // Safety: The input and output buffers have the same length, the key has the given
+    // length, the IV is GCM_IV_LENGTH bytes, and the tag buffer is TAG_LENGTH bytes long.
+    if !unsafe {
+        AES_gcm_encrypt(
+            PLAINTEXT.as_ptr(),
+            ciphertext.as_mut_ptr(),
+            PLAINTEXT.len(),
+            KEY.as_ptr(),
+            KEY.len(),
+            IV.as_ptr(),
+            tag.as_mut_ptr(),
+        )
+    } {
+        return Err(Error::SelfTestFailed("AES-GCM encrypt"));
+    }
+    if ciphertext != CIPHERTEXT || tag != TAG {
+        return Err(Error::SelfTestFailed("AES-GCM encrypt"));
+    }
+
+    let plaintext = aes_gcm_decrypt(&CIPHERTEXT, &IV, &TAG, &KEY)
+        .map_err(|_| Error::SelfTestFailed("AES-GCM decrypt"))?;
+    if plaintext[..] != PLAINTEXT {
+        return Err(Error::SelfTestFailed("AES-GCM decrypt"));
+    }
+    Ok(())
+}
+
+/// HMAC-SHA256 known answer test (RFC 4231, test case 2).
+pub fn hmac_sha256_kat() -> Result<(), Error> {
+    const EXPECTED: [u8; 32] = [
+        0x5b, 0xdc, 0xc1, 0x46, 0xbf, 0x60, 0x75, 0x4e, 0x6a, 0x04, 0x24, 0x26, 0x08, 0x95, 0x75,
+        0xc7, 0x5a, 0x00, 0x3f, 0x08, 0x9d, 0x27, 0x39, 0x83, 0x9d, 0xec, 0x58, 0xb9, 0x64, 0xec,
+        0x38, 0x43,
+    ];
+    let tag = hmac_sha256(b"Jefe", b"what do ya want for nothing?")
+        .map_err(|_| Error::SelfTestFailed("HMAC-SHA256"))?;
+    if tag != EXPECTED {
+        return Err(Error::SelfTestFailed("HMAC-SHA256"));
+    }
+    Ok(())
+}
+
+/// HKDF-SHA256 known answer test (RFC 5869, test case 1).
+pub fn hkdf_kat() -> Result<(), Error> {
+    const IKM: [u8; 22] = [0x0b; 22];
+    const SALT: [u8; 13] =
+        [0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c];
+    const INFO: [u8; 10] = [0xf0, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8, 0xf9];
+    const PRK: [u8; 32] = [
+        0x07, 0x77, 0x09, 0x36, 0x2c, 0x2e, 0x32, 0xdf, 0x0d, 0xdc, 0x3f, 0x0d, 0xc4, 0x7b, 0xba,
+        0x63, 0x90, 0xb6, 0xc7, 0x3b, 0xb5, 0x0f, 0x9c, 0x31, 0x22, 0xec, 0x84, 0x4a, 0xd7, 0xc2,
+        0xb3, 0xe5,
+    ];
+    const OKM: [u8; 42] = [
+        0x3c, 0xb2, 0x5f, 0x25, 0xfa, 0xac, 0xd5, 0x7a, 0x90, 0x43, 0x4f, 0x64, 0xd0, 0x36, 0x2f,
+        0x2a, 0x2d, 0x2d, 0x0a, 0x90, 0xcf, 0x1a, 0x5a, 0x4c, 0x5d, 0xb0, 0x2d, 0x56, 0xec, 0xc4,
+        0xc5, 0xbf, 0x34, 0x00, 0x72, 0x08, 0xd5, 0xb8, 0x87, 0x18, 0x58, 0x65,
+    ];
+    let prk = hkdf_extract(&IKM, &SALT).map_err(|_| Error::SelfTestFailed("HKDF extract"))?;
+    if prk[..] != PRK {
+        return Err(Error::SelfTestFailed("HKDF extract"));
+    }
+    let okm =
+        hkdf_expand(OKM.len(), &prk, &INFO).map_err(|_| Error::SelfTestFailed("HKDF expand"))?;
+    if okm[..] != OKM {
+        return Err(Error::SelfTestFailed("HKDF expand"));
+    }
+    Ok(())
+}
+
+/// Generates two EC keys and checks that ECDH yields the same shared secret on both sides.
+pub fn ecdh_round_trip_test() -> Result<(), Error> {
+    let fail = |_| Error::SelfTestFailed("ECDH");
+    let priv0 = ec_key_generate_key().map_err(fail)?;
+    let priv1 = ec_key_generate_key().map_err(fail)?;
+    let pub0 = ec_key_get0_public_key(&priv0);
+    let pub1 = ec_key_get0_public_key(&priv1);
+    let left = ecdh_compute_key(pub0.get_point(), &priv1).map_err(fail)?;
+    let right = ecdh_compute_key(pub1.get_point(), &priv0).map_err(fail)?;
+    if left[..] != right[..] {
+        return Err(Error::SelfTestFailed("ECDH"));
+    }
+    Ok(())
+}
+
+/// Number of bytes drawn from the RNG by `rng_health_check`. The statistical tests below are the
+/// ones of FIPS 140-2, section 4.9.1, which are defined on a sample of 20000 bits.
+pub const RNG_SAMPLE_LENGTH: usize = 20000 / 8;
+
+/// Draws a sample from BoringSSL's RNG and checks it with `check_randomness`.
+pub fn rng_health_check() -> Result<(), Error> {
+    rng_health_check_with(generate_random_data)
+}
+
+/// Like `rng_health_check`, but draws the sample from `rng`, which is called with
+/// `RNG_SAMPLE_LENGTH`.
+pub fn rng_health_check_with<F>(rng: F) -> Result<(), Error>
+where
+    F: FnOnce(usize) -> Result<Vec<u8>, Error>,
+{
+    let sample = rng(RNG_SAMPLE_LENGTH)?;
+    if sample.len() != RNG_SAMPLE_LENGTH {
+        return Err(Error::SelfTestFailed("RNG sample length"));
+    }
+    check_randomness(&sample)
+}
+
+/// Runs the FIPS 140-2 monobit, runs and long run tests on `sample`, which must be
+/// `RNG_SAMPLE_LENGTH` bytes long. These only catch a catastrophically broken RNG, e.g., one
+/// that returns all zeros or is stuck on a value; passing them says little about the quality
+/// of the randomness.
+pub fn check_randomness(sample: &[u8]) -> Result<(), Error> {
+    // Inclusive bounds on the number of runs of each length, for runs of 1 to 5 and 6 or more.
+    const RUN_BOUNDS: [(u32, u32); 6] =
+        [(2315, 2685), (1114, 1386), (527, 723), (240, 384), (103, 209), (103, 209)];
+    const LONG_RUN: usize = 26;
+
+    if sample.len() != RNG_SAMPLE_LENGTH {
+        return Err(Error::SelfTestFailed("RNG sample length"));
+    }
+
+    let ones: u32 = sample.iter().map(|b| b.count_ones()).sum();
+    if !(9726..=10274).contains(&ones) {
+        return Err(Error::SelfTestFailed("RNG monobit"));
+    }
+
+    // Counts the runs of zeros in runs[0] and the runs of ones in runs[1].
+    let mut runs = [[0u32; RUN_BOUNDS.len()]; 2];
+    let mut bits = sample.iter().flat_map(|b| (0..8).rev().map(move |i| (b >> i) & 1));
+    let mut current = bits.next().unwrap_or(0);
+    let mut length = 1;
+    for bit in bits.chain(std::iter::once(2)) {
+        if bit == current {
+            length += 1;
+            continue;
+        }
+        if length >= LONG_RUN {
+            return Err(Error::SelfTestFailed("RNG long run"));
+        }
+        runs[current as usize][length.min(RUN_BOUNDS.len()) - 1] += 1;
+        current = bit;
+        length = 1;
+    }
+    for counts in runs {
+        if counts.iter().zip(RUN_BOUNDS).any(|(n, (low, high))| !(low..=high).contains(n)) {
+            return Err(Error::SelfTestFailed("RNG runs"));
+        }
+    }
+    Ok(())
+}
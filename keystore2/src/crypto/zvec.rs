@@ -23,9 +23,9 @@ use std::ptr::write_volatile;
 use std::ptr::NonNull;
 
 /// A semi fixed size u8 vector that is zeroed when dropped.  It can shrink in
-/// size but cannot grow larger than the original size (and if it shrinks it
-/// still owns the entire buffer).  Also the data is pinned in memory with
-/// mlock.
+/// size and still owns the entire buffer.  Growing it beyond the size of that
+/// buffer with `resize` or `try_extend_from_slice` moves the data to a new
+/// buffer and zeroes the old one.  Also the data is pinned in memory with mlock.
 #[derive(Default, Eq, PartialEq)]
 pub struct ZVec {
     elems: Box<[u8]>,
@@ -38,6 +38,9 @@ pub enum Error {
     /// Underlying libc error.
     #[error(transparent)]
     NixError(#[from] nix::Error),
+    /// The input of `ZVec::from_hex` has odd length or contains a non-hex digit.
+    #[error("Invalid hex encoding.")]
+    InvalidHex,
 }
 
 impl ZVec {
@@ -52,6 +55,52 @@ impl ZVec {
         Ok(Self { elems: b, len: size })
     }
 
+    /// Create a ZVec holding the concatenation of `parts`.  The result is
+    /// allocated once and the parts are copied straight into it, so the joined
+    /// secret never lives in a buffer that is not zeroed.
+    pub fn try_concat(parts: &[&[u8]]) -> Result<Self, Error> {
+        let mut result = Self::new(parts.iter().map(|part| part.len()).sum())?;
+        let mut offset = 0;
+        for part in parts {
+            result[offset..offset + part.len()].copy_from_slice(part);
+            offset += part.len();
+        }
+        Ok(result)
+    }
+
+    /// Hex encodes the contents as lower case ASCII into a new ZVec, so that secrets can be
+    /// dumped for debugging without ever being held in a buffer that is not zeroed.
+    pub fn to_hex(&self) -> Result<Self, Error> {
+        const DIGITS: &[u8; 16] = b"0123456789abcdef";
+        let mut result = Self::new(self.len() * 2)?;
+        for (i, b) in self.iter().enumerate() {
+            result[2 * i] = DIGITS[(b >> 4) as usize];
+            result[2 * i + 1] = DIGITS[(b & 0xf) as usize];
+        }
+        Ok(result)
+    }
+
+    /// Decodes hex encoded ASCII, upper or lower case, into a new ZVec. Fails with
+    /// `Error::InvalidHex` if `hex` has odd length or contains anything but hex digits.
+    pub fn from_hex(hex: &[u8]) -> Result<Self, Error> {
+        fn nibble(c: u8) -> Result<u8, Error> {
+            match c {
+                b'0'..=b'9' => Ok(c - b'0'),
+                b'a'..=b'f' => Ok(c - b'a' + 10),
+                b'A'..=b'F' => Ok(c - b'A' + 10),
+                _ => Err(Error::InvalidHex),
+            }
+        }
+        if hex.len() % 2 != 0 {
+            return Err(Error::InvalidHex);
+        }
+        let mut result = Self::new(hex.len() / 2)?;
+        for (i, pair) in hex.chunks_exact(2).enumerate() {
+            result[i] = (nibble(pair[0])? << 4) | nibble(pair[1])?;
+        }
+        Ok(result)
+    }
+
     /// Reduce the length to the given value.  Does nothing if that length is
     /// greater than the length of the vector.  Note that it still owns the
     /// original allocation even if the length is reduced.
@@ -61,6 +110,42 @@ impl ZVec {
         }
     }
 
+    /// Resizes the vector to `new_len`, setting new elements to `fill`.  If the
+    /// current buffer is too small, the data is copied to a new mlocked buffer
+    /// and the old one is zeroed before it is freed.  Elements dropped by
+    /// shrinking are zeroed right away, so they cannot reappear if the vector
+    /// grows again.
+    pub fn resize(&mut self, new_len: usize, fill: u8) -> Result<(), Error> {
+        if new_len > self.elems.len() {
+            self.reallocate(new_len)?;
+        }
+        if new_len < self.len {
+            for i in new_len..self.len {
+                // SAFETY: The pointer is valid and properly aligned because it came from a
+                // reference.
+                unsafe { write_volatile(&mut self.elems[i], 0) };
+            }
+        } else {
+            self.elems[self.len..new_len].fill(fill);
+        }
+        self.len = new_len;
+        Ok(())
+    }
+
+    /// Appends `data` to the vector, growing it as described for `resize`.  The
+    /// buffer is grown to at least twice its size, so that appending in a loop
+    /// does not copy the data, and mlock a new buffer, on every call.
+    pub fn try_extend_from_slice(&mut self, data: &[u8]) -> Result<(), Error> {
+        let old_len = self.len;
+        let new_len = old_len + data.len();
+        if new_len > self.elems.len() {
+            self.reallocate(new_len.max(self.elems.len().saturating_mul(2)))?;
+        }
+        self.resize(new_len, 0)?;
+        self[old_len..].copy_from_slice(data);
+        Ok(())
+    }
+
     /// Attempts to make a clone of the Zvec. This may fail due trying to mlock
     /// the new memory region.
     pub fn try_clone(&self) -> Result<Self, Error> {
@@ -70,12 +155,30 @@ impl ZVec {
     }
 }
 
-impl Drop for ZVec {
-    fn drop(&mut self) {
+impl ZVec {
+    /// Moves the data to a new mlocked buffer of `capacity` bytes, which must not be less than
+    /// the current length.  The old buffer is zeroed before it is freed.
+    fn reallocate(&mut self, capacity: usize) -> Result<(), Error> {
+        let mut grown = Self::new(capacity)?;
+        grown[..self.len].copy_from_slice(&self[..]);
+        grown.len = self.len;
+        // The old buffer is zeroed when `grown` is dropped.
+        std::mem::swap(self, &mut grown);
+        Ok(())
+    }
+
+    /// Zeroes the entire buffer, including any elements beyond the current length.
+    fn zeroize(&mut self) {
         for i in 0..self.elems.len() {
             // SAFETY: The pointer is valid and properly aligned because it came from a reference.
             unsafe { write_volatile(&mut self.elems[i], 0) };
         }
+    }
+}
+
+impl Drop for ZVec {
+    fn drop(&mut self) {
+        self.zeroize();
         if !self.elems.is_empty() {
             if let Err(e) =
                 // SAFETY: The address range is part of our address space, and was previously locked
@@ -141,3 +244,76 @@ impl TryFrom<Vec<u8>> for ZVec {
         Ok(Self { elems: b, len })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_try_concat() -> Result<(), Error> {
+        let mut z = ZVec::try_concat(&[b"first", b"", b"second", &[0xff]])?;
+        assert_eq!(z.len(), 12);
+        assert_eq!(&z[..], b"firstsecond\xff");
+        assert_eq!(ZVec::try_concat(&[])?.len(), 0);
+
+        // This is what drop does before the buffer is freed. It also covers elements beyond
+        // the current length.
+        z.reduce_len(5);
+        z.zeroize();
+        assert!(z.elems.iter().all(|b| *b == 0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_hex() -> Result<(), Error> {
+        let z = ZVec::try_from(&[0x00u8, 0x01, 0x7f, 0x80, 0xab, 0xff][..])?;
+        let hex = z.to_hex()?;
+        assert_eq!(&hex[..], b"00017f80abff");
+        assert_eq!(ZVec::from_hex(&hex)?, z);
+        assert_eq!(&ZVec::from_hex(b"00017F80ABFF")?[..], &z[..]);
+        assert_eq!(ZVec::new(0)?.to_hex()?.len(), 0);
+        assert_eq!(ZVec::from_hex(b"")?.len(), 0);
+
+        for invalid in [&b"abc"[..], b"0g", b"zz", b" 01", b"0x01", b"01\n"] {
+            assert_eq!(ZVec::from_hex(invalid), Err(Error::InvalidHex), "{:?}", invalid);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_zvec_resize() -> Result<(), Error> {
+        let mut v = ZVec::try_from(&[1u8, 2, 3][..])?;
+        v.resize(6, 0xaa)?;
+        assert_eq!(&v[..], &[1, 2, 3, 0xaa, 0xaa, 0xaa]);
+        v.try_extend_from_slice(&[4, 5])?;
+        assert_eq!(&v[..], &[1, 2, 3, 0xaa, 0xaa, 0xaa, 4, 5]);
+
+        // Shrinking and growing again within the buffer does not bring back stale bytes.
+        v.resize(2, 0)?;
+        assert_eq!(&v[..], &[1, 2]);
+        v.resize(5, 0)?;
+        assert_eq!(&v[..], &[1, 2, 0, 0, 0]);
+        v.reduce_len(1);
+        v.try_extend_from_slice(&[])?;
+        assert_eq!(&v[..], &[1]);
+
+        let mut empty = ZVec::new(0)?;
+        empty.try_extend_from_slice(b"secret")?;
+        assert_eq!(&empty[..], b"secret");
+
+        // Appending grows the buffer geometrically.
+        let mut z = ZVec::new(0)?;
+        let mut reallocations = 0;
+        for i in 0..1000u32 {
+            let capacity = z.elems.len();
+            z.try_extend_from_slice(&[i as u8])?;
+            if z.elems.len() != capacity {
+                assert!(z.elems.len() >= 2 * capacity);
+                reallocations += 1;
+            }
+        }
+        assert!(reallocations <= 11, "{}", reallocations);
+        assert!(z.iter().enumerate().all(|(i, b)| *b == i as u8));
+        Ok(())
+    }
+}
@@ -53,14 +53,14 @@ use crate::impl_metadata; // This is in database/utils.rs
 use crate::key_parameter::{KeyParameter, KeyParameterValue, Tag};
 use crate::ks_err;
 use crate::permission::KeyPermSet;
-use crate::utils::{get_current_time_in_milliseconds, watchdog as wd, AID_USER_OFFSET};
+use crate::utils::{get_current_time_in_milliseconds, watchdog as wd, BulkResult, AID_USER_OFFSET};
 use crate::{
     error::{Error as KsError, ErrorCode, ResponseCode},
     super_key::SuperKeyType,
 };
 use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
     HardwareAuthToken::HardwareAuthToken, HardwareAuthenticatorType::HardwareAuthenticatorType,
-    SecurityLevel::SecurityLevel,
+    KeyPurpose::KeyPurpose, SecurityLevel::SecurityLevel,
 };
 use android_security_metrics::aidl::android::security::metrics::{
     Storage::Storage as MetricsStorage, StorageStats::StorageStats,
@@ -133,6 +133,21 @@ struct KeyAccessInfo {
     vector: Option<KeyPermSet>,
 }
 
+/// Maximum number of entries kept in the access log of a key. When the log is full, the oldest
+/// entry is dropped for each new one.
+pub const MAX_KEY_ACCESS_LOG_ENTRIES: usize = 64;
+
+/// An entry of the access log of a key, see [`KeystoreDB::log_key_access`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyAccessLogEntry {
+    /// The uid that created the operation.
+    pub uid: u32,
+    /// When the operation was created.
+    pub timestamp: DateTime,
+    /// The purpose of the operation.
+    pub purpose: KeyPurpose,
+}
+
 /// If the database returns a busy error code, retry after this interval.
 const DB_BUSY_RETRY_INTERVAL: Duration = Duration::from_micros(500);
 
@@ -155,6 +170,14 @@ impl_metadata!(
         AttestationRawPubKey(Vec<u8>) with accessor attestation_raw_pub_key,
         /// SEC1 public key for ECDH encryption
         Sec1PublicKey(Vec<u8>) with accessor sec1_public_key,
+        /// The key was marked exportable with [`KeystoreDB::set_key_exportable`] and its
+        /// super-encrypted blob may be exported for backup.
+        Exportable(bool) with accessor exportable,
+        /// The key was created with `IKeystoreMaintenance::KEY_FLAG_ACCESS_LOG` and each
+        /// operation on it is recorded in its access log.
+        AccessLogged(bool) with accessor access_logged,
+        /// The flags passed to `generateKey` or `importKey` when the key was created.
+        CreationFlags(i32) with accessor creation_flags,
         //  --- ADD NEW META DATA FIELDS HERE ---
         // For backwards compatibility add new entries only to
         // end of this list and above this comment.
@@ -231,6 +254,9 @@ impl_metadata!(
         /// If the key is encrypted with a MaxBootLevel key, this is the boot level
         /// of that key
         MaxBootLevel(i32) with accessor max_boot_level,
+        /// If the blob replaced an older blob of the same key, this is why the older blob was
+        /// upgraded.
+        UpgradeReason(KeyUpgradeReason) with accessor upgrade_reason,
         //  --- ADD NEW META DATA FIELDS HERE ---
         // For backwards compatibility add new entries only to
         // end of this list and above this comment.
@@ -311,6 +337,47 @@ impl FromSql for KeyType {
     }
 }
 
+/// The trigger that made keystore replace a key blob with an upgraded one. KeyMint does not
+/// report why a blob requires an upgrade, so this records what keystore observed.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum KeyUpgradeReason {
+    /// KeyMint returned `KEY_REQUIRES_UPGRADE`, typically after an OS version or patch level
+    /// change.
+    KeyRequiresUpgrade,
+    /// The blob was created by a Keymaster device behind km_compat, which has since been
+    /// replaced by a KeyMint device.
+    KeymasterHardwareBlob,
+    /// The blob was emulated in software by km_compat on a Keymaster device, and its key
+    /// material was imported into KeyMint.
+    KeymasterSoftwareBlob,
+    /// The blob was emulated in software by km_compat.rs for an older KeyMint device, and its
+    /// key material was imported into the current KeyMint device.
+    KeyMintSoftwareBlob,
+}
+
+impl ToSql for KeyUpgradeReason {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput> {
+        Ok(ToSqlOutput::Owned(Value::Integer(match self {
+            KeyUpgradeReason::KeyRequiresUpgrade => 0,
+            KeyUpgradeReason::KeymasterHardwareBlob => 1,
+            KeyUpgradeReason::KeymasterSoftwareBlob => 2,
+            KeyUpgradeReason::KeyMintSoftwareBlob => 3,
+        })))
+    }
+}
+
+impl FromSql for KeyUpgradeReason {
+    fn column_result(value: ValueRef) -> FromSqlResult<Self> {
+        match i64::column_result(value)? {
+            0 => Ok(KeyUpgradeReason::KeyRequiresUpgrade),
+            1 => Ok(KeyUpgradeReason::KeymasterHardwareBlob),
+            2 => Ok(KeyUpgradeReason::KeymasterSoftwareBlob),
+            3 => Ok(KeyUpgradeReason::KeyMintSoftwareBlob),
+            v => Err(FromSqlError::OutOfRange(v)),
+        }
+    }
+}
+
 /// Uuid representation that can be stored in the database.
 /// Right now it can only be initialized from SecurityLevel.
 /// Once KeyMint provides a UUID type a corresponding From impl shall be added.
@@ -898,6 +965,15 @@ impl AuthTokenEntry {
     }
 }
 
+/// Snapshot of the key blobs awaiting garbage collection, see [`KeystoreDB::get_gc_backlog`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct GcBacklog {
+    /// Number of superseded or orphaned key blobs that have not been deleted yet.
+    pub pending_key_blobs: usize,
+    /// Ids of the key entries owning the pending key blobs, if requested.
+    pub key_ids: Vec<i64>,
+}
+
 /// Information about a superseded blob (a blob that is no longer the
 /// most recent blob of that type for a given key, due to upgrade or
 /// replacement).
@@ -1141,6 +1217,24 @@ impl KeystoreDB {
         )
         .context("Failed to create index keymetadata_keyentryid_index.")?;
 
+        tx.execute(
+            "CREATE TABLE IF NOT EXISTS persistent.keyaccesslog (
+                     id INTEGER PRIMARY KEY,
+                     keyentryid INTEGER,
+                     uid INTEGER,
+                     timestamp INTEGER,
+                     purpose INTEGER);",
+            [],
+        )
+        .context("Failed to initialize \"keyaccesslog\" table.")?;
+
+        tx.execute(
+            "CREATE INDEX IF NOT EXISTS persistent.keyaccesslog_keyentryid_index
+            ON keyaccesslog(keyentryid);",
+            [],
+        )
+        .context("Failed to create index keyaccesslog_keyentryid_index.")?;
+
         tx.execute(
             "CREATE TABLE IF NOT EXISTS persistent.grant (
                     id INTEGER UNIQUE,
@@ -1415,6 +1509,68 @@ impl KeystoreDB {
         .context(ks_err!())
     }
 
+    /// Reports the key blobs that the garbage collector has yet to delete, i.e., the key blobs
+    /// that `handle_next_superseded_blobs` would hand out. This does not modify the database.
+    /// The ids of the owning key entries are only collected if `with_key_ids` is true. Note that
+    /// the key entries of orphaned blobs have already been deleted.
+    pub fn get_gc_backlog(&mut self, with_key_ids: bool) -> Result<GcBacklog> {
+        let _wp = wd::watch("KeystoreDB::get_gc_backlog");
+        let use_state = keystore2_flags::use_blob_state_column();
+        let condition = if use_state {
+            "subcomponent_type = ?1 AND state != ?2"
+        } else {
+            "subcomponent_type = ?1
+            AND (
+                id NOT IN (
+                    SELECT MAX(id) FROM persistent.blobentry
+                    WHERE subcomponent_type = ?1
+                    GROUP BY keyentryid, subcomponent_type
+                )
+                OR keyentryid NOT IN (SELECT id FROM persistent.keyentry)
+            )"
+        };
+        self.with_transaction(TransactionBehavior::Deferred, |tx| {
+            let mut backlog = GcBacklog::default();
+            if with_key_ids {
+                let mut stmt = tx
+                    .prepare(&format!(
+                        "SELECT keyentryid FROM persistent.blobentry WHERE {condition};"
+                    ))
+                    .context("Trying to prepare query for pending blobs.")?;
+                let rows = if use_state {
+                    stmt.query_map(params![SubComponentType::KEY_BLOB, BlobState::Current], |row| {
+                        row.get(0)
+                    })
+                } else {
+                    stmt.query_map(params![SubComponentType::KEY_BLOB], |row| row.get(0))
+                }
+                .context("Trying to query pending blobs.")?;
+                let mut key_ids = rows
+                    .collect::<Result<Vec<i64>, rusqlite::Error>>()
+                    .context("Trying to extract pending blobs.")?;
+                backlog.pending_key_blobs = key_ids.len();
+                key_ids.sort_unstable();
+                key_ids.dedup();
+                backlog.key_ids = key_ids;
+            } else {
+                let query =
+                    format!("SELECT COUNT(id) FROM persistent.blobentry WHERE {condition};");
+                backlog.pending_key_blobs = if use_state {
+                    tx.query_row(
+                        &query,
+                        params![SubComponentType::KEY_BLOB, BlobState::Current],
+                        |row| row.get(0),
+                    )
+                } else {
+                    tx.query_row(&query, params![SubComponentType::KEY_BLOB], |row| row.get(0))
+                }
+                .context("Trying to count pending blobs.")?;
+            }
+            Ok(backlog).no_gc()
+        })
+        .context(ks_err!())
+    }
+
     /// This maintenance function should be called only once before the database is used for the
     /// first time. It restores the invariant that `KeyLifeCycle::Existing` is a transient state.
     /// The function transitions all key entries from Existing to Unreferenced unconditionally and
@@ -1890,6 +2046,8 @@ impl KeystoreDB {
     /// Store a new key in a single transaction.
     /// The function creates a new key entry, populates the blob, key parameter, and metadata
     /// fields, and rebinds the given alias to the new key.
+    /// The key blob, certificate, and certificate chain rows are written in the same transaction,
+    /// so a failure at any step rolls back all of them and never leaves a key without its cert.
     /// The boolean returned is a hint for the garbage collector. If true, a key was replaced,
     /// is now unreferenced and needs to be collected.
     #[allow(clippy::too_many_arguments)]
@@ -2428,6 +2586,8 @@ impl KeystoreDB {
             .context("Trying to delete keymetadata.")?;
         tx.execute("DELETE FROM persistent.keyparameter WHERE keyentryid = ?;", params![key_id])
             .context("Trying to delete keyparameters.")?;
+        tx.execute("DELETE FROM persistent.keyaccesslog WHERE keyentryid = ?;", params![key_id])
+            .context("Trying to delete key access log.")?;
         tx.execute("DELETE FROM persistent.grant WHERE keyentryid = ?;", params![key_id])
             .context("Trying to delete grants to other apps.")?;
         // The associated blobentry rows are not immediately deleted when the owning keyentry is
@@ -2484,6 +2644,137 @@ impl KeystoreDB {
         .context(ks_err!())
     }
 
+    /// Returns true if `new` is at least as restrictive as `old`. Only parameters that keystore
+    /// enforces from the database can be tightened this way, see
+    /// [`KeystoreDB::tighten_key_parameters`].
+    fn is_tightening(old: &KeyParameterValue, new: &KeyParameterValue) -> bool {
+        match (old, new) {
+            (KeyParameterValue::UsageCountLimit(old), KeyParameterValue::UsageCountLimit(new)) => {
+                (1..=*old).contains(new)
+            }
+            (KeyParameterValue::ActiveDateTime(old), KeyParameterValue::ActiveDateTime(new)) => {
+                new >= old
+            }
+            (
+                KeyParameterValue::OriginationExpireDateTime(old),
+                KeyParameterValue::OriginationExpireDateTime(new),
+            )
+            | (
+                KeyParameterValue::UsageExpireDateTime(old),
+                KeyParameterValue::UsageExpireDateTime(new),
+            ) => new <= old,
+            _ => false,
+        }
+    }
+
+    /// Atomically replaces parameters of an existing key with more restrictive values while
+    /// keeping the same KeyMint blob, so that tightening them does not require a new key.
+    ///
+    /// Only parameters that keystore enforces from the database qualify, i.e., parameters with
+    /// security level `KEYSTORE` or `SOFTWARE` whose tag is one of `USAGE_COUNT_LIMIT` (may only
+    /// decrease), `ACTIVE_DATETIME` (may only move later), `ORIGINATION_EXPIRE_DATETIME` or
+    /// `USAGE_EXPIRE_DATETIME` (may only move earlier). Each must replace an existing parameter
+    /// with the same tag and security level. Parameters enforced by KeyMint are bound to the key
+    /// blob; changing them requires generating a new key. Any other parameter fails the whole
+    /// update with `ResponseCode::INVALID_ARGUMENT` and leaves the key unchanged.
+    pub fn tighten_key_parameters(
+        &mut self,
+        key: &KeyDescriptor,
+        key_type: KeyType,
+        caller_uid: u32,
+        params: &[KeyParameter],
+        check_permission: impl FnOnce(&KeyDescriptor, Option<KeyPermSet>) -> Result<()>,
+    ) -> Result<()> {
+        let _wp = wd::watch("KeystoreDB::tighten_key_parameters");
+
+        self.with_transaction(Immediate("TX_tighten_key_parameters"), |tx| {
+            let access = Self::load_access_tuple(tx, key, key_type, caller_uid)
+                .context("Trying to get access tuple.")?;
+
+            // Perform access control. It is vital that we return here if the permission is denied.
+            // So do not touch that '?' at the end.
+            check_permission(&access.descriptor, access.vector)
+                .context("While checking permission.")?;
+
+            let current = Self::load_key_parameters(access.key_id, tx)
+                .context("Trying to load key parameters.")?;
+            for param in params {
+                let sec_level = *param.security_level();
+                if sec_level != SecurityLevel::KEYSTORE && sec_level != SecurityLevel::SOFTWARE {
+                    return Err(KsError::Rc(ResponseCode::INVALID_ARGUMENT)).context(format!(
+                        "{:?} is enforced by KeyMint and cannot be changed.",
+                        param.get_tag()
+                    ));
+                }
+                let tightens = current.iter().any(|c| {
+                    c.get_tag() == param.get_tag()
+                        && *c.security_level() == sec_level
+                        && Self::is_tightening(c.key_parameter_value(), param.key_parameter_value())
+                });
+                if !tightens {
+                    return Err(KsError::Rc(ResponseCode::INVALID_ARGUMENT)).context(format!(
+                        "{:?} does not tighten an existing parameter.",
+                        param.get_tag()
+                    ));
+                }
+                tx.execute(
+                    "UPDATE persistent.keyparameter SET data = ?
+                     WHERE keyentryid = ? AND tag = ? AND security_level = ?;",
+                    params![
+                        param.key_parameter_value(),
+                        access.key_id,
+                        param.get_tag().0,
+                        sec_level.0
+                    ],
+                )
+                .context("Failed to update key parameter.")?;
+            }
+            Ok(()).no_gc()
+        })
+        .context(ks_err!())
+    }
+
+    /// Marks the given key as exportable for backup, see `IKeystoreMaintenance::exportBlob`.
+    /// This is keystore controlled metadata that cannot be requested when the key is created.
+    pub fn set_key_exportable(
+        &mut self,
+        key: &KeyDescriptor,
+        key_type: KeyType,
+        caller_uid: u32,
+        check_permission: impl FnOnce(&KeyDescriptor, Option<KeyPermSet>) -> Result<()>,
+    ) -> Result<()> {
+        let _wp = wd::watch("KeystoreDB::set_key_exportable");
+
+        self.with_transaction(Immediate("TX_set_key_exportable"), |tx| {
+            let access = Self::load_access_tuple(tx, key, key_type, caller_uid)
+                .context("Trying to get access tuple.")?;
+
+            // Perform access control. It is vital that we return here if the permission is denied.
+            // So do not touch that '?' at the end.
+            check_permission(&access.descriptor, access.vector)
+                .context("While checking permission.")?;
+
+            let mut metadata = KeyMetaData::new();
+            metadata.add(KeyMetaEntry::Exportable(true));
+            metadata.store_in_db(access.key_id, tx).no_gc()
+        })
+        .context(ks_err!())
+    }
+
+    /// Unbinds each of `keys` like [`KeystoreDB::unbind_key`]. Every key is unbound in its own
+    /// transaction, so keys that were unbound successfully stay unbound even if later keys fail.
+    pub fn unbind_keys(
+        &mut self,
+        keys: &[KeyDescriptor],
+        key_type: KeyType,
+        caller_uid: u32,
+        check_permission: impl Fn(&KeyDescriptor, Option<KeyPermSet>) -> Result<()>,
+    ) -> BulkResult<KeyDescriptor, ()> {
+        BulkResult::run(keys.iter().cloned(), |key| {
+            self.unbind_key(key, key_type, caller_uid, &check_permission)
+        })
+    }
+
     fn get_key_km_uuid(tx: &Transaction, key_id: i64) -> Result<Uuid> {
         tx.query_row(
             "SELECT km_uuid FROM persistent.keyentry WHERE id = ?",
@@ -2520,6 +2811,15 @@ impl KeystoreDB {
                 params![domain.0, namespace, KeyType::Client],
             )
             .context("Trying to delete keyparameters.")?;
+            tx.execute(
+                "DELETE FROM persistent.keyaccesslog
+                WHERE keyentryid IN (
+                    SELECT id FROM persistent.keyentry
+                    WHERE domain = ? AND namespace = ? AND key_type = ?
+                );",
+                params![domain.0, namespace, KeyType::Client],
+            )
+            .context("Trying to delete key access log.")?;
             tx.execute(
                 "DELETE FROM persistent.grant
                 WHERE keyentryid IN (
@@ -2573,6 +2873,15 @@ impl KeystoreDB {
                 params![KeyLifeCycle::Unreferenced],
             )
             .context("Trying to delete keyparameters.")?;
+            tx.execute(
+                "DELETE FROM persistent.keyaccesslog
+            WHERE keyentryid IN (
+                SELECT id FROM persistent.keyentry
+                WHERE state = ?
+            );",
+                params![KeyLifeCycle::Unreferenced],
+            )
+            .context("Trying to delete key access log.")?;
             tx.execute(
                 "DELETE FROM persistent.grant
             WHERE keyentryid IN (
@@ -2656,6 +2965,48 @@ impl KeystoreDB {
         .context(ks_err!())
     }
 
+    /// Deletes all client keys that carry the `UserID` key parameter of the given user. Unlike
+    /// `unbind_keys_for_user` this also covers keys that were created on behalf of the user in
+    /// other domains, e.g., `Domain::SELINUX`. As usual, the key blobs are deleted from the
+    /// device by the garbage collector.
+    pub fn unbind_keys_with_user_id_param(&mut self, user_id: u32) -> Result<()> {
+        let _wp = wd::watch("KeystoreDB::unbind_keys_with_user_id_param");
+
+        self.with_transaction(Immediate("TX_unbind_keys_with_user_id_param"), |tx| {
+            let mut stmt = tx
+                .prepare(
+                    "SELECT DISTINCT keyentry.id FROM persistent.keyentry
+                     INNER JOIN persistent.keyparameter
+                        ON keyentry.id = keyparameter.keyentryid
+                     WHERE keyentry.key_type = ?
+                     AND keyentry.state = ?
+                     AND keyparameter.tag = ?
+                     AND keyparameter.data = ?;",
+                )
+                .context(ks_err!("Failed to prepare the query."))?;
+
+            let mut rows = stmt
+                .query(params![KeyType::Client, KeyLifeCycle::Live, Tag::USER_ID.0, user_id])
+                .context(ks_err!("Failed to query the keys of the user."))?;
+
+            let mut key_ids: Vec<i64> = Vec::new();
+            db_utils::with_rows_extract_all(&mut rows, |row| {
+                key_ids.push(row.get(0).context("Failed to read key id.")?);
+                Ok(())
+            })
+            .context(ks_err!())?;
+
+            let mut notify_gc = false;
+            for key_id in key_ids {
+                notify_gc = Self::mark_unreferenced(tx, key_id)
+                    .context(ks_err!("Failed to mark key id as unreferenced."))?
+                    || notify_gc;
+            }
+            Ok(()).do_gc(notify_gc)
+        })
+        .context(ks_err!())
+    }
+
     /// Deletes all auth-bound keys, i.e. keys that require user authentication, for the given user.
     /// This runs when the user's lock screen is being changed to Swipe or None.
     ///
@@ -2941,6 +3292,160 @@ impl KeystoreDB {
         })
     }
 
+    /// Lists all grants of the given key as (grantee uid, access vector) tuples, ordered by
+    /// grantee. The permission check gets the fully qualified key descriptor and must ensure
+    /// that the caller is allowed to manage the grants of the key.
+    pub fn list_grants(
+        &mut self,
+        key: &KeyDescriptor,
+        caller_uid: u32,
+        check_permission: impl Fn(&KeyDescriptor) -> Result<()>,
+    ) -> Result<Vec<(u32, KeyPermSet)>> {
+        let _wp = wd::watch("KeystoreDB::list_grants");
+
+        self.with_transaction(TransactionBehavior::Deferred, |tx| {
+            let access =
+                Self::load_access_tuple(tx, key, KeyType::Client, caller_uid).context(ks_err!())?;
+
+            // Perform access control. We must return here if the permission
+            // was denied. So do not touch the '?' at the end of this line.
+            check_permission(&access.descriptor).context(ks_err!("check_permission failed."))?;
+
+            let mut stmt = tx
+                .prepare(
+                    "SELECT grantee, access_vector FROM persistent.grant
+                    WHERE keyentryid = ? ORDER BY grantee;",
+                )
+                .context(ks_err!("Failed to prepare statement."))?;
+            let grants = stmt
+                .query_map(params![access.key_id], |row| {
+                    Ok((row.get(0)?, KeyPermSet::from(row.get::<_, i32>(1)?)))
+                })
+                .context(ks_err!("Failed to query grants."))?
+                .collect::<rusqlite::Result<Vec<(u32, KeyPermSet)>>>()
+                .context(ks_err!("Failed to read grants."))?;
+            Ok(grants).no_gc()
+        })
+    }
+
+    /// Appends an entry for an operation with `purpose` created by `uid` to the access log of the
+    /// key `key_id`. Only the latest [`MAX_KEY_ACCESS_LOG_ENTRIES`] entries are kept.
+    pub fn log_key_access(&mut self, key_id: i64, uid: u32, purpose: KeyPurpose) -> Result<()> {
+        let _wp = wd::watch("KeystoreDB::log_key_access");
+
+        let timestamp = DateTime::now().context(ks_err!("Trying to make access time."))?;
+        self.with_transaction(Immediate("TX_log_key_access"), |tx| {
+            tx.execute(
+                "INSERT INTO persistent.keyaccesslog (keyentryid, uid, timestamp, purpose)
+                VALUES (?, ?, ?, ?);",
+                params![key_id, uid, timestamp, purpose.0],
+            )
+            .context("Failed to insert key access log entry.")?;
+            tx.execute(
+                "DELETE FROM persistent.keyaccesslog
+                WHERE keyentryid = ? AND id NOT IN (
+                    SELECT id FROM persistent.keyaccesslog
+                    WHERE keyentryid = ? ORDER BY id DESC LIMIT ?
+                );",
+                params![key_id, key_id, MAX_KEY_ACCESS_LOG_ENTRIES as i64],
+            )
+            .context("Failed to trim key access log.")?;
+            Ok(()).no_gc()
+        })
+        .context(ks_err!())
+    }
+
+    /// Returns the access log of the given key, oldest entry first. The permission check gets
+    /// the fully qualified key descriptor and the access vector of a grant, if any.
+    pub fn get_key_access_log(
+        &mut self,
+        key: &KeyDescriptor,
+        caller_uid: u32,
+        check_permission: impl Fn(&KeyDescriptor, Option<KeyPermSet>) -> Result<()>,
+    ) -> Result<Vec<KeyAccessLogEntry>> {
+        let _wp = wd::watch("KeystoreDB::get_key_access_log");
+
+        self.with_transaction(TransactionBehavior::Deferred, |tx| {
+            let access =
+                Self::load_access_tuple(tx, key, KeyType::Client, caller_uid).context(ks_err!())?;
+
+            // Perform access control. We must return here if the permission
+            // was denied. So do not touch the '?' at the end of this line.
+            check_permission(&access.descriptor, access.vector)
+                .context(ks_err!("check_permission failed."))?;
+
+            let mut stmt = tx
+                .prepare(
+                    "SELECT uid, timestamp, purpose FROM persistent.keyaccesslog
+                    WHERE keyentryid = ? ORDER BY id;",
+                )
+                .context(ks_err!("Failed to prepare statement."))?;
+            let entries = stmt
+                .query_map(params![access.key_id], |row| {
+                    Ok(KeyAccessLogEntry {
+                        uid: row.get(0)?,
+                        timestamp: row.get(1)?,
+                        purpose: KeyPurpose(row.get(2)?),
+                    })
+                })
+                .context(ks_err!("Failed to query key access log."))?
+                .collect::<rusqlite::Result<Vec<KeyAccessLogEntry>>>()
+                .context(ks_err!("Failed to read key access log."))?;
+            Ok(entries).no_gc()
+        })
+    }
+
+    /// Returns the flags the given key was created with, or 0 if none were recorded. The
+    /// permission check is performed like in `load_key_entry`.
+    pub fn get_key_creation_flags(
+        &mut self,
+        key: &KeyDescriptor,
+        caller_uid: u32,
+        check_permission: impl Fn(&KeyDescriptor, Option<KeyPermSet>) -> Result<()>,
+    ) -> Result<i32> {
+        let _wp = wd::watch("KeystoreDB::get_key_creation_flags");
+
+        let (_, key_entry) = self
+            .load_key_entry(
+                key,
+                KeyType::Client,
+                KeyEntryLoadBits::NONE,
+                caller_uid,
+                check_permission,
+            )
+            .context(ks_err!())?;
+        Ok(key_entry.metadata().creation_flags().copied().unwrap_or(0))
+    }
+
+    /// Removes all grants of the given key and returns the number of removed grants. The
+    /// permission check is performed like in `ungrant`.
+    pub fn revoke_all_grants(
+        &mut self,
+        key: &KeyDescriptor,
+        caller_uid: u32,
+        check_permission: impl Fn(&KeyDescriptor) -> Result<()>,
+    ) -> Result<usize> {
+        let _wp = wd::watch("KeystoreDB::revoke_all_grants");
+
+        self.with_transaction(Immediate("TX_revoke_all_grants"), |tx| {
+            let access =
+                Self::load_access_tuple(tx, key, KeyType::Client, caller_uid).context(ks_err!())?;
+
+            // Perform access control. We must return here if the permission
+            // was denied. So do not touch the '?' at the end of this line.
+            check_permission(&access.descriptor).context(ks_err!("check_permission failed."))?;
+
+            let removed = tx
+                .execute(
+                    "DELETE FROM persistent.grant WHERE keyentryid = ?;",
+                    params![access.key_id],
+                )
+                .context("Failed to delete grants.")?;
+
+            Ok(removed).no_gc()
+        })
+    }
+
     // Generates a random id and passes it to the given function, which will
     // try to insert it into a database.  If that insertion fails, retry;
     // otherwise return the id.
@@ -30,6 +30,7 @@ use android_hardware_security_keymint::aidl::android::hardware::security::keymin
 use android_hardware_security_secureclock::aidl::android::hardware::security::secureclock::{
     Timestamp::Timestamp,
 };
+use android_security_maintenance::aidl::android::security::maintenance::IKeystoreMaintenance;
 use std::cell::RefCell;
 use std::collections::BTreeMap;
 use std::fmt::Write;
@@ -496,6 +497,36 @@ fn test_set_blob() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_upgrade_reason_round_trip() -> Result<()> {
+    let key_id = KEY_ID_LOCK.get(3001);
+    let mut db = new_test_db()?;
+    for reason in [
+        KeyUpgradeReason::KeyRequiresUpgrade,
+        KeyUpgradeReason::KeymasterHardwareBlob,
+        KeyUpgradeReason::KeymasterSoftwareBlob,
+        KeyUpgradeReason::KeyMintSoftwareBlob,
+    ] {
+        let mut blob_metadata = BlobMetaData::new();
+        blob_metadata.add(BlobMetaEntry::KmUuid(KEYSTORE_UUID));
+        blob_metadata.add(BlobMetaEntry::UpgradeReason(reason));
+        db.set_blob(
+            &key_id,
+            SubComponentType::KEY_BLOB,
+            Some(TEST_KEY_BLOB),
+            Some(&blob_metadata),
+        )?;
+
+        let id: i64 =
+            db.conn.query_row("SELECT MAX(id) FROM persistent.blobentry;", [], |row| row.get(0))?;
+        let loaded = db
+            .with_transaction(Immediate("TX_test"), |tx| BlobMetaData::load_from_db(id, tx).no_gc())
+            .expect("Should find blob metadata.");
+        assert_eq!(loaded.upgrade_reason(), Some(&reason));
+    }
+    Ok(())
+}
+
 static TEST_ALIAS: &str = "my super duper key";
 
 #[test]
@@ -683,6 +714,44 @@ fn test_insert_and_load_full_keyentry_domain_selinux() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_unbind_keys_reports_partial_failure() -> Result<()> {
+    let mut db = new_test_db()?;
+    make_test_key_entry(&mut db, Domain::APP, 1, "key0", None)?;
+    make_test_key_entry(&mut db, Domain::APP, 1, "key1", None)?;
+    let key = |alias: &str| KeyDescriptor {
+        domain: Domain::APP,
+        nspace: 1,
+        alias: Some(alias.to_string()),
+        blob: None,
+    };
+
+    let keys = [key("key0"), key("missing"), key("key1")];
+    let result = db.unbind_keys(&keys, KeyType::Client, 1, |k, _| {
+        if k.alias.as_deref() == Some("key1") {
+            Err(KsError::perm()).context("Permission denied")
+        } else {
+            Ok(())
+        }
+    });
+    assert!(!result.all_succeeded());
+    assert_eq!(result.successes().map(|(k, _)| k.clone()).collect::<Vec<_>>(), vec![key("key0")]);
+    let failures: Vec<(&KeyDescriptor, Option<&KsError>)> =
+        result.failures().map(|(k, e)| (k, e.root_cause().downcast_ref::<KsError>())).collect();
+    assert_eq!(
+        failures,
+        vec![
+            (&key("missing"), Some(&KsError::Rc(ResponseCode::KEY_NOT_FOUND))),
+            (&key("key1"), Some(&KsError::perm())),
+        ]
+    );
+
+    // The successful item stays unbound, the failed ones are untouched.
+    assert!(!db.key_exists(Domain::APP, 1, "key0", KeyType::Client)?);
+    assert!(db.key_exists(Domain::APP, 1, "key1", KeyType::Client)?);
+    Ok(())
+}
+
 #[test]
 fn test_insert_and_load_full_keyentry_domain_key_id() -> Result<()> {
     let mut db = new_test_db()?;
@@ -774,6 +843,41 @@ fn test_check_and_update_key_usage_count_with_exhausted_limited_use_key() -> Res
     Ok(())
 }
 
+#[test]
+fn test_list_and_revoke_all_grants() -> Result<()> {
+    const CALLER_UID: u32 = 15;
+    const PVEC1: KeyPermSet = key_perm_set![KeyPerm::Use, KeyPerm::GetInfo];
+    const PVEC2: KeyPermSet = key_perm_set![KeyPerm::Use];
+
+    let mut db = new_test_db()?;
+    make_test_key_entry(&mut db, Domain::APP, CALLER_UID as i64, "key", None)?;
+    make_test_key_entry(&mut db, Domain::APP, CALLER_UID as i64, "other", None)?;
+    let key = KeyDescriptor {
+        domain: Domain::APP,
+        nspace: CALLER_UID as i64,
+        alias: Some("key".to_string()),
+        blob: None,
+    };
+    let other_key = KeyDescriptor { alias: Some("other".to_string()), ..key.clone() };
+
+    db.grant(&key, CALLER_UID, 21, PVEC1, |_, _| Ok(()))?;
+    db.grant(&key, CALLER_UID, 12, PVEC2, |_, _| Ok(()))?;
+    db.grant(&other_key, CALLER_UID, 12, PVEC1, |_, _| Ok(()))?;
+
+    assert_eq!(db.list_grants(&key, CALLER_UID, |_| Ok(()))?, vec![(12, PVEC2), (21, PVEC1)]);
+
+    // Permission denied must not reveal or remove any grants.
+    assert!(db.list_grants(&key, CALLER_UID, |_| Err(KsError::perm().into())).is_err());
+    assert!(db.revoke_all_grants(&key, CALLER_UID, |_| Err(KsError::perm().into())).is_err());
+
+    assert_eq!(db.revoke_all_grants(&key, CALLER_UID, |_| Ok(()))?, 2);
+    assert!(db.list_grants(&key, CALLER_UID, |_| Ok(()))?.is_empty());
+
+    // Grants of other keys are left untouched.
+    assert_eq!(db.list_grants(&other_key, CALLER_UID, |_| Ok(()))?, vec![(12, PVEC1)]);
+    Ok(())
+}
+
 #[test]
 fn test_insert_and_load_full_keyentry_from_grant() -> Result<()> {
     let mut db = new_test_db()?;
@@ -2545,6 +2649,175 @@ fn blob_count_in_state(db: &mut KeystoreDB, sc_type: SubComponentType, state: Bl
     .unwrap()
 }
 
+#[test]
+fn test_gc_backlog() -> Result<()> {
+    let mut db = new_test_db()?;
+    let _key_id1 = make_test_key_entry(&mut db, Domain::APP, 1, "key1", None)?.0;
+    let key_guard2 = make_test_key_entry(&mut db, Domain::APP, 2, "key2", None)?;
+    let key_id3 = make_test_key_entry(&mut db, Domain::APP, 3, "key3", None)?.0;
+    assert_eq!(db.get_gc_backlog(true)?, GcBacklog::default());
+
+    // Supersede the key blob of key 2 twice and delete key 3.
+    db.set_blob(&key_guard2, SubComponentType::KEY_BLOB, Some(&[1, 2, 3]), None)?;
+    db.set_blob(&key_guard2, SubComponentType::KEY_BLOB, Some(&[4, 5, 6]), None)?;
+    db.with_transaction(Immediate("TX_delete_test_keys"), |tx| {
+        KeystoreDB::mark_unreferenced(tx, key_id3)?;
+        Ok(()).no_gc()
+    })?;
+
+    assert_eq!(
+        db.get_gc_backlog(true)?,
+        GcBacklog { pending_key_blobs: 3, key_ids: vec![key_guard2.id(), key_id3] }
+    );
+    assert_eq!(db.get_gc_backlog(false)?, GcBacklog { pending_key_blobs: 3, key_ids: vec![] });
+
+    // Reporting the backlog does not delete anything.
+    assert_eq!(5, blob_count(&mut db, SubComponentType::KEY_BLOB));
+    Ok(())
+}
+
+#[test]
+fn test_store_new_key_rolls_back_on_failure() -> Result<()> {
+    let mut db = new_test_db()?;
+    let key = KeyDescriptor {
+        domain: Domain::APP,
+        nspace: 1,
+        alias: Some("key".to_string()),
+        blob: None,
+    };
+    let cert_info = CertificateInfo::new(Some(vec![1, 2, 3]), Some(vec![4, 5, 6]));
+    let store = |db: &mut KeystoreDB| {
+        db.store_new_key(
+            &key,
+            KeyType::Client,
+            &[],
+            &BlobInfo::new(&[7, 8, 9], &BlobMetaData::new()),
+            &cert_info,
+            &KeyMetaData::new(),
+            &KEYSTORE_UUID,
+        )
+    };
+
+    // Force the store to fail when rebinding the alias, i.e., after the key blob and the
+    // certificates have been inserted.
+    db.conn.execute(
+        "CREATE TEMP TRIGGER fail_rebind BEFORE UPDATE ON persistent.keyentry
+             BEGIN SELECT RAISE(ABORT, 'forced failure'); END;",
+        [],
+    )?;
+    assert!(store(&mut db).is_err());
+    assert!(get_keyentry(&db)?.is_empty());
+    assert_eq!(0, blob_count(&mut db, SubComponentType::KEY_BLOB));
+    assert_eq!(0, blob_count(&mut db, SubComponentType::CERT));
+    assert_eq!(0, blob_count(&mut db, SubComponentType::CERT_CHAIN));
+
+    // Without the forced failure all rows are committed together.
+    db.conn.execute("DROP TRIGGER fail_rebind;", [])?;
+    store(&mut db)?;
+    assert_eq!(1, get_keyentry(&db)?.len());
+    assert_eq!(1, blob_count(&mut db, SubComponentType::KEY_BLOB));
+    assert_eq!(1, blob_count(&mut db, SubComponentType::CERT));
+    assert_eq!(1, blob_count(&mut db, SubComponentType::CERT_CHAIN));
+    Ok(())
+}
+
+#[test]
+fn test_creation_flags_round_trip() -> Result<()> {
+    let mut db = new_test_db()?;
+    let key = KeyDescriptor {
+        domain: Domain::APP,
+        nspace: 1,
+        alias: Some("key".to_string()),
+        blob: None,
+    };
+    let flags = IKeystoreMaintenance::KEY_FLAG_ACCESS_LOG | 0x4;
+    let mut metadata = KeyMetaData::new();
+    metadata.add(KeyMetaEntry::CreationFlags(flags));
+    db.store_new_key(
+        &key,
+        KeyType::Client,
+        &[],
+        &BlobInfo::new(&[1, 2, 3], &BlobMetaData::new()),
+        &CertificateInfo::new(None, None),
+        &metadata,
+        &KEYSTORE_UUID,
+    )?;
+
+    let (_, key_entry) =
+        db.load_key_entry(&key, KeyType::Client, KeyEntryLoadBits::NONE, 1, |_k, _av| Ok(()))?;
+    assert_eq!(key_entry.metadata().creation_flags(), Some(&flags));
+    Ok(())
+}
+
+#[test]
+fn test_tighten_key_parameters() -> Result<()> {
+    let mut db = new_test_db()?;
+    let key = KeyDescriptor {
+        domain: Domain::APP,
+        nspace: 1,
+        alias: Some("key".to_string()),
+        blob: None,
+    };
+    let algorithm = |algorithm| {
+        KeyParameter::new(
+            KeyParameterValue::Algorithm(algorithm),
+            SecurityLevel::TRUSTED_ENVIRONMENT,
+        )
+    };
+    let usage_count_limit = |limit| {
+        KeyParameter::new(KeyParameterValue::UsageCountLimit(limit), SecurityLevel::KEYSTORE)
+    };
+    let params = [algorithm(Algorithm::AES), usage_count_limit(5)];
+    let key_id = db
+        .store_new_key(
+            &key,
+            KeyType::Client,
+            &params,
+            &BlobInfo::new(&[1, 2, 3], &BlobMetaData::new()),
+            &CertificateInfo::new(None, None),
+            &KeyMetaData::new(),
+            &KEYSTORE_UUID,
+        )?
+        .id();
+    let tighten = |db: &mut KeystoreDB, new_params: &[KeyParameter]| {
+        db.tighten_key_parameters(&key, KeyType::Client, 1, new_params, |_, _| Ok(()))
+    };
+    let load_params = |db: &mut KeystoreDB| {
+        db.with_transaction(TransactionBehavior::Deferred, |tx| {
+            KeystoreDB::load_key_parameters(key_id, tx).no_gc()
+        })
+    };
+
+    tighten(&mut db, &[usage_count_limit(2)])?;
+    assert!(load_params(&mut db)?.contains(&usage_count_limit(2)));
+
+    // Loosening the limit, changing a parameter enforced by KeyMint, and adding a parameter
+    // that did not exist all fail and leave the key unchanged.
+    for new_params in [
+        vec![usage_count_limit(3)],
+        vec![usage_count_limit(0)],
+        vec![usage_count_limit(1), algorithm(Algorithm::EC)],
+        vec![KeyParameter::new(KeyParameterValue::ActiveDateTime(1), SecurityLevel::KEYSTORE)],
+    ] {
+        assert_eq!(
+            Some(&KsError::Rc(ResponseCode::INVALID_ARGUMENT)),
+            tighten(&mut db, &new_params).unwrap_err().root_cause().downcast_ref::<KsError>()
+        );
+        let mut loaded = load_params(&mut db)?;
+        loaded.sort_by_key(|p| p.get_tag().0);
+        assert_eq!(loaded, vec![algorithm(Algorithm::AES), usage_count_limit(2)]);
+    }
+
+    // The permission check is honored.
+    let result =
+        db.tighten_key_parameters(&key, KeyType::Client, 1, &[usage_count_limit(1)], |_, _| {
+            Err(KsError::perm()).context("Permission denied.")
+        });
+    assert!(result.is_err());
+    assert!(load_params(&mut db)?.contains(&usage_count_limit(2)));
+    Ok(())
+}
+
 #[test]
 fn test_blobentry_gc() -> Result<()> {
     let mut db = new_test_db()?;
@@ -2975,3 +3248,49 @@ fn test_upgrade_1_to_2_with_many_keys() -> Result<()> {
         },
     )
 }
+
+#[test]
+fn test_key_access_log() -> Result<()> {
+    let mut db = new_test_db()?;
+    let key_id = make_test_key_entry(&mut db, Domain::APP, 1, "key", None)?.0;
+    let key = KeyDescriptor {
+        domain: Domain::APP,
+        nspace: 1,
+        alias: Some("key".to_string()),
+        blob: None,
+    };
+    let access_log = |db: &mut KeystoreDB| db.get_key_access_log(&key, 1, |_, _| Ok(()));
+    assert!(access_log(&mut db)?.is_empty());
+
+    db.log_key_access(key_id, 10001, KeyPurpose::SIGN)?;
+    db.log_key_access(key_id, 10002, KeyPurpose::VERIFY)?;
+    let log = access_log(&mut db)?;
+    assert_eq!(
+        log.iter().map(|e| (e.uid, e.purpose)).collect::<Vec<_>>(),
+        vec![(10001, KeyPurpose::SIGN), (10002, KeyPurpose::VERIFY)]
+    );
+    assert!(log[0].timestamp <= log[1].timestamp);
+    assert!(db.get_key_access_log(&key, 1, |_, _| Err(KsError::perm().into())).is_err());
+
+    // Once the log is full, the oldest entries are dropped.
+    for uid in 0..MAX_KEY_ACCESS_LOG_ENTRIES as u32 {
+        db.log_key_access(key_id, uid, KeyPurpose::SIGN)?;
+    }
+    let log = access_log(&mut db)?;
+    assert_eq!(log.len(), MAX_KEY_ACCESS_LOG_ENTRIES);
+    assert_eq!(log[0].uid, 0);
+    assert_eq!(log[MAX_KEY_ACCESS_LOG_ENTRIES - 1].uid, MAX_KEY_ACCESS_LOG_ENTRIES as u32 - 1);
+
+    // Other keys have their own log, and the log goes away with the key.
+    let other_key_id = make_test_key_entry(&mut db, Domain::APP, 1, "other", None)?.0;
+    db.log_key_access(other_key_id, 10003, KeyPurpose::DECRYPT)?;
+    assert_eq!(access_log(&mut db)?.len(), MAX_KEY_ACCESS_LOG_ENTRIES);
+    db.unbind_key(&key, KeyType::Client, 1, |_, _| Ok(()))?;
+    let count: i64 = db.conn.query_row(
+        "SELECT COUNT(*) FROM persistent.keyaccesslog WHERE keyentryid = ?;",
+        params![key_id],
+        |row| row.get(0),
+    )?;
+    assert_eq!(count, 0);
+    Ok(())
+}
@@ -14,6 +14,7 @@
 
 //! Implement ECDH-based encryption.
 
+use crate::async_task::AsyncTask;
 use crate::ks_err;
 use anyhow::{Context, Result};
 use keystore2_crypto::{
@@ -22,6 +23,7 @@ use keystore2_crypto::{
     ec_point_point_to_oct, ecdh_compute_key, generate_salt, hkdf_expand, hkdf_extract, ECKey, ZVec,
     AES_256_KEY_LENGTH,
 };
+use std::sync::{Arc, Mutex, Weak};
 
 /// Private key for ECDH encryption.
 pub struct ECDHPrivateKey(ECKey);
@@ -106,6 +108,104 @@ impl ECDHPrivateKey {
     }
 }
 
+/// A bounded pool of pre-generated ECDH keypairs. EC key generation has a noticeable latency, so
+/// bursty ECDH workloads can take keys from the pool and leave the generation of replacements to
+/// a background job on an [`AsyncTask`].
+/// The private keys are cleared when they are freed, so keys left in the pool when it is dropped
+/// are zeroized.
+pub struct EcKeyPool {
+    state: Arc<Mutex<EcKeyPoolState>>,
+    async_task: Arc<AsyncTask>,
+}
+
+struct EcKeyPoolState {
+    keys: Vec<ECDHPrivateKey>,
+    capacity: usize,
+    refill_pending: bool,
+}
+
+impl EcKeyPool {
+    /// Creates a pool holding up to `capacity` keys, which is filled by the global
+    /// [`crate::globals::ASYNC_TASK`].
+    pub fn new(capacity: usize) -> Self {
+        Self::new_with_async_task(capacity, crate::globals::ASYNC_TASK.clone())
+    }
+
+    /// Creates a pool holding up to `capacity` keys, which is filled by the given `async_task`.
+    pub fn new_with_async_task(capacity: usize, async_task: Arc<AsyncTask>) -> Self {
+        let pool = Self {
+            state: Arc::new(Mutex::new(EcKeyPoolState {
+                keys: Vec::with_capacity(capacity),
+                capacity,
+                refill_pending: false,
+            })),
+            async_task,
+        };
+        pool.schedule_refill();
+        pool
+    }
+
+    /// Takes a keypair from the pool and schedules a refill. If the pool is empty, a fresh
+    /// keypair is generated on the calling thread.
+    pub fn take(&self) -> Result<ECDHPrivateKey> {
+        let key = self.state.lock().unwrap().keys.pop();
+        self.schedule_refill();
+        match key {
+            Some(key) => Ok(key),
+            None => ECDHPrivateKey::generate().context(ks_err!("pool empty, generation failed")),
+        }
+    }
+
+    /// Returns the number of keypairs currently held by the pool.
+    pub fn available(&self) -> usize {
+        self.state.lock().unwrap().keys.len()
+    }
+
+    fn schedule_refill(&self) {
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.refill_pending || state.keys.len() >= state.capacity {
+                return;
+            }
+            state.refill_pending = true;
+        }
+        let state = Arc::downgrade(&self.state);
+        self.async_task.queue_lo(move |_shelf| Self::refill(state));
+    }
+
+    /// Generates keys until the pool is full. The lock is not held during key generation so
+    /// that `take` is never blocked behind it. The job gives up once the pool has been dropped.
+    fn refill(state: Weak<Mutex<EcKeyPoolState>>) {
+        loop {
+            let Some(state) = state.upgrade() else {
+                return;
+            };
+            {
+                let mut state = state.lock().unwrap();
+                if state.keys.len() >= state.capacity {
+                    state.refill_pending = false;
+                    return;
+                }
+            }
+            match ECDHPrivateKey::generate() {
+                Ok(key) => state.lock().unwrap().keys.push(key),
+                Err(e) => {
+                    log::error!("Failed to refill EC key pool: {:?}", e);
+                    state.lock().unwrap().refill_pending = false;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl Drop for EcKeyPool {
+    fn drop(&mut self) {
+        // Free the pooled keys right away, even if a refill job still holds on to the state.
+        self.state.lock().unwrap().keys.clear();
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -123,4 +223,37 @@ mod test {
         assert_eq!(message, dc);
         Ok(())
     }
+
+    fn wait_for_pool(pool: &EcKeyPool, count: usize) {
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        while pool.available() < count {
+            assert!(std::time::Instant::now() < deadline, "EC key pool was not refilled");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_ec_key_pool() -> Result<()> {
+        const CAPACITY: usize = 4;
+        let pool = EcKeyPool::new_with_async_task(CAPACITY, Arc::new(AsyncTask::default()));
+        wait_for_pool(&pool, CAPACITY);
+
+        let keys = (0..CAPACITY + 1).map(|_| pool.take()).collect::<Result<Vec<_>>>()?;
+        let mut public_keys = keys.iter().map(|k| k.public_key()).collect::<Result<Vec<_>>>()?;
+        public_keys.sort();
+        public_keys.dedup();
+        assert_eq!(public_keys.len(), CAPACITY + 1);
+
+        let message = b"Hello world";
+        let recipient = &keys[0];
+        let (sender_public_key, salt, iv, ciphertext, tag) =
+            ECDHPrivateKey::encrypt_message(&recipient.public_key()?, message)?;
+        let decrypted =
+            recipient.decrypt_message(&sender_public_key, &salt, &iv, &ciphertext, &tag)?;
+        let dc: &[u8] = &decrypted;
+        assert_eq!(message, dc);
+
+        wait_for_pool(&pool, CAPACITY);
+        Ok(())
+    }
 }
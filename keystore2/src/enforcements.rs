@@ -37,6 +37,7 @@ use android_system_keystore2::aidl::android::system::keystore2::{
     OperationChallenge::OperationChallenge,
 };
 use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
 use std::{
     collections::{HashMap, HashSet},
     sync::{
@@ -81,13 +82,17 @@ impl AuthRequest {
     }
 
     fn get_auth_tokens(&self) -> Result<(HardwareAuthToken, Option<TimeStampToken>)> {
-        let hat = self
-            .hat
-            .lock()
-            .unwrap()
-            .take()
-            .ok_or(Error::Km(ErrorCode::KEY_USER_NOT_AUTHENTICATED))
-            .context(ks_err!("No operation auth token received."))?;
+        let hat = {
+            let mut hat = self.hat.lock().unwrap();
+            match self.state {
+                // A per operation auth token stays in place until the client replaces it with a
+                // fresh token for the same challenge, see `TokenReceiverMap::add_auth_token`.
+                AuthRequestState::OpAuth => hat.clone(),
+                AuthRequestState::TimeStamp(_) => hat.take(),
+            }
+        }
+        .ok_or(Error::Km(ErrorCode::KEY_USER_NOT_AUTHENTICATED))
+        .context(ks_err!("No operation auth token received."))?;
 
         let tst = match &self.state {
             AuthRequestState::TimeStamp(recv) => {
@@ -143,12 +148,57 @@ pub struct AuthInfo {
     confirmation_token_receiver: Option<Arc<Mutex<Option<Receiver<Vec<u8>>>>>>,
 }
 
+/// The kind of deferred authorization an operation is subject to, see `DeferredAuthState`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeferredAuthKind {
+    /// The operation requires no further authorization.
+    NoAuthRequired,
+    /// The operation requires an operation specific auth token.
+    OpAuth,
+    /// The operation requires a time stamp token to accompany the auth token of a time-bound
+    /// key.
+    TimeStamp,
+}
+
+/// The non-secret part of an `AuthInfo`, which can be serialized so that the authorization state
+/// of an operation can be reconstructed after a controlled restart of keystore.
+///
+/// Auth tokens and timestamp tokens are never part of this state. `Enforcements::
+/// restore_auth_info` fetches them again, which has the following limits:
+///  * A time-bound key can only be restored while a sufficiently fresh auth token is available.
+///  * Operation specific auth tokens are bound to the challenge of the original KeyMint
+///    operation. A restored operation gets a new challenge, and the client has to authorize it
+///    again.
+///  * A pending trusted confirmation is lost; the restored operation waits for a new one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthInfoState {
+    /// The kind of deferred authorization of the operation.
+    pub deferred_auth: DeferredAuthKind,
+    /// The id of the key if its usage count is limited.
+    pub key_usage_limited: Option<i64>,
+    /// True if the operation requires a trusted confirmation token.
+    pub confirmation_required: bool,
+}
+
+impl AuthInfoState {
+    /// Serializes the state as CBOR.
+    pub fn to_cbor(&self) -> Result<Vec<u8>> {
+        serde_cbor::to_vec(self).context(ks_err!("Failed to serialize auth info state."))
+    }
+
+    /// Deserializes a state serialized by `to_cbor`.
+    pub fn from_cbor(data: &[u8]) -> Result<Self> {
+        serde_cbor::from_slice(data).context(ks_err!("Failed to deserialize auth info state."))
+    }
+}
+
 struct TokenReceiverMap {
-    /// The map maps an outstanding challenge to a TokenReceiver. If an incoming Hardware Auth
-    /// Token (HAT) has the map key in its challenge field, it gets passed to the TokenReceiver
-    /// and the entry is removed from the map. In the case where no HAT is received before the
-    /// corresponding operation gets dropped, the entry goes stale. So every time the cleanup
-    /// counter (second field in the tuple) turns 0, the map is cleaned from stale entries.
+    /// The map maps the challenge of an operation to a TokenReceiver. If an incoming Hardware
+    /// Auth Token (HAT) has the map key in its challenge field, it gets passed to the
+    /// TokenReceiver. The entry stays in the map, so that a later HAT with the same challenge
+    /// replaces an expired one, until the corresponding operation gets dropped and the entry
+    /// goes stale. So every time the cleanup counter (second field in the tuple) turns 0, the
+    /// map is cleaned from stale entries.
     /// The cleanup counter is decremented every time a new receiver is added.
     /// and reset to TokenReceiverMap::CLEANUP_PERIOD + 1 after each cleanup.
     map_and_cleanup_counter: Mutex<(HashMap<i64, TokenReceiver>, u8)>,
@@ -173,10 +223,17 @@ impl TokenReceiverMap {
             // added.
             let mut map = self.map_and_cleanup_counter.lock().unwrap();
             let (ref mut map, _) = *map;
-            map.remove_entry(&hat.challenge)
+            match map.get(&hat.challenge) {
+                Some(recv) if recv.is_obsolete() => {
+                    map.remove(&hat.challenge);
+                    None
+                }
+                Some(recv) => Some(TokenReceiver(recv.0.clone())),
+                None => None,
+            }
         };
 
-        if let Some((_, recv)) = recv {
+        if let Some(recv) = recv {
             recv.add_auth_token(hat);
         }
     }
@@ -210,11 +267,128 @@ impl TokenReceiver {
     }
 }
 
+/// Checks that `purpose` can be used with a key described by `key_params` at all, regardless of
+/// the purposes the key was authorized for.
+fn check_purpose_compatibility(purpose: KeyPurpose, key_params: &[KeyParameter]) -> Result<()> {
+    match purpose {
+        // Allow SIGN, DECRYPT for both symmetric and asymmetric keys.
+        KeyPurpose::SIGN | KeyPurpose::DECRYPT => {}
+        // Rule out WRAP_KEY purpose
+        KeyPurpose::WRAP_KEY => {
+            return Err(Error::Km(Ec::INCOMPATIBLE_PURPOSE))
+                .context(ks_err!("WRAP_KEY purpose is not allowed here.",));
+        }
+        // Allow AGREE_KEY for EC keys only.
+        KeyPurpose::AGREE_KEY => {
+            for kp in key_params.iter() {
+                if kp.get_tag() == Tag::ALGORITHM
+                    && *kp.key_parameter_value() != KeyParameterValue::Algorithm(Algorithm::EC)
+                {
+                    return Err(Error::Km(Ec::UNSUPPORTED_PURPOSE))
+                        .context(ks_err!("key agreement is only supported for EC keys.",));
+                }
+            }
+        }
+        KeyPurpose::VERIFY | KeyPurpose::ENCRYPT => {
+            // We do not support ENCRYPT and VERIFY (the remaining two options of purpose) for
+            // asymmetric keys.
+            for kp in key_params.iter() {
+                match *kp.key_parameter_value() {
+                    KeyParameterValue::Algorithm(Algorithm::RSA)
+                    | KeyParameterValue::Algorithm(Algorithm::EC) => {
+                        return Err(Error::Km(Ec::UNSUPPORTED_PURPOSE)).context(ks_err!(
+                            "public operations on asymmetric keys are \
+                             not supported."
+                        ));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        _ => {
+            return Err(Error::Km(Ec::UNSUPPORTED_PURPOSE))
+                .context(ks_err!("specified purpose is not supported."));
+        }
+    }
+    Ok(())
+}
+
+/// Checks that a caller provided nonce is only present for origination purposes if the key
+/// allows it.
+fn check_caller_nonce(
+    purpose: KeyPurpose,
+    caller_nonce_allowed: bool,
+    op_params: &[KmKeyParameter],
+) -> Result<()> {
+    // validate caller nonce for origination purposes
+    if (purpose == KeyPurpose::ENCRYPT || purpose == KeyPurpose::SIGN)
+        && !caller_nonce_allowed
+        && op_params.iter().any(|kp| kp.tag == Tag::NONCE)
+    {
+        return Err(Error::Km(Ec::CALLER_NONCE_PROHIBITED))
+            .context(ks_err!("NONCE is present, although CALLER_NONCE is not present"));
+    }
+    Ok(())
+}
+
+/// Runs the checks on the operation parameters that `Enforcements::authorize_create` and
+/// KeyMint would run, as far as possible without consulting the device or the authorization
+/// state: Parameters must be well formed, a `PURPOSE` among them must match `purpose`, and, if
+/// the parameters `key_params` of the key are known, the purpose must be compatible with and
+/// authorized for the key and caller provided nonces must be allowed.
+pub fn check_operation_params(
+    purpose: KeyPurpose,
+    key_params: Option<&[KeyParameter]>,
+    op_params: &[KmKeyParameter],
+) -> Result<()> {
+    for param in op_params {
+        if KeyParameterValue::from(param) == KeyParameterValue::Invalid {
+            return Err(Error::Km(Ec::INVALID_ARGUMENT))
+                .context(ks_err!("Malformed operation parameter with tag {:?}.", param.tag));
+        }
+        if param.tag == Tag::PURPOSE
+            && KeyParameterValue::from(param) != KeyParameterValue::KeyPurpose(purpose)
+        {
+            return Err(Error::Km(Ec::INVALID_ARGUMENT))
+                .context(ks_err!("Operation parameters specify a different purpose."));
+        }
+    }
+
+    let key_params = match key_params {
+        Some(key_params) => key_params,
+        None => return Ok(()),
+    };
+    check_purpose_compatibility(purpose, key_params).context(ks_err!())?;
+    let purpose_param = KeyParameterValue::KeyPurpose(purpose);
+    if !key_params.iter().any(|kp| *kp.key_parameter_value() == purpose_param) {
+        return Err(Error::Km(Ec::INCOMPATIBLE_PURPOSE))
+            .context(ks_err!("the purpose is not authorized."));
+    }
+    let caller_nonce_allowed =
+        key_params.iter().any(|kp| *kp.key_parameter_value() == KeyParameterValue::CallerNonce);
+    check_caller_nonce(purpose, caller_nonce_allowed, op_params).context(ks_err!())
+}
+
+/// The error returned when a timestamp token is required, but the secure clock is unavailable.
+/// It is distinct from a generic system error, so that the failure can be attributed.
+pub const SECURE_CLOCK_UNAVAILABLE: Error = Error::Km(Ec::SECURE_HW_COMMUNICATION_FAILED);
+
+/// Connects to the secure clock with `connect`, retrying once if the first attempt fails.
+/// Returns `SECURE_CLOCK_UNAVAILABLE` if the secure clock cannot be reached.
+fn connect_secure_clock_with<T>(connect: impl Fn() -> Result<T>) -> Result<T, Error> {
+    connect()
+        .or_else(|e| {
+            log::warn!("Failed to connect to the secure clock, retrying once: {e:?}");
+            connect()
+        })
+        .map_err(|e| {
+            log::error!("Secure clock unavailable, cannot provide timestamp tokens: {e:?}");
+            SECURE_CLOCK_UNAVAILABLE
+        })
+}
+
 fn get_timestamp_token(challenge: i64) -> Result<TimeStampToken, Error> {
-    let dev = get_timestamp_service().expect(concat!(
-        "Secure Clock service must be present ",
-        "if TimeStampTokens are required."
-    ));
+    let dev = connect_secure_clock_with(get_timestamp_service)?;
     map_binder_status(dev.generateTimeStamp(challenge))
 }
 
@@ -228,6 +402,28 @@ fn timestamp_token_request(challenge: i64, sender: Sender<Result<TimeStampToken,
 }
 
 impl AuthInfo {
+    /// Returns the non-secret part of the authorization state, see `AuthInfoState`.
+    pub fn export_state(&self) -> AuthInfoState {
+        let deferred_auth = match &self.state {
+            DeferredAuthState::NoAuthRequired => DeferredAuthKind::NoAuthRequired,
+            DeferredAuthState::OpAuthRequired | DeferredAuthState::Token(_, None) => {
+                DeferredAuthKind::OpAuth
+            }
+            DeferredAuthState::TimeStampRequired(_) | DeferredAuthState::Token(_, Some(_)) => {
+                DeferredAuthKind::TimeStamp
+            }
+            DeferredAuthState::Waiting(auth_request) => match auth_request.state {
+                AuthRequestState::OpAuth => DeferredAuthKind::OpAuth,
+                AuthRequestState::TimeStamp(_) => DeferredAuthKind::TimeStamp,
+            },
+        };
+        AuthInfoState {
+            deferred_auth,
+            key_usage_limited: self.key_usage_limited,
+            confirmation_required: self.confirmation_token_receiver.is_some(),
+        }
+    }
+
     /// This function gets called after an operation was successfully created.
     /// It makes all the preparations required, so that the operation has all the authentication
     /// related artifacts to advance on update and finish.
@@ -289,6 +485,17 @@ impl AuthInfo {
         self.get_auth_tokens().map(|(hat, tst)| (hat, tst, confirmation_token))
     }
 
+    /// Returns true if the operation is authorized by per operation auth tokens. Such an
+    /// operation uses the most recent token that was added for its challenge, so a client
+    /// whose token expired during a long running operation can supply a fresh one through
+    /// `Enforcements::add_auth_token` and retry. Whether KeyMint accepts the retry is up to the
+    /// implementation; implementations that abort the operation after rejecting a token fail
+    /// the retry with INVALID_OPERATION_HANDLE, and the client has to start a new operation.
+    pub fn accepts_fresh_auth_token(&self) -> bool {
+        matches!(&self.state, DeferredAuthState::Waiting(auth_request)
+            if matches!(auth_request.state, AuthRequestState::OpAuth))
+    }
+
     /// This function is the authorization hook called after finish succeeded.
     /// As of this writing it checks if the key was a limited use key. If so it updates the
     /// use counter of the key in the database. When the use counter is depleted, the key gets
@@ -311,10 +518,17 @@ impl AuthInfo {
     /// with ErrorCode::KEY_USER_NOT_AUTHENTICATED. If this was called for the first time
     /// after a deferred authorization was requested by finalize_create_authorization, this
     /// function may block on the generation of a time stamp token. It then moves the
-    /// tokens into the DeferredAuthState::Token state for future use.
+    /// tokens into the DeferredAuthState::Token state for future use. Per operation auth
+    /// tokens are looked up anew on every call instead, so that they can be refreshed.
     fn get_auth_tokens(&mut self) -> Result<(Option<HardwareAuthToken>, Option<TimeStampToken>)> {
         let deferred_tokens = if let DeferredAuthState::Waiting(ref auth_request) = self.state {
-            Some(auth_request.get_auth_tokens().context("In AuthInfo::get_auth_tokens.")?)
+            let (hat, tst) =
+                auth_request.get_auth_tokens().context("In AuthInfo::get_auth_tokens.")?;
+            // Per operation auth keeps waiting for fresh tokens, see `accepts_fresh_auth_token`.
+            if let AuthRequestState::OpAuth = auth_request.state {
+                return Ok((Some(hat), tst));
+            }
+            Some((hat, tst))
         } else {
             None
         };
@@ -340,6 +554,87 @@ impl AuthInfo {
     }
 }
 
+/// The enforcement restrictions of a key, resolved against the current state of the
+/// enforcements module. This is meant to explain why a key cannot be used.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct EnforcementView {
+    /// True if the key requires user authentication.
+    pub auth_required: bool,
+    /// The authenticator types accepted for user authentication.
+    pub auth_type: Option<HardwareAuthenticatorType>,
+    /// The auth timeout in seconds. None means that every operation requires its own token.
+    pub auth_timeout: Option<i64>,
+    /// For time-bound keys, whether a sufficiently fresh auth token is currently available.
+    pub auth_token_valid: Option<bool>,
+    /// The remaining number of uses if the key is usage count limited.
+    pub remaining_uses: Option<i32>,
+    /// The start of the validity window in milliseconds since the epoch.
+    pub active_datetime: Option<i64>,
+    /// The end of the validity window for encryption and signing in milliseconds since the epoch.
+    pub origination_expire_datetime: Option<i64>,
+    /// The end of the validity window for decryption and verification in milliseconds since the
+    /// epoch.
+    pub usage_expire_datetime: Option<i64>,
+    /// True if the current time lies before `active_datetime`.
+    pub not_yet_valid: bool,
+    /// True if the key is expired for encryption and signing.
+    pub origination_expired: bool,
+    /// True if the key is expired for decryption and verification.
+    pub usage_expired: bool,
+    /// True if the key can only be used before the end of early boot.
+    pub early_boot_only: bool,
+    /// True if operations with the key require a trusted confirmation.
+    pub confirmation_required: bool,
+    /// True if the key can only be used while the device is unlocked.
+    pub unlocked_device_required: bool,
+    /// True if `unlocked_device_required` is set and the device is currently locked for the
+    /// owner of the key.
+    pub device_locked: bool,
+    /// The boot level after which the key can no longer be used.
+    pub max_boot_level: Option<i32>,
+    /// True if `max_boot_level` is set and the boot level has been exceeded.
+    pub boot_level_exceeded: bool,
+    user_id: i32,
+    user_secure_ids: Vec<i64>,
+}
+
+impl EnforcementView {
+    /// Collects the enforcement restrictions from the key parameters without looking at the
+    /// current state.
+    fn from_key_params(key_params: &[KeyParameter]) -> Self {
+        let mut view = Self { user_id: -1, ..Default::default() };
+        for key_param in key_params {
+            match key_param.key_parameter_value() {
+                KeyParameterValue::HardwareAuthenticatorType(a) => view.auth_type = Some(*a),
+                KeyParameterValue::AuthTimeout(t) => view.auth_timeout = Some(*t as i64),
+                KeyParameterValue::UserSecureID(s) => view.user_secure_ids.push(*s),
+                KeyParameterValue::UserID(u) => view.user_id = *u,
+                KeyParameterValue::UsageCountLimit(n) => view.remaining_uses = Some(*n),
+                KeyParameterValue::ActiveDateTime(a) => view.active_datetime = Some(*a),
+                KeyParameterValue::OriginationExpireDateTime(o) => {
+                    view.origination_expire_datetime = Some(*o)
+                }
+                KeyParameterValue::UsageExpireDateTime(u) => view.usage_expire_datetime = Some(*u),
+                KeyParameterValue::EarlyBootOnly => view.early_boot_only = true,
+                KeyParameterValue::TrustedConfirmationRequired => view.confirmation_required = true,
+                KeyParameterValue::UnlockedDeviceRequired => view.unlocked_device_required = true,
+                KeyParameterValue::MaxBootLevel(level) => view.max_boot_level = Some(*level),
+                _ => {}
+            }
+        }
+        view.auth_required = !view.user_secure_ids.is_empty();
+        view.not_yet_valid =
+            view.active_datetime.is_some_and(|a| !Enforcements::is_given_time_passed(a, true));
+        view.origination_expired = view
+            .origination_expire_datetime
+            .is_some_and(|o| Enforcements::is_given_time_passed(o, false));
+        view.usage_expired = view
+            .usage_expire_datetime
+            .is_some_and(|u| Enforcements::is_given_time_passed(u, false));
+        view
+    }
+}
+
 /// Enforcements data structure
 #[derive(Default)]
 pub struct Enforcements {
@@ -400,46 +695,7 @@ impl Enforcements {
             }
         };
 
-        match purpose {
-            // Allow SIGN, DECRYPT for both symmetric and asymmetric keys.
-            KeyPurpose::SIGN | KeyPurpose::DECRYPT => {}
-            // Rule out WRAP_KEY purpose
-            KeyPurpose::WRAP_KEY => {
-                return Err(Error::Km(Ec::INCOMPATIBLE_PURPOSE))
-                    .context(ks_err!("WRAP_KEY purpose is not allowed here.",));
-            }
-            // Allow AGREE_KEY for EC keys only.
-            KeyPurpose::AGREE_KEY => {
-                for kp in key_params.iter() {
-                    if kp.get_tag() == Tag::ALGORITHM
-                        && *kp.key_parameter_value() != KeyParameterValue::Algorithm(Algorithm::EC)
-                    {
-                        return Err(Error::Km(Ec::UNSUPPORTED_PURPOSE))
-                            .context(ks_err!("key agreement is only supported for EC keys.",));
-                    }
-                }
-            }
-            KeyPurpose::VERIFY | KeyPurpose::ENCRYPT => {
-                // We do not support ENCRYPT and VERIFY (the remaining two options of purpose) for
-                // asymmetric keys.
-                for kp in key_params.iter() {
-                    match *kp.key_parameter_value() {
-                        KeyParameterValue::Algorithm(Algorithm::RSA)
-                        | KeyParameterValue::Algorithm(Algorithm::EC) => {
-                            return Err(Error::Km(Ec::UNSUPPORTED_PURPOSE)).context(ks_err!(
-                                "public operations on asymmetric keys are \
-                                 not supported."
-                            ));
-                        }
-                        _ => {}
-                    }
-                }
-            }
-            _ => {
-                return Err(Error::Km(Ec::UNSUPPORTED_PURPOSE))
-                    .context(ks_err!("authorize_create: specified purpose is not supported."));
-            }
-        }
+        check_purpose_compatibility(purpose, key_params).context(ks_err!())?;
         // The following variables are to record information from key parameters to be used in
         // enforcements, when two or more such pieces of information are required for enforcements.
         // There is only one additional variable than what legacy keystore has, but this helps
@@ -551,14 +807,7 @@ impl Enforcements {
             ));
         }
 
-        // validate caller nonce for origination purposes
-        if (purpose == KeyPurpose::ENCRYPT || purpose == KeyPurpose::SIGN)
-            && !caller_nonce_allowed
-            && op_params.iter().any(|kp| kp.tag == Tag::NONCE)
-        {
-            return Err(Error::Km(Ec::CALLER_NONCE_PROHIBITED))
-                .context(ks_err!("NONCE is present, although CALLER_NONCE is not present"));
-        }
+        check_caller_nonce(purpose, caller_nonce_allowed, op_params).context(ks_err!())?;
 
         if unlocked_device_required {
             // check the device locked status. If locked, operations on the key are not
@@ -615,6 +864,9 @@ impl Enforcements {
                 ));
             }
             let state = if requires_timestamp {
+                // Fail early and clearly rather than when the token is needed on update.
+                connect_secure_clock_with(get_timestamp_service)
+                    .context(ks_err!("Timestamp token required, but no secure clock."))?;
                 DeferredAuthState::TimeStampRequired(hat.auth_token().clone())
             } else {
                 DeferredAuthState::NoAuthRequired
@@ -626,6 +878,73 @@ impl Enforcements {
         Ok((hat, AuthInfo { state, key_usage_limited, confirmation_token_receiver }))
     }
 
+    /// Resolves the enforcement restrictions of a key with the given parameters against the
+    /// current state, i.e., the available auth tokens, the device lock state, and the boot
+    /// level. Unlike `authorize_create` this does not fail on the first violated restriction.
+    pub fn effective_enforcements(&self, key_params: &[KeyParameter]) -> EnforcementView {
+        let mut view = EnforcementView::from_key_params(key_params);
+        if let (true, Some(auth_type), Some(key_time_out)) =
+            (view.auth_required, view.auth_type, view.auth_timeout)
+        {
+            let hat = Self::find_auth_token(|hat| hat.satisfies(&view.user_secure_ids, auth_type));
+            view.auth_token_valid = Some(hat.is_some_and(|hat| {
+                BootTime::now()
+                    .checked_sub(&hat.time_received())
+                    .is_some_and(|age| age.seconds() <= key_time_out)
+            }));
+        }
+        view.device_locked = view.unlocked_device_required && self.is_device_locked(view.user_id);
+        view.boot_level_exceeded = view
+            .max_boot_level
+            .is_some_and(|level| !SUPER_KEY.read().unwrap().level_accessible(level));
+        view
+    }
+
+    /// Reconstructs the `AuthInfo` of an operation from a state exported with
+    /// `AuthInfo::export_state`. The key parameters must be those of the operation's key.
+    /// Tokens are fetched anew, see `AuthInfoState` for the limits of this. The returned
+    /// `AuthInfo` is in the same state as one returned by `authorize_create`, i.e., the caller
+    /// has to call `finalize_create_authorization` with the challenge of the new KeyMint
+    /// operation.
+    pub fn restore_auth_info(
+        &self,
+        state: &AuthInfoState,
+        key_params: &[KeyParameter],
+    ) -> Result<AuthInfo> {
+        let deferred = match state.deferred_auth {
+            DeferredAuthKind::NoAuthRequired => DeferredAuthState::NoAuthRequired,
+            DeferredAuthKind::OpAuth => DeferredAuthState::OpAuthRequired,
+            DeferredAuthKind::TimeStamp => {
+                let view = EnforcementView::from_key_params(key_params);
+                let (auth_type, key_time_out) = match (view.auth_type, view.auth_timeout) {
+                    (Some(auth_type), Some(key_time_out)) => (auth_type, key_time_out),
+                    _ => {
+                        return Err(Error::Km(Ec::INVALID_ARGUMENT))
+                            .context(ks_err!("Key of a timestamped operation is not time-bound."))
+                    }
+                };
+                let satisfies =
+                    |hat: &AuthTokenEntry| hat.satisfies(&view.user_secure_ids, auth_type);
+                let hat = Self::find_auth_token(satisfies)
+                    .filter(|hat| {
+                        BootTime::now()
+                            .checked_sub(&hat.time_received())
+                            .is_some_and(|age| age.seconds() <= key_time_out)
+                    })
+                    .ok_or(Error::Km(Ec::KEY_USER_NOT_AUTHENTICATED))
+                    .context(ks_err!("No fresh auth token to restore the operation with."))?;
+                DeferredAuthState::TimeStampRequired(hat.take_auth_token())
+            }
+        };
+        Ok(AuthInfo {
+            state: deferred,
+            key_usage_limited: state.key_usage_limited,
+            confirmation_token_receiver: state
+                .confirmation_required
+                .then(|| self.confirmation_token_receiver.clone()),
+        })
+    }
+
     fn find_auth_token<F>(p: F) -> Option<AuthTokenEntry>
     where
         F: Fn(&AuthTokenEntry) -> bool,
@@ -635,7 +954,7 @@ impl Enforcements {
 
     /// Checks if the time now since epoch is greater than (or equal, if is_given_time_inclusive is
     /// set) the given time (in milliseconds)
-    fn is_given_time_passed(given_time: i64, is_given_time_inclusive: bool) -> bool {
+    pub(crate) fn is_given_time_passed(given_time: i64, is_given_time_inclusive: bool) -> bool {
         let duration_since_epoch = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH);
 
         let time_since_epoch = match duration_since_epoch {
@@ -668,10 +987,15 @@ impl Enforcements {
     }
 
     /// Add this auth token to the database.
-    /// Then present the auth token to the op auth map. If an operation is waiting for this
-    /// auth token this fulfills the request and removes the receiver from the map.
+    /// Then present the auth token to the op auth map, see `add_op_auth_token`.
     pub fn add_auth_token(&self, hat: HardwareAuthToken) {
         DB.with(|db| db.borrow_mut().insert_auth_token(&hat));
+        self.add_op_auth_token(hat);
+    }
+
+    /// Presents the auth token to the op auth map. If an operation is authorized by auth tokens
+    /// with this challenge, the token becomes the one used by its next update or finish.
+    pub(crate) fn add_op_auth_token(&self, hat: HardwareAuthToken) {
         self.op_auth_map.add_auth_token(hat);
     }
 
@@ -794,3 +1118,208 @@ impl Enforcements {
 }
 
 // TODO: Add tests to enforcement module (b/175578618).
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use android_hardware_security_keymint::aidl::android::hardware::security::keymint::SecurityLevel::SecurityLevel;
+
+    #[test]
+    fn test_connect_secure_clock_with() {
+        use std::cell::Cell;
+
+        let attempts = Cell::new(0);
+        let unavailable = || -> Result<()> {
+            attempts.set(attempts.get() + 1);
+            Err(Error::sys()).context("secure clock down")
+        };
+        assert_eq!(connect_secure_clock_with(unavailable), Err(SECURE_CLOCK_UNAVAILABLE));
+        assert_eq!(attempts.get(), 2);
+        assert_ne!(SECURE_CLOCK_UNAVAILABLE, Error::sys());
+
+        // A transient failure is covered by the retry.
+        attempts.set(0);
+        let flaky = || -> Result<i32> {
+            attempts.set(attempts.get() + 1);
+            if attempts.get() == 1 {
+                Err(Error::sys()).context("secure clock starting")
+            } else {
+                Ok(42)
+            }
+        };
+        assert_eq!(connect_secure_clock_with(flaky), Ok(42));
+        assert_eq!(attempts.get(), 2);
+    }
+
+    fn auth_info_with_token(hat: HardwareAuthToken) -> AuthInfo {
+        AuthInfo {
+            state: DeferredAuthState::Token(hat, None),
+            key_usage_limited: None,
+            confirmation_token_receiver: None,
+        }
+    }
+
+    #[test]
+    fn test_auth_info_state_round_trip() -> Result<()> {
+        let secret_mac = vec![0xa5; 32];
+        let hat =
+            HardwareAuthToken { challenge: 42, mac: secret_mac.clone(), ..Default::default() };
+        let mut auth_info = auth_info_with_token(hat);
+        auth_info.key_usage_limited = Some(7);
+        auth_info.confirmation_token_receiver = Some(Arc::new(Mutex::new(None)));
+
+        let state = auth_info.export_state();
+        assert_eq!(
+            state,
+            AuthInfoState {
+                deferred_auth: DeferredAuthKind::OpAuth,
+                key_usage_limited: Some(7),
+                confirmation_required: true,
+            }
+        );
+        let serialized = state.to_cbor()?;
+        assert!(!serialized.windows(secret_mac.len()).any(|w| w == secret_mac));
+        assert_eq!(AuthInfoState::from_cbor(&serialized)?, state);
+        assert!(AuthInfoState::from_cbor(&serialized[..serialized.len() - 1]).is_err());
+
+        let enforcements = Enforcements::default();
+        let restored = enforcements.restore_auth_info(&state, &[])?;
+        assert!(matches!(restored.state, DeferredAuthState::OpAuthRequired));
+        assert_eq!(restored.export_state(), state);
+
+        let state = AuthInfoState {
+            deferred_auth: DeferredAuthKind::NoAuthRequired,
+            key_usage_limited: None,
+            confirmation_required: false,
+        };
+        let restored = enforcements.restore_auth_info(&state, &[])?;
+        assert!(matches!(restored.state, DeferredAuthState::NoAuthRequired));
+        assert!(restored.confirmation_token_receiver.is_none());
+        assert_eq!(AuthInfoState::from_cbor(&state.to_cbor()?)?, state);
+
+        // A timestamped operation cannot be restored for a key that is not time-bound.
+        let state = AuthInfoState { deferred_auth: DeferredAuthKind::TimeStamp, ..state };
+        assert_eq!(
+            Some(&Error::Km(ErrorCode::INVALID_ARGUMENT)),
+            enforcements.restore_auth_info(&state, &[]).unwrap_err().root_cause().downcast_ref()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_effective_enforcements() {
+        let key_params: Vec<KeyParameter> = vec![
+            KeyParameterValue::UserSecureID(42),
+            KeyParameterValue::HardwareAuthenticatorType(HardwareAuthenticatorType::FINGERPRINT),
+            KeyParameterValue::UsageCountLimit(3),
+            KeyParameterValue::ActiveDateTime(0),
+            KeyParameterValue::UsageExpireDateTime(1),
+            KeyParameterValue::EarlyBootOnly,
+            KeyParameterValue::TrustedConfirmationRequired,
+            KeyParameterValue::UnlockedDeviceRequired,
+            KeyParameterValue::UserID(10),
+        ]
+        .into_iter()
+        .map(|v| KeyParameter::new(v, SecurityLevel::TRUSTED_ENVIRONMENT))
+        .collect();
+
+        let enforcements = Enforcements::default();
+        let view = enforcements.effective_enforcements(&key_params);
+        assert!(view.auth_required);
+        assert_eq!(view.auth_type, Some(HardwareAuthenticatorType::FINGERPRINT));
+        assert_eq!(view.auth_timeout, None);
+        assert_eq!(view.auth_token_valid, None);
+        assert_eq!(view.remaining_uses, Some(3));
+        assert!(!view.not_yet_valid);
+        assert!(!view.origination_expired);
+        assert!(view.usage_expired);
+        assert!(view.early_boot_only);
+        assert!(view.confirmation_required);
+        assert!(view.unlocked_device_required);
+        assert!(view.device_locked);
+        assert_eq!(view.max_boot_level, None);
+        assert!(!view.boot_level_exceeded);
+
+        enforcements.set_device_locked(10, false);
+        assert!(!enforcements.effective_enforcements(&key_params).device_locked);
+    }
+
+    #[test]
+    fn test_check_operation_params() {
+        use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
+            Digest::Digest, KeyParameterValue::KeyParameterValue as KmKeyParameterValue,
+        };
+
+        let key_params = |values: Vec<KeyParameterValue>| -> Vec<KeyParameter> {
+            values
+                .into_iter()
+                .map(|v| KeyParameter::new(v, SecurityLevel::TRUSTED_ENVIRONMENT))
+                .collect()
+        };
+        let fails_with = |r: Result<()>, ec: Ec| match r {
+            Err(e) => e.root_cause().downcast_ref::<Error>() == Some(&Error::Km(ec)),
+            Ok(()) => false,
+        };
+        let ec_key = key_params(vec![
+            KeyParameterValue::Algorithm(Algorithm::EC),
+            KeyParameterValue::KeyPurpose(KeyPurpose::SIGN),
+        ]);
+        let aes_key = key_params(vec![
+            KeyParameterValue::Algorithm(Algorithm::AES),
+            KeyParameterValue::KeyPurpose(KeyPurpose::ENCRYPT),
+            KeyParameterValue::KeyPurpose(KeyPurpose::DECRYPT),
+        ]);
+        let digest: KmKeyParameter = KeyParameterValue::Digest(Digest::SHA_2_256).into();
+        let sign: KmKeyParameter = KeyParameterValue::KeyPurpose(KeyPurpose::SIGN).into();
+        let nonce: KmKeyParameter = KeyParameterValue::Nonce(vec![0; 12]).into();
+
+        // Valid parameter sets, with and without the purpose among them.
+        let ec_key = Some(&ec_key[..]);
+        let aes_key = Some(&aes_key[..]);
+        assert!(check_operation_params(KeyPurpose::SIGN, ec_key, &[digest.clone()]).is_ok());
+        assert!(check_operation_params(KeyPurpose::SIGN, ec_key, &[sign.clone()]).is_ok());
+        assert!(check_operation_params(KeyPurpose::DECRYPT, aes_key, &[nonce.clone()]).is_ok());
+        // Without key parameters, e.g. for Domain::BLOB, only the parameters are checked.
+        assert!(check_operation_params(KeyPurpose::SIGN, None, &[digest.clone()]).is_ok());
+
+        // Malformed parameter and conflicting purpose.
+        let malformed = KmKeyParameter { tag: Tag::DIGEST, value: KmKeyParameterValue::Integer(1) };
+        assert!(fails_with(
+            check_operation_params(KeyPurpose::SIGN, None, &[malformed]),
+            Ec::INVALID_ARGUMENT
+        ));
+        assert!(fails_with(
+            check_operation_params(KeyPurpose::DECRYPT, aes_key, &[sign]),
+            Ec::INVALID_ARGUMENT
+        ));
+
+        // Purposes that are not supported for the key type or not authorized for the key.
+        assert!(fails_with(
+            check_operation_params(KeyPurpose::VERIFY, ec_key, &[]),
+            Ec::UNSUPPORTED_PURPOSE
+        ));
+        assert!(fails_with(
+            check_operation_params(KeyPurpose::AGREE_KEY, aes_key, &[]),
+            Ec::UNSUPPORTED_PURPOSE
+        ));
+        assert!(fails_with(
+            check_operation_params(KeyPurpose::WRAP_KEY, aes_key, &[]),
+            Ec::INCOMPATIBLE_PURPOSE
+        ));
+        assert!(fails_with(
+            check_operation_params(KeyPurpose::DECRYPT, ec_key, &[]),
+            Ec::INCOMPATIBLE_PURPOSE
+        ));
+
+        // A caller provided nonce for encryption requires CALLER_NONCE.
+        assert!(fails_with(
+            check_operation_params(KeyPurpose::ENCRYPT, aes_key, &[nonce.clone()]),
+            Ec::CALLER_NONCE_PROHIBITED
+        ));
+        let caller_nonce_key =
+            [aes_key.unwrap(), &key_params(vec![KeyParameterValue::CallerNonce])[..]].concat();
+        assert!(
+            check_operation_params(KeyPurpose::ENCRYPT, Some(&caller_nonce_key), &[nonce]).is_ok()
+        );
+    }
+}
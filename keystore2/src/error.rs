@@ -232,6 +232,11 @@ pub fn error_to_serialized_error(e: &Error) -> SerializedError {
 }
 
 /// Returns a SerializedError given a reference to anyhow::Error.
+///
+/// The root cause determines the error code. If the root cause is neither a Keystore nor an
+/// SELinux error, e.g., because a KeyMint error was attached as context to a lower level error,
+/// the Keystore error found in the context chain is used, so that specific KeyMint error codes
+/// reach the client instead of being collapsed into `ResponseCode::SYSTEM_ERROR`.
 pub fn anyhow_error_to_serialized_error(e: &anyhow::Error) -> SerializedError {
     let root_cause = e.root_cause();
     match root_cause.downcast_ref::<Error>() {
@@ -240,7 +245,10 @@ pub fn anyhow_error_to_serialized_error(e: &anyhow::Error) -> SerializedError {
             Some(selinux::Error::PermissionDenied) => {
                 SerializedError(ResponseCode::PERMISSION_DENIED.0)
             }
-            _ => SerializedError(ResponseCode::SYSTEM_ERROR.0),
+            _ => match e.downcast_ref::<Error>() {
+                Some(e) => error_to_serialized_error(e),
+                None => SerializedError(ResponseCode::SYSTEM_ERROR.0),
+            },
         },
     }
 }
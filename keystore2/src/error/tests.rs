@@ -169,6 +169,51 @@ fn keystore_error_test() -> anyhow::Result<(), String> {
     Ok(())
 }
 
+#[test]
+fn keymint_error_fidelity_test() {
+    // Errors that clients act upon must reach them with their specific KeyMint error code.
+    let codes = [
+        ErrorCode::KEY_EXPIRED,
+        ErrorCode::KEY_NOT_YET_VALID,
+        ErrorCode::KEY_USER_NOT_AUTHENTICATED,
+        ErrorCode::KEY_REQUIRES_UPGRADE,
+        ErrorCode::INVALID_KEY_BLOB,
+        ErrorCode::TOO_MANY_OPERATIONS,
+        ErrorCode::UNSUPPORTED_DIGEST,
+        ErrorCode::INVALID_ARGUMENT,
+    ];
+    for ec in codes {
+        // Received from KeyMint through a Binder Result.
+        assert_eq!(
+            Result::<(), i32>::Err(ec.0),
+            map_km_error(binder_sse_error(ec.0))
+                .context("Received from KeyMint.")
+                .map_err(into_logged_binder)
+                .map_err(|s| s.service_specific_error())
+        );
+        // Raised by Keystore itself.
+        assert_eq!(
+            Result::<(), i32>::Err(ec.0),
+            nested_ec(ec).map_err(into_logged_binder).map_err(|s| s.service_specific_error())
+        );
+        // Attached as context to an error of a different type.
+        assert_eq!(
+            Result::<(), i32>::Err(ec.0),
+            nested_other_error()
+                .context(Error::Km(ec))
+                .context("Outer context.")
+                .map_err(into_logged_binder)
+                .map_err(|s| s.service_specific_error())
+        );
+    }
+    assert_ne!(
+        anyhow_error_to_serialized_error(&anyhow!(Error::Km(ErrorCode::KEY_EXPIRED))),
+        anyhow_error_to_serialized_error(&anyhow!(Error::Km(
+            ErrorCode::KEY_USER_NOT_AUTHENTICATED
+        )))
+    );
+}
+
 //Helper function to test whether error cases are handled as expected.
 pub fn check_result_contains_error_string<T>(
     result: anyhow::Result<T>,
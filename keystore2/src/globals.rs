@@ -19,11 +19,12 @@
 use crate::async_task::AsyncTask;
 use crate::gc::Gc;
 use crate::km_compat::{BacklevelKeyMintWrapper, KeyMintV1};
+use crate::km_version;
 use crate::ks_err;
 use crate::legacy_blob::LegacyBlobLoader;
 use crate::legacy_importer::LegacyImporter;
 use crate::super_key::SuperKeyManager;
-use crate::utils::{retry_get_interface, watchdog as wd};
+use crate::utils::{retry_get_interface, watchdog as wd, KeyMintCapabilities};
 use crate::{
     database::KeystoreDB,
     database::Uuid,
@@ -31,8 +32,8 @@ use crate::{
 };
 use crate::{enforcements::Enforcements, error::map_km_error};
 use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
-    IKeyMintDevice::BpKeyMintDevice, IKeyMintDevice::IKeyMintDevice,
-    KeyMintHardwareInfo::KeyMintHardwareInfo, SecurityLevel::SecurityLevel,
+    Algorithm::Algorithm, IKeyMintDevice::BpKeyMintDevice, IKeyMintDevice::IKeyMintDevice,
+    KeyMintHardwareInfo::KeyMintHardwareInfo, KeyPurpose::KeyPurpose, SecurityLevel::SecurityLevel,
 };
 use android_hardware_security_keymint::binder::{StatusCode, Strong};
 use android_hardware_security_rkp::aidl::android::hardware::security::keymint::{
@@ -52,7 +53,7 @@ use std::sync::{
     Arc, LazyLock, Mutex, RwLock,
 };
 use std::{cell::RefCell, sync::Once};
-use std::{collections::HashMap, path::Path, path::PathBuf};
+use std::{collections::HashMap, path::Path, path::PathBuf, time::Duration, time::Instant};
 
 static DB_INIT: Once = Once::new();
 
@@ -98,9 +99,81 @@ thread_local! {
     pub static DB: RefCell<KeystoreDB> = RefCell::new(create_thread_local_db());
 }
 
+/// Number of times `with_db_recovering` replaces the thread local connection before it gives up.
+const DB_CORRUPTION_RETRIES: usize = 1;
+
+/// Returns true if `e` was caused by an SQLite error indicating that the database or the
+/// connection to it is corrupt.
+pub fn is_db_corruption_error(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        matches!(
+            cause.downcast_ref::<rusqlite::Error>(),
+            Some(rusqlite::Error::SqliteFailure(
+                libsqlite3_sys::Error {
+                    code: libsqlite3_sys::ErrorCode::DatabaseCorrupt
+                        | libsqlite3_sys::ErrorCode::NotADatabase,
+                    ..
+                },
+                _,
+            ))
+        )
+    })
+}
+
+/// Runs `f` and, while it fails with a corruption error, calls `reconnect` and runs `f` again,
+/// at most `DB_CORRUPTION_RETRIES` times. If `reconnect` fails, the error of `f` is returned.
+fn retry_on_db_corruption<T>(
+    mut f: impl FnMut() -> Result<T>,
+    mut reconnect: impl FnMut() -> Result<()>,
+) -> Result<T> {
+    let mut retries = 0;
+    loop {
+        match f() {
+            Err(e) if retries < DB_CORRUPTION_RETRIES && is_db_corruption_error(&e) => {
+                log::warn!("Database corruption detected, reopening the connection: {e:?}");
+                if let Err(reconnect_error) = reconnect() {
+                    log::error!("Failed to reopen the database: {reconnect_error:?}");
+                    return Err(e);
+                }
+                retries += 1;
+            }
+            Ok(v) if retries != 0 => {
+                log::info!("Recovered from database corruption with a new connection.");
+                return Ok(v);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Runs `f` with the thread local database connection like `DB.with`. If `f` fails with a
+/// corruption error, e.g. `SQLITE_CORRUPT` on a read, the cached connection is replaced with a
+/// new one and `f` is retried once. If no new connection can be opened, the old one is kept and
+/// the error of `f` is returned. `f` must be safe to run twice, so this is meant for reads and
+/// for writes that run in a single transaction.
+pub fn with_db_recovering<T>(mut f: impl FnMut(&mut KeystoreDB) -> Result<T>) -> Result<T> {
+    DB.with(|db| {
+        retry_on_db_corruption(
+            || f(&mut db.borrow_mut()),
+            || {
+                let db_path = DB_PATH.read().expect("Could not get the database directory");
+                *db.borrow_mut() = KeystoreDB::new(&db_path, Some(GC.clone()))
+                    .context(ks_err!("Failed to reopen the database at {db_path:?}."))?;
+                Ok(())
+            },
+        )
+    })
+}
+
+/// Time after which `get_keymint_device` re-queries the hardware info of a cached KeyMint device,
+/// so that changed metadata of a HAL that was updated in place is picked up without reconnecting.
+/// `None` disables the refresh.
+const KEYMINT_HW_INFO_TTL: Option<Duration> = None;
+
 struct DevicesMap<T: FromIBinder + ?Sized> {
     devices_by_uuid: HashMap<Uuid, (Strong<T>, KeyMintHardwareInfo)>,
     uuid_by_sec_level: HashMap<SecurityLevel, Uuid>,
+    hw_info_fetched_at: HashMap<Uuid, Instant>,
 }
 
 impl<T: FromIBinder + ?Sized> DevicesMap<T> {
@@ -130,6 +203,25 @@ impl<T: FromIBinder + ?Sized> DevicesMap<T> {
         let uuid: Uuid = sec_level.into();
         self.devices_by_uuid.insert(uuid, (dev, hw_info));
         self.uuid_by_sec_level.insert(sec_level, uuid);
+        self.hw_info_fetched_at.insert(uuid, Instant::now());
+    }
+
+    /// Returns true if the cached hardware info of `uuid` is at least `ttl` old at `now`.
+    fn hw_info_is_stale(&self, uuid: &Uuid, ttl: Option<Duration>, now: Instant) -> bool {
+        match (ttl, self.hw_info_fetched_at.get(uuid)) {
+            (Some(ttl), Some(fetched_at)) => now.saturating_duration_since(*fetched_at) >= ttl,
+            _ => false,
+        }
+    }
+
+    /// Replaces the cached hardware info of `uuid`, keeping the version number that
+    /// `connect_keymint` normalized.
+    fn update_hw_info(&mut self, uuid: &Uuid, mut hw_info: KeyMintHardwareInfo, now: Instant) {
+        if let Some((_, cached)) = self.devices_by_uuid.get_mut(uuid) {
+            hw_info.versionNumber = cached.versionNumber;
+            *cached = hw_info;
+            self.hw_info_fetched_at.insert(*uuid, now);
+        }
     }
 }
 
@@ -138,6 +230,7 @@ impl<T: FromIBinder + ?Sized> Default for DevicesMap<T> {
         Self {
             devices_by_uuid: HashMap::<Uuid, (Strong<T>, KeyMintHardwareInfo)>::new(),
             uuid_by_sec_level: Default::default(),
+            hw_info_fetched_at: Default::default(),
         }
     }
 }
@@ -150,6 +243,10 @@ pub static SUPER_KEY: LazyLock<Arc<RwLock<SuperKeyManager>>> = LazyLock::new(Def
 /// Map of KeyMint devices.
 static KEY_MINT_DEVICES: LazyLock<Mutex<DevicesMap<dyn IKeyMintDevice>>> =
     LazyLock::new(Default::default);
+/// Key purposes supported by each KeyMint instance, per algorithm. Filled lazily by
+/// `get_supported_purposes`.
+static SUPPORTED_PURPOSES: LazyLock<Mutex<HashMap<(Uuid, Algorithm), Vec<KeyPurpose>>>> =
+    LazyLock::new(Default::default);
 /// Timestamp service.
 static TIME_STAMP_DEVICE: Mutex<Option<Strong<dyn ISecureClock>>> = Mutex::new(None);
 /// A single on-demand worker thread that handles deferred tasks with two different
@@ -243,13 +340,9 @@ fn connect_keymint(
                 map_binder_status_code(binder::get_interface(&service_name))
             }
             .context(ks_err!("Trying to connect to genuine KeyMint service."))?;
-        // Map the HAL version code for KeyMint to be <AIDL version> * 100, so
-        // - V1 is 100
-        // - V2 is 200
-        // - V3 is 300
-        // etc.
-        let km_version = km.getInterfaceVersion()?;
-        (km, Some(km_version * 100))
+        // Map the HAL version code for KeyMint to be <AIDL version> * 100, see km_version.
+        let aidl_version = km.getInterfaceVersion()?;
+        (km, Some(km_version::aidl_to_internal(aidl_version)))
     } else {
         // This is a no-op if it was called before.
         keystore2_km_compat::add_keymint_device_service();
@@ -277,7 +370,9 @@ fn connect_keymint(
     // If the KeyMint device is back-level, use a wrapper that intercepts and
     // emulates things that are not supported by the hardware.
     let keymint = match hal_version {
-        Some(400) | Some(300) | Some(200) => {
+        Some(km_version::KEY_MINT_V4)
+        | Some(km_version::KEY_MINT_V3)
+        | Some(km_version::KEY_MINT_V2) => {
             // KeyMint v2+: use as-is (we don't have any software emulation of v3 or v4-specific KeyMint features).
             log::info!(
                 "KeyMint device is current version ({:?}) for security level: {:?}",
@@ -286,7 +381,7 @@ fn connect_keymint(
             );
             keymint
         }
-        Some(100) => {
+        Some(km_version::KEY_MINT_V1) => {
             // KeyMint v1: perform software emulation.
             log::info!(
                 "Add emulation wrapper around {:?} device for security level: {:?}",
@@ -338,12 +433,83 @@ fn connect_keymint(
     Ok((keymint, hw_info))
 }
 
+/// Outcome of probing the key purposes that a KeyMint instance supports for an algorithm.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PurposeProbe {
+    /// The purposes that the instance supports.
+    pub supported: Vec<KeyPurpose>,
+    /// False if the support of some purpose could not be determined, e.g., because of a
+    /// transient KeyMint error. Such a probe is not cached.
+    pub conclusive: bool,
+}
+
+/// Returns the key purposes that the KeyMint instance `km_uuid` supports for `algorithm`. A
+/// conclusive result is cached, so `probe` only runs until an instance and algorithm were
+/// probed successfully. `probe` runs without holding the cache lock, because it may have to
+/// make several calls to KeyMint.
+pub fn get_supported_purposes<F>(
+    km_uuid: &Uuid,
+    algorithm: Algorithm,
+    probe: F,
+) -> Result<Vec<KeyPurpose>>
+where
+    F: FnOnce() -> Result<PurposeProbe>,
+{
+    if let Some(purposes) = SUPPORTED_PURPOSES.lock().unwrap().get(&(*km_uuid, algorithm)) {
+        return Ok(purposes.clone());
+    }
+    let probe = probe().context(ks_err!("Failed to probe supported purposes."))?;
+    if probe.conclusive {
+        SUPPORTED_PURPOSES
+            .lock()
+            .unwrap()
+            .entry((*km_uuid, algorithm))
+            .or_insert_with(|| probe.supported.clone());
+    }
+    Ok(probe.supported)
+}
+
+/// Re-queries the hardware info of the cached KeyMint device `uuid` if it is at least `ttl` old.
+/// The device is queried without holding the lock on `devices`, so that a slow HAL does not
+/// block other callers.
+fn refresh_stale_hw_info(
+    devices: &Mutex<DevicesMap<dyn IKeyMintDevice>>,
+    uuid: &Uuid,
+    ttl: Option<Duration>,
+    now: Instant,
+) -> Result<()> {
+    let dev = {
+        let devices_map = devices.lock().unwrap();
+        if !devices_map.hw_info_is_stale(uuid, ttl, now) {
+            return Ok(());
+        }
+        let Some((dev, _, _)) = devices_map.dev_by_uuid(uuid) else {
+            return Ok(());
+        };
+        dev
+    };
+    let _wp = wd::watch("refresh_stale_hw_info: calling IKeyMintDevice::getHardwareInfo()");
+    let hw_info =
+        map_km_error(dev.getHardwareInfo()).context(ks_err!("Failed to get hardware info."))?;
+    devices.lock().unwrap().update_hw_info(uuid, hw_info, now);
+    Ok(())
+}
+
 /// Get a keymint device for the given security level either from our cache or
 /// by making a new connection. Returns the device, the hardware info and the uuid.
 /// TODO the latter can be removed when the uuid is part of the hardware info.
 pub fn get_keymint_device(
     security_level: &SecurityLevel,
 ) -> Result<(Strong<dyn IKeyMintDevice>, KeyMintHardwareInfo, Uuid)> {
+    let uuid = KEY_MINT_DEVICES.lock().unwrap().uuid_by_sec_level.get(security_level).copied();
+    if let Some(uuid) = uuid {
+        // A failed refresh is not fatal, the cached hardware info is still usable.
+        if let Err(e) =
+            refresh_stale_hw_info(&KEY_MINT_DEVICES, &uuid, KEYMINT_HW_INFO_TTL, Instant::now())
+        {
+            log::warn!("Failed to refresh KeyMint hardware info: {e:?}");
+        }
+    }
     let mut devices_map = KEY_MINT_DEVICES.lock().unwrap();
     if let Some((dev, hw_info, uuid)) = devices_map.dev_by_sec_level(security_level) {
         Ok((dev, hw_info, uuid))
@@ -376,6 +542,66 @@ pub fn get_keymint_devices() -> Vec<Strong<dyn IKeyMintDevice>> {
     KEY_MINT_DEVICES.lock().unwrap().devices()
 }
 
+/// Describes how Keystore talks to a device with the given normalized version number, i.e.,
+/// whether the device is used directly or through one of the compatibility wrappers.
+fn emulation_status(version: i32) -> &'static str {
+    if version >= km_version::KEY_MINT_V2 {
+        "native"
+    } else if version >= km_version::KEY_MINT_V1 {
+        "emulated KeyMint V1"
+    } else {
+        "emulated KeyMaster"
+    }
+}
+
+/// Formats the capability report of the given security levels as a single line. Levels whose
+/// device could not be connected are left out.
+fn capability_report<F>(security_levels: &[SecurityLevel], get_hw_info: F) -> String
+where
+    F: Fn(&SecurityLevel) -> Result<KeyMintHardwareInfo>,
+{
+    let entries: Vec<String> = security_levels
+        .iter()
+        .filter_map(|sec_level| match get_hw_info(sec_level) {
+            Ok(hw_info) => Some((sec_level, hw_info)),
+            Err(e) => {
+                log::info!("No KeyMint device at {sec_level:?} for capability report: {e:?}");
+                None
+            }
+        })
+        .map(|(sec_level, hw_info)| {
+            // Only report what can be derived from the hardware info. KeyMint does not report
+            // the algorithms or curves it supports.
+            let capabilities = KeyMintCapabilities::new(&hw_info);
+            format!(
+                "{sec_level:?}: {} by {} v{} ({}), attest_key: {}, curve_25519: {}",
+                hw_info.keyMintName,
+                hw_info.keyMintAuthorName,
+                hw_info.versionNumber,
+                emulation_status(hw_info.versionNumber),
+                capabilities.supports_attest_key(),
+                capabilities.supports_curve_25519(),
+            )
+        })
+        .collect();
+    if entries.is_empty() {
+        "no KeyMint devices available".to_string()
+    } else {
+        entries.join("; ")
+    }
+}
+
+/// Logs a one line summary of the capabilities of every KeyMint device. This is best effort,
+/// security levels without a device are skipped. It is meant to be called once at startup after
+/// the devices have been connected, so that the report is served from the device cache.
+pub fn log_capability_report() {
+    let report = capability_report(
+        &[SecurityLevel::TRUSTED_ENVIRONMENT, SecurityLevel::STRONGBOX],
+        |sec_level| get_keymint_device(sec_level).map(|(_, hw_info, _)| hw_info),
+    );
+    log::info!("KeyMint capabilities: {report}");
+}
+
 /// Make a new connection to a secure clock service.
 /// If no native SecureClock device can be found brings up the compatibility service and attempts
 /// to connect to the legacy wrapper.
@@ -454,6 +680,20 @@ pub fn get_remotely_provisioned_component_name(security_level: &SecurityLevel) -
     .context(ks_err!("Failed to get rpc for sec level {:?}", *security_level))
 }
 
+/// Cleared if the RNG failed its startup health check and key generation must be refused.
+static RNG_HEALTHY: AtomicBool = AtomicBool::new(true);
+
+/// Indicates whether new keys may be generated, i.e., whether the RNG was not found to be
+/// broken at startup.
+pub fn rng_healthy() -> bool {
+    RNG_HEALTHY.load(Ordering::Acquire)
+}
+
+/// Makes `rng_healthy` return false for the remaining lifetime of the process.
+pub fn mark_rng_unhealthy() {
+    RNG_HEALTHY.store(false, Ordering::Release);
+}
+
 /// Whether boot is complete.
 static BOOT_COMPLETED: AtomicBool = AtomicBool::new(false);
 
@@ -464,6 +704,23 @@ pub fn boot_completed() -> bool {
     BOOT_COMPLETED.load(Ordering::Acquire)
 }
 
+/// Delay between attempts to watch for boot completion.
+const BOOT_COMPLETED_RETRY_DELAY: Duration = Duration::from_secs(5);
+/// Upper bound of the random jitter added to [`BOOT_COMPLETED_RETRY_DELAY`], so that retries of
+/// independent subsystems don't stay in lockstep.
+const BOOT_COMPLETED_RETRY_JITTER_MS: u64 = 2_000;
+
+/// Returns the delay before the next attempt to watch for boot completion. If no random data
+/// can be obtained the delay is returned without jitter.
+fn boot_completed_retry_delay() -> Duration {
+    let jitter_ms = keystore2_crypto::generate_random_data(8)
+        .map(|bytes| {
+            u64::from_ne_bytes(bytes.try_into().unwrap()) % (BOOT_COMPLETED_RETRY_JITTER_MS + 1)
+        })
+        .unwrap_or(0);
+    BOOT_COMPLETED_RETRY_DELAY + Duration::from_millis(jitter_ms)
+}
+
 /// Monitor the system property for boot complete.  This blocks and so needs to be run in a separate
 /// thread.
 pub fn await_boot_completed() {
@@ -474,7 +731,7 @@ pub fn await_boot_completed() {
     log::info!("monitoring for sys.boot_completed=1");
     while let Err(e) = watch_for_boot_completed() {
         log::error!("failed to watch for boot_completed: {e:?}");
-        std::thread::sleep(std::time::Duration::from_secs(5));
+        std::thread::sleep(boot_completed_retry_delay());
     }
 
     BOOT_COMPLETED.store(true, Ordering::Release);
@@ -490,3 +747,153 @@ fn watch_for_boot_completed() -> Result<()> {
     w.wait_for_value("1", None).context(ks_err!("Failed to wait for sys.boot_completed"))?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn corruption_error() -> anyhow::Error {
+        anyhow::Error::new(rusqlite::Error::SqliteFailure(
+            libsqlite3_sys::Error::new(libsqlite3_sys::SQLITE_CORRUPT),
+            None,
+        ))
+        .context("Failed to load key entry.")
+    }
+
+    #[test]
+    fn test_retry_on_db_corruption() {
+        use std::cell::Cell;
+
+        assert!(is_db_corruption_error(&corruption_error()));
+        assert!(!is_db_corruption_error(&anyhow::anyhow!("not an sqlite error")));
+
+        // A transient corruption is recovered from with a new connection.
+        let reconnects = Cell::new(0);
+        let attempts = Cell::new(0);
+        let result = retry_on_db_corruption(
+            || {
+                attempts.set(attempts.get() + 1);
+                if reconnects.get() == 0 {
+                    Err(corruption_error())
+                } else {
+                    Ok(42)
+                }
+            },
+            || {
+                reconnects.set(reconnects.get() + 1);
+                Ok(())
+            },
+        );
+        assert_eq!(result.unwrap(), 42);
+        assert_eq!((attempts.get(), reconnects.get()), (2, 1));
+
+        // Persistent corruption is retried only once.
+        reconnects.set(0);
+        attempts.set(0);
+        let result: Result<()> = retry_on_db_corruption(
+            || {
+                attempts.set(attempts.get() + 1);
+                Err(corruption_error())
+            },
+            || {
+                reconnects.set(reconnects.get() + 1);
+                Ok(())
+            },
+        );
+        assert!(is_db_corruption_error(&result.unwrap_err()));
+        assert_eq!((attempts.get(), reconnects.get()), (2, 1));
+
+        // Other errors are not retried.
+        attempts.set(0);
+        let result: Result<()> = retry_on_db_corruption(
+            || {
+                attempts.set(attempts.get() + 1);
+                Err(anyhow::anyhow!("key not found"))
+            },
+            || panic!("Must not reconnect."),
+        );
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 1);
+
+        // If the database cannot be reopened, the original error is returned without a retry.
+        attempts.set(0);
+        let result: Result<()> = retry_on_db_corruption(
+            || {
+                attempts.set(attempts.get() + 1);
+                Err(corruption_error())
+            },
+            || Err(anyhow::anyhow!("cannot open database")),
+        );
+        assert!(is_db_corruption_error(&result.unwrap_err()));
+        assert_eq!(attempts.get(), 1);
+    }
+
+    #[test]
+    fn test_boot_completed_retry_delay() {
+        let max_delay =
+            BOOT_COMPLETED_RETRY_DELAY + Duration::from_millis(BOOT_COMPLETED_RETRY_JITTER_MS);
+        let delays: Vec<Duration> = (0..32).map(|_| boot_completed_retry_delay()).collect();
+        assert!(delays.iter().all(|d| *d >= BOOT_COMPLETED_RETRY_DELAY && *d <= max_delay));
+        // With 32 samples from 2001 possible values, identical delays throughout would mean
+        // that no jitter is applied.
+        assert!(delays.iter().any(|d| *d != delays[0]));
+    }
+
+    #[test]
+    #[cfg_attr(not(target_os = "android"), ignore)]
+    fn test_refresh_stale_hw_info() -> Result<()> {
+        let (dev, hw_info, uuid) = get_keymint_device(&SecurityLevel::TRUSTED_ENVIRONMENT)?;
+        let mut devices_map = DevicesMap::<dyn IKeyMintDevice>::default();
+        let stale_info =
+            KeyMintHardwareInfo { keyMintName: "stale".to_string(), ..hw_info.clone() };
+        devices_map.insert(SecurityLevel::TRUSTED_ENVIRONMENT, dev, stale_info);
+        let ttl = Some(Duration::from_secs(60));
+        let devices = Mutex::new(devices_map);
+        let cached_info = || devices.lock().unwrap().dev_by_uuid(&uuid).unwrap().1;
+
+        // Nothing is refreshed before the TTL elapses, or without a TTL.
+        refresh_stale_hw_info(&devices, &uuid, ttl, Instant::now())?;
+        refresh_stale_hw_info(&devices, &uuid, None, Instant::now() + ttl.unwrap())?;
+        assert_eq!(cached_info().keyMintName, "stale");
+
+        refresh_stale_hw_info(&devices, &uuid, ttl, Instant::now() + ttl.unwrap())?;
+        assert_eq!(cached_info().keyMintName, hw_info.keyMintName);
+        assert_eq!(cached_info().versionNumber, hw_info.versionNumber);
+        Ok(())
+    }
+
+    #[test]
+    fn test_capability_report() {
+        let get_hw_info = |sec_level: &SecurityLevel| match *sec_level {
+            SecurityLevel::TRUSTED_ENVIRONMENT => Ok(KeyMintHardwareInfo {
+                versionNumber: km_version::KEY_MINT_V3,
+                securityLevel: SecurityLevel::TRUSTED_ENVIRONMENT,
+                keyMintName: "tee".to_string(),
+                keyMintAuthorName: "author".to_string(),
+                ..Default::default()
+            }),
+            SecurityLevel::SOFTWARE => Ok(KeyMintHardwareInfo {
+                versionNumber: km_version::KEY_MASTER_V4_1,
+                keyMintName: "km".to_string(),
+                ..Default::default()
+            }),
+            _ => Err(Error::Km(ErrorCode::HARDWARE_TYPE_UNAVAILABLE).into()),
+        };
+
+        let report = capability_report(
+            &[SecurityLevel::TRUSTED_ENVIRONMENT, SecurityLevel::STRONGBOX],
+            get_hw_info,
+        );
+        assert_eq!(
+            report,
+            "TRUSTED_ENVIRONMENT: tee by author v300 (native), attest_key: true, curve_25519: true"
+        );
+
+        let report = capability_report(&[SecurityLevel::SOFTWARE], get_hw_info);
+        assert!(report.contains("v41 (emulated KeyMaster)"));
+        assert!(report.contains("attest_key: false, curve_25519: false"));
+
+        let report = capability_report(&[SecurityLevel::STRONGBOX], get_hw_info);
+        assert_eq!(report, "no KeyMint devices available");
+    }
+}
@@ -23,8 +23,11 @@ use keystore2::service::KeystoreService;
 use keystore2::{apc::ApcManager, shared_secret_negotiation};
 use keystore2::{authorization::AuthorizationManager, id_rotation::IdRotationState};
 use legacykeystore::LegacyKeystore;
-use log::{error, info};
+use log::{error, info, warn};
 use rusqlite::trace as sqlite_trace;
+use rustutils::system_properties::read_bool;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
 use std::{os::raw::c_int, panic, path::Path, sync::mpsc::channel};
 
 static KS2_SERVICE_NAME: &str = "android.system.keystore2.IKeystoreService/default";
@@ -73,6 +76,30 @@ fn main() {
     unsafe { sqlite_trace::config_log(Some(sqlite_log_handler)) }
         .expect("Error setting sqlite log callback.");
 
+    info!("Crypto library: {}", keystore2_crypto::library_version());
+
+    // Optionally verify the crypto library with known answer tests before it touches any keys.
+    if read_bool("keystore.crypto_self_test", false).unwrap_or(false) {
+        match keystore2_crypto::self_test() {
+            Ok(()) => info!("Crypto self test passed."),
+            Err(e) => error!("Crypto self test failed: {:?}", e),
+        }
+    }
+
+    // Catch a catastrophically broken RNG before it is used to generate keys. A healthy RNG fails
+    // the statistical tests once in a while, so the check is retried once before giving up.
+    let rng_check_failed = (1..=2).all(|attempt| {
+        keystore2_crypto::self_test::rng_health_check()
+            .map_err(|e| warn!("RNG health check attempt {} failed: {:?}", attempt, e))
+            .is_err()
+    });
+    if rng_check_failed {
+        if read_bool("keystore.refuse_keygen_on_rng_failure", false).unwrap_or(false) {
+            warn!("Refusing to generate keys.");
+            keystore2::globals::mark_rng_unhealthy();
+        }
+    }
+
     // Write/update keystore.crash_count system property.
     metrics_store::update_keystore_crash_sysprop();
 
@@ -89,6 +116,16 @@ fn main() {
         let db_path = Path::new(&dir);
         *keystore2::globals::DB_PATH.write().expect("Could not lock DB_PATH.") =
             db_path.to_path_buf();
+        // A mislabeled database directory causes hard to diagnose failures later on, so
+        // restore its label before the database is opened. Failing to do so is not fatal.
+        match CString::new(db_path.as_os_str().as_bytes()) {
+            Ok(c_path) => {
+                if let Err(e) = keystore2_selinux::restore_file_context(&c_path) {
+                    error!("Failed to restore the label of {:?}: {:?}", db_path, e);
+                }
+            }
+            Err(e) => error!("Database path {:?} is not a valid C string: {:?}", db_path, e),
+        }
         IdRotationState::new(db_path)
     } else {
         panic!("Must specify a database directory.");
@@ -105,12 +142,14 @@ fn main() {
     info!("Starting thread pool now.");
     binder::ProcessState::start_thread_pool();
 
-    let ks_service = KeystoreService::new_native_binder(id_rotation_state).unwrap_or_else(|e| {
-        panic!("Failed to create service {} because of {:?}.", KS2_SERVICE_NAME, e);
-    });
+    let ks_service =
+        KeystoreService::new_native_binder(id_rotation_state.clone()).unwrap_or_else(|e| {
+            panic!("Failed to create service {} because of {:?}.", KS2_SERVICE_NAME, e);
+        });
     binder::add_service(KS2_SERVICE_NAME, ks_service.as_binder()).unwrap_or_else(|e| {
         panic!("Failed to register service {} because of {:?}.", KS2_SERVICE_NAME, e);
     });
+    keystore2::globals::log_capability_report();
 
     let apc_service =
         ApcManager::new_native_binder(confirmation_token_sender).unwrap_or_else(|e| {
@@ -132,9 +171,10 @@ fn main() {
         &keystore2::globals::DB_PATH.read().expect("Could not get DB_PATH."),
     );
 
-    let maintenance_service = Maintenance::new_native_binder(delete_listener).unwrap_or_else(|e| {
-        panic!("Failed to create service {} because of {:?}.", USER_MANAGER_SERVICE_NAME, e);
-    });
+    let maintenance_service = Maintenance::new_native_binder(delete_listener, id_rotation_state)
+        .unwrap_or_else(|e| {
+            panic!("Failed to create service {} because of {:?}.", USER_MANAGER_SERVICE_NAME, e);
+        });
     binder::add_service(USER_MANAGER_SERVICE_NAME, maintenance_service.as_binder()).unwrap_or_else(
         |e| {
             panic!("Failed to register service {} because of {:?}.", USER_MANAGER_SERVICE_NAME, e);
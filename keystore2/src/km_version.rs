@@ -0,0 +1,82 @@
+// Copyright 2024, The Android Open Source Project
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! This module defines the normalized version numbers that Keystore 2.0 stores in the
+//! `versionNumber` field of the `KeyMintHardwareInfo` of every KeyMint device.
+//!
+//! The versionNumber reported by a KeyMint implementation is implementation defined and thus
+//! meaningless to Keystore 2.0. It is replaced by <AIDL version> * 100. The legacy wrapper
+//! around KeyMaster devices reports 10 * <major> + <minor> of the underlying HAL instead.
+//! Together this keeps the version number monotonically increasing across KeyMaster and
+//! KeyMint, so comparisons like `version >= KEY_MINT_V1` are valid.
+
+/// Version number of KeyMasterDevice@V3_0
+pub const KEY_MASTER_V3_0: i32 = 30;
+/// Version number of KeyMasterDevice@V4_0
+pub const KEY_MASTER_V4_0: i32 = 40;
+/// Version number of KeyMasterDevice@V4_1
+pub const KEY_MASTER_V4_1: i32 = 41;
+/// Version number of KeyMintDevice@V1
+pub const KEY_MINT_V1: i32 = 100;
+/// Version number of KeyMintDevice@V2
+pub const KEY_MINT_V2: i32 = 200;
+/// Version number of KeyMintDevice@V3
+pub const KEY_MINT_V3: i32 = 300;
+/// Version number of KeyMintDevice@V4
+pub const KEY_MINT_V4: i32 = 400;
+
+/// Maps the AIDL interface version of a KeyMint device to its normalized version number.
+pub fn aidl_to_internal(aidl_version: i32) -> i32 {
+    aidl_version * 100
+}
+
+/// Maps the HAL version of a KeyMaster device to the normalized version number reported by
+/// the legacy wrapper.
+pub fn keymaster_to_internal(major: i32, minor: i32) -> i32 {
+    10 * major + minor
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aidl_to_internal() {
+        assert_eq!(aidl_to_internal(1), KEY_MINT_V1);
+        assert_eq!(aidl_to_internal(2), KEY_MINT_V2);
+        assert_eq!(aidl_to_internal(3), KEY_MINT_V3);
+        assert_eq!(aidl_to_internal(4), KEY_MINT_V4);
+    }
+
+    #[test]
+    fn test_keymaster_to_internal() {
+        assert_eq!(keymaster_to_internal(3, 0), KEY_MASTER_V3_0);
+        assert_eq!(keymaster_to_internal(4, 0), KEY_MASTER_V4_0);
+        assert_eq!(keymaster_to_internal(4, 1), KEY_MASTER_V4_1);
+    }
+
+    #[test]
+    fn test_versions_are_monotonic() {
+        let versions = [
+            KEY_MASTER_V3_0,
+            KEY_MASTER_V4_0,
+            KEY_MASTER_V4_1,
+            KEY_MINT_V1,
+            KEY_MINT_V2,
+            KEY_MINT_V3,
+            KEY_MINT_V4,
+        ];
+        assert!(versions.windows(2).all(|w| w[0] < w[1]));
+    }
+}
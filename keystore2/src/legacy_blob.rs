@@ -366,18 +366,31 @@ impl LegacyBlobLoader {
     fn new_from_stream(stream: &mut dyn Read) -> Result<Blob> {
         let mut buffer = Vec::new();
         stream.read_to_end(&mut buffer).context(ks_err!())?;
+        Self::parse_blob(&buffer).context(ks_err!())
+    }
 
+    /// Parses the content of a legacy blob file without decrypting it. Unlike the loading
+    /// functions, this does not touch the file system or any global state, and it never panics,
+    /// whatever the input. This makes it the entry point for fuzzing the legacy blob format.
+    pub fn parse_blob(buffer: &[u8]) -> Result<Blob> {
         if buffer.len() < Self::COMMON_HEADER_SIZE {
             return Err(Error::BadLen).context(ks_err!())?;
         }
+        let header_field = |offset: usize, len: usize| {
+            buffer.get(offset..offset + len).ok_or(Error::BadLen).context(ks_err!())
+        };
+        let header_byte =
+            |offset: usize| buffer.get(offset).copied().ok_or(Error::BadLen).context(ks_err!());
 
-        let version: u8 = buffer[Self::VERSION_OFFSET];
+        let version: u8 = header_byte(Self::VERSION_OFFSET)?;
 
-        let flags: u8 = buffer[Self::FLAGS_OFFSET];
-        let blob_type: u8 = buffer[Self::TYPE_OFFSET];
+        let flags: u8 = header_byte(Self::FLAGS_OFFSET)?;
+        let blob_type: u8 = header_byte(Self::TYPE_OFFSET)?;
         let is_encrypted = flags & (flags::ENCRYPTED | flags::SUPER_ENCRYPTED) != 0;
-        let salt = match buffer[Self::SALT_SIZE_OFFSET] as usize {
-            Self::SALT_SIZE => Some(&buffer[buffer.len() - Self::SALT_SIZE..buffer.len()]),
+        let salt = match header_byte(Self::SALT_SIZE_OFFSET)? as usize {
+            Self::SALT_SIZE => {
+                buffer.len().checked_sub(Self::SALT_SIZE).and_then(|start| buffer.get(start..))
+            }
             _ => None,
         };
 
@@ -386,19 +399,22 @@ impl LegacyBlobLoader {
                 .context(ks_err!("Unknown blob version: {}.", version));
         }
 
-        let length = u32::from_be_bytes(
-            buffer[Self::LENGTH_OFFSET..Self::LENGTH_OFFSET + 4].try_into().unwrap(),
-        ) as usize;
-        if buffer.len() < Self::COMMON_HEADER_SIZE + length {
-            return Err(Error::BadLen).context(ks_err!(
-                "Expected: {} got: {}.",
-                Self::COMMON_HEADER_SIZE + length,
-                buffer.len()
-            ));
-        }
-        let value = &buffer[Self::COMMON_HEADER_SIZE..Self::COMMON_HEADER_SIZE + length];
-        let iv = &buffer[Self::IV_OFFSET..Self::IV_OFFSET + Self::IV_SIZE];
-        let tag = &buffer[Self::AEAD_TAG_OFFSET..Self::AEAD_TAG_OFFSET + Self::GCM_TAG_LENGTH];
+        let length_bytes: [u8; 4] = header_field(Self::LENGTH_OFFSET, 4)?
+            .try_into()
+            .map_err(|_| Error::BadLen)
+            .context(ks_err!())?;
+        let length = u32::from_be_bytes(length_bytes) as usize;
+        let value = Self::COMMON_HEADER_SIZE
+            .checked_add(length)
+            .and_then(|end| buffer.get(Self::COMMON_HEADER_SIZE..end))
+            .ok_or(Error::BadLen)
+            .context(ks_err!(
+                "Expected: {} more bytes after the header, got: {}.",
+                length,
+                buffer.len() - Self::COMMON_HEADER_SIZE
+            ))?;
+        let iv = header_field(Self::IV_OFFSET, Self::IV_SIZE)?;
+        let tag = header_field(Self::AEAD_TAG_OFFSET, Self::GCM_TAG_LENGTH)?;
 
         match (blob_type, is_encrypted, salt) {
             (blob_types::GENERIC, false, _) => {
@@ -456,7 +472,7 @@ impl LegacyBlobLoader {
             }),
             (blob_types::KM_BLOB, false, _) => Ok(Blob {
                 flags,
-                value: BlobValue::Decrypted(value.try_into().context("In new_from_stream.")?),
+                value: BlobValue::Decrypted(value.try_into().context("In parse_blob.")?),
             }),
             (blob_types::SUPER_KEY, _, None) | (blob_types::SUPER_KEY_AES256, _, None) => {
                 Err(KsError::Rc(ResponseCode::VALUE_CORRUPTED))
@@ -602,7 +618,8 @@ impl LegacyBlobLoader {
                     KeyParameterValue::new_from_tag_primitive_pair(
                         tag,
                         indirect_buffer
-                            .get(indirect_offset..indirect_offset + blob_size)
+                            .get(indirect_offset..)
+                            .and_then(|indirect| indirect.get(..blob_size))
                             .context("While reading blob value.")?
                             .to_vec(),
                     )
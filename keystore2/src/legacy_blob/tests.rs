@@ -144,6 +144,65 @@ fn read_golden_key_blob_too_short_test() {
     assert_eq!(Some(&Error::BadLen), error.root_cause().downcast_ref::<Error>());
 }
 
+#[test]
+fn parse_blob_test() {
+    assert_eq!(
+        LegacyBlobLoader::parse_blob(BLOB).unwrap().value(),
+        &BlobValue::Generic([0xde, 0xed, 0xbe, 0xef].to_vec())
+    );
+
+    // Every truncation of a valid blob is rejected.
+    for len in 0..BLOB.len() {
+        let error = LegacyBlobLoader::parse_blob(&BLOB[..len]).unwrap_err();
+        assert_eq!(Some(&Error::BadLen), error.root_cause().downcast_ref::<Error>(), "{len}");
+    }
+
+    let with_header = |version: u8, blob_type: u8, info: u8, length: [u8; 4]| {
+        let mut blob = BLOB.to_vec();
+        blob[0] = version;
+        blob[1] = blob_type;
+        blob[3] = info;
+        blob[36..40].copy_from_slice(&length);
+        blob
+    };
+    let value_corrupted = Some(&KsError::Rc(ResponseCode::VALUE_CORRUPTED));
+
+    // Unknown version.
+    let error = LegacyBlobLoader::parse_blob(&with_header(2, 1, 0, [0, 0, 0, 4])).unwrap_err();
+    assert_eq!(value_corrupted, error.root_cause().downcast_ref::<KsError>());
+
+    // Length beyond the end of the buffer, including the largest representable length.
+    for length in [[0, 0, 0, 5], [0xff, 0xff, 0xff, 0xff]] {
+        let error = LegacyBlobLoader::parse_blob(&with_header(3, 1, 0, length)).unwrap_err();
+        assert_eq!(Some(&Error::BadLen), error.root_cause().downcast_ref::<Error>());
+    }
+
+    // Super key without a salt and unknown blob type.
+    for blob_type in [2, 0x7f] {
+        let error =
+            LegacyBlobLoader::parse_blob(&with_header(3, blob_type, 0, [0, 0, 0, 4])).unwrap_err();
+        assert_eq!(value_corrupted, error.root_cause().downcast_ref::<KsError>());
+    }
+}
+
+#[test]
+fn read_key_parameters_malformed_test() {
+    // Truncated key parameter lists must fail gracefully.
+    for len in 0..KEY_PARAMETERS.len() {
+        let _ = LegacyBlobLoader::read_key_parameters(&mut &KEY_PARAMETERS[..len]);
+    }
+
+    // A blob parameter whose indirect offset and size overflow.
+    let mut params = Vec::new();
+    params.extend_from_slice(&0u32.to_ne_bytes()); // indirect_size
+    params.extend_from_slice(&1u32.to_ne_bytes()); // element_count
+    params.extend_from_slice(&12u32.to_ne_bytes()); // elements_size
+    params.extend_from_slice(&Tag::APPLICATION_ID.0.to_ne_bytes());
+    params.extend_from_slice(&u32::MAX.to_ne_bytes()); // blob_length
+    params.extend_from_slice(&u32::MAX.to_ne_bytes()); // indirect_offset
+    assert!(LegacyBlobLoader::read_key_parameters(&mut &params[..]).is_err());
+}
+
 #[test]
 fn test_is_empty() {
     let temp_dir = TempDir::new("test_is_empty").expect("Failed to create temp dir.");
@@ -28,6 +28,7 @@ pub mod globals;
 pub mod id_rotation;
 /// Internal Representation of Key Parameter and convenience functions.
 pub mod key_parameter;
+pub mod km_version;
 pub mod legacy_blob;
 pub mod legacy_importer;
 pub mod maintenance;
@@ -14,27 +14,38 @@
 
 //! This module implements IKeystoreMaintenance AIDL interface.
 
-use crate::database::{KeyEntryLoadBits, KeyType};
+use crate::audit_log::log_key_deleted;
+use crate::database::{DateTime, KeyEntryLoadBits, KeyType, KeyUpgradeReason as DbKeyUpgradeReason};
+use crate::error::anyhow_error_to_serialized_error;
 use crate::error::into_logged_binder;
 use crate::error::map_km_error;
 use crate::error::Error;
 use crate::globals::get_keymint_device;
-use crate::globals::{DB, ENCODED_MODULE_INFO, LEGACY_IMPORTER, SUPER_KEY};
+use crate::globals::{DB, ENCODED_MODULE_INFO, ENFORCEMENTS, LEGACY_IMPORTER, SUPER_KEY};
+use crate::id_rotation::IdRotationState;
+use crate::key_parameter::KeyParameter as KsKeyParameter;
 use crate::ks_err;
 use crate::permission::{KeyPerm, KeystorePerm};
+use crate::raw_device::KeyMintDevice;
+use crate::security_level::KeystoreSecurityLevel;
+use crate::service::KeystoreService;
 use crate::super_key::SuperKeyManager;
 use crate::utils::{
     check_dump_permission, check_get_app_uids_affected_by_sid_permissions, check_key_permission,
-    check_keystore_permission, uid_to_android_user, watchdog as wd,
+    check_keystore_permission, exportable_key_blob, uid_to_android_user, watchdog as wd,
+    BulkResult,
 };
 use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
-    ErrorCode::ErrorCode, IKeyMintDevice::IKeyMintDevice, KeyParameter::KeyParameter, KeyParameterValue::KeyParameterValue, SecurityLevel::SecurityLevel, Tag::Tag,
+    Algorithm::Algorithm, ErrorCode::ErrorCode, HardwareAuthenticatorType::HardwareAuthenticatorType, IKeyMintDevice::IKeyMintDevice, KeyParameter::KeyParameter, KeyParameterValue::KeyParameterValue, KeyPurpose::KeyPurpose, SecurityLevel::SecurityLevel, Tag::Tag,
 };
 use apex_aidl_interface::aidl::android::apex::{
     IApexService::IApexService,
 };
-use android_security_maintenance::aidl::android::security::maintenance::IKeystoreMaintenance::{
-    BnKeystoreMaintenance, IKeystoreMaintenance,
+use android_security_maintenance::aidl::android::security::maintenance::{
+    EffectiveEnforcements::EffectiveEnforcements,
+    IKeystoreMaintenance::{BnKeystoreMaintenance, IKeystoreMaintenance},
+    KeyAccessLogEntry::KeyAccessLogEntry, KeyDeletionResult::KeyDeletionResult,
+    KeyGrant::KeyGrant, KeyUpgradeReason::KeyUpgradeReason,
 };
 use android_security_maintenance::binder::{
     BinderFeatures, Interface, Result as BinderResult, Strong, ThreadState,
@@ -42,7 +53,9 @@ use android_security_maintenance::binder::{
 use android_security_metrics::aidl::android::security::metrics::{
     KeystoreAtomPayload::KeystoreAtomPayload::StorageStats
 };
+use android_system_keystore2::aidl::android::system::keystore2::Authorization::Authorization;
 use android_system_keystore2::aidl::android::system::keystore2::KeyDescriptor::KeyDescriptor;
+use android_system_keystore2::aidl::android::system::keystore2::KeyMetadata::KeyMetadata;
 use android_system_keystore2::aidl::android::system::keystore2::ResponseCode::ResponseCode;
 use anyhow::{anyhow, Context, Result};
 use binder::wait_for_interface;
@@ -92,15 +105,17 @@ pub trait DeleteListener {
 /// This struct is defined to implement the aforementioned AIDL interface.
 pub struct Maintenance {
     delete_listener: Box<dyn DeleteListener + Send + Sync + 'static>,
+    id_rotation_state: IdRotationState,
 }
 
 impl Maintenance {
     /// Create a new instance of Keystore Maintenance service.
     pub fn new_native_binder(
         delete_listener: Box<dyn DeleteListener + Send + Sync + 'static>,
+        id_rotation_state: IdRotationState,
     ) -> Result<Strong<dyn IKeystoreMaintenance>> {
         Ok(BnKeystoreMaintenance::new_binder(
-            Self { delete_listener },
+            Self { delete_listener, id_rotation_state },
             BinderFeatures { set_requesting_sid: true, ..BinderFeatures::default() },
         ))
     }
@@ -111,7 +126,7 @@ impl Maintenance {
         check_keystore_permission(KeystorePerm::ChangeUser).context(ks_err!())?;
 
         DB.with(|db| {
-            SUPER_KEY.write().unwrap().remove_user(
+            SUPER_KEY.write().unwrap().wipe_user(
                 &mut db.borrow_mut(),
                 &LEGACY_IMPORTER,
                 user_id as u32,
@@ -396,6 +411,244 @@ impl Maintenance {
         })
     }
 
+    fn import_migrated_key(
+        &self,
+        security_level: SecurityLevel,
+        key: &KeyDescriptor,
+        params: &[KeyParameter],
+        flags: i32,
+        key_data: &[u8],
+        creation_date_ms: i64,
+    ) -> Result<KeyMetadata> {
+        // Check the permission before connecting to the KeyMint instance.
+        check_keystore_permission(KeystorePerm::ImportMigratedKey).context(ks_err!())?;
+        let sec_level = KeystoreSecurityLevel::new(security_level, self.id_rotation_state.clone())
+            .context(ks_err!("Failed to get security level {security_level:?}."))?;
+        sec_level.import_key_for_migration(
+            key,
+            params,
+            flags,
+            key_data,
+            DateTime::from_millis_epoch(creation_date_ms),
+        )
+    }
+
+    fn get_supported_purposes(
+        security_level: SecurityLevel,
+        algorithm: Algorithm,
+    ) -> Result<Vec<KeyPurpose>> {
+        check_keystore_permission(KeystorePerm::GetSupportedPurposes).context(ks_err!())?;
+        KeyMintDevice::get(security_level)
+            .context(ks_err!("Failed to get KeyMint device for {security_level:?}."))?
+            .supported_purposes(algorithm)
+            .context(ks_err!())
+    }
+
+    fn validate_operation_params(
+        &self,
+        security_level: SecurityLevel,
+        key: &KeyDescriptor,
+        operation_parameters: &[KeyParameter],
+        purpose: KeyPurpose,
+    ) -> Result<()> {
+        let sec_level = KeystoreSecurityLevel::new(security_level, self.id_rotation_state.clone())
+            .context(ks_err!("Failed to get security level {security_level:?}."))?;
+        sec_level.validate_operation_params(key, operation_parameters, purpose)
+    }
+
+    fn get_key_creation_flags(key: &KeyDescriptor) -> Result<i32> {
+        DB.with(|db| {
+            db.borrow_mut().get_key_creation_flags(key, ThreadState::get_calling_uid(), |k, av| {
+                check_key_permission(KeyPerm::GetInfo, k, &av)
+            })
+        })
+        .context(ks_err!())
+    }
+
+    fn get_key_access_log(key: &KeyDescriptor) -> Result<Vec<KeyAccessLogEntry>> {
+        check_dump_permission().context(ks_err!("Checking dump permission."))?;
+        let entries = DB
+            .with(|db| {
+                db.borrow_mut().get_key_access_log(key, ThreadState::get_calling_uid(), |k, av| {
+                    check_key_permission(KeyPerm::GetInfo, k, &av)
+                })
+            })
+            .context(ks_err!())?;
+        Ok(entries
+            .into_iter()
+            .map(|entry| KeyAccessLogEntry {
+                uid: entry.uid as i32,
+                timestampMs: entry.timestamp.to_millis_epoch(),
+                purpose: entry.purpose,
+            })
+            .collect())
+    }
+
+    fn list_grants(key: &KeyDescriptor) -> Result<Vec<KeyGrant>> {
+        let grants = DB
+            .with(|db| {
+                db.borrow_mut().list_grants(key, ThreadState::get_calling_uid(), |k| {
+                    check_key_permission(KeyPerm::Grant, k, &None)
+                })
+            })
+            .context(ks_err!())?;
+        Ok(grants
+            .into_iter()
+            .map(|(grantee_uid, access_vector)| KeyGrant {
+                granteeUid: grantee_uid as i32,
+                accessVector: access_vector.into(),
+            })
+            .collect())
+    }
+
+    fn revoke_all_grants(key: &KeyDescriptor) -> Result<i32> {
+        let revoked = DB
+            .with(|db| {
+                db.borrow_mut().revoke_all_grants(key, ThreadState::get_calling_uid(), |k| {
+                    check_key_permission(KeyPerm::Grant, k, &None)
+                })
+            })
+            .context(ks_err!())?;
+        i32::try_from(revoked).context(ks_err!("Too many revoked grants: {revoked}."))
+    }
+
+    fn set_key_exportable(key: &KeyDescriptor) -> Result<()> {
+        check_keystore_permission(KeystorePerm::ExportKeyBlob)
+            .context(ks_err!("Caller may not mark keys exportable."))?;
+        DB.with(|db| {
+            db.borrow_mut().set_key_exportable(
+                key,
+                KeyType::Client,
+                ThreadState::get_calling_uid(),
+                |k, av| check_key_permission(KeyPerm::ManageBlob, k, &av),
+            )
+        })
+        .context(ks_err!())
+    }
+
+    fn export_blob(key: &KeyDescriptor) -> Result<Vec<u8>> {
+        check_keystore_permission(KeystorePerm::ExportKeyBlob)
+            .context(ks_err!("Caller may not export blobs."))?;
+        let (_, key_entry) = DB
+            .with(|db| {
+                db.borrow_mut().load_key_entry(
+                    key,
+                    KeyType::Client,
+                    KeyEntryLoadBits::KM,
+                    ThreadState::get_calling_uid(),
+                    |k, av| check_key_permission(KeyPerm::ManageBlob, k, &av),
+                )
+            })
+            .context(ks_err!("while trying to load key."))?;
+        exportable_key_blob(&key_entry).context(ks_err!())
+    }
+
+    fn tighten_key_parameters(key: &KeyDescriptor, authorizations: &[Authorization]) -> Result<()> {
+        let params: Vec<KsKeyParameter> = authorizations
+            .iter()
+            .map(|auth| KsKeyParameter::new((&auth.keyParameter).into(), auth.securityLevel))
+            .collect();
+        DB.with(|db| {
+            db.borrow_mut().tighten_key_parameters(
+                key,
+                KeyType::Client,
+                ThreadState::get_calling_uid(),
+                &params,
+                |k, av| check_key_permission(KeyPerm::Rebind, k, &av),
+            )
+        })
+        .context(ks_err!())
+    }
+
+    fn get_effective_enforcements(key: &KeyDescriptor) -> Result<EffectiveEnforcements> {
+        check_dump_permission().context(ks_err!("Checking dump permission."))?;
+        let (_, key_entry) = DB
+            .with(|db| {
+                db.borrow_mut().load_key_entry(
+                    key,
+                    KeyType::Client,
+                    KeyEntryLoadBits::NONE,
+                    ThreadState::get_calling_uid(),
+                    |k, av| check_key_permission(KeyPerm::GetInfo, k, &av),
+                )
+            })
+            .context(ks_err!("while trying to load key info."))?;
+        let view = ENFORCEMENTS.effective_enforcements(&key_entry.into_key_parameters());
+        Ok(EffectiveEnforcements {
+            authRequired: view.auth_required,
+            authType: view.auth_type.unwrap_or(HardwareAuthenticatorType::NONE),
+            authTimeoutSeconds: view.auth_timeout.unwrap_or(-1),
+            authTokenValid: view.auth_token_valid.unwrap_or(false),
+            remainingUses: view.remaining_uses.unwrap_or(-1),
+            activeDateTimeMs: view.active_datetime.unwrap_or(-1),
+            originationExpireDateTimeMs: view.origination_expire_datetime.unwrap_or(-1),
+            usageExpireDateTimeMs: view.usage_expire_datetime.unwrap_or(-1),
+            notYetValid: view.not_yet_valid,
+            originationExpired: view.origination_expired,
+            usageExpired: view.usage_expired,
+            earlyBootOnly: view.early_boot_only,
+            confirmationRequired: view.confirmation_required,
+            unlockedDeviceRequired: view.unlocked_device_required,
+            deviceLocked: view.device_locked,
+            maxBootLevel: view.max_boot_level.unwrap_or(-1),
+            bootLevelExceeded: view.boot_level_exceeded,
+        })
+    }
+
+    fn get_key_upgrade_reason(key: &KeyDescriptor) -> Result<KeyUpgradeReason> {
+        check_dump_permission().context(ks_err!("Checking dump permission."))?;
+        let (_, key_entry) = DB
+            .with(|db| {
+                db.borrow_mut().load_key_entry(
+                    key,
+                    KeyType::Client,
+                    KeyEntryLoadBits::KM,
+                    ThreadState::get_calling_uid(),
+                    |k, av| check_key_permission(KeyPerm::GetInfo, k, &av),
+                )
+            })
+            .context(ks_err!("while trying to load key info."))?;
+        let reason = key_entry
+            .key_blob_info()
+            .as_ref()
+            .and_then(|(_, blob_metadata)| blob_metadata.upgrade_reason().copied());
+        Ok(match reason {
+            None => KeyUpgradeReason::NONE,
+            Some(DbKeyUpgradeReason::KeyRequiresUpgrade) => KeyUpgradeReason::KEY_REQUIRES_UPGRADE,
+            Some(DbKeyUpgradeReason::KeymasterHardwareBlob) => {
+                KeyUpgradeReason::KEYMASTER_HARDWARE_BLOB
+            }
+            Some(DbKeyUpgradeReason::KeymasterSoftwareBlob) => {
+                KeyUpgradeReason::KEYMASTER_SOFTWARE_BLOB
+            }
+            Some(DbKeyUpgradeReason::KeyMintSoftwareBlob) => {
+                KeyUpgradeReason::KEYMINT_SOFTWARE_BLOB
+            }
+        })
+    }
+
+    fn delete_keys(keys: &[KeyDescriptor]) -> Vec<KeyDeletionResult> {
+        let results = BulkResult::run(keys.iter().cloned(), |key| {
+            let result = KeystoreService::delete_key(key);
+            log_key_deleted(key, ThreadState::get_calling_uid(), result.is_ok());
+            result
+        });
+        results
+            .into_results()
+            .into_iter()
+            .map(|(key, result)| {
+                let error_code = match result {
+                    Ok(()) => 0,
+                    Err(e) => {
+                        log::error!("Failed to delete {key:?}: {e:?}");
+                        anyhow_error_to_serialized_error(&e).0
+                    }
+                };
+                KeyDeletionResult { key, errorCode: error_code }
+            })
+            .collect()
+    }
+
     fn delete_all_keys() -> Result<()> {
         // Security critical permission check. This statement must return on fail.
         check_keystore_permission(KeystorePerm::DeleteAllKeys)
@@ -436,6 +689,13 @@ impl Maintenance {
         }
         writeln!(f)?;
 
+        // Display the outcome of the shared secret negotiation.
+        match crate::shared_secret_negotiation::last_negotiation_report() {
+            Some(report) => write!(f, "Shared secret negotiation:\n{report}")?,
+            None => writeln!(f, "Shared secret negotiation not concluded")?,
+        }
+        writeln!(f)?;
+
         // Display module attestation information
         {
             let info = ENCODED_MODULE_INFO.read().unwrap();
@@ -474,6 +734,32 @@ impl Maintenance {
         }
         writeln!(f)?;
 
+        // Display in-flight calls guarded by the watchdog, closest to their threshold first.
+        let watch_points = wd::active_watch_points();
+        writeln!(f, "In-flight watched calls: {}", watch_points.len())?;
+        for wp in watch_points {
+            writeln!(
+                f,
+                "  {} on {}: elapsed {:?} of {:?} (remaining {:?}){}",
+                wp.id,
+                wp.thread,
+                wp.elapsed,
+                wp.timeout,
+                wp.remaining(),
+                wp.context.map(|ctx| format!(" for {ctx}")).unwrap_or_default()
+            )?;
+        }
+        writeln!(f)?;
+
+        // Display the garbage collection backlog.
+        match DB.with(|db| db.borrow_mut().get_gc_backlog(false)) {
+            Ok(backlog) => {
+                writeln!(f, "Key blobs pending garbage collection: {}", backlog.pending_key_blobs)?
+            }
+            Err(e) => writeln!(f, "Failed to retrieve garbage collection backlog: {e:?}")?,
+        }
+        writeln!(f)?;
+
         // Display database config information.
         writeln!(f, "Database configuration:")?;
         DB.with(|db| -> std::io::Result<()> {
@@ -640,4 +926,109 @@ impl IKeystoreMaintenance for Maintenance {
         let _wp = wd::watch("IKeystoreMaintenance::getAppUidsAffectedBySid");
         Self::get_app_uids_affected_by_sid(user_id, secure_user_id).map_err(into_logged_binder)
     }
+
+    fn importMigratedKey(
+        &self,
+        security_level: SecurityLevel,
+        key: &KeyDescriptor,
+        params: &[KeyParameter],
+        flags: i32,
+        key_data: &[u8],
+        creation_date_ms: i64,
+    ) -> BinderResult<KeyMetadata> {
+        log::info!("importMigratedKey(sec_level={security_level:?}, key={key:?})");
+        let _wp = wd::watch("IKeystoreMaintenance::importMigratedKey");
+        self.import_migrated_key(security_level, key, params, flags, key_data, creation_date_ms)
+            .map_err(into_logged_binder)
+    }
+
+    fn getSupportedPurposes(
+        &self,
+        security_level: SecurityLevel,
+        algorithm: Algorithm,
+    ) -> BinderResult<Vec<KeyPurpose>> {
+        log::info!("getSupportedPurposes(sec_level={security_level:?}, algorithm={algorithm:?})");
+        let _wp = wd::watch("IKeystoreMaintenance::getSupportedPurposes");
+        Self::get_supported_purposes(security_level, algorithm).map_err(into_logged_binder)
+    }
+
+    fn getKeyCreationFlags(&self, key: &KeyDescriptor) -> BinderResult<i32> {
+        log::info!("getKeyCreationFlags(key={key:?})");
+        let _wp = wd::watch("IKeystoreMaintenance::getKeyCreationFlags");
+        Self::get_key_creation_flags(key).map_err(into_logged_binder)
+    }
+
+    fn validateOperationParams(
+        &self,
+        security_level: SecurityLevel,
+        key: &KeyDescriptor,
+        operation_parameters: &[KeyParameter],
+        purpose: KeyPurpose,
+    ) -> BinderResult<()> {
+        log::info!(
+            "validateOperationParams(sec_level={security_level:?}, key={key:?}, \
+            purpose={purpose:?})"
+        );
+        let _wp = wd::watch("IKeystoreMaintenance::validateOperationParams");
+        self.validate_operation_params(security_level, key, operation_parameters, purpose)
+            .map_err(into_logged_binder)
+    }
+
+    fn getKeyAccessLog(&self, key: &KeyDescriptor) -> BinderResult<Vec<KeyAccessLogEntry>> {
+        log::info!("getKeyAccessLog(key={key:?})");
+        let _wp = wd::watch("IKeystoreMaintenance::getKeyAccessLog");
+        Self::get_key_access_log(key).map_err(into_logged_binder)
+    }
+
+    fn listGrants(&self, key: &KeyDescriptor) -> BinderResult<Vec<KeyGrant>> {
+        log::info!("listGrants(key={key:?})");
+        let _wp = wd::watch("IKeystoreMaintenance::listGrants");
+        Self::list_grants(key).map_err(into_logged_binder)
+    }
+
+    fn revokeAllGrants(&self, key: &KeyDescriptor) -> BinderResult<i32> {
+        log::info!("revokeAllGrants(key={key:?})");
+        let _wp = wd::watch("IKeystoreMaintenance::revokeAllGrants");
+        Self::revoke_all_grants(key).map_err(into_logged_binder)
+    }
+
+    fn setKeyExportable(&self, key: &KeyDescriptor) -> BinderResult<()> {
+        log::info!("setKeyExportable(key={key:?})");
+        let _wp = wd::watch("IKeystoreMaintenance::setKeyExportable");
+        Self::set_key_exportable(key).map_err(into_logged_binder)
+    }
+
+    fn exportBlob(&self, key: &KeyDescriptor) -> BinderResult<Vec<u8>> {
+        log::info!("exportBlob(key={key:?})");
+        let _wp = wd::watch("IKeystoreMaintenance::exportBlob");
+        Self::export_blob(key).map_err(into_logged_binder)
+    }
+
+    fn tightenKeyParameters(
+        &self,
+        key: &KeyDescriptor,
+        authorizations: &[Authorization],
+    ) -> BinderResult<()> {
+        log::info!("tightenKeyParameters(key={key:?})");
+        let _wp = wd::watch("IKeystoreMaintenance::tightenKeyParameters");
+        Self::tighten_key_parameters(key, authorizations).map_err(into_logged_binder)
+    }
+
+    fn getEffectiveEnforcements(&self, key: &KeyDescriptor) -> BinderResult<EffectiveEnforcements> {
+        log::info!("getEffectiveEnforcements(key={key:?})");
+        let _wp = wd::watch("IKeystoreMaintenance::getEffectiveEnforcements");
+        Self::get_effective_enforcements(key).map_err(into_logged_binder)
+    }
+
+    fn getKeyUpgradeReason(&self, key: &KeyDescriptor) -> BinderResult<KeyUpgradeReason> {
+        log::info!("getKeyUpgradeReason(key={key:?})");
+        let _wp = wd::watch("IKeystoreMaintenance::getKeyUpgradeReason");
+        Self::get_key_upgrade_reason(key).map_err(into_logged_binder)
+    }
+
+    fn deleteKeys(&self, keys: &[KeyDescriptor]) -> BinderResult<Vec<KeyDeletionResult>> {
+        log::info!("deleteKeys(count={})", keys.len());
+        let _wp = wd::watch("IKeystoreMaintenance::deleteKeys");
+        Ok(Self::delete_keys(keys))
+    }
 }
@@ -45,8 +45,9 @@ use android_security_metrics::aidl::android::security::metrics::{
     SecurityLevel::SecurityLevel as MetricsSecurityLevel, Storage::Storage as MetricsStorage,
 };
 use anyhow::{anyhow, Context, Result};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
 
 #[cfg(test)]
 mod tests;
@@ -71,6 +72,54 @@ pub static METRICS_STORE: LazyLock<MetricsStore> = LazyLock::new(Default::defaul
 #[derive(Default)]
 pub struct MetricsStore {
     metrics_store: Mutex<HashMap<AtomID, HashMap<KeystoreAtomPayload, i32>>>,
+    cert_chain_sizes: Mutex<[u64; CERT_CHAIN_SIZE_BUCKETS.len()]>,
+    create_operation_latency: Mutex<CreateOperationLatency>,
+}
+
+/// Maximum number of key ids remembered to tell the first use of a key from subsequent uses.
+/// Operations on keys beyond this limit that were not used before are not recorded.
+const MAX_TRACKED_KEY_IDS: usize = 4096;
+
+/// Number of samples and accumulated latency of successful create_operation calls.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LatencyStats {
+    /// Number of recorded operations.
+    pub count: u64,
+    /// Sum of the latencies of all recorded operations.
+    pub total: Duration,
+}
+
+impl LatencyStats {
+    fn add(&mut self, latency: Duration) {
+        self.count += 1;
+        self.total += latency;
+    }
+
+    fn average(&self) -> Duration {
+        match self.count {
+            0 => Duration::ZERO,
+            count => Duration::from_nanos((self.total.as_nanos() / count as u128) as u64),
+        }
+    }
+}
+
+/// Latencies of create_operation, split by whether it was the first operation on a key since
+/// keystore started. The first use may include a key upgrade or super decryption.
+#[derive(Default)]
+struct CreateOperationLatency {
+    used_key_ids: HashSet<i64>,
+    first_use: LatencyStats,
+    subsequent_use: LatencyStats,
+}
+
+/// Inclusive upper bounds in bytes of the buckets used to track the sizes of the certificate
+/// chains stored on key creation. The last bucket catches everything else.
+pub const CERT_CHAIN_SIZE_BUCKETS: [usize; 8] =
+    [1024, 2 * 1024, 4 * 1024, 8 * 1024, 16 * 1024, 32 * 1024, 64 * 1024, usize::MAX];
+
+/// Returns the index of the bucket in `CERT_CHAIN_SIZE_BUCKETS` that covers `size`.
+fn cert_chain_size_bucket(size: usize) -> usize {
+    CERT_CHAIN_SIZE_BUCKETS.iter().position(|bound| size <= *bound).unwrap()
 }
 
 impl std::fmt::Debug for MetricsStore {
@@ -89,6 +138,30 @@ impl std::fmt::Debug for MetricsStore {
             }
             writeln!(f, "  ]")?;
         }
+        drop(store);
+        let histogram = *self.cert_chain_sizes.lock().unwrap();
+        match self.cert_chain_size_p99() {
+            Some(usize::MAX) => {
+                let largest = CERT_CHAIN_SIZE_BUCKETS[CERT_CHAIN_SIZE_BUCKETS.len() - 2];
+                writeln!(f, "  CertChainSize : p99 > {largest} bytes, buckets={histogram:?}")?;
+            }
+            Some(p99) => {
+                writeln!(f, "  CertChainSize : p99 <= {p99} bytes, buckets={histogram:?}")?;
+            }
+            None => {}
+        }
+        let (first_use, subsequent_use) = self.create_operation_latency_stats();
+        if first_use.count + subsequent_use.count > 0 {
+            writeln!(
+                f,
+                "  CreateOperationLatency : first_use count={} avg={:?}, \
+                subsequent_use count={} avg={:?}",
+                first_use.count,
+                first_use.average(),
+                subsequent_use.count,
+                subsequent_use.average()
+            )?;
+        }
         Ok(())
     }
 }
@@ -135,6 +208,56 @@ impl MetricsStore {
         })
     }
 
+    /// Records the size of a certificate chain stored for a newly created key.
+    pub fn record_cert_chain_size(&self, size: usize) {
+        self.cert_chain_sizes.lock().unwrap()[cert_chain_size_bucket(size)] += 1;
+    }
+
+    /// Returns the upper bound of the bucket that holds the 99th percentile of the recorded
+    /// certificate chain sizes, or None if no sizes were recorded yet.
+    pub fn cert_chain_size_p99(&self) -> Option<usize> {
+        let histogram = self.cert_chain_sizes.lock().unwrap();
+        let total: u64 = histogram.iter().sum();
+        if total == 0 {
+            return None;
+        }
+        // Smallest count such that at least 99% of all samples are at or below it.
+        let rank = (total * 99).div_ceil(100);
+        let mut seen = 0;
+        histogram.iter().zip(CERT_CHAIN_SIZE_BUCKETS).find_map(|(count, bound)| {
+            seen += count;
+            (seen >= rank).then_some(bound)
+        })
+    }
+
+    /// Records the latency of a successful create_operation on the key with the given id.
+    /// Returns Some(true) if this was the first operation on the key since keystore started,
+    /// Some(false) if the key was used before, and None if the key is not tracked.
+    pub fn record_create_operation_latency(&self, key_id: i64, latency: Duration) -> Option<bool> {
+        let mut stats = self.create_operation_latency.lock().unwrap();
+        if stats.used_key_ids.contains(&key_id) {
+            stats.subsequent_use.add(latency);
+            Some(false)
+        } else if stats.used_key_ids.len() < MAX_TRACKED_KEY_IDS {
+            stats.used_key_ids.insert(key_id);
+            stats.first_use.add(latency);
+            Some(true)
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if an operation on the key with the given id was recorded before.
+    pub fn key_used_before(&self, key_id: i64) -> bool {
+        self.create_operation_latency.lock().unwrap().used_key_ids.contains(&key_id)
+    }
+
+    /// Returns the create_operation latency stats for first and subsequent uses of keys.
+    pub fn create_operation_latency_stats(&self) -> (LatencyStats, LatencyStats) {
+        let stats = self.create_operation_latency.lock().unwrap();
+        (stats.first_use, stats.subsequent_use)
+    }
+
     /// Insert an atom object to the metrics_store indexed by the atom ID.
     fn insert_atom(&self, atom_id: AtomID, atom: KeystoreAtomPayload) {
         let mut metrics_store_guard = self.metrics_store.lock().unwrap();
@@ -182,6 +305,18 @@ pub fn log_key_creation_event_stats<U>(
     );
 }
 
+/// Log the size of the certificate chain stored with a newly created key. Only the size is
+/// recorded, never the contents.
+pub fn log_cert_chain_size(size: usize) {
+    METRICS_STORE.record_cert_chain_size(size);
+}
+
+/// Log the latency of a successful create_operation on the key with the given id, distinguishing
+/// the first use of the key from subsequent uses. Only the key id is tracked, never key material.
+pub fn log_create_operation_latency(key_id: i64, latency: Duration) {
+    METRICS_STORE.record_create_operation_latency(key_id, latency);
+}
+
 // Process the statistics related to key creations and return the three atom objects related to key
 // creations: i) KeyCreationWithGeneralInfo ii) KeyCreationWithAuthInfo
 // iii) KeyCreationWithPurposeAndModesInfo
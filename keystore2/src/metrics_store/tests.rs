@@ -158,3 +158,63 @@ fn test_security_level() {
         ));
     }
 }
+
+#[test]
+fn test_cert_chain_size_metric() {
+    assert_eq!(cert_chain_size_bucket(0), 0);
+    assert_eq!(cert_chain_size_bucket(1024), 0);
+    assert_eq!(cert_chain_size_bucket(1025), 1);
+    assert_eq!(cert_chain_size_bucket(3000), 2);
+    assert_eq!(cert_chain_size_bucket(1 << 20), CERT_CHAIN_SIZE_BUCKETS.len() - 1);
+
+    let store = MetricsStore::default();
+    assert_eq!(store.cert_chain_size_p99(), None);
+
+    store.record_cert_chain_size(3000);
+    assert_eq!(*store.cert_chain_sizes.lock().unwrap(), [0, 0, 1, 0, 0, 0, 0, 0]);
+    assert_eq!(store.cert_chain_size_p99(), Some(4 * 1024));
+
+    // A single outlier among many small chains stays below the 99th percentile...
+    for _ in 0..199 {
+        store.record_cert_chain_size(900);
+    }
+    store.record_cert_chain_size(100 * 1024);
+    assert_eq!(store.cert_chain_size_p99(), Some(1024));
+
+    // ...but shows up once more than one percent of the chains are bloated.
+    for _ in 0..3 {
+        store.record_cert_chain_size(100 * 1024);
+    }
+    assert_eq!(store.cert_chain_size_p99(), Some(usize::MAX));
+    assert!(format!("{store:?}").contains("CertChainSize"));
+}
+
+#[test]
+fn test_create_operation_first_use_flag() {
+    let store = MetricsStore::default();
+    assert!(!store.key_used_before(7));
+
+    assert_eq!(store.record_create_operation_latency(7, Duration::from_millis(30)), Some(true));
+    assert!(store.key_used_before(7));
+    assert!(!store.key_used_before(8));
+
+    assert_eq!(store.record_create_operation_latency(7, Duration::from_millis(10)), Some(false));
+    assert_eq!(store.record_create_operation_latency(7, Duration::from_millis(20)), Some(false));
+    assert_eq!(
+        store.create_operation_latency_stats(),
+        (
+            LatencyStats { count: 1, total: Duration::from_millis(30) },
+            LatencyStats { count: 2, total: Duration::from_millis(30) }
+        )
+    );
+}
+
+#[test]
+fn test_latency_stats_average() {
+    assert_eq!(LatencyStats::default().average(), Duration::ZERO);
+    let stats = LatencyStats { count: 2, total: Duration::from_millis(30) };
+    assert_eq!(stats.average(), Duration::from_millis(15));
+    // Counts beyond u32::MAX must not be truncated.
+    let stats = LatencyStats { count: 1 << 32, total: Duration::from_secs(1 << 32) };
+    assert_eq!(stats.average(), Duration::from_secs(1));
+}
@@ -319,6 +319,23 @@ impl Operation {
         err
     }
 
+    // Like `update_outcome`, except that the operation stays active if KeyMint rejected the
+    // auth token of an operation that accepts fresh per operation auth tokens. The client can
+    // then supply a new token for the operation challenge and retry, see
+    // `AuthInfo::accepts_fresh_auth_token`.
+    fn update_outcome_unless_reauthorizable<T>(
+        &self,
+        locked_outcome: &mut Outcome,
+        err: Result<T, Error>,
+    ) -> Result<T, Error> {
+        if matches!(err, Err(Error::Km(ErrorCode::KEY_USER_NOT_AUTHENTICATED)))
+            && self.auth_info.lock().unwrap().accepts_fresh_auth_token()
+        {
+            return err;
+        }
+        self.update_outcome(locked_outcome, err)
+    }
+
     // This function grabs the outcome lock and checks the current outcome state.
     // If the outcome is still `Outcome::Unknown`, this function returns
     // the locked outcome for further updates. In any other case it returns
@@ -366,7 +383,7 @@ impl Operation {
             .before_update()
             .context(ks_err!("Trying to get auth tokens for uid {}", self.owner))?;
 
-        self.update_outcome(&mut outcome, {
+        self.update_outcome_unless_reauthorizable(&mut outcome, {
             let _wp = self.watch("Operation::update_aad: calling IKeyMintOperation::updateAad");
             map_km_error(self.km_op.updateAad(aad_input, hat.as_ref(), tst.as_ref()))
         })
@@ -390,7 +407,7 @@ impl Operation {
             .context(ks_err!("Trying to get auth tokens for uid {}", self.owner))?;
 
         let output = self
-            .update_outcome(&mut outcome, {
+            .update_outcome_unless_reauthorizable(&mut outcome, {
                 let _wp = self.watch("Operation::update: calling IKeyMintOperation::update");
                 map_km_error(self.km_op.update(input, hat.as_ref(), tst.as_ref()))
             })
@@ -420,7 +437,7 @@ impl Operation {
             .context(ks_err!("Trying to get auth tokens for uid {}", self.owner))?;
 
         let output = self
-            .update_outcome(&mut outcome, {
+            .update_outcome_unless_reauthorizable(&mut outcome, {
                 let _wp = self.watch("Operation::finish: calling IKeyMintOperation::finish");
                 map_km_error(self.km_op.finish(
                     input,
@@ -873,3 +890,109 @@ impl IKeystoreOperation for KeystoreOperation {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::enforcements::{AuthInfoState, DeferredAuthKind};
+    use crate::globals::ENFORCEMENTS;
+    use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
+        HardwareAuthToken::HardwareAuthToken, IKeyMintOperation::BnKeyMintOperation,
+    };
+    use android_hardware_security_secureclock::aidl::android::hardware::security::secureclock::TimeStampToken::TimeStampToken;
+
+    /// A mock KeyMint operation that echoes its input, but only accepts auth tokens with the
+    /// MAC "fresh".
+    struct MockKeyMintOperation;
+
+    impl MockKeyMintOperation {
+        fn check_auth_token(auth_token: Option<&HardwareAuthToken>) -> binder::Result<()> {
+            match auth_token {
+                Some(hat) if hat.mac == b"fresh" => Ok(()),
+                _ => Err(binder::Status::new_service_specific_error(
+                    ErrorCode::KEY_USER_NOT_AUTHENTICATED.0,
+                    None,
+                )),
+            }
+        }
+    }
+
+    impl binder::Interface for MockKeyMintOperation {}
+
+    impl IKeyMintOperation for MockKeyMintOperation {
+        fn updateAad(
+            &self,
+            _input: &[u8],
+            auth_token: Option<&HardwareAuthToken>,
+            _time_stamp_token: Option<&TimeStampToken>,
+        ) -> binder::Result<()> {
+            Self::check_auth_token(auth_token)
+        }
+        fn update(
+            &self,
+            input: &[u8],
+            auth_token: Option<&HardwareAuthToken>,
+            _time_stamp_token: Option<&TimeStampToken>,
+        ) -> binder::Result<Vec<u8>> {
+            Self::check_auth_token(auth_token).map(|_| input.to_vec())
+        }
+        fn finish(
+            &self,
+            input: Option<&[u8]>,
+            _signature: Option<&[u8]>,
+            auth_token: Option<&HardwareAuthToken>,
+            _time_stamp_token: Option<&TimeStampToken>,
+            _confirmation_token: Option<&[u8]>,
+        ) -> binder::Result<Vec<u8>> {
+            Self::check_auth_token(auth_token).map(|_| input.unwrap_or_default().to_vec())
+        }
+        fn abort(&self) -> binder::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_update_after_auth_token_refresh() -> Result<()> {
+        const CHALLENGE: i64 = 0x212_0212;
+        let state = AuthInfoState {
+            deferred_auth: DeferredAuthKind::OpAuth,
+            key_usage_limited: None,
+            confirmation_required: false,
+        };
+        let mut auth_info = ENFORCEMENTS.restore_auth_info(&state, &[])?;
+        auth_info.finalize_create_authorization(CHALLENGE);
+        let operation = Operation::new(
+            0,
+            BnKeyMintOperation::new_binder(MockKeyMintOperation, BinderFeatures::default()),
+            10001,
+            auth_info,
+            false,
+            LoggingInfo::new(
+                SecurityLevel::TRUSTED_ENVIRONMENT,
+                KeyPurpose::DECRYPT,
+                vec![],
+                false,
+            ),
+        );
+        let token = |mac: &[u8]| HardwareAuthToken {
+            challenge: CHALLENGE,
+            mac: mac.to_vec(),
+            ..Default::default()
+        };
+
+        // KeyMint rejects the expired token, but the operation stays active.
+        ENFORCEMENTS.add_op_auth_token(token(b"expired"));
+        assert_eq!(
+            Some(&Error::Km(ErrorCode::KEY_USER_NOT_AUTHENTICATED)),
+            operation.update(b"data").unwrap_err().root_cause().downcast_ref::<Error>()
+        );
+        assert!(operation.check_active().is_ok());
+
+        // A fresh token for the operation challenge lets it continue.
+        ENFORCEMENTS.add_op_auth_token(token(b"fresh"));
+        assert_eq!(operation.update(b"data")?, Some(b"data".to_vec()));
+        assert_eq!(operation.finish(Some(b"last"), None)?, Some(b"last".to_vec()));
+        assert!(operation.check_active().is_err());
+        Ok(())
+    }
+}
@@ -26,7 +26,7 @@ use android_system_keystore2::aidl::android::system::keystore2::{
 };
 use anyhow::Context as AnyhowContext;
 use keystore2_selinux as selinux;
-use selinux::{implement_class, Backend, ClassPermission};
+use selinux::{implement_class, ClassPermission};
 use std::cmp::PartialEq;
 use std::convert::From;
 use std::ffi::CStr;
@@ -41,13 +41,12 @@ use tests::test_getcon as getcon;
 #[cfg(test)]
 mod tests;
 
-// Panicking here is allowed because keystore cannot function without this backend
-// and it would happen early and indicate a gross misconfiguration of the device.
-static KEYSTORE2_KEY_LABEL_BACKEND: LazyLock<selinux::KeystoreKeyBackend> =
-    LazyLock::new(|| selinux::KeystoreKeyBackend::new().unwrap());
+// Holds the label backends. They are opened on first use.
+static LABEL_BACKENDS: LazyLock<selinux::BackendRegistry> =
+    LazyLock::new(selinux::BackendRegistry::new);
 
 fn lookup_keystore2_key_context(namespace: i64) -> anyhow::Result<selinux::Context> {
-    KEYSTORE2_KEY_LABEL_BACKEND.lookup(&namespace.to_string())
+    LABEL_BACKENDS.lookup(selinux::BackendType::KeystoreKey, &namespace.to_string())
 }
 
 implement_class!(
@@ -150,6 +149,15 @@ implement_class!(
         /// Checked on IKeystoreAuthorization::getLastAuthTime() is called.
         #[selinux(name = get_last_auth_time)]
         GetLastAuthTime,
+        /// Checked when IKeystoreMaintenance::importMigratedKey is called.
+        #[selinux(name = import_migrated_key)]
+        ImportMigratedKey,
+        /// Checked when IKeystoreMaintenance::getSupportedPurposes is called.
+        #[selinux(name = get_supported_purposes)]
+        GetSupportedPurposes,
+        /// Checked when IKeystoreMaintenance::setKeyExportable or exportBlob is called.
+        #[selinux(name = export_key_blob)]
+        ExportKeyBlob,
     }
 );
 
@@ -365,8 +373,15 @@ pub fn check_key_permission(
             }
             getcon().context(ks_err!("getcon failed."))?
         }
-        Domain::SELINUX => lookup_keystore2_key_context(key.nspace)
-            .context(ks_err!("Domain::SELINUX: Failed to lookup namespace."))?,
+        Domain::SELINUX => {
+            // Look up the namespace and check the permission under a single lock, because this
+            // is the hot path of the permission checks.
+            return LABEL_BACKENDS.check_keystore_key_perm(
+                caller_ctx,
+                &key.nspace.to_string(),
+                perm,
+            );
+        }
         Domain::GRANT => {
             match access_vector {
                 Some(_) => {
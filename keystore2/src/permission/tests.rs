@@ -128,6 +128,9 @@ fn check_keystore_permission_test() -> Result<()> {
     assert_perm_failed!(check_keystore_permission(&shell_ctx, KeystorePerm::ChangeUser));
     assert_perm_failed!(check_keystore_permission(&shell_ctx, KeystorePerm::ChangePassword));
     assert_perm_failed!(check_keystore_permission(&shell_ctx, KeystorePerm::ClearUID));
+    assert_perm_failed!(check_keystore_permission(&shell_ctx, KeystorePerm::ImportMigratedKey));
+    assert_perm_failed!(check_keystore_permission(&shell_ctx, KeystorePerm::GetSupportedPurposes));
+    assert_perm_failed!(check_keystore_permission(&shell_ctx, KeystorePerm::ExportKeyBlob));
     Ok(())
 }
 
@@ -18,26 +18,111 @@ use crate::{
     database::{
         BlobInfo, BlobMetaData, BlobMetaEntry, CertificateInfo, DateTime, KeyEntry,
         KeyEntryLoadBits, KeyIdGuard, KeyMetaData, KeyMetaEntry, KeyType, KeystoreDB,
-        Uuid,
+        SubComponentType, Uuid,
     },
-    error::{map_km_error, Error, ErrorCode},
-    globals::get_keymint_device,
-    ks_err,
+    error::{map_binder_status_code, map_km_error, Error, ErrorCode},
+    globals::{
+        get_keymint_dev_by_uuid, get_keymint_device, get_remotely_provisioned_component_name,
+        get_supported_purposes, PurposeProbe,
+    },
+    key_parameter::KeyParameterValue,
+    km_version, ks_err,
     super_key::KeyBlob,
     utils::{key_characteristics_to_internal, watchdog as wd, AID_KEYSTORE},
 };
 use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
+    Algorithm::Algorithm, BlockMode::BlockMode, Digest::Digest, EcCurve::EcCurve,
     HardwareAuthToken::HardwareAuthToken, IKeyMintDevice::IKeyMintDevice,
     IKeyMintOperation::IKeyMintOperation, KeyCharacteristics::KeyCharacteristics,
     KeyCreationResult::KeyCreationResult, KeyParameter::KeyParameter, KeyPurpose::KeyPurpose,
-    SecurityLevel::SecurityLevel,
+    PaddingMode::PaddingMode, SecurityLevel::SecurityLevel,
 };
+use android_hardware_security_rkp::aidl::android::hardware::security::keymint::IRemotelyProvisionedComponent::IRemotelyProvisionedComponent;
 use android_system_keystore2::aidl::android::system::keystore2::{
     Domain::Domain, KeyDescriptor::KeyDescriptor, ResponseCode::ResponseCode,
 };
 use anyhow::{Context, Result};
 use binder::Strong;
 
+/// Key used to derive a stable hardware id from the KeyMint hardware info.
+const HARDWARE_ID_DERIVATION_KEY: &[u8] = b"keystore2 KeyMint hardware id";
+
+/// Key purposes tried by `KeyMintDevice::supported_purposes`.
+const PROBED_PURPOSES: [KeyPurpose; 7] = [
+    KeyPurpose::ENCRYPT,
+    KeyPurpose::DECRYPT,
+    KeyPurpose::SIGN,
+    KeyPurpose::VERIFY,
+    KeyPurpose::WRAP_KEY,
+    KeyPurpose::AGREE_KEY,
+    KeyPurpose::ATTEST_KEY,
+];
+
+/// Returns the minimal key generation parameters for a probe key of the given algorithm.
+fn probe_key_params(algorithm: Algorithm) -> Result<Vec<KeyParameter>> {
+    let mut params: Vec<KeyParameter> = vec![
+        KeyParameterValue::Algorithm(algorithm).into(),
+        KeyParameterValue::NoAuthRequired.into(),
+    ];
+    match algorithm {
+        // Block ciphers get a block mode that every device supports, so that the probe key
+        // can only be refused because of its purpose.
+        Algorithm::AES => params.extend([
+            KeyParameterValue::KeySize(128).into(),
+            KeyParameterValue::BlockMode(BlockMode::CBC).into(),
+            KeyParameterValue::PaddingMode(PaddingMode::NONE).into(),
+        ]),
+        Algorithm::TRIPLE_DES => params.extend([
+            KeyParameterValue::KeySize(168).into(),
+            KeyParameterValue::BlockMode(BlockMode::CBC).into(),
+            KeyParameterValue::PaddingMode(PaddingMode::NONE).into(),
+        ]),
+        Algorithm::HMAC => params.extend([
+            KeyParameterValue::KeySize(256).into(),
+            KeyParameterValue::Digest(Digest::SHA_2_256).into(),
+            KeyParameterValue::MinMacLength(256).into(),
+        ]),
+        Algorithm::EC => params.push(KeyParameterValue::EcCurve(EcCurve::P_256).into()),
+        Algorithm::RSA => params.extend([
+            KeyParameterValue::KeySize(2048).into(),
+            KeyParameterValue::RSAPublicExponent(65537).into(),
+        ]),
+        _ => {
+            return Err(Error::Km(ErrorCode::UNSUPPORTED_ALGORITHM))
+                .context(ks_err!("Cannot probe algorithm {:?}.", algorithm));
+        }
+    }
+    Ok(params)
+}
+
+/// Determines the purposes supported for `algorithm` by attempting to generate a key with each
+/// purpose in `PROBED_PURPOSES` through `generate`. Only `UNSUPPORTED_PURPOSE` and
+/// `INCOMPATIBLE_PURPOSE` mean that a purpose is not supported. Any other KeyMint error leaves
+/// the purpose out but makes the probe inconclusive, and any other error aborts the probe.
+fn probe_supported_purposes<F>(algorithm: Algorithm, mut generate: F) -> Result<PurposeProbe>
+where
+    F: FnMut(&[KeyParameter]) -> Result<(), Error>,
+{
+    let base_params = probe_key_params(algorithm)?;
+    let mut probe = PurposeProbe { supported: Vec::new(), conclusive: true };
+    for purpose in PROBED_PURPOSES {
+        let mut params = base_params.clone();
+        params.push(KeyParameterValue::KeyPurpose(purpose).into());
+        match generate(&params) {
+            Ok(()) => probe.supported.push(purpose),
+            Err(Error::Km(ErrorCode::UNSUPPORTED_PURPOSE | ErrorCode::INCOMPATIBLE_PURPOSE)) => {}
+            Err(Error::Km(e)) => {
+                log::warn!("Probing {purpose:?} for {algorithm:?} failed with {e:?}.");
+                probe.conclusive = false;
+            }
+            Err(e) => {
+                return Err(e).context(ks_err!("Failed to probe {purpose:?} for {algorithm:?}."));
+            }
+        }
+    }
+    Ok(probe)
+}
+
 /// Wrapper for operating directly on a KeyMint device.
 /// These methods often mirror methods in [`crate::security_level`]. However
 /// the functions in [`crate::security_level`] make assumptions that hold, and has side effects
@@ -56,15 +141,15 @@ pub struct KeyMintDevice {
 
 impl KeyMintDevice {
     /// Version number of KeyMasterDevice@V4_0
-    pub const KEY_MASTER_V4_0: i32 = 40;
+    pub const KEY_MASTER_V4_0: i32 = km_version::KEY_MASTER_V4_0;
     /// Version number of KeyMasterDevice@V4_1
-    pub const KEY_MASTER_V4_1: i32 = 41;
+    pub const KEY_MASTER_V4_1: i32 = km_version::KEY_MASTER_V4_1;
     /// Version number of KeyMintDevice@V1
-    pub const KEY_MINT_V1: i32 = 100;
+    pub const KEY_MINT_V1: i32 = km_version::KEY_MINT_V1;
     /// Version number of KeyMintDevice@V2
-    pub const KEY_MINT_V2: i32 = 200;
+    pub const KEY_MINT_V2: i32 = km_version::KEY_MINT_V2;
     /// Version number of KeyMintDevice@V3
-    pub const KEY_MINT_V3: i32 = 300;
+    pub const KEY_MINT_V3: i32 = km_version::KEY_MINT_V3;
 
     /// Get a [`KeyMintDevice`] for the given [`SecurityLevel`]
     pub fn get(security_level: SecurityLevel) -> Result<KeyMintDevice> {
@@ -79,6 +164,21 @@ impl KeyMintDevice {
         })
     }
 
+    /// Get the [`KeyMintDevice`] with the given KeyMint instance uuid. Keys created through it
+    /// are tagged with that uuid, which lets provisioning tools target a specific device when
+    /// there is more than one instance. Fails if no such instance is known.
+    pub fn get_by_uuid(km_uuid: &Uuid) -> Result<KeyMintDevice> {
+        let (km_dev, hw_info) =
+            get_keymint_dev_by_uuid(km_uuid).context(ks_err!("get_keymint_dev_by_uuid failed"))?;
+
+        Ok(KeyMintDevice {
+            km_dev,
+            km_uuid: *km_uuid,
+            version: hw_info.versionNumber,
+            security_level: hw_info.securityLevel,
+        })
+    }
+
     /// Get a [`KeyMintDevice`] for the given [`SecurityLevel`], return
     /// [`None`] if the error `HARDWARE_TYPE_UNAVAILABLE` is returned
     pub fn get_or_none(security_level: SecurityLevel) -> Result<Option<KeyMintDevice>> {
@@ -102,6 +202,71 @@ impl KeyMintDevice {
         self.security_level
     }
 
+    /// Returns the key purposes that this device supports for keys of the given algorithm, so
+    /// that clients can avoid unsupported combinations up front. The first query for an
+    /// algorithm generates (and deletes) a throwaway key per purpose; the result is cached for
+    /// the lifetime of keystore.
+    pub fn supported_purposes(&self, algorithm: Algorithm) -> Result<Vec<KeyPurpose>> {
+        get_supported_purposes(&self.km_uuid, algorithm, || {
+            probe_supported_purposes(algorithm, |params| {
+                let creation_result = map_km_error({
+                    let _wp = wd::watch(
+                        "KeyMintDevice::supported_purposes: calling IKeyMintDevice::generateKey",
+                    );
+                    self.km_dev.generateKey(params, None)
+                })?;
+                // The probe key is never used. Failing to delete it merely leaks a blob that
+                // nobody refers to.
+                let _ = map_km_error({
+                    let _wp = wd::watch(
+                        "KeyMintDevice::supported_purposes: calling IKeyMintDevice::deleteKey",
+                    );
+                    self.km_dev.deleteKey(&creation_result.keyBlob)
+                });
+                Ok(())
+            })
+        })
+        .context(ks_err!())
+    }
+
+    /// Returns an identifier of the secure hardware backing this KeyMint device. It can be
+    /// recorded with key blobs to detect blobs that were created on a different secure element.
+    ///
+    /// KeyMint does not expose a hardware identifier itself, so the `uniqueId` reported by the
+    /// remotely provisioned component of the same security level is used where available.
+    /// Otherwise, a stable identifier is derived from the KeyMint hardware info. Note that the
+    /// derived identifier tells different implementations apart, but not different physical
+    /// instances of the same implementation.
+    pub fn hardware_id(&self) -> Result<Vec<u8>> {
+        if let Some(unique_id) = self.rpc_unique_id() {
+            return Ok(unique_id.into_bytes());
+        }
+        let hw_info = map_km_error({
+            let _wp = wd::watch("KeyMintDevice::hardware_id: calling getHardwareInfo");
+            self.km_dev.getHardwareInfo()
+        })
+        .context(ks_err!("Failed to get hardware info."))?;
+        let description = format!(
+            "{:?}/{}/{}/{}",
+            hw_info.securityLevel,
+            hw_info.keyMintAuthorName,
+            hw_info.keyMintName,
+            hw_info.versionNumber
+        );
+        keystore2_crypto::hmac_sha256(HARDWARE_ID_DERIVATION_KEY, description.as_bytes())
+            .context(ks_err!("Failed to derive hardware id."))
+    }
+
+    /// Returns the `uniqueId` of the remotely provisioned component of the same security level,
+    /// if there is one and it reports an id.
+    fn rpc_unique_id(&self) -> Option<String> {
+        let name = get_remotely_provisioned_component_name(&self.security_level).ok()?;
+        let rpc: Strong<dyn IRemotelyProvisionedComponent> =
+            map_binder_status_code(binder::get_interface(&name)).ok()?;
+        let _wp = wd::watch("KeyMintDevice::rpc_unique_id: calling getHardwareInfo");
+        rpc.getHardwareInfo().ok()?.uniqueId
+    }
+
     /// Create a KM key and store in the database.
     pub fn create_and_store_key<F>(
         &self,
@@ -252,8 +417,8 @@ impl KeyMintDevice {
     /// write the upgraded key to the database.
     fn upgrade_keyblob_if_required_with<'a, T, F>(
         &self,
-        _db: &mut KeystoreDB,
-        _key_id_guard: &KeyIdGuard,
+        db: &mut KeystoreDB,
+        key_id_guard: &KeyIdGuard,
         key_blob: KeyBlob<'a>,
         f: F,
     ) -> Result<(T, KeyBlob<'a>)>
@@ -266,21 +431,18 @@ impl KeyMintDevice {
             &key_blob,
             &[],
             f,
-            |_upgraded_blob| {
+            |upgraded_blob, reason| {
                 let mut new_blob_metadata = BlobMetaData::new();
                 new_blob_metadata.add(BlobMetaEntry::KmUuid(self.km_uuid));
+                new_blob_metadata.add(BlobMetaEntry::UpgradeReason(reason));
 
-                /*
                 db.set_blob(
                     key_id_guard,
                     SubComponentType::KEY_BLOB,
                     Some(upgraded_blob),
                     Some(&new_blob_metadata),
                 )
-                .context(ks_err!("Failed to insert upgraded blob into the database"))?;
-
-*/
-                Ok(())
+                .context(ks_err!("Failed to insert upgraded blob into the database"))
             },
         )?;
         let returned_blob = match upgraded_blob {
@@ -325,3 +487,109 @@ impl KeyMintDevice {
         .context(ks_err!("Failed to finish operation."))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{tests::new_test_db, KEYSTORE_UUID};
+    use android_hardware_security_keymint::aidl::android::hardware::security::keymint::KeyParameterValue::KeyParameterValue as KmKeyParameterValue;
+
+    #[test]
+    #[cfg_attr(not(target_os = "android"), ignore)]
+    fn test_hardware_id_is_stable() -> Result<()> {
+        let Some(km_dev) = KeyMintDevice::get_or_none(SecurityLevel::TRUSTED_ENVIRONMENT)? else {
+            return Ok(());
+        };
+        let hardware_id = km_dev.hardware_id()?;
+        assert!(!hardware_id.is_empty());
+        assert_eq!(hardware_id, km_dev.hardware_id()?);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(not(target_os = "android"), ignore)]
+    fn test_create_key_on_device_selected_by_uuid() -> Result<()> {
+        let Some(tee) = KeyMintDevice::get_or_none(SecurityLevel::TRUSTED_ENVIRONMENT)? else {
+            return Ok(());
+        };
+        let km_dev = KeyMintDevice::get_by_uuid(&tee.km_uuid)?;
+        assert_eq!(km_dev.km_uuid, tee.km_uuid);
+        assert!(KeyMintDevice::get_by_uuid(&KEYSTORE_UUID).is_err());
+
+        let mut db = new_test_db()?;
+        let key_desc = KeyMintDevice::internal_descriptor("uuid_selected_key".to_string());
+        let params: Vec<KeyParameter> = vec![
+            KeyParameterValue::Algorithm(Algorithm::AES).into(),
+            KeyParameterValue::KeySize(256).into(),
+            KeyParameterValue::KeyPurpose(KeyPurpose::ENCRYPT).into(),
+            KeyParameterValue::NoAuthRequired.into(),
+        ];
+        km_dev.create_and_store_key(&mut db, &key_desc, KeyType::Client, |km_dev| {
+            km_dev.generateKey(&params, None)
+        })?;
+
+        let (_, key_entry) = KeyMintDevice::lookup_from_desc(&mut db, &key_desc, KeyType::Client)?;
+        assert_eq!(key_entry.km_uuid(), &tee.km_uuid);
+        let (_, blob_metadata) = key_entry.key_blob_info().as_ref().unwrap();
+        assert_eq!(blob_metadata.km_uuid(), Some(&tee.km_uuid));
+        Ok(())
+    }
+
+    #[test]
+    fn test_probe_supported_purposes() -> Result<()> {
+        // A mock device that only supports encryption and decryption with AES keys.
+        let mut probes = 0;
+        let mock_generate = |params: &[KeyParameter]| {
+            probes += 1;
+            let supported = params.iter().any(|p| {
+                p.value == KmKeyParameterValue::KeyPurpose(KeyPurpose::ENCRYPT)
+                    || p.value == KmKeyParameterValue::KeyPurpose(KeyPurpose::DECRYPT)
+            });
+            if supported {
+                Ok(())
+            } else {
+                Err(Error::Km(ErrorCode::UNSUPPORTED_PURPOSE))
+            }
+        };
+        let purposes = get_supported_purposes(&KEYSTORE_UUID, Algorithm::AES, || {
+            probe_supported_purposes(Algorithm::AES, mock_generate)
+        })?;
+        assert_eq!(purposes, vec![KeyPurpose::ENCRYPT, KeyPurpose::DECRYPT]);
+        assert_eq!(probes, PROBED_PURPOSES.len());
+
+        // The second query is answered from the cache.
+        let cached = get_supported_purposes(&KEYSTORE_UUID, Algorithm::AES, || {
+            panic!("Supported purposes were probed again.")
+        })?;
+        assert_eq!(cached, purposes);
+
+        // The AES probe key is complete apart from its purpose.
+        assert!(probe_key_params(Algorithm::AES)?
+            .iter()
+            .any(|p| p.value == KmKeyParameterValue::BlockMode(BlockMode::CBC)));
+
+        // A transient KeyMint error makes the probe inconclusive, so it is not cached.
+        let flaky_generate = |params: &[KeyParameter]| {
+            if params.iter().any(|p| p.value == KmKeyParameterValue::KeyPurpose(KeyPurpose::SIGN)) {
+                Err(Error::Km(ErrorCode::HARDWARE_TYPE_UNAVAILABLE))
+            } else {
+                Err(Error::Km(ErrorCode::INCOMPATIBLE_PURPOSE))
+            }
+        };
+        let probe = probe_supported_purposes(Algorithm::HMAC, flaky_generate)?;
+        assert_eq!(probe, PurposeProbe { supported: vec![], conclusive: false });
+        get_supported_purposes(&KEYSTORE_UUID, Algorithm::HMAC, || {
+            probe_supported_purposes(Algorithm::HMAC, flaky_generate)
+        })?;
+        let mut probed_again = false;
+        get_supported_purposes(&KEYSTORE_UUID, Algorithm::HMAC, || {
+            probed_again = true;
+            probe_supported_purposes(Algorithm::HMAC, flaky_generate)
+        })?;
+        assert!(probed_again);
+
+        // Errors other than KeyMint errors abort the probe.
+        assert!(probe_supported_purposes(Algorithm::EC, |_| Err(Error::sys())).is_err());
+        Ok(())
+    }
+}
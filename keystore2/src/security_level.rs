@@ -18,44 +18,52 @@ use crate::attestation_key_utils::{get_attest_key_info, AttestationKeyInfo};
 use crate::audit_log::{
     log_key_deleted, log_key_generated, log_key_imported, log_key_integrity_violation,
 };
-use crate::database::{BlobInfo, CertificateInfo, KeyIdGuard};
+use crate::database::{BlobInfo, CertificateInfo, KeyIdGuard, KeystoreDB};
+use crate::enforcements::check_operation_params;
 use crate::error::{
     self, into_logged_binder, map_km_error, wrapped_rkpd_error_to_ks_error, Error, ErrorCode,
 };
 use crate::globals::{
-    get_remotely_provisioned_component_name, DB, ENFORCEMENTS, LEGACY_IMPORTER, SUPER_KEY,
+    get_remotely_provisioned_component_name, rng_healthy, DB, ENFORCEMENTS, LEGACY_IMPORTER,
+    SUPER_KEY,
 };
 use crate::key_parameter::KeyParameter as KsKeyParam;
 use crate::key_parameter::KeyParameterValue as KsKeyParamValue;
 use crate::ks_err;
-use crate::metrics_store::log_key_creation_event_stats;
+use crate::metrics_store::{
+    log_cert_chain_size, log_create_operation_latency, log_key_creation_event_stats,
+};
 use crate::remote_provisioning::RemProvState;
 use crate::super_key::{KeyBlob, SuperKeyManager};
 use crate::utils::{
-    check_device_attestation_permissions, check_key_permission,
-    check_unique_id_attestation_permissions, is_device_id_attestation_tag,
-    key_characteristics_to_internal, log_security_safe_params, uid_to_android_user, watchdog as wd,
-    UNDEFINED_NOT_AFTER,
+    check_device_attestation_permissions, check_key_permission, check_keystore_permission,
+    check_unique_id_attestation_permissions, fingerprint_key_descriptor,
+    is_device_id_attestation_tag, is_system_uid, key_characteristics_to_internal,
+    log_security_safe_params, normalize_cert_chain, uid_to_android_user, watchdog as wd,
+    KeyMintCapabilities, UNDEFINED_NOT_AFTER,
 };
 use crate::{
     database::{
         BlobMetaData, BlobMetaEntry, DateTime, KeyEntry, KeyEntryLoadBits, KeyMetaData,
-        KeyMetaEntry, KeyType, Uuid,
+        KeyMetaEntry, KeyType, KeyUpgradeReason, SubComponentType, Uuid,
     },
     operation::KeystoreOperation,
     operation::LoggingInfo,
     operation::OperationDb,
-    permission::KeyPerm,
+    permission::{KeyPerm, KeystorePerm},
 };
 use crate::{globals::get_keymint_device, id_rotation::IdRotationState};
 use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
     Algorithm::Algorithm, AttestationKey::AttestationKey, Certificate::Certificate,
-    HardwareAuthenticatorType::HardwareAuthenticatorType, IKeyMintDevice::IKeyMintDevice,
+    EcCurve::EcCurve, HardwareAuthenticatorType::HardwareAuthenticatorType,
+    IKeyMintDevice::IKeyMintDevice, KeyCharacteristics::KeyCharacteristics,
     KeyCreationResult::KeyCreationResult, KeyFormat::KeyFormat,
     KeyMintHardwareInfo::KeyMintHardwareInfo, KeyParameter::KeyParameter,
-    KeyParameterValue::KeyParameterValue, SecurityLevel::SecurityLevel, Tag::Tag,
+    KeyParameterValue::KeyParameterValue, KeyPurpose::KeyPurpose, SecurityLevel::SecurityLevel,
+    Tag::Tag,
 };
 use android_hardware_security_keymint::binder::{BinderFeatures, Strong, ThreadState};
+use android_security_maintenance::aidl::android::security::maintenance::IKeystoreMaintenance;
 use android_system_keystore2::aidl::android::system::keystore2::{
     AuthenticatorSpec::AuthenticatorSpec, CreateOperationResponse::CreateOperationResponse,
     Domain::Domain, EphemeralStorageKeyResponse::EphemeralStorageKeyResponse,
@@ -67,8 +75,9 @@ use anyhow::{anyhow, Context, Result};
 use postprocessor_client::process_certificate_chain;
 use rkpd_client::store_rkpd_attestation_key;
 use rustutils::system_properties::read_bool;
-use std::convert::TryInto;
-use std::time::SystemTime;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Instant, SystemTime};
 
 /// Implementation of the IKeystoreSecurityLevel Interface.
 pub struct KeystoreSecurityLevel {
@@ -84,6 +93,392 @@ pub struct KeystoreSecurityLevel {
 // Blob of 32 zeroes used as empty masking key.
 static ZERO_BLOB_32: &[u8] = &[0; 32];
 
+/// Maximum number of keys an app uid may own in `Domain::APP`. This is only enforced if the
+/// `keystore.enforce_key_count_quota` system property is set. System uids are exempt.
+pub const MAX_KEYS_PER_UID: usize = 5000;
+
+/// Maximum number of concurrent `createOperation` calls admitted per uid. This is only enforced
+/// if the `keystore.limit_concurrent_create_operations` system property is set. System uids are
+/// exempt.
+pub const MAX_CONCURRENT_CREATE_OPERATIONS_PER_UID: usize = 8;
+
+/// Admission control for `createOperation`, shared by all security levels. It keeps one uid from
+/// occupying all binder threads with slow operation creations.
+static CREATE_OPERATION_GATE: LazyLock<CreateOperationGate> = LazyLock::new(Default::default);
+
+/// Counts the `createOperation` calls in flight per uid.
+#[derive(Default)]
+struct CreateOperationGate {
+    in_flight: Mutex<HashMap<u32, usize>>,
+}
+
+/// Admits one `createOperation` call of a uid into the gate and releases it on drop.
+struct CreateOperationPermit<'a> {
+    gate: &'a CreateOperationGate,
+    uid: Option<u32>,
+}
+
+impl CreateOperationGate {
+    /// Admits a call from `uid` unless `limit` calls of that uid are already in flight, in which
+    /// case the call is rejected with `ResponseCode::BACKEND_BUSY`. System uids are always
+    /// admitted and are not counted.
+    fn admit(&self, uid: u32, limit: usize) -> Result<CreateOperationPermit<'_>> {
+        if is_system_uid(uid) {
+            return Ok(CreateOperationPermit { gate: self, uid: None });
+        }
+        let mut in_flight = self.in_flight.lock().unwrap();
+        let count = in_flight.entry(uid).or_default();
+        if *count >= limit {
+            return Err(Error::Rc(ResponseCode::BACKEND_BUSY)).context(ks_err!(
+                "uid {uid} has {count} createOperation calls in flight, the limit is {limit}."
+            ));
+        }
+        *count += 1;
+        Ok(CreateOperationPermit { gate: self, uid: Some(uid) })
+    }
+}
+
+impl Drop for CreateOperationPermit<'_> {
+    fn drop(&mut self) {
+        let Some(uid) = self.uid else { return };
+        let mut in_flight = self.gate.in_flight.lock().unwrap();
+        if let Some(count) = in_flight.get_mut(&uid) {
+            *count -= 1;
+            if *count == 0 {
+                in_flight.remove(&uid);
+            }
+        }
+    }
+}
+
+/// Upper bound for the total size in bytes of the certificate chain stored with a new key.
+/// Anything larger indicates a misbehaving KeyMint implementation.
+pub const MAX_CERT_CHAIN_BYTES: usize = 256 * 1024;
+
+/// Upper bound for the size in bytes of the wrapped key data passed to `importWrappedKey`. The
+/// KeyMint API imports wrapped keys in one shot, so the whole payload is held in memory. Real
+/// payloads are a few kilobytes at most, even for large RSA keys.
+pub const MAX_WRAPPED_KEY_DATA_BYTES: usize = 64 * 1024;
+
+/// Returns the creation date to record for a new key. This is the current time, unless the key
+/// is migrated from another key store and brings its original `creation_date` along.
+fn key_creation_date(creation_date: Option<DateTime>) -> Result<DateTime> {
+    match creation_date {
+        Some(creation_date) => Ok(creation_date),
+        None => DateTime::now().context(ks_err!("Trying to make creation time.")),
+    }
+}
+
+/// Raw key material that appears in well known test vectors and must never protect real data.
+const KNOWN_TEST_KEYS: &[&[u8]] = &[
+    // FIPS-197 and NIST SP 800-38A AES-128 key.
+    &[
+        0x2b, 0x7e, 0x15, 0x16, 0x28, 0xae, 0xd2, 0xa6, 0xab, 0xf7, 0x15, 0x88, 0x09, 0xcf, 0x4f,
+        0x3c,
+    ],
+    // NIST SP 800-38A AES-256 key.
+    &[
+        0x60, 0x3d, 0xeb, 0x10, 0x15, 0xca, 0x71, 0xbe, 0x2b, 0x73, 0xae, 0xf0, 0x85, 0x7d, 0x77,
+        0x81, 0x1f, 0x35, 0x2c, 0x07, 0x3b, 0x61, 0x08, 0xd7, 0x2d, 0x98, 0x10, 0xa3, 0x09, 0x14,
+        0xdf, 0xf4,
+    ],
+];
+
+/// Returns true if the raw symmetric key material `key_data` is obviously weak: a single
+/// repeated byte (e.g. all zeros), a counting sequence, a well known test vector key, or a key of
+/// 16 bytes or more made up of fewer than four distinct byte values. The checks are deliberately
+/// conservative so that properly generated keys are never rejected.
+fn is_weak_key_material(key_data: &[u8]) -> bool {
+    if key_data.is_empty() {
+        return false;
+    }
+    if key_data.iter().all(|b| *b == key_data[0]) {
+        return true;
+    }
+    if key_data.windows(2).all(|w| w[1] == w[0].wrapping_add(1)) {
+        return true;
+    }
+    if KNOWN_TEST_KEYS.contains(&key_data) {
+        return true;
+    }
+    let mut seen = [false; 256];
+    key_data.iter().for_each(|b| seen[*b as usize] = true);
+    key_data.len() >= 16 && seen.iter().filter(|s| **s).count() < 4
+}
+
+/// Tags of requested parameters that the characteristics of a newly generated key must reflect.
+const CHECKED_GENERATION_TAGS: [Tag; 3] = [Tag::ALGORITHM, Tag::KEY_SIZE, Tag::PURPOSE];
+
+/// Checks that KeyMint honored the critical parameters of a key generation request, i.e., that
+/// every requested parameter with a tag in `CHECKED_GENERATION_TAGS` appears in the returned
+/// `characteristics`. This catches devices that, e.g., generate a 128 bit key when 256 bits
+/// were requested.
+fn check_generated_characteristics(
+    requested: &[KeyParameter],
+    characteristics: &[KeyCharacteristics],
+) -> Result<()> {
+    for param in requested.iter().filter(|p| CHECKED_GENERATION_TAGS.contains(&p.tag)) {
+        let honored = characteristics
+            .iter()
+            .any(|c| c.authorizations.iter().any(|a| a.tag == param.tag && a.value == param.value));
+        if !honored {
+            return Err(Error::sys()).context(ks_err!(
+                "KeyMint did not honor the requested {:?}: {:?}.",
+                param.tag,
+                param.value
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Confirms that a key blob is gone after `deleteKey` reported success. `get_key_characteristics`
+/// queries the characteristics of the deleted blob, which must fail with `INVALID_KEY_BLOB`.
+/// This catches HALs that report success without destroying the key.
+fn check_key_deleted<F>(get_key_characteristics: F) -> Result<()>
+where
+    F: FnOnce() -> Result<Vec<KeyCharacteristics>, Error>,
+{
+    match get_key_characteristics() {
+        Err(Error::Km(ErrorCode::INVALID_KEY_BLOB)) => Ok(()),
+        Ok(_) => {
+            log::error!("deleteKey reported success, but the key blob is still usable.");
+            Err(Error::sys()).context(ks_err!("Key blob still usable after deleteKey."))
+        }
+        Err(e) => Err(e).context(ks_err!("Failed to probe the deleted key blob.")),
+    }
+}
+
+/// Deletes a key blob with `delete` and, if `verify` is set, confirms the deletion with
+/// `check_key_deleted`. Only keys with `Tag::ROLLBACK_RESISTANCE` are verified, because
+/// `deleteKey` is allowed to be a no-op for all other keys. `get_key_characteristics` must probe
+/// the blob the same way before and after the deletion. If the blob cannot be probed before the
+/// deletion, e.g., because it is bound to an application id or data that Keystore does not know,
+/// the deletion is not verified.
+fn delete_key_blob<D, C>(delete: D, get_key_characteristics: C, verify: bool) -> Result<()>
+where
+    D: FnOnce() -> Result<()>,
+    C: Fn() -> Result<Vec<KeyCharacteristics>, Error>,
+{
+    if !verify {
+        return delete();
+    }
+    let rollback_resistant = match get_key_characteristics() {
+        Ok(characteristics) => characteristics
+            .iter()
+            .any(|c| c.authorizations.iter().any(|a| a.tag == Tag::ROLLBACK_RESISTANCE)),
+        Err(e) => {
+            log::warn!("Cannot verify the deletion of a key blob that cannot be probed: {e:?}");
+            false
+        }
+    };
+    delete()?;
+    if rollback_resistant {
+        check_key_deleted(get_key_characteristics)?;
+    }
+    Ok(())
+}
+
+/// Replaces the key blob of the key `key_id_guard` with `upgraded_blob`. The blob metadata is
+/// completed with the KeyMint instance `km_uuid` and, if the blob was upgraded rather than just
+/// re-encrypted, the `reason` for the upgrade.
+fn store_upgraded_blob(
+    db: &mut KeystoreDB,
+    key_id_guard: &KeyIdGuard,
+    upgraded_blob: &[u8],
+    mut blob_metadata: BlobMetaData,
+    km_uuid: Option<Uuid>,
+    reason: Option<KeyUpgradeReason>,
+) -> Result<()> {
+    if let Some(uuid) = km_uuid {
+        blob_metadata.add(BlobMetaEntry::KmUuid(uuid));
+    }
+    if let Some(reason) = reason {
+        log::info!("Upgraded key blob of key {}: {:?}", key_id_guard.id(), reason);
+        blob_metadata.add(BlobMetaEntry::UpgradeReason(reason));
+    }
+    db.set_blob(key_id_guard, SubComponentType::KEY_BLOB, Some(upgraded_blob), Some(&blob_metadata))
+        .context(ks_err!("Failed to insert upgraded blob into the database."))
+}
+
+/// Returns the total size of the certificate chain of a new key. A chain larger than
+/// `MAX_CERT_CHAIN_BYTES` is rejected, and the new key blob is deleted from `keymint` as it is
+/// never stored.
+fn check_cert_chain_size(
+    keymint: &dyn IKeyMintDevice,
+    key_blob: &[u8],
+    certificate_chain: &[Certificate],
+) -> Result<usize> {
+    let cert_chain_size: usize = certificate_chain.iter().map(|c| c.encodedCertificate.len()).sum();
+    if cert_chain_size > MAX_CERT_CHAIN_BYTES {
+        if let Err(e) = map_km_error(keymint.deleteKey(key_blob)) {
+            log::warn!("Failed to delete the key with the oversized certificate chain: {e:?}");
+        }
+        return Err(Error::Rc(ResponseCode::SYSTEM_ERROR)).context(ks_err!(
+            "Certificate chain of {} bytes exceeds the limit of {} bytes.",
+            cert_chain_size,
+            MAX_CERT_CHAIN_BYTES
+        ));
+    }
+    Ok(cert_chain_size)
+}
+
+/// Rejects wrapped key data larger than `MAX_WRAPPED_KEY_DATA_BYTES`.
+fn check_wrapped_key_data_size(wrapped_data: &[u8]) -> Result<()> {
+    if wrapped_data.len() > MAX_WRAPPED_KEY_DATA_BYTES {
+        return Err(Error::Km(ErrorCode::INVALID_INPUT_LENGTH)).context(ks_err!(
+            "Wrapped key data of {} bytes exceeds the limit of {} bytes.",
+            wrapped_data.len(),
+            MAX_WRAPPED_KEY_DATA_BYTES
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that a key blob was created by the KeyMint instance `km_uuid` it is about to be used
+/// with. Handing a blob to the wrong security level fails at the device with `INVALID_KEY_BLOB`,
+/// which looks like corruption. Blobs without a recorded `KmUuid` are not checked.
+fn check_blob_security_level(blob_metadata: &BlobMetaData, km_uuid: &Uuid) -> Result<()> {
+    match blob_metadata.km_uuid() {
+        Some(blob_uuid) if blob_uuid != km_uuid => Err(Error::Rc(ResponseCode::INVALID_ARGUMENT))
+            .context(ks_err!(
+                "Wrong security level: the key blob belongs to KeyMint instance {:?}, not {:?}.",
+                blob_uuid,
+                km_uuid
+            )),
+        _ => Ok(()),
+    }
+}
+
+/// Validates the parameters of an EC key on `EcCurve::CURVE_25519` before they reach KeyMint.
+/// Such a key is an Ed25519 key if it has the SIGN or VERIFY purpose, and an X25519 key
+/// otherwise. Ed25519 keys may only sign and verify. Devices that do not support the curve are
+/// rejected with `UNSUPPORTED_EC_CURVE` rather than with whatever error the device returns.
+fn check_curve_25519_params(
+    params: &[KeyParameter],
+    capabilities: &KeyMintCapabilities,
+) -> Result<()> {
+    let has = |tag, value| params.iter().any(|p| p.tag == tag && p.value == value);
+    if !has(Tag::ALGORITHM, KeyParameterValue::Algorithm(Algorithm::EC))
+        || !has(Tag::EC_CURVE, KeyParameterValue::EcCurve(EcCurve::CURVE_25519))
+    {
+        return Ok(());
+    }
+    if !capabilities.supports_curve_25519() {
+        return Err(Error::Km(ErrorCode::UNSUPPORTED_EC_CURVE))
+            .context(ks_err!("Curve 25519 requires a KeyMint V2 or newer TEE."));
+    }
+
+    let purposes: Vec<KeyPurpose> = params
+        .iter()
+        .filter_map(|p| match p.value {
+            KeyParameterValue::KeyPurpose(purpose) if p.tag == Tag::PURPOSE => Some(purpose),
+            _ => None,
+        })
+        .collect();
+    let is_signing =
+        |purpose: &KeyPurpose| matches!(*purpose, KeyPurpose::SIGN | KeyPurpose::VERIFY);
+    if purposes.iter().any(is_signing) {
+        if let Some(purpose) = purposes.iter().find(|purpose| !is_signing(purpose)) {
+            return Err(Error::Km(ErrorCode::INCOMPATIBLE_PURPOSE))
+                .context(ks_err!("Ed25519 keys may only sign and verify, not {:?}.", purpose));
+        }
+    }
+    Ok(())
+}
+
+/// Generates a key with `generate` on behalf of `generate_key`. The requested `params` are
+/// checked against the `capabilities` of the device before `generate` is called, and the
+/// characteristics of the new key are checked against `params` afterwards. A key that deviates
+/// from the request is deleted from `keymint` and never handed out.
+fn generate_checked_key<G>(
+    keymint: &dyn IKeyMintDevice,
+    key: &KeyDescriptor,
+    params: &[KeyParameter],
+    capabilities: &KeyMintCapabilities,
+    generate: G,
+) -> Result<KeyCreationResult>
+where
+    G: FnOnce() -> Result<KeyCreationResult>,
+{
+    check_curve_25519_params(params, capabilities).context(ks_err!())?;
+    let creation_result = generate()?;
+    if let Err(e) = check_generated_characteristics(params, &creation_result.keyCharacteristics) {
+        log_key_integrity_violation(key);
+        // The key does not match the request and is never handed out, so get rid of it.
+        let _ = map_km_error(keymint.deleteKey(&creation_result.keyBlob));
+        return Err(e).context(ks_err!("Generated key deviates from the request."));
+    }
+    Ok(creation_result)
+}
+
+/// Rejects the creation of `key` if it would push the number of keys owned by `uid` over
+/// `limit`. Replacing an existing alias does not count towards the quota, and only keys in
+/// `Domain::APP` are subject to it.
+fn check_key_count_quota(
+    db: &mut KeystoreDB,
+    key: &KeyDescriptor,
+    uid: u32,
+    limit: usize,
+) -> Result<()> {
+    if key.domain != Domain::APP || is_system_uid(uid) {
+        return Ok(());
+    }
+    if let Some(alias) = &key.alias {
+        if db
+            .key_exists(Domain::APP, uid as i64, alias, KeyType::Client)
+            .context(ks_err!("Failed to check for existing key."))?
+        {
+            return Ok(());
+        }
+    }
+    let count = db
+        .count_keys(Domain::APP, uid as i64, KeyType::Client)
+        .context(ks_err!("Failed to count keys."))?;
+    if count >= limit {
+        return Err(Error::Rc(ResponseCode::BACKEND_BUSY))
+            .context(ks_err!("uid {uid} owns {count} keys, which reaches the quota of {limit}."));
+    }
+    Ok(())
+}
+
+/// Stores a newly created key in `db`. `key_blob` and `blob_metadata` describe the key blob
+/// after super encryption. The key metadata records the `creation_date` and the creation
+/// `flags`, and the blob is tagged with the KeyMint instance `km_uuid` that created it.
+#[allow(clippy::too_many_arguments)]
+fn store_key_entry(
+    db: &mut KeystoreDB,
+    key: &KeyDescriptor,
+    key_parameters: &[KsKeyParam],
+    key_blob: &[u8],
+    mut blob_metadata: BlobMetaData,
+    cert_info: &CertificateInfo,
+    creation_date: DateTime,
+    flags: Option<i32>,
+    km_uuid: &Uuid,
+) -> Result<KeyIdGuard> {
+    let mut key_metadata = KeyMetaData::new();
+    key_metadata.add(KeyMetaEntry::CreationDate(creation_date));
+    if let Some(flags) = flags {
+        key_metadata.add(KeyMetaEntry::CreationFlags(flags));
+    }
+    if flags.unwrap_or(0) & IKeystoreMaintenance::KEY_FLAG_ACCESS_LOG != 0 {
+        key_metadata.add(KeyMetaEntry::AccessLogged(true));
+    }
+    blob_metadata.add(BlobMetaEntry::KmUuid(*km_uuid));
+
+    db.store_new_key(
+        key,
+        KeyType::Client,
+        key_parameters,
+        &BlobInfo::new(key_blob, &blob_metadata),
+        cert_info,
+        &key_metadata,
+        km_uuid,
+    )
+    .context(ks_err!())
+}
+
 impl KeystoreSecurityLevel {
     /// Creates a new security level instance wrapped in a
     /// BnKeystoreSecurityLevel proxy object. It also enables
@@ -93,23 +488,51 @@ impl KeystoreSecurityLevel {
         security_level: SecurityLevel,
         id_rotation_state: IdRotationState,
     ) -> Result<(Strong<dyn IKeystoreSecurityLevel>, Uuid)> {
-        let (dev, hw_info, km_uuid) = get_keymint_device(&security_level)
+        let sec_level = Self::new(security_level, id_rotation_state)
             .context(ks_err!("KeystoreSecurityLevel::new_native_binder."))?;
+        let km_uuid = sec_level.km_uuid;
         let result = BnKeystoreSecurityLevel::new_binder(
-            Self {
-                security_level,
-                keymint: dev,
-                hw_info,
-                km_uuid,
-                operation_db: OperationDb::new(),
-                rem_prov_state: RemProvState::new(security_level),
-                id_rotation_state,
-            },
+            sec_level,
             BinderFeatures { set_requesting_sid: true, ..BinderFeatures::default() },
         );
         Ok((result, km_uuid))
     }
 
+    /// Creates a new security level instance for in-process use, e.g., by the maintenance
+    /// service.
+    pub fn new(security_level: SecurityLevel, id_rotation_state: IdRotationState) -> Result<Self> {
+        let (dev, hw_info, km_uuid) =
+            get_keymint_device(&security_level).context(ks_err!("KeystoreSecurityLevel::new."))?;
+        Ok(Self {
+            security_level,
+            keymint: dev,
+            hw_info,
+            km_uuid,
+            operation_db: OperationDb::new(),
+            rem_prov_state: RemProvState::new(security_level),
+            id_rotation_state,
+        })
+    }
+
+    fn enforce_key_count_quota(&self, key: &KeyDescriptor, uid: u32) -> Result<()> {
+        if !read_bool("keystore.enforce_key_count_quota", false).unwrap_or(false) {
+            return Ok(());
+        }
+        DB.with(|db| check_key_count_quota(&mut db.borrow_mut(), key, uid, MAX_KEYS_PER_UID))
+    }
+
+    fn reject_weak_key_material(&self, format: KeyFormat, key_data: &[u8]) -> Result<()> {
+        if !read_bool("keystore.reject_weak_imported_keys", false).unwrap_or(false) {
+            return Ok(());
+        }
+        // Asymmetric keys are imported as structured PKCS#8 and are not checked.
+        if format == KeyFormat::RAW && is_weak_key_material(key_data) {
+            return Err(error::Error::Km(ErrorCode::INVALID_ARGUMENT))
+                .context(ks_err!("Refusing to import weak or test key material."));
+        }
+        Ok(())
+    }
+
     fn watch_millis(&self, id: &'static str, millis: u64) -> Option<wd::WatchPoint> {
         let sec_level = self.security_level;
         wd::watch_millis_with(id, millis, sec_level)
@@ -126,6 +549,7 @@ impl KeystoreSecurityLevel {
         creation_result: KeyCreationResult,
         user_id: u32,
         flags: Option<i32>,
+        creation_date: Option<DateTime>,
     ) -> Result<KeyMetadata> {
         let KeyCreationResult {
             keyBlob: key_blob,
@@ -133,6 +557,20 @@ impl KeystoreSecurityLevel {
             certificateChain: mut certificate_chain,
         } = creation_result;
 
+        // Chains are stored as returned by KeyMint unless normalization is enabled, see
+        // `normalize_cert_chain`.
+        if read_bool("keystore.normalize_cert_chain", false).unwrap_or(false) {
+            let drop_root =
+                read_bool("keystore.normalize_cert_chain_drop_root", false).unwrap_or(false);
+            certificate_chain = normalize_cert_chain(certificate_chain, drop_root);
+        }
+
+        let cert_chain_size = check_cert_chain_size(&*self.keymint, &key_blob, &certificate_chain)
+            .context(ks_err!())?;
+        if cert_chain_size > 0 {
+            log_cert_chain_size(cert_chain_size);
+        }
+
         // Unify the possible contents of the certificate chain.  The first entry in the `Vec` is
         // always the leaf certificate (if present), but the rest of the chain may be present as
         // either:
@@ -167,7 +605,7 @@ impl KeystoreSecurityLevel {
             SecurityLevel::SOFTWARE,
         ));
 
-        let creation_date = DateTime::now().context(ks_err!("Trying to make creation time."))?;
+        let creation_date = key_creation_date(creation_date)?;
 
         let key = match key.domain {
             Domain::BLOB => KeyDescriptor {
@@ -179,7 +617,7 @@ impl KeystoreSecurityLevel {
                 .with::<_, Result<KeyDescriptor>>(|db| {
                     let mut db = db.borrow_mut();
 
-                    let (key_blob, mut blob_metadata) = SUPER_KEY
+                    let (key_blob, blob_metadata) = SUPER_KEY
                         .read()
                         .unwrap()
                         .handle_super_encryption_on_key_init(
@@ -193,21 +631,18 @@ impl KeystoreSecurityLevel {
                         )
                         .context(ks_err!("Failed to handle super encryption."))?;
 
-                    let mut key_metadata = KeyMetaData::new();
-                    key_metadata.add(KeyMetaEntry::CreationDate(creation_date));
-                    blob_metadata.add(BlobMetaEntry::KmUuid(self.km_uuid));
-
-                    let key_id = db
-                        .store_new_key(
-                            &key,
-                            KeyType::Client,
-                            &key_parameters,
-                            &BlobInfo::new(&key_blob, &blob_metadata),
-                            &cert_info,
-                            &key_metadata,
-                            &self.km_uuid,
-                        )
-                        .context(ks_err!())?;
+                    let key_id = store_key_entry(
+                        &mut db,
+                        &key,
+                        &key_parameters,
+                        &key_blob,
+                        blob_metadata,
+                        &cert_info,
+                        creation_date,
+                        flags,
+                        &self.km_uuid,
+                    )
+                    .context(ks_err!())?;
                     Ok(KeyDescriptor {
                         domain: Domain::KEY_ID,
                         nspace: key_id.id(),
@@ -233,11 +668,23 @@ impl KeystoreSecurityLevel {
         operation_parameters: &[KeyParameter],
         forced: bool,
     ) -> Result<CreateOperationResponse> {
+        let start = Instant::now();
         let caller_uid = ThreadState::get_calling_uid();
+        let _permit =
+            if read_bool("keystore.limit_concurrent_create_operations", false).unwrap_or(false) {
+                Some(
+                    CREATE_OPERATION_GATE
+                        .admit(caller_uid, MAX_CONCURRENT_CREATE_OPERATIONS_PER_UID)
+                        .context(ks_err!("Too many concurrent createOperation calls."))?,
+                )
+            } else {
+                None
+            };
         // We use `scoping_blob` to extend the life cycle of the blob loaded from the database,
         // so that we can use it by reference like the blob provided by the key descriptor.
         // Otherwise, we would have to clone the blob from the key descriptor.
         let scoping_blob: Vec<u8>;
+        let mut access_logged = false;
         let (km_blob, key_properties, key_id_guard, blob_metadata) = match key.domain {
             Domain::BLOB => {
                 check_key_permission(KeyPerm::Use, key, &None)
@@ -292,6 +739,7 @@ impl KeystoreSecurityLevel {
                         but KM blob was missing."
                     ))?;
                 scoping_blob = blob;
+                access_logged = key_entry.metadata().access_logged() == Some(&true);
 
                 (
                     &scoping_blob,
@@ -302,6 +750,9 @@ impl KeystoreSecurityLevel {
             }
         };
 
+        check_blob_security_level(&blob_metadata, &self.km_uuid)
+            .context(ks_err!("Key cannot be used on this security level."))?;
+
         let purpose = operation_parameters.iter().find(|p| p.tag == Tag::PURPOSE).map_or(
             Err(Error::Km(ErrorCode::INVALID_ARGUMENT))
                 .context(ks_err!("No operation purpose specified.")),
@@ -327,11 +778,12 @@ impl KeystoreSecurityLevel {
             )
             .context(ks_err!())?;
 
-        let km_blob = SUPER_KEY
-            .read()
-            .unwrap()
-            .unwrap_key_if_required(&blob_metadata, km_blob)
-            .context(ks_err!("Failed to handle super encryption."))?;
+        let km_blob =
+            SUPER_KEY.read().unwrap().unwrap_key_if_required(&blob_metadata, km_blob).context(
+                ks_err!(
+                    "Failed to handle super encryption. The key blob was not passed to KeyMint."
+                ),
+            )?;
 
         let (begin_result, upgraded_blob) = self
             .upgrade_keyblob_if_required_with(
@@ -399,6 +851,18 @@ impl KeystoreSecurityLevel {
                 .into_interface()
                 .context(ks_err!("Failed to create IKeystoreOperation."))?;
 
+        if let Some((key_id, _)) = key_properties {
+            log_create_operation_latency(key_id, start.elapsed());
+        }
+
+        if let (true, Some((key_id, _))) = (access_logged, &key_properties) {
+            // The operation was created, so a failure to record it is not reported to the caller.
+            let logged = DB.with(|db| db.borrow_mut().log_key_access(*key_id, caller_uid, purpose));
+            if let Err(e) = logged {
+                log::error!("Failed to record access to key {key_id}: {e:?}");
+            }
+        }
+
         Ok(CreateOperationResponse {
             iOperation: Some(op_binder),
             operationChallenge: operation_challenge,
@@ -413,15 +877,54 @@ impl KeystoreSecurityLevel {
         })
     }
 
+    /// Runs the pre-flight checks of `create_operation` on `operation_parameters` for `key` and
+    /// `purpose` without calling into KeyMint, so that clients can find out cheaply whether an
+    /// operation is likely to succeed before spending an auth token or an operation slot. The
+    /// caller needs the `use` permission on the key. Checks that depend on the authorization
+    /// state, e.g., auth tokens or the device lock state, are not run.
+    pub fn validate_operation_params(
+        &self,
+        key: &KeyDescriptor,
+        operation_parameters: &[KeyParameter],
+        purpose: KeyPurpose,
+    ) -> Result<()> {
+        let _wp = self.watch("KeystoreSecurityLevel::validate_operation_params");
+        let key_params = match key.domain {
+            Domain::BLOB => {
+                check_key_permission(KeyPerm::Use, key, &None)
+                    .context(ks_err!("checking use permission for Domain::BLOB."))?;
+                None
+            }
+            _ => {
+                let (_, key_entry) = DB
+                    .with(|db| {
+                        db.borrow_mut().load_key_entry(
+                            key,
+                            KeyType::Client,
+                            KeyEntryLoadBits::NONE,
+                            ThreadState::get_calling_uid(),
+                            |k, av| check_key_permission(KeyPerm::Use, k, &av),
+                        )
+                    })
+                    .context(ks_err!("Failed to load key parameters."))?;
+                Some(key_entry.into_key_parameters())
+            }
+        };
+        check_operation_params(purpose, key_params.as_deref(), operation_parameters)
+            .context(ks_err!())
+    }
+
     fn add_required_parameters(
         &self,
         uid: u32,
         params: &[KeyParameter],
         key: &KeyDescriptor,
+        creation_date: Option<DateTime>,
     ) -> Result<Vec<KeyParameter>> {
         let mut result = params.to_vec();
 
-        // Prevent callers from specifying the CREATION_DATETIME tag.
+        // Prevent callers from specifying the CREATION_DATETIME tag. Migrated keys pass their
+        // original creation date in `creation_date` instead.
         if params.iter().any(|kp| kp.tag == Tag::CREATION_DATETIME) {
             return Err(Error::Rc(ResponseCode::INVALID_ARGUMENT)).context(ks_err!(
                 "KeystoreSecurityLevel::add_required_parameters: \
@@ -435,22 +938,16 @@ impl KeystoreSecurityLevel {
 
         // Add CREATION_DATETIME only if the backend version Keymint V1 (100) or newer.
         if self.hw_info.versionNumber >= 100 {
+            let creation_date = match creation_date {
+                Some(creation_date) => creation_date,
+                None => DateTime::try_from(creation_datetime).context(ks_err!(
+                    "KeystoreSecurityLevel::add_required_parameters: \
+                        Failed to convert epoch time."
+                ))?,
+            };
             result.push(KeyParameter {
                 tag: Tag::CREATION_DATETIME,
-                value: KeyParameterValue::DateTime(
-                    creation_datetime
-                        .duration_since(SystemTime::UNIX_EPOCH)
-                        .context(ks_err!(
-                            "KeystoreSecurityLevel::add_required_parameters: \
-                                Failed to get epoch time."
-                        ))?
-                        .as_millis()
-                        .try_into()
-                        .context(ks_err!(
-                            "KeystoreSecurityLevel::add_required_parameters: \
-                                Failed to convert epoch time."
-                        ))?,
-                ),
+                value: KeyParameterValue::DateTime(creation_date.to_millis_epoch()),
             });
         }
 
@@ -538,6 +1035,10 @@ impl KeystoreSecurityLevel {
             return Err(error::Error::Km(ErrorCode::INVALID_ARGUMENT))
                 .context(ks_err!("Alias must be specified"));
         }
+        if !rng_healthy() {
+            return Err(error::Error::sys())
+                .context(ks_err!("Refusing to generate keys, the RNG failed its health check."));
+        }
         let caller_uid = ThreadState::get_calling_uid();
 
         let key = match key.domain {
@@ -554,6 +1055,8 @@ impl KeystoreSecurityLevel {
         // Must return on error for security reasons.
         check_key_permission(KeyPerm::Rebind, &key, &None).context(ks_err!())?;
 
+        self.enforce_key_count_quota(&key, caller_uid).context(ks_err!())?;
+
         let attestation_key_info = match (key.domain, attest_key_descriptor) {
             (Domain::BLOB, _) => None,
             _ => DB
@@ -564,16 +1067,17 @@ impl KeystoreSecurityLevel {
                         attest_key_descriptor,
                         params,
                         &self.rem_prov_state,
+                        &KeyMintCapabilities::new(&self.hw_info),
                         &mut db.borrow_mut(),
                     )
                 })
                 .context(ks_err!("Trying to get an attestation key"))?,
         };
         let params = self
-            .add_required_parameters(caller_uid, params, &key)
+            .add_required_parameters(caller_uid, params, &key, None)
             .context(ks_err!("Trying to get aaid."))?;
 
-        let creation_result = match attestation_key_info {
+        let generate = || match attestation_key_info {
             Some(AttestationKeyInfo::UserGenerated {
                 key_id_guard,
                 blob,
@@ -628,9 +1132,9 @@ impl KeystoreSecurityLevel {
                     })
                 })
                 .context(ks_err!(
-                    "While generating Key {:?} with remote \
+                    "While generating Key {} with remote \
                     provisioned attestation key and params: {:?}.",
-                    key.alias,
+                    fingerprint_key_descriptor(&key),
                     log_security_safe_params(&params)
                 ))
                 .map(|(mut result, _)| {
@@ -676,11 +1180,18 @@ impl KeystoreSecurityLevel {
                  attestation key and params: {:?}.",
                 log_security_safe_params(&params)
             )),
-        }
+        };
+        let creation_result = generate_checked_key(
+            &*self.keymint,
+            &key,
+            &params,
+            &KeyMintCapabilities::new(&self.hw_info),
+            generate,
+        )
         .context(ks_err!())?;
 
         let user_id = uid_to_android_user(caller_uid);
-        self.store_new_key(key, creation_result, user_id, Some(flags)).context(ks_err!())
+        self.store_new_key(key, creation_result, user_id, Some(flags), None).context(ks_err!())
     }
 
     fn import_key(
@@ -690,6 +1201,38 @@ impl KeystoreSecurityLevel {
         params: &[KeyParameter],
         flags: i32,
         key_data: &[u8],
+    ) -> Result<KeyMetadata> {
+        self.import_key_with_creation_date(key, params, flags, key_data, None)
+    }
+
+    /// Imports a key that is migrated from another key store. Unlike `import_key`, the original
+    /// `creation_date` of the key is preserved instead of using the current time. The caller
+    /// needs the `ImportMigratedKey` keystore permission, see
+    /// `IKeystoreMaintenance::importMigratedKey`.
+    pub fn import_key_for_migration(
+        &self,
+        key: &KeyDescriptor,
+        params: &[KeyParameter],
+        flags: i32,
+        key_data: &[u8],
+        creation_date: DateTime,
+    ) -> Result<KeyMetadata> {
+        check_keystore_permission(KeystorePerm::ImportMigratedKey).context(ks_err!())?;
+        let caller_uid = ThreadState::get_calling_uid();
+        let result =
+            self.import_key_with_creation_date(key, params, flags, key_data, Some(creation_date));
+        log_key_creation_event_stats(self.security_level, params, &result);
+        log_key_imported(key, caller_uid, result.is_ok());
+        result
+    }
+
+    fn import_key_with_creation_date(
+        &self,
+        key: &KeyDescriptor,
+        params: &[KeyParameter],
+        flags: i32,
+        key_data: &[u8],
+        creation_date: Option<DateTime>,
     ) -> Result<KeyMetadata> {
         if key.domain != Domain::BLOB && key.alias.is_none() {
             return Err(error::Error::Km(ErrorCode::INVALID_ARGUMENT))
@@ -710,8 +1253,10 @@ impl KeystoreSecurityLevel {
         // import_key requires the rebind permission.
         check_key_permission(KeyPerm::Rebind, &key, &None).context(ks_err!("In import_key."))?;
 
+        self.enforce_key_count_quota(&key, caller_uid).context(ks_err!())?;
+
         let params = self
-            .add_required_parameters(caller_uid, params, &key)
+            .add_required_parameters(caller_uid, params, &key, creation_date)
             .context(ks_err!("Trying to get aaid."))?;
 
         let format = params
@@ -730,6 +1275,8 @@ impl KeystoreSecurityLevel {
             })
             .context(ks_err!())?;
 
+        self.reject_weak_key_material(format, key_data).context(ks_err!())?;
+
         let km_dev = &self.keymint;
         let creation_result = map_km_error({
             let _wp =
@@ -739,7 +1286,8 @@ impl KeystoreSecurityLevel {
         .context(ks_err!("Trying to call importKey"))?;
 
         let user_id = uid_to_android_user(caller_uid);
-        self.store_new_key(key, creation_result, user_id, Some(flags)).context(ks_err!())
+        self.store_new_key(key, creation_result, user_id, Some(flags), creation_date)
+            .context(ks_err!())
     }
 
     fn import_wrapped_key(
@@ -757,11 +1305,12 @@ impl KeystoreSecurityLevel {
             } => blob,
             _ => {
                 return Err(error::Error::Km(ErrorCode::INVALID_ARGUMENT)).context(ks_err!(
-                    "Alias and blob must be specified and domain must be APP or SELINUX. {:?}",
-                    key
+                    "Alias and blob must be specified and domain must be APP or SELINUX. {}",
+                    fingerprint_key_descriptor(key)
                 ));
             }
         };
+        check_wrapped_key_data_size(wrapped_data).context(ks_err!())?;
 
         if wrapping_key.domain == Domain::BLOB {
             return Err(error::Error::Km(ErrorCode::INVALID_ARGUMENT))
@@ -790,6 +1339,8 @@ impl KeystoreSecurityLevel {
         // Import_wrapped_key requires the rebind permission for the new key.
         check_key_permission(KeyPerm::Rebind, &key, &None).context(ks_err!())?;
 
+        self.enforce_key_count_quota(&key, caller_uid).context(ks_err!())?;
+
         let super_key = SUPER_KEY.read().unwrap().get_after_first_unlock_key_by_user_id(user_id);
 
         let (wrapping_key_id_guard, mut wrapping_key_entry) = DB
@@ -862,38 +1413,33 @@ impl KeystoreSecurityLevel {
             )
             .context(ks_err!())?;
 
-        self.store_new_key(key, creation_result, user_id, None)
+        self.store_new_key(key, creation_result, user_id, None, None)
             .context(ks_err!("Trying to store the new key."))
     }
 
     fn store_upgraded_keyblob(
-        _key_id_guard: KeyIdGuard,
+        key_id_guard: KeyIdGuard,
         km_uuid: Option<Uuid>,
         key_blob: &KeyBlob,
         upgraded_blob: &[u8],
+        reason: Option<KeyUpgradeReason>,
     ) -> Result<()> {
-        let (_upgraded_blob_to_be_stored, new_blob_metadata) =
+        let (upgraded_blob_to_be_stored, new_blob_metadata) =
             SuperKeyManager::reencrypt_if_required(key_blob, upgraded_blob)
                 .context(ks_err!("Failed to handle super encryption."))?;
 
-        let mut new_blob_metadata = new_blob_metadata.unwrap_or_default();
-        if let Some(uuid) = km_uuid {
-            new_blob_metadata.add(BlobMetaEntry::KmUuid(uuid));
-        }
-/*
         DB.with(|db| {
-            let mut db = db.borrow_mut();
-            db.set_blob(
+            store_upgraded_blob(
+                &mut db.borrow_mut(),
                 &key_id_guard,
-                SubComponentType::KEY_BLOB,
-                Some(&upgraded_blob_to_be_stored),
-                Some(&new_blob_metadata),
+                &upgraded_blob_to_be_stored,
+                new_blob_metadata.unwrap_or_default(),
+                km_uuid,
+                reason,
             )
         })
-        .context(ks_err!("Failed to insert upgraded blob into the database."))
-*/
-        Ok(())
     }
+
     fn upgrade_keyblob_if_required_with<T, F>(
         &self,
         mut key_id_guard: Option<KeyIdGuard>,
@@ -911,12 +1457,18 @@ impl KeystoreSecurityLevel {
             key_blob,
             params,
             f,
-            |upgraded_blob| {
+            |upgraded_blob, reason| {
                 if key_id_guard.is_some() {
                     // Unwrap cannot panic, because the is_some was true.
                     let kid = key_id_guard.take().unwrap();
-                    Self::store_upgraded_keyblob(kid, km_uuid, key_blob, upgraded_blob)
-                        .context(ks_err!("store_upgraded_keyblob failed"))
+                    Self::store_upgraded_keyblob(
+                        kid,
+                        km_uuid,
+                        key_blob,
+                        upgraded_blob,
+                        Some(reason),
+                    )
+                    .context(ks_err!("store_upgraded_keyblob failed"))
                 } else {
                     Ok(())
                 }
@@ -929,7 +1481,7 @@ impl KeystoreSecurityLevel {
         // upgrade was performed above and if one was given in the first place.
         if key_blob.force_reencrypt() {
             if let Some(kid) = key_id_guard {
-                Self::store_upgraded_keyblob(kid, km_uuid, key_blob, key_blob)
+                Self::store_upgraded_keyblob(kid, km_uuid, key_blob, key_blob, None)
                     .context(ks_err!("store_upgraded_keyblob failed in forced reencrypt"))?;
             }
         }
@@ -953,7 +1505,7 @@ impl KeystoreSecurityLevel {
             key_blob,
             params,
             f,
-            |upgraded_blob| {
+            |upgraded_blob, _reason| {
                 let _wp = wd::watch("Calling store_rkpd_attestation_key()");
                 if let Err(e) = store_rkpd_attestation_key(&rpc_name, key_blob, upgraded_blob) {
                     Err(wrapped_rkpd_error_to_ks_error(&e)).context(format!("{e:?}"))
@@ -1037,11 +1589,26 @@ impl KeystoreSecurityLevel {
             .context(ks_err!("delete_key: Checking delete permissions"))?;
 
         let km_dev = &self.keymint;
-        {
-            let _wp =
-                self.watch("KeystoreSecuritylevel::delete_key: calling IKeyMintDevice::deleteKey");
-            map_km_error(km_dev.deleteKey(key_blob)).context(ks_err!("keymint device deleteKey"))
-        }
+        // Verifying the deletion costs two more round trips to KeyMint, so it is opt-in.
+        // The application id and data of a Domain::BLOB key are not known to Keystore, so the
+        // blob is probed without them.
+        delete_key_blob(
+            || {
+                let _wp = self
+                    .watch("KeystoreSecuritylevel::delete_key: calling IKeyMintDevice::deleteKey");
+                map_km_error(km_dev.deleteKey(key_blob))
+                    .context(ks_err!("keymint device deleteKey"))
+            },
+            || {
+                let _wp = self.watch(
+                    "KeystoreSecuritylevel::delete_key: \
+                     calling IKeyMintDevice::getKeyCharacteristics",
+                );
+                map_km_error(km_dev.getKeyCharacteristics(key_blob, &[], &[]))
+            },
+            read_bool("keystore.verify_key_deletion", false).unwrap_or(false),
+        )
+        .context(ks_err!("delete_key"))
     }
 }
 
@@ -1120,15 +1687,566 @@ impl IKeystoreSecurityLevel for KeystoreSecurityLevel {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::database::tests::{make_test_key_entry, new_test_db};
+    use crate::database::KEYSTORE_UUID;
     use crate::error::map_km_error;
     use crate::globals::get_keymint_device;
+    use crate::permission::KeyPermSet;
     use crate::utils::upgrade_keyblob_if_required_with;
     use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
-        Algorithm::Algorithm, AttestationKey::AttestationKey, KeyParameter::KeyParameter,
-        KeyParameterValue::KeyParameterValue, Tag::Tag,
+        Algorithm::Algorithm, AttestationKey::AttestationKey, BeginResult::BeginResult,
+        HardwareAuthToken::HardwareAuthToken, KeyParameter::KeyParameter,
+        KeyParameterValue::KeyParameterValue, KeyPurpose::KeyPurpose, Tag::Tag,
     };
+    use android_hardware_security_secureclock::aidl::android::hardware::security::secureclock::TimeStampToken::TimeStampToken;
     use keystore2_crypto::parse_subject_from_certificate;
     use rkpd_client::get_rkpd_attestation_key;
+    use std::cell::RefCell;
+
+    const APP_UID: u32 = 10_001;
+
+    fn app_key(alias: &str) -> KeyDescriptor {
+        KeyDescriptor {
+            domain: Domain::APP,
+            nspace: APP_UID as i64,
+            alias: Some(alias.to_string()),
+            blob: None,
+        }
+    }
+
+    #[test]
+    fn test_weak_key_material() -> Result<()> {
+        assert!(is_weak_key_material(&[0; 32]));
+        assert!(is_weak_key_material(&[0xaa; 16]));
+        assert!(is_weak_key_material(&(0..32).collect::<Vec<u8>>()));
+        assert!(is_weak_key_material(KNOWN_TEST_KEYS[0]));
+        assert!(is_weak_key_material(&[0, 1, 0, 2].repeat(8)));
+
+        let random_key = keystore2_crypto::generate_random_data(32)?;
+        assert!(!is_weak_key_material(&random_key));
+        assert!(!is_weak_key_material(b"hmac secret"));
+        Ok(())
+    }
+
+    /// A mock KeyMint device that only tracks which key blobs are live.
+    struct MockKeyMint {
+        live_blobs: RefCell<Vec<Vec<u8>>>,
+        delete_is_noop: bool,
+        rollback_resistant: bool,
+        // Set if the blobs are bound to an application id, which the probes do not supply.
+        app_bound: bool,
+    }
+
+    impl MockKeyMint {
+        fn new(blob: &[u8], delete_is_noop: bool, rollback_resistant: bool) -> Self {
+            Self {
+                live_blobs: RefCell::new(vec![blob.to_vec()]),
+                delete_is_noop,
+                rollback_resistant,
+                app_bound: false,
+            }
+        }
+
+        fn delete_key(&self, blob: &[u8]) -> Result<()> {
+            if !self.delete_is_noop {
+                self.live_blobs.borrow_mut().retain(|b| b != blob);
+            }
+            Ok(())
+        }
+
+        fn get_key_characteristics(&self, blob: &[u8]) -> Result<Vec<KeyCharacteristics>, Error> {
+            if self.app_bound || !self.live_blobs.borrow().iter().any(|b| b == blob) {
+                return Err(Error::Km(ErrorCode::INVALID_KEY_BLOB));
+            }
+            let mut authorizations = vec![];
+            if self.rollback_resistant {
+                authorizations.push(KeyParameter {
+                    tag: Tag::ROLLBACK_RESISTANCE,
+                    value: KeyParameterValue::BoolValue(true),
+                });
+            }
+            Ok(vec![KeyCharacteristics {
+                securityLevel: SecurityLevel::TRUSTED_ENVIRONMENT,
+                authorizations,
+            }])
+        }
+
+        fn delete_key_blob(&self, blob: &[u8], verify: bool) -> Result<()> {
+            delete_key_blob(|| self.delete_key(blob), || self.get_key_characteristics(blob), verify)
+        }
+    }
+
+    #[test]
+    fn test_check_key_deleted() {
+        let blob = b"key blob".to_vec();
+        for (delete_is_noop, expect_ok) in [(false, true), (true, false)] {
+            let km = MockKeyMint::new(&blob, delete_is_noop, true);
+            km.delete_key(&blob).unwrap();
+            assert_eq!(check_key_deleted(|| km.get_key_characteristics(&blob)).is_ok(), expect_ok);
+        }
+
+        // Other errors are reported as they are.
+        let result = check_key_deleted(|| Err(Error::Km(ErrorCode::HARDWARE_TYPE_UNAVAILABLE)));
+        assert_eq!(
+            Some(&Error::Km(ErrorCode::HARDWARE_TYPE_UNAVAILABLE)),
+            result.unwrap_err().root_cause().downcast_ref::<Error>()
+        );
+    }
+
+    #[test]
+    fn test_delete_key_blob() {
+        let blob = b"key blob".to_vec();
+
+        // A rollback resistant key that survives deleteKey is reported.
+        assert!(MockKeyMint::new(&blob, true, true).delete_key_blob(&blob, true).is_err());
+        assert!(MockKeyMint::new(&blob, false, true).delete_key_blob(&blob, true).is_ok());
+        // Unless verification is off.
+        assert!(MockKeyMint::new(&blob, true, true).delete_key_blob(&blob, false).is_ok());
+
+        // deleteKey may be a no-op for keys without rollback resistance.
+        let km = MockKeyMint::new(&blob, true, false);
+        assert!(km.delete_key_blob(&blob, true).is_ok());
+
+        // Blobs that cannot be probed without their application id are not verified.
+        let km = MockKeyMint { app_bound: true, ..MockKeyMint::new(&blob, true, true) };
+        assert!(km.delete_key_blob(&blob, true).is_ok());
+    }
+
+    #[test]
+    fn test_check_generated_characteristics() {
+        let param = |tag, value| KeyParameter { tag, value };
+        let key_size = |size| param(Tag::KEY_SIZE, KeyParameterValue::Integer(size));
+        let algorithm = param(Tag::ALGORITHM, KeyParameterValue::Algorithm(Algorithm::AES));
+        let purpose = param(Tag::PURPOSE, KeyParameterValue::KeyPurpose(KeyPurpose::ENCRYPT));
+        let requested = vec![
+            algorithm.clone(),
+            key_size(256),
+            purpose.clone(),
+            param(Tag::NO_AUTH_REQUIRED, KeyParameterValue::BoolValue(true)),
+        ];
+        // Mock device responses. Tags that are not checked need not be reflected.
+        let characteristics = |authorizations: Vec<KeyParameter>| {
+            vec![KeyCharacteristics {
+                securityLevel: SecurityLevel::TRUSTED_ENVIRONMENT,
+                authorizations,
+            }]
+        };
+        let honored = characteristics(vec![algorithm.clone(), key_size(256), purpose.clone()]);
+        assert!(check_generated_characteristics(&requested, &honored).is_ok());
+
+        // A device that makes a 128 bit key when asked for 256 bits, or drops a purpose.
+        let smaller_key = characteristics(vec![algorithm.clone(), key_size(128), purpose]);
+        assert!(check_generated_characteristics(&requested, &smaller_key).is_err());
+        let no_purpose = characteristics(vec![algorithm, key_size(256)]);
+        assert!(check_generated_characteristics(&requested, &no_purpose).is_err());
+    }
+
+    #[test]
+    fn test_generate_ed25519_key() {
+        let capabilities = |version, security_level| {
+            KeyMintCapabilities::new(&KeyMintHardwareInfo {
+                versionNumber: version,
+                securityLevel: security_level,
+                ..Default::default()
+            })
+        };
+        let tee = |version| capabilities(version, SecurityLevel::TRUSTED_ENVIRONMENT);
+        let strongbox = capabilities(300, SecurityLevel::STRONGBOX);
+        let param = |tag, value| KeyParameter { tag, value };
+        let purpose = |purpose| param(Tag::PURPOSE, KeyParameterValue::KeyPurpose(purpose));
+        let curve = |curve| param(Tag::EC_CURVE, KeyParameterValue::EcCurve(curve));
+        let ec = param(Tag::ALGORITHM, KeyParameterValue::Algorithm(Algorithm::EC));
+        let ed25519 = vec![
+            ec.clone(),
+            curve(EcCurve::CURVE_25519),
+            purpose(KeyPurpose::SIGN),
+            purpose(KeyPurpose::VERIFY),
+        ];
+
+        // Generates a key on a device with the given capabilities and returns the resulting
+        // KeyMint error code, if any, and whether the device was asked to generate the key.
+        let keymint = MockKeyMintDevice::default();
+        let key = app_key("curve_25519");
+        let generate = |params: &[KeyParameter], capabilities: &KeyMintCapabilities| {
+            let mut generated = false;
+            let result = generate_checked_key(&keymint, &key, params, capabilities, || {
+                generated = true;
+                Ok(map_km_error(keymint.generateKey(params, None))?)
+            });
+            let error_code = match result {
+                Err(e) => match e.root_cause().downcast_ref::<Error>() {
+                    Some(Error::Km(code)) => Some(*code),
+                    _ => None,
+                },
+                Ok(_) => None,
+            };
+            (error_code, generated)
+        };
+        assert_eq!((None, true), generate(&ed25519, &tee(200)));
+        assert_eq!((Some(ErrorCode::UNSUPPORTED_EC_CURVE), false), generate(&ed25519, &tee(100)));
+        assert_eq!((Some(ErrorCode::UNSUPPORTED_EC_CURVE), false), generate(&ed25519, &strongbox));
+        let mut sign_and_agree = ed25519.clone();
+        sign_and_agree.push(purpose(KeyPurpose::AGREE_KEY));
+        assert_eq!(
+            (Some(ErrorCode::INCOMPATIBLE_PURPOSE), false),
+            generate(&sign_and_agree, &tee(200))
+        );
+
+        // X25519 keys may agree keys, but still need the curve. Other curves are not restricted.
+        let x25519 = [ec.clone(), curve(EcCurve::CURVE_25519), purpose(KeyPurpose::AGREE_KEY)];
+        assert_eq!((None, true), generate(&x25519, &tee(200)));
+        assert_eq!((Some(ErrorCode::UNSUPPORTED_EC_CURVE), false), generate(&x25519, &strongbox));
+        let p256 = [ec, curve(EcCurve::P_256), purpose(KeyPurpose::SIGN)];
+        assert_eq!((None, true), generate(&p256, &tee(100)));
+        assert_eq!((None, true), generate(&p256, &strongbox));
+    }
+
+    #[test]
+    fn test_check_cert_chain_size() {
+        let keymint = MockKeyMintDevice::default();
+        let chain = |sizes: &[usize]| -> Vec<Certificate> {
+            sizes.iter().map(|size| Certificate { encodedCertificate: vec![0; *size] }).collect()
+        };
+        assert_eq!(check_cert_chain_size(&keymint, b"blob", &[]).unwrap(), 0);
+        let at_limit = chain(&[MAX_CERT_CHAIN_BYTES - 100, 100]);
+        assert_eq!(
+            check_cert_chain_size(&keymint, b"blob", &at_limit).unwrap(),
+            MAX_CERT_CHAIN_BYTES
+        );
+        assert!(keymint.deleted_blobs.lock().unwrap().is_empty());
+
+        // An oversized chain is rejected, and the key it came with is deleted.
+        let oversized = chain(&[MAX_CERT_CHAIN_BYTES, 1]);
+        assert_eq!(
+            Some(&Error::Rc(ResponseCode::SYSTEM_ERROR)),
+            check_cert_chain_size(&keymint, b"blob", &oversized)
+                .unwrap_err()
+                .root_cause()
+                .downcast_ref::<Error>()
+        );
+        assert_eq!(*keymint.deleted_blobs.lock().unwrap(), vec![b"blob".to_vec()]);
+    }
+
+    #[test]
+    fn test_check_wrapped_key_data_size() {
+        assert!(check_wrapped_key_data_size(&[]).is_ok());
+        assert!(check_wrapped_key_data_size(&vec![0; MAX_WRAPPED_KEY_DATA_BYTES]).is_ok());
+        assert_eq!(
+            Some(&Error::Km(ErrorCode::INVALID_INPUT_LENGTH)),
+            check_wrapped_key_data_size(&vec![0; MAX_WRAPPED_KEY_DATA_BYTES + 1])
+                .unwrap_err()
+                .root_cause()
+                .downcast_ref::<Error>()
+        );
+    }
+
+    #[test]
+    fn test_check_blob_security_level() {
+        let tee = Uuid::from(SecurityLevel::TRUSTED_ENVIRONMENT);
+        let strongbox = Uuid::from(SecurityLevel::STRONGBOX);
+        let mut tee_blob = BlobMetaData::new();
+        tee_blob.add(BlobMetaEntry::KmUuid(tee));
+
+        assert!(check_blob_security_level(&tee_blob, &tee).is_ok());
+        // A TEE blob must not be handed to StrongBox.
+        assert_eq!(
+            Some(&Error::Rc(ResponseCode::INVALID_ARGUMENT)),
+            check_blob_security_level(&tee_blob, &strongbox)
+                .unwrap_err()
+                .root_cause()
+                .downcast_ref::<Error>()
+        );
+        // Blobs without a recorded KeyMint instance, e.g., Domain::BLOB keys, are not checked.
+        assert!(check_blob_security_level(&BlobMetaData::new(), &strongbox).is_ok());
+    }
+
+    #[test]
+    fn test_create_operation_gate() -> Result<()> {
+        let gate = CreateOperationGate::default();
+        let busy = Error::Rc(ResponseCode::BACKEND_BUSY);
+        let throttled = |result: Result<CreateOperationPermit>| {
+            result.is_err_and(|e| e.root_cause().downcast_ref::<Error>() == Some(&busy))
+        };
+
+        let first = gate.admit(APP_UID, 2)?;
+        let second = gate.admit(APP_UID, 2)?;
+        assert!(throttled(gate.admit(APP_UID, 2)));
+
+        // Other uids proceed, and system uids are exempt.
+        let other = gate.admit(APP_UID + 1, 2)?;
+        let system: Vec<_> = (0..3).map(|_| gate.admit(1000, 2)).collect::<Result<_>>()?;
+
+        // Completing a call makes room for the next one.
+        drop(first);
+        let third = gate.admit(APP_UID, 2)?;
+        assert!(throttled(gate.admit(APP_UID, 2)));
+
+        drop((second, third, other, system));
+        assert!(gate.in_flight.lock().unwrap().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_count_quota() -> Result<()> {
+        let mut db = new_test_db()?;
+        for i in 0..3 {
+            check_key_count_quota(&mut db, &app_key(&format!("key{i}")), APP_UID, 3)?;
+            make_test_key_entry(&mut db, Domain::APP, APP_UID as i64, &format!("key{i}"), None)?;
+        }
+
+        // The quota is exhausted for new aliases, but replacing an existing alias is fine.
+        assert_eq!(
+            Some(&Error::Rc(ResponseCode::BACKEND_BUSY)),
+            check_key_count_quota(&mut db, &app_key("key3"), APP_UID, 3)
+                .unwrap_err()
+                .root_cause()
+                .downcast_ref::<Error>()
+        );
+        check_key_count_quota(&mut db, &app_key("key0"), APP_UID, 3)?;
+
+        // Other uids are not affected, and system uids are exempt.
+        check_key_count_quota(&mut db, &app_key("key3"), APP_UID + 1, 3)?;
+        check_key_count_quota(&mut db, &app_key("key3"), 1000, 0)?;
+
+        // Deleting a key frees up quota.
+        db.unbind_key(&app_key("key1"), KeyType::Client, APP_UID, |_, _| Ok(()))?;
+        check_key_count_quota(&mut db, &app_key("key3"), APP_UID, 3)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrated_key_keeps_creation_date() -> Result<()> {
+        let mut db = new_test_db()?;
+        let mut store = |alias: &str, creation_date: Option<DateTime>| -> Result<KeyEntry> {
+            let key = app_key(alias);
+            store_key_entry(
+                &mut db,
+                &key,
+                &[],
+                &[1, 2, 3],
+                BlobMetaData::new(),
+                &CertificateInfo::new(None, None),
+                key_creation_date(creation_date)?,
+                Some(0),
+                &KEYSTORE_UUID,
+            )?;
+            let (_, entry) = db.load_key_entry(
+                &key,
+                KeyType::Client,
+                KeyEntryLoadBits::NONE,
+                APP_UID,
+                |_, _| Ok(()),
+            )?;
+            Ok(entry)
+        };
+
+        // A migrated key keeps the creation date it brings along, others get the current time.
+        let preserved = DateTime::from_millis_epoch(1_234_567_890_000);
+        let migrated = store("migrated", Some(preserved))?;
+        assert_eq!(migrated.metadata().creation_date(), Some(&preserved));
+        let imported = store("imported", None)?;
+        assert!(imported.metadata().creation_date().unwrap() > &preserved);
+        Ok(())
+    }
+
+    /// A mock KeyMint device. `generateKey` generates exactly the requested key, `upgradeKey`
+    /// appends to the key blob, and `deleteKey` records the deleted blobs.
+    #[derive(Default)]
+    struct MockKeyMintDevice {
+        deleted_blobs: Mutex<Vec<Vec<u8>>>,
+    }
+
+    fn unimplemented<T>() -> binder::Result<T> {
+        Err(binder::Status::new_service_specific_error(ErrorCode::UNIMPLEMENTED.0, None))
+    }
+
+    impl binder::Interface for MockKeyMintDevice {}
+
+    impl IKeyMintDevice for MockKeyMintDevice {
+        fn getHardwareInfo(&self) -> binder::Result<KeyMintHardwareInfo> {
+            unimplemented()
+        }
+        fn addRngEntropy(&self, _data: &[u8]) -> binder::Result<()> {
+            unimplemented()
+        }
+        fn generateKey(
+            &self,
+            key_params: &[KeyParameter],
+            _attestation_key: Option<&AttestationKey>,
+        ) -> binder::Result<KeyCreationResult> {
+            Ok(KeyCreationResult {
+                keyBlob: b"generated blob".to_vec(),
+                keyCharacteristics: vec![KeyCharacteristics {
+                    securityLevel: SecurityLevel::TRUSTED_ENVIRONMENT,
+                    authorizations: key_params.to_vec(),
+                }],
+                certificateChain: vec![],
+            })
+        }
+        fn importKey(
+            &self,
+            _key_params: &[KeyParameter],
+            _key_format: KeyFormat,
+            _key_data: &[u8],
+            _attestation_key: Option<&AttestationKey>,
+        ) -> binder::Result<KeyCreationResult> {
+            unimplemented()
+        }
+        fn importWrappedKey(
+            &self,
+            _wrapped_key_data: &[u8],
+            _wrapping_key_blob: &[u8],
+            _masking_key: &[u8],
+            _unwrapping_params: &[KeyParameter],
+            _password_sid: i64,
+            _biometric_sid: i64,
+        ) -> binder::Result<KeyCreationResult> {
+            unimplemented()
+        }
+        fn upgradeKey(
+            &self,
+            keyblob_to_upgrade: &[u8],
+            _upgrade_params: &[KeyParameter],
+        ) -> binder::Result<Vec<u8>> {
+            Ok([keyblob_to_upgrade, b" upgraded"].concat())
+        }
+        fn deleteKey(&self, keyblob: &[u8]) -> binder::Result<()> {
+            self.deleted_blobs.lock().unwrap().push(keyblob.to_vec());
+            Ok(())
+        }
+        fn deleteAllKeys(&self) -> binder::Result<()> {
+            unimplemented()
+        }
+        fn destroyAttestationIds(&self) -> binder::Result<()> {
+            unimplemented()
+        }
+        fn begin(
+            &self,
+            _purpose: KeyPurpose,
+            _keyblob: &[u8],
+            _params: &[KeyParameter],
+            _auth_token: Option<&HardwareAuthToken>,
+        ) -> binder::Result<BeginResult> {
+            unimplemented()
+        }
+        fn deviceLocked(
+            &self,
+            _password_only: bool,
+            _timestamp_token: Option<&TimeStampToken>,
+        ) -> binder::Result<()> {
+            unimplemented()
+        }
+        fn earlyBootEnded(&self) -> binder::Result<()> {
+            unimplemented()
+        }
+        fn convertStorageKeyToEphemeral(&self, _storage_keyblob: &[u8]) -> binder::Result<Vec<u8>> {
+            unimplemented()
+        }
+        fn getKeyCharacteristics(
+            &self,
+            _keyblob: &[u8],
+            _app_id: &[u8],
+            _app_data: &[u8],
+        ) -> binder::Result<Vec<KeyCharacteristics>> {
+            unimplemented()
+        }
+        fn getRootOfTrustChallenge(&self) -> binder::Result<[u8; 16]> {
+            unimplemented()
+        }
+        fn getRootOfTrust(&self, _challenge: &[u8; 16]) -> binder::Result<Vec<u8>> {
+            unimplemented()
+        }
+        fn sendRootOfTrust(&self, _root_of_trust: &[u8]) -> binder::Result<()> {
+            unimplemented()
+        }
+        fn setAdditionalAttestationInfo(
+            &self,
+            _additional_attestation_info: &[KeyParameter],
+        ) -> binder::Result<()> {
+            unimplemented()
+        }
+    }
+
+    #[test]
+    fn test_creation_flags_persisted() -> Result<()> {
+        let mut db = new_test_db()?;
+        let mut store = |alias: &str, flags: Option<i32>| -> Result<KeyDescriptor> {
+            let key = app_key(alias);
+            store_key_entry(
+                &mut db,
+                &key,
+                &[],
+                &[1, 2, 3],
+                BlobMetaData::new(),
+                &CertificateInfo::new(None, None),
+                DateTime::now()?,
+                flags,
+                &KEYSTORE_UUID,
+            )?;
+            Ok(key)
+        };
+        let flags = IKeystoreMaintenance::KEY_FLAG_ACCESS_LOG | 0x4;
+        let flagged = store("flagged", Some(flags))?;
+        let unflagged = store("unflagged", None)?;
+
+        let allow = |_: &KeyDescriptor, _: Option<KeyPermSet>| Ok(());
+        assert_eq!(db.get_key_creation_flags(&flagged, APP_UID, allow)?, flags);
+        assert_eq!(db.get_key_creation_flags(&unflagged, APP_UID, allow)?, 0);
+
+        // The flags are only returned if the permission check passes.
+        assert!(db
+            .get_key_creation_flags(&flagged, APP_UID, |_, _| Err(Error::perm().into()))
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_upgrade_records_reason() -> Result<()> {
+        let mut db = new_test_db()?;
+        let key = app_key("upgraded");
+        let key_id_guard = store_key_entry(
+            &mut db,
+            &key,
+            &[],
+            b"old blob",
+            BlobMetaData::new(),
+            &CertificateInfo::new(None, None),
+            DateTime::now()?,
+            None,
+            &KEYSTORE_UUID,
+        )?;
+
+        let (_, upgraded_blob) = upgrade_keyblob_if_required_with(
+            &MockKeyMintDevice::default(),
+            crate::km_version::KEY_MINT_V3,
+            b"old blob",
+            &[],
+            |blob| match blob {
+                b"old blob" => Err(Error::Km(ErrorCode::KEY_REQUIRES_UPGRADE)),
+                _ => Ok(()),
+            },
+            |new_blob, reason| {
+                store_upgraded_blob(
+                    &mut db,
+                    &key_id_guard,
+                    new_blob,
+                    BlobMetaData::new(),
+                    Some(KEYSTORE_UUID),
+                    Some(reason),
+                )
+            },
+        )?;
+        assert_eq!(upgraded_blob.as_deref(), Some(&b"old blob upgraded"[..]));
+        drop(key_id_guard);
+
+        let (_, key_entry) =
+            db.load_key_entry(&key, KeyType::Client, KeyEntryLoadBits::KM, APP_UID, |_, _| Ok(()))?;
+        let (blob, blob_metadata) = key_entry.key_blob_info().as_ref().unwrap();
+        assert_eq!(blob, b"old blob upgraded");
+        assert_eq!(blob_metadata.upgrade_reason(), Some(&KeyUpgradeReason::KeyRequiresUpgrade));
+        assert_eq!(blob_metadata.km_uuid(), Some(&KEYSTORE_UUID));
+        Ok(())
+    }
 
     #[test]
     // This is a helper for a manual test. We want to check that after a system upgrade RKPD
@@ -1179,7 +2297,7 @@ mod tests {
                 map_km_error(keymint.generateKey(&params, Some(&attestation_key)))
             },
             /*new_blob_handler=*/
-            |new_blob| {
+            |new_blob, _reason| {
                 // This handler is only executed if a key upgrade was performed.
                 key_upgraded = true;
                 let _wp = wd::watch("Calling store_rkpd_attestation_key()");
@@ -28,8 +28,8 @@ use crate::utils::{
 use crate::{
     database::Uuid,
     globals::{
-        create_thread_local_db, DB, ENCODED_MODULE_INFO, LEGACY_BLOB_LOADER, LEGACY_IMPORTER,
-        SUPER_KEY,
+        create_thread_local_db, with_db_recovering, DB, ENCODED_MODULE_INFO, LEGACY_BLOB_LOADER,
+        LEGACY_IMPORTER, SUPER_KEY,
     },
 };
 use crate::{database::KEYSTORE_UUID, permission};
@@ -309,13 +309,13 @@ impl KeystoreService {
     fn list_entries(&self, domain: Domain, namespace: i64) -> Result<Vec<KeyDescriptor>> {
         let k = self.get_key_descriptor_for_lookup(domain, namespace)?;
 
-        DB.with(|db| list_key_entries(&mut db.borrow_mut(), k.domain, k.nspace, None))
+        with_db_recovering(|db| list_key_entries(db, k.domain, k.nspace, None))
     }
 
     fn count_num_entries(&self, domain: Domain, namespace: i64) -> Result<i32> {
         let k = self.get_key_descriptor_for_lookup(domain, namespace)?;
 
-        DB.with(|db| count_key_entries(&mut db.borrow_mut(), k.domain, k.nspace))
+        with_db_recovering(|db| count_key_entries(db, k.domain, k.nspace))
     }
 
     fn get_supplementary_attestation_info(&self, tag: Tag) -> Result<Vec<u8>> {
@@ -342,7 +342,8 @@ impl KeystoreService {
         DB.with(|db| list_key_entries(&mut db.borrow_mut(), k.domain, k.nspace, start_past_alias))
     }
 
-    fn delete_key(&self, key: &KeyDescriptor) -> Result<()> {
+    /// Deletes the given key on behalf of the calling uid, see `IKeystoreService::deleteKey`.
+    pub fn delete_key(key: &KeyDescriptor) -> Result<()> {
         let caller_uid = ThreadState::get_calling_uid();
         let super_key = SUPER_KEY
             .read()
@@ -428,7 +429,7 @@ impl IKeystoreService for KeystoreService {
     }
     fn deleteKey(&self, key: &KeyDescriptor) -> binder::Result<()> {
         let _wp = wd::watch("IKeystoreService::deleteKey");
-        let result = self.delete_key(key);
+        let result = Self::delete_key(key);
         log_key_deleted(key, ThreadState::get_calling_uid(), result.is_ok());
         result.map_err(into_logged_binder)
     }
@@ -27,6 +27,7 @@ use anyhow::Result;
 use binder::get_declared_instances;
 use keystore2_hal_names::get_hidl_instances;
 use std::fmt::{self, Display, Formatter};
+use std::sync::Mutex;
 use std::time::Duration;
 
 /// This function initiates the shared secret negotiation. It starts a thread and then returns
@@ -44,8 +45,9 @@ pub fn perform_shared_secret_negotiation() {
         let participants = list_participants()
             .expect("In perform_shared_secret_negotiation: Trying to list participants.");
         let connected = connect_participants(participants);
-        negotiate_shared_secret(connected);
+        let report = negotiate_shared_secret(connected);
         log::info!("Shared secret negotiation concluded successfully.");
+        *LAST_NEGOTIATION.lock().unwrap() = Some(report);
 
         // Once shared secret negotiation is done, the StrongBox and TEE have a common key that
         // can be used to authenticate a possible RootOfTrust transfer.
@@ -53,6 +55,49 @@ pub fn perform_shared_secret_negotiation() {
     });
 }
 
+/// Outcome of the most recent shared secret negotiation. It is kept to help debugging auth token
+/// verification failures across HALs and never holds secret material.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NegotiationReport {
+    /// The HAL instances that took part in the negotiation.
+    pub participants: Vec<String>,
+    /// True if all participants computed the same sharing check value.
+    pub succeeded: bool,
+    /// Leading bytes of the agreed sharing check value in hex. The check value is designed to be
+    /// compared in the clear and does not reveal the shared key.
+    pub check_fingerprint: Option<String>,
+    /// Reason of the failure if the negotiation failed.
+    pub error: Option<String>,
+}
+
+/// Number of bytes of the sharing check value included in `NegotiationReport`.
+const CHECK_FINGERPRINT_LEN: usize = 8;
+
+static LAST_NEGOTIATION: Mutex<Option<NegotiationReport>> = Mutex::new(None);
+
+/// Returns the report of the most recent shared secret negotiation, or None if no negotiation
+/// has concluded yet.
+pub fn last_negotiation_report() -> Option<NegotiationReport> {
+    LAST_NEGOTIATION.lock().unwrap().clone()
+}
+
+impl Display for NegotiationReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.succeeded {
+            writeln!(f, "  Status:       succeeded")?;
+        } else {
+            writeln!(f, "  Status:       failed: {}", self.error.as_deref().unwrap_or("unknown"))?;
+        }
+        for participant in &self.participants {
+            writeln!(f, "  Participant:  {}", participant)?;
+        }
+        if let Some(fingerprint) = &self.check_fingerprint {
+            writeln!(f, "  Check value:  {}...", fingerprint)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 enum SharedSecretParticipant {
     /// Represents an instance of android.hardware.security.sharedsecret.ISharedSecret.
@@ -232,7 +277,12 @@ fn connect_participants(
 
 fn negotiate_shared_secret(
     participants: Vec<(Strong<dyn ISharedSecret>, SharedSecretParticipant)>,
-) {
+) -> NegotiationReport {
+    let mut report = NegotiationReport {
+        participants: participants.iter().map(|(_, p)| p.to_string()).collect(),
+        ..Default::default()
+    };
+
     // Phase 1: Get the sharing parameters from all participants.
     let mut params = loop {
         let result: Result<Vec<SharedSecretParameters>, SharedSecretError> = participants
@@ -270,16 +320,26 @@ fn negotiate_shared_secret(
         }
     });
 
-    if let Err(e) = negotiation_result {
-        log::error!("In negotiate_shared_secret: {:?}.", e);
-        if let SharedSecretError::Checksum(_) = e {
-            log::error!(concat!(
-                "This means that this device is NOT PROVISIONED CORRECTLY.\n",
-                "User authorization and other security functions will not work\n",
-                "as expected. Please contact your OEM for instructions.",
-            ));
+    match negotiation_result {
+        Ok(check) => {
+            report.succeeded = true;
+            report.check_fingerprint = check.map(|check| {
+                hex::encode(&check[..std::cmp::min(check.len(), CHECK_FINGERPRINT_LEN)])
+            });
+        }
+        Err(e) => {
+            log::error!("In negotiate_shared_secret: {:?}.", e);
+            if let SharedSecretError::Checksum(_) = e {
+                log::error!(concat!(
+                    "This means that this device is NOT PROVISIONED CORRECTLY.\n",
+                    "User authorization and other security functions will not work\n",
+                    "as expected. Please contact your OEM for instructions.",
+                ));
+            }
+            report.error = Some(e.to_string());
         }
     }
+    report
 }
 
 /// Perform RootOfTrust transfer from TEE to StrongBox (if available).
@@ -286,9 +286,24 @@ impl SkmState {
     }
 }
 
+/// A lock state transition of a user's super keys, as reported to observers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SuperKeyTransition {
+    /// The user's super keys were unlocked, e.g., because the user unlocked the device.
+    Unlocked,
+    /// The user's UnlockedDeviceRequired super keys were locked.
+    Locked,
+}
+
+/// Callback invoked on super key transitions. Observers are called without the super key
+/// manager lock held, but they must not block as they run on the thread that reported the
+/// transition.
+pub type SuperKeyObserver = Arc<dyn Fn(UserId, SuperKeyTransition) + Send + Sync>;
+
 #[derive(Default)]
 pub struct SuperKeyManager {
     data: SkmState,
+    observers: Mutex<Vec<SuperKeyObserver>>,
 }
 
 impl SuperKeyManager {
@@ -353,6 +368,68 @@ impl SuperKeyManager {
         Ok(())
     }
 
+    /// Registers an observer that gets notified about lock and unlock transitions of all users.
+    pub fn register_observer(&self, observer: SuperKeyObserver) {
+        self.observers.lock().unwrap().push(observer);
+    }
+
+    /// Notifies all registered observers about a transition for the given user. Neither the
+    /// super key manager lock nor the observer list lock is held while the observers run, so
+    /// the observers are free to access the super key manager and to register observers.
+    pub fn notify_observers(
+        skm: &Arc<RwLock<Self>>,
+        user_id: UserId,
+        transition: SuperKeyTransition,
+    ) {
+        let observers = skm.read().unwrap().observers.lock().unwrap().clone();
+        for observer in observers {
+            observer(user_id, transition);
+        }
+    }
+
+    /// Unlocks the user's super keys, with the password if one is given and with a biometric
+    /// otherwise, and notifies the observers on success.
+    pub fn unlock_user_and_notify(
+        skm: &Arc<RwLock<Self>>,
+        db: &mut KeystoreDB,
+        legacy_importer: &LegacyImporter,
+        user_id: UserId,
+        password: Option<&Password>,
+    ) -> Result<()> {
+        {
+            let mut skm_guard = skm.write().unwrap();
+            if let Some(password) = password {
+                skm_guard
+                    .unlock_user(db, legacy_importer, user_id, password)
+                    .context(ks_err!("Unlock with password."))?;
+            } else {
+                skm_guard
+                    .try_unlock_user_with_biometric(db, user_id)
+                    .context(ks_err!("try_unlock_user_with_biometric failed user_id={user_id}"))?;
+            }
+        }
+        Self::notify_observers(skm, user_id, SuperKeyTransition::Unlocked);
+        Ok(())
+    }
+
+    /// Locks the user's UnlockedDeviceRequired super keys, see
+    /// `lock_unlocked_device_required_keys`, and notifies the observers.
+    pub fn lock_user_and_notify(
+        skm: &Arc<RwLock<Self>>,
+        db: &mut KeystoreDB,
+        user_id: UserId,
+        unlocking_sids: &[i64],
+        weak_unlock_enabled: bool,
+    ) {
+        skm.write().unwrap().lock_unlocked_device_required_keys(
+            db,
+            user_id,
+            unlocking_sids,
+            weak_unlock_enabled,
+        );
+        Self::notify_observers(skm, user_id, SuperKeyTransition::Locked);
+    }
+
     pub fn level_accessible(&self, boot_level: i32) -> bool {
         self.data
             .boot_level_key_cache
@@ -441,8 +518,26 @@ impl SuperKeyManager {
         })
     }
 
-    /// Unwraps an encrypted key blob given an encryption key.
+    /// Unwraps an encrypted key blob given an encryption key. Both super encryption algorithms
+    /// authenticate the KeyMint blob with AES-GCM, so a blob that fails authentication was not
+    /// encrypted with `key`. This is reported as such rather than as a generic failure, so that a
+    /// super key mixup is not mistaken for KeyMint rejecting a corrupted blob.
     fn unwrap_key_with_key(blob: &[u8], metadata: &BlobMetaData, key: &SuperKey) -> Result<ZVec> {
+        Self::decrypt_with_key(blob, metadata, key).map_err(|e| {
+            match e.root_cause().downcast_ref::<keystore2_crypto::Error>() {
+                Some(keystore2_crypto::Error::DecryptionFailed) => {
+                    anyhow::Error::new(Error::Rc(ResponseCode::VALUE_CORRUPTED)).context(ks_err!(
+                        "Key blob failed authentication with super key {:?}: {:?}",
+                        key.id,
+                        e
+                    ))
+                }
+                _ => e,
+            }
+        })
+    }
+
+    fn decrypt_with_key(blob: &[u8], metadata: &BlobMetaData, key: &SuperKey) -> Result<ZVec> {
         match key.algorithm {
             SuperEncryptionAlgorithm::Aes256Gcm => match (metadata.iv(), metadata.aead_tag()) {
                 (Some(iv), Some(tag)) => {
@@ -1099,6 +1194,22 @@ impl SuperKeyManager {
         Ok(())
     }
 
+    /// Destroys every key of the given user. In addition to what `remove_user` deletes, this
+    /// covers all keys carrying the user's `UserID` key parameter regardless of their domain.
+    /// The key blobs are deleted on the device by the garbage collector. Wiping a user that has
+    /// no keys left is a no-op.
+    pub fn wipe_user(
+        &mut self,
+        db: &mut KeystoreDB,
+        legacy_importer: &LegacyImporter,
+        user_id: UserId,
+    ) -> Result<()> {
+        log::info!("wipe_user(user={user_id})");
+        self.remove_user(db, legacy_importer, user_id).context(ks_err!())?;
+        db.unbind_keys_with_user_id_param(user_id)
+            .context(ks_err!("Error in unbinding keys by user id parameter."))
+    }
+
     /// Initializes the given user by creating their super keys, both AfterFirstUnlock and
     /// UnlockedDeviceRequired. If allow_existing is true, then the user already being initialized
     /// is not considered an error.
@@ -285,3 +285,125 @@ fn test_remove_unlocked_user() {
 fn test_remove_locked_user() {
     test_user_removal(true);
 }
+
+#[test]
+fn test_wipe_user() {
+    const SELINUX_NAMESPACE: i64 = 101;
+    // All test keys carry the `UserID` key parameter of this user.
+    const PARAM_USER_ID: u32 = 1;
+
+    let pw: Password = generate_password_blob();
+    let (skm, mut keystore_db, legacy_importer) = setup_test(&pw);
+    make_test_key_entry(&mut keystore_db, Domain::APP, USER_ID.into(), TEST_KEY_ALIAS, None)
+        .unwrap();
+    make_test_key_entry(&mut keystore_db, Domain::SELINUX, SELINUX_NAMESPACE, TEST_KEY_ALIAS, None)
+        .unwrap();
+
+    skm.write().unwrap().wipe_user(&mut keystore_db, &legacy_importer, USER_ID).unwrap();
+    assert_uninitialized(
+        &skm,
+        &mut keystore_db,
+        &legacy_importer,
+        USER_ID,
+        "The user was not wiped!",
+    );
+    assert!(!keystore_db
+        .key_exists(Domain::APP, USER_ID.into(), TEST_KEY_ALIAS, KeyType::Client)
+        .unwrap());
+    // The key in the SELinux namespace belongs to a different user.
+    assert!(keystore_db
+        .key_exists(Domain::SELINUX, SELINUX_NAMESPACE, TEST_KEY_ALIAS, KeyType::Client)
+        .unwrap());
+
+    skm.write().unwrap().wipe_user(&mut keystore_db, &legacy_importer, PARAM_USER_ID).unwrap();
+    assert!(!keystore_db
+        .key_exists(Domain::SELINUX, SELINUX_NAMESPACE, TEST_KEY_ALIAS, KeyType::Client)
+        .unwrap());
+
+    // Wiping is idempotent.
+    skm.write().unwrap().wipe_user(&mut keystore_db, &legacy_importer, USER_ID).unwrap();
+    assert!(!skm
+        .write()
+        .unwrap()
+        .super_key_exists_in_db_for_user(&mut keystore_db, &legacy_importer, USER_ID)
+        .unwrap());
+}
+
+#[test]
+fn test_unwrap_key_with_wrong_super_key() -> Result<()> {
+    let make_super_key = || -> Result<SuperKey> {
+        Ok(SuperKey {
+            algorithm: SuperEncryptionAlgorithm::Aes256Gcm,
+            key: generate_aes256_key()?,
+            id: SuperKeyIdentifier::DatabaseId(1),
+            reencrypt_with: None,
+        })
+    };
+    let super_key = make_super_key()?;
+    let km_blob = b"keymint key blob";
+    let (encrypted, iv, tag) = super_key.encrypt(km_blob)?;
+    let mut metadata = BlobMetaData::new();
+    metadata.add(BlobMetaEntry::Iv(iv));
+    metadata.add(BlobMetaEntry::AeadTag(tag));
+
+    let unwrapped = SuperKeyManager::unwrap_key_with_key(&encrypted, &metadata, &super_key)?;
+    assert_eq!(&unwrapped[..], km_blob);
+
+    // A wrong super key is reported as such instead of as a generic decryption failure.
+    let wrong_super_key = make_super_key()?;
+    assert_eq!(
+        Some(&Error::Rc(ResponseCode::VALUE_CORRUPTED)),
+        SuperKeyManager::unwrap_key_with_key(&encrypted, &metadata, &wrong_super_key)
+            .unwrap_err()
+            .root_cause()
+            .downcast_ref::<Error>()
+    );
+
+    // So is a blob that was tampered with.
+    let mut tampered = encrypted.clone();
+    tampered[0] ^= 1;
+    assert_eq!(
+        Some(&Error::Rc(ResponseCode::VALUE_CORRUPTED)),
+        SuperKeyManager::unwrap_key_with_key(&tampered, &metadata, &super_key)
+            .unwrap_err()
+            .root_cause()
+            .downcast_ref::<Error>()
+    );
+    Ok(())
+}
+
+#[test]
+fn test_observer_notified_on_lock_and_unlock() {
+    let pw: Password = generate_password_blob();
+    let (skm, mut keystore_db, legacy_importer) = setup_test(&pw);
+
+    let events: Arc<Mutex<Vec<(u32, SuperKeyTransition, bool)>>> = Default::default();
+    let events_clone = events.clone();
+    let skm_clone = skm.clone();
+    skm.read().unwrap().register_observer(Arc::new(move |user_id, transition| {
+        // Neither the super key manager nor the observer list may be locked while observers
+        // run.
+        let skm_free = skm_clone.try_write().is_ok();
+        skm_clone.read().unwrap().register_observer(Arc::new(|_, _| {}));
+        events_clone.lock().unwrap().push((user_id, transition, skm_free));
+    }));
+
+    SuperKeyManager::lock_user_and_notify(&skm, &mut keystore_db, USER_ID, &[], false);
+
+    SuperKeyManager::unlock_user_and_notify(
+        &skm,
+        &mut keystore_db,
+        &legacy_importer,
+        USER_ID,
+        Some(&pw),
+    )
+    .unwrap();
+
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![
+            (USER_ID, SuperKeyTransition::Locked, true),
+            (USER_ID, SuperKeyTransition::Unlocked, true)
+        ]
+    );
+}
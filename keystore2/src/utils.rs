@@ -22,14 +22,16 @@ use crate::permission;
 use crate::permission::{KeyPerm, KeyPermSet, KeystorePerm};
 pub use crate::watchdog_helper::watchdog;
 use crate::{
-    database::{KeyType, KeystoreDB},
+    database::{KeyEntry, KeyType, KeyUpgradeReason, KeystoreDB},
     globals::LEGACY_IMPORTER,
-    km_compat,
+    km_compat, km_version,
     raw_device::KeyMintDevice,
 };
 use android_hardware_security_keymint::aidl::android::hardware::security::keymint::{
-    Algorithm::Algorithm, IKeyMintDevice::IKeyMintDevice, KeyCharacteristics::KeyCharacteristics,
-    KeyParameter::KeyParameter as KmKeyParameter, KeyParameterValue::KeyParameterValue, Tag::Tag,
+    Algorithm::Algorithm, Certificate::Certificate, IKeyMintDevice::IKeyMintDevice,
+    KeyCharacteristics::KeyCharacteristics, KeyMintHardwareInfo::KeyMintHardwareInfo,
+    KeyParameter::KeyParameter as KmKeyParameter, KeyParameterValue::KeyParameterValue,
+    SecurityLevel::SecurityLevel, Tag::Tag, TagType::TagType,
 };
 use android_os_permissions_aidl::aidl::android::os::IPermissionController;
 use android_security_apc::aidl::android::security::apc::{
@@ -47,7 +49,10 @@ use keystore2_apc_compat::{
     APC_COMPAT_ERROR_IGNORED, APC_COMPAT_ERROR_OK, APC_COMPAT_ERROR_OPERATION_PENDING,
     APC_COMPAT_ERROR_SYSTEM_ERROR,
 };
-use keystore2_crypto::{aes_gcm_decrypt, aes_gcm_encrypt, ZVec};
+use keystore2_crypto::{
+    aes_gcm_decrypt, aes_gcm_encrypt, is_self_signed_certificate, sha256, ZVec,
+};
+use keystore2_selinux::{self as selinux, ClassPermission};
 use log::{info, warn};
 use std::iter::IntoIterator;
 use std::thread::sleep;
@@ -98,9 +103,10 @@ pub fn check_key_permission(
     key: &KeyDescriptor,
     access_vector: &Option<KeyPermSet>,
 ) -> anyhow::Result<()> {
+    let caller_uid = ThreadState::get_calling_uid();
     ThreadState::with_calling_sid(|calling_sid| {
         permission::check_key_permission(
-            ThreadState::get_calling_uid(),
+            caller_uid,
             calling_sid
                 .ok_or_else(Error::sys)
                 .context(ks_err!("Cannot check permission without calling_sid."))?,
@@ -109,6 +115,25 @@ pub fn check_key_permission(
             access_vector,
         )
     })
+    .inspect_err(|e| {
+        if let Some(selinux::Error::PermissionDenied) = e.root_cause().downcast_ref() {
+            warn!("{}", key_permission_denial_line(caller_uid, perm, key));
+        }
+    })
+}
+
+/// Formats a structured, greppable log line describing a denied key permission check. The key
+/// blob is never logged, only whether one was present.
+fn key_permission_denial_line(caller_uid: u32, perm: KeyPerm, key: &KeyDescriptor) -> String {
+    format!(
+        "keystore2 key permission denied: uid={} perm={} domain={:?} nspace={} alias={} blob={}",
+        caller_uid,
+        perm.name(),
+        key.domain,
+        key.nspace,
+        key.alias.as_deref().unwrap_or("none"),
+        if key.blob.is_some() { "redacted" } else { "none" },
+    )
 }
 
 /// This function checks whether a given tag corresponds to the access of device identifiers.
@@ -205,12 +230,13 @@ fn import_keyblob_and_perform_op<T, KmOp, NewBlobHandler>(
     km_dev: &dyn IKeyMintDevice,
     inner_keyblob: &[u8],
     upgrade_params: &[KmKeyParameter],
+    reason: KeyUpgradeReason,
     km_op: KmOp,
     new_blob_handler: NewBlobHandler,
 ) -> Result<(T, Option<Vec<u8>>)>
 where
     KmOp: Fn(&[u8]) -> Result<T, Error>,
-    NewBlobHandler: FnOnce(&[u8]) -> Result<()>,
+    NewBlobHandler: FnOnce(&[u8], KeyUpgradeReason) -> Result<()>,
 {
     let (format, key_material, mut chars) =
         crate::sw_keyblob::export_key(inner_keyblob, upgrade_params)?;
@@ -303,7 +329,8 @@ where
     // than the original count bound to the keyblob. This means that Keystore's policing of
     // usage counts will continue where it left off.
 
-    new_blob_handler(&creation_result.keyBlob).context(ks_err!("calling new_blob_handler."))?;
+    new_blob_handler(&creation_result.keyBlob, reason)
+        .context(ks_err!("calling new_blob_handler."))?;
 
     km_op(&creation_result.keyBlob)
         .map(|v| (v, Some(creation_result.keyBlob)))
@@ -316,12 +343,13 @@ fn upgrade_keyblob_and_perform_op<T, KmOp, NewBlobHandler>(
     km_dev: &dyn IKeyMintDevice,
     key_blob: &[u8],
     upgrade_params: &[KmKeyParameter],
+    reason: KeyUpgradeReason,
     km_op: KmOp,
     new_blob_handler: NewBlobHandler,
 ) -> Result<(T, Option<Vec<u8>>)>
 where
     KmOp: Fn(&[u8]) -> Result<T, Error>,
-    NewBlobHandler: FnOnce(&[u8]) -> Result<()>,
+    NewBlobHandler: FnOnce(&[u8], KeyUpgradeReason) -> Result<()>,
 {
     let upgraded_blob = {
         let _wp = watchdog::watch(
@@ -331,7 +359,7 @@ where
     }
     .context(ks_err!("Upgrade failed."))?;
 
-    new_blob_handler(&upgraded_blob).context(ks_err!("calling new_blob_handler."))?;
+    new_blob_handler(&upgraded_blob, reason).context(ks_err!("calling new_blob_handler."))?;
 
     km_op(&upgraded_blob)
         .map(|v| (v, Some(upgraded_blob)))
@@ -341,9 +369,9 @@ where
 /// This function can be used to upgrade key blobs on demand. The return value of
 /// `km_op` is inspected and if ErrorCode::KEY_REQUIRES_UPGRADE is encountered,
 /// an attempt is made to upgrade the key blob. On success `new_blob_handler` is called
-/// with the upgraded blob as argument. Then `km_op` is called a second time with the
-/// upgraded blob as argument. On success a tuple of the `km_op`s result and the
-/// optional upgraded blob is returned.
+/// with the upgraded blob and the reason for the upgrade as arguments. Then `km_op` is
+/// called a second time with the upgraded blob as argument. On success a tuple of the
+/// `km_op`s result and the optional upgraded blob is returned.
 pub fn upgrade_keyblob_if_required_with<T, KmOp, NewBlobHandler>(
     km_dev: &dyn IKeyMintDevice,
     km_dev_version: i32,
@@ -354,13 +382,14 @@ pub fn upgrade_keyblob_if_required_with<T, KmOp, NewBlobHandler>(
 ) -> Result<(T, Option<Vec<u8>>)>
 where
     KmOp: Fn(&[u8]) -> Result<T, Error>,
-    NewBlobHandler: FnOnce(&[u8]) -> Result<()>,
+    NewBlobHandler: FnOnce(&[u8], KeyUpgradeReason) -> Result<()>,
 {
     match km_op(key_blob) {
         Err(Error::Km(ErrorCode::KEY_REQUIRES_UPGRADE)) => upgrade_keyblob_and_perform_op(
             km_dev,
             key_blob,
             upgrade_params,
+            KeyUpgradeReason::KeyRequiresUpgrade,
             km_op,
             new_blob_handler,
         ),
@@ -390,6 +419,7 @@ where
                     km_dev,
                     inner_keyblob,
                     upgrade_params,
+                    KeyUpgradeReason::KeymasterHardwareBlob,
                     km_op,
                     new_blob_handler,
                 )
@@ -411,6 +441,7 @@ where
                     km_dev,
                     inner_keyblob,
                     upgrade_params,
+                    KeyUpgradeReason::KeymasterSoftwareBlob,
                     km_op,
                     new_blob_handler,
                 )
@@ -436,6 +467,7 @@ where
                     km_dev,
                     inner_keyblob,
                     upgrade_params,
+                    KeyUpgradeReason::KeyMintSoftwareBlob,
                     km_op,
                     new_blob_handler,
                 )
@@ -499,11 +531,53 @@ pub const AID_USER_OFFSET: u32 = rustutils::users::AID_USER_OFFSET;
 /// keystore generates for its own use.
 pub const AID_KEYSTORE: u32 = rustutils::users::AID_KEYSTORE;
 
+/// Capabilities of a KeyMint device that Keystore needs to take into account, derived from the
+/// device's hardware info. Relies on `versionNumber` having been normalized to
+/// <AIDL version> * 100 by `globals::connect_keymint`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyMintCapabilities {
+    version: i32,
+    security_level: SecurityLevel,
+}
+
+impl KeyMintCapabilities {
+    /// Normalized version number of KeyMint V2, the first version to support user generated
+    /// attestation keys, i.e., keys with the ATTEST_KEY purpose.
+    pub const KEY_MINT_V2: i32 = km_version::KEY_MINT_V2;
+
+    /// Creates the capabilities for the device described by `hw_info`.
+    pub fn new(hw_info: &KeyMintHardwareInfo) -> Self {
+        Self { version: hw_info.versionNumber, security_level: hw_info.securityLevel }
+    }
+
+    /// Returns true if the device accepts an `AttestationKey` in `generateKey`.
+    pub fn supports_attest_key(&self) -> bool {
+        self.version >= Self::KEY_MINT_V2
+    }
+
+    /// Returns true if the device supports EC keys on `EcCurve::CURVE_25519`, i.e., Ed25519
+    /// signing keys and X25519 key agreement keys. StrongBox does not support the curve.
+    pub fn supports_curve_25519(&self) -> bool {
+        self.version >= Self::KEY_MINT_V2
+            && self.security_level == SecurityLevel::TRUSTED_ENVIRONMENT
+    }
+}
+
+/// First app id assigned to applications. App ids below this value belong to
+/// system components.
+pub const AID_APP_START: u32 = 10000;
+
 /// Extracts the android user from the given uid.
 pub fn uid_to_android_user(uid: u32) -> u32 {
     rustutils::users::multiuser_get_user_id(uid)
 }
 
+/// Returns true if the given uid belongs to a system component rather than an app,
+/// irrespective of the android user it runs as.
+pub fn is_system_uid(uid: u32) -> bool {
+    uid % AID_USER_OFFSET < AID_APP_START
+}
+
 /// Merges and filters two lists of key descriptors. The first input list, legacy_descriptors,
 /// is assumed to not be sorted or filtered. As such, all key descriptors in that list whose
 /// alias is less than, or equal to, start_past_alias (if provided) will be removed.
@@ -624,6 +698,22 @@ pub fn count_key_entries(db: &mut KeystoreDB, domain: Domain, namespace: i64) ->
     Ok((legacy_keys.len() + num_keys_in_db) as i32)
 }
 
+/// Returns the stored key blob of `key_entry` for backup. This is only permitted for keys that
+/// were created exportable, and only if the blob is super-encrypted, so that the exported blob is
+/// useless off-device without the user's super key.
+pub fn exportable_key_blob(key_entry: &KeyEntry) -> Result<Vec<u8>> {
+    if key_entry.metadata().exportable() != Some(&true) {
+        return Err(Error::perm()).context(ks_err!("Key is not exportable."));
+    }
+    match key_entry.key_blob_info() {
+        Some((blob, blob_metadata)) if blob_metadata.encrypted_by().is_some() => Ok(blob.clone()),
+        Some(_) => Err(Error::perm()).context(ks_err!("Key blob is not super-encrypted.")),
+        None => {
+            Err(Error::Rc(ResponseCode::KEY_NOT_FOUND)).context(ks_err!("Key has no key blob."))
+        }
+    }
+}
+
 /// For params remove sensitive data before returning a string for logging
 pub fn log_security_safe_params(params: &[KmKeyParameter]) -> Vec<KmKeyParameter> {
     params
@@ -633,6 +723,139 @@ pub fn log_security_safe_params(params: &[KmKeyParameter]) -> Vec<KmKeyParameter
         .collect::<Vec<KmKeyParameter>>()
 }
 
+/// Returns true if `tag` may legitimately appear more than once in a set of key parameters.
+fn is_repeatable_tag(tag: Tag) -> bool {
+    matches!(
+        TagType((tag.0 as u32 & 0xF0000000) as i32),
+        TagType::ENUM_REP | TagType::UINT_REP | TagType::ULONG_REP
+    )
+}
+
+/// Computes the difference between two sets of key parameters as `(added, removed, changed)`,
+/// e.g., to see what KeyMint changed in the characteristics of an upgraded key. Parameters are
+/// compared including their security level. Repeatable tags like `PURPOSE` are compared as
+/// multisets of values, so their entries are only ever added or removed. A single valued tag
+/// present on both sides with different values is reported as an `(old, new)` pair in `changed`.
+pub fn diff_key_params(
+    before: &[KeyParameter],
+    after: &[KeyParameter],
+) -> (Vec<KeyParameter>, Vec<KeyParameter>, Vec<(KeyParameter, KeyParameter)>) {
+    // Match up identical parameters first, respecting how often each one occurs.
+    let mut added = after.to_vec();
+    let mut removed = Vec::new();
+    for param in before {
+        match added.iter().position(|p| p == param) {
+            Some(i) => {
+                added.remove(i);
+            }
+            None => removed.push(param.clone()),
+        }
+    }
+
+    let mut changed = Vec::new();
+    removed.retain(|old| {
+        let tag = old.get_tag();
+        if is_repeatable_tag(tag) {
+            return true;
+        }
+        match added.iter().position(|new| new.get_tag() == tag) {
+            Some(i) => {
+                changed.push((old.clone(), added.remove(i)));
+                false
+            }
+            None => true,
+        }
+    });
+    (added, removed, changed)
+}
+
+/// Returns a short, stable description of `key` for logs and error contexts. It consists of the
+/// domain, the namespace and the first four bytes of the SHA-256 hash of the alias, because
+/// aliases are chosen by apps and may contain personal information. The blob is never included,
+/// only its presence is indicated.
+pub fn fingerprint_key_descriptor(key: &KeyDescriptor) -> String {
+    let alias = match &key.alias {
+        Some(alias) => {
+            sha256(alias.as_bytes())[..4].iter().map(|b| format!("{b:02x}")).collect::<String>()
+        }
+        None => "-".to_string(),
+    };
+    let blob = if key.blob.is_some() { "/blob" } else { "" };
+    format!("{:?}/{}/{}{}", key.domain, key.nspace, alias, blob)
+}
+
+/// Normalizes a certificate chain as returned by KeyMint before it is stored. Identical DER
+/// certificates are reduced to their first occurrence. If `drop_self_signed_root` is set, a
+/// trailing self-signed root certificate is dropped as well; clients can obtain the root out of
+/// band. The leaf certificate is never dropped, and a certificate that cannot be parsed, such as
+/// the concatenated chain returned by RKP, is never considered a root.
+pub fn normalize_cert_chain(
+    mut chain: Vec<Certificate>,
+    drop_self_signed_root: bool,
+) -> Vec<Certificate> {
+    let mut seen: Vec<Vec<u8>> = Vec::new();
+    chain.retain(|cert| {
+        if seen.contains(&cert.encodedCertificate) {
+            return false;
+        }
+        seen.push(cert.encodedCertificate.clone());
+        true
+    });
+    if drop_self_signed_root && chain.len() > 1 {
+        let last = &chain[chain.len() - 1].encodedCertificate;
+        if is_self_signed_certificate(last).unwrap_or(false) {
+            chain.pop();
+        }
+    }
+    chain
+}
+
+/// The outcome of a bulk operation. Each input item is paired with the result of the operation on
+/// that item, so that callers can tell exactly which items failed and retry only those. Items are
+/// processed independently; a failing item does not undo the items that succeeded before it.
+#[derive(Debug)]
+pub struct BulkResult<I, T> {
+    results: Vec<(I, Result<T>)>,
+}
+
+impl<I, T> BulkResult<I, T> {
+    /// Applies `f` to each of `items` in order and collects the individual results.
+    pub fn run<F>(items: impl IntoIterator<Item = I>, mut f: F) -> Self
+    where
+        F: FnMut(&I) -> Result<T>,
+    {
+        Self {
+            results: items
+                .into_iter()
+                .map(|item| {
+                    let result = f(&item);
+                    (item, result)
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns true if the operation succeeded for every item.
+    pub fn all_succeeded(&self) -> bool {
+        self.results.iter().all(|(_, result)| result.is_ok())
+    }
+
+    /// Returns the items for which the operation failed together with the errors.
+    pub fn failures(&self) -> impl Iterator<Item = (&I, &anyhow::Error)> {
+        self.results.iter().filter_map(|(item, result)| result.as_ref().err().map(|e| (item, e)))
+    }
+
+    /// Returns the items for which the operation succeeded together with the outputs.
+    pub fn successes(&self) -> impl Iterator<Item = (&I, &T)> {
+        self.results.iter().filter_map(|(item, result)| result.as_ref().ok().map(|v| (item, v)))
+    }
+
+    /// Consumes the bulk result and returns the per item results in input order.
+    pub fn into_results(self) -> Vec<(I, Result<T>)> {
+        self.results
+    }
+}
+
 /// Trait implemented by objects that can be used to decrypt cipher text using AES-GCM.
 pub trait AesGcm {
     /// Deciphers `data` using the initialization vector `iv` and AEAD tag `tag`
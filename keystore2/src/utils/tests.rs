@@ -123,3 +123,273 @@ fn test_list_key_parameters_with_filter_on_security_sensitive_info() -> Result<(
     assert_eq!(log_security_safe_params(&params), wanted);
     Ok(())
 }
+
+#[test]
+fn test_keymint_capabilities_attest_key() {
+    let capabilities = |version| {
+        KeyMintCapabilities::new(&KeyMintHardwareInfo {
+            versionNumber: version,
+            ..Default::default()
+        })
+    };
+    // Keymaster devices behind the compatibility wrapper report 10 * <major> + <minor>.
+    assert!(!capabilities(41).supports_attest_key());
+    assert!(!capabilities(100).supports_attest_key());
+    assert!(capabilities(200).supports_attest_key());
+    assert!(capabilities(300).supports_attest_key());
+}
+
+#[test]
+fn test_keymint_capabilities_curve_25519() {
+    let capabilities = |version, security_level| {
+        KeyMintCapabilities::new(&KeyMintHardwareInfo {
+            versionNumber: version,
+            securityLevel: security_level,
+            ..Default::default()
+        })
+    };
+    assert!(!capabilities(41, SecurityLevel::TRUSTED_ENVIRONMENT).supports_curve_25519());
+    assert!(!capabilities(100, SecurityLevel::TRUSTED_ENVIRONMENT).supports_curve_25519());
+    assert!(capabilities(200, SecurityLevel::TRUSTED_ENVIRONMENT).supports_curve_25519());
+    assert!(capabilities(300, SecurityLevel::TRUSTED_ENVIRONMENT).supports_curve_25519());
+    assert!(!capabilities(300, SecurityLevel::STRONGBOX).supports_curve_25519());
+}
+
+#[test]
+fn test_exportable_key_blob() -> Result<()> {
+    use crate::database::{
+        tests::new_test_db, BlobInfo, BlobMetaData, BlobMetaEntry, CertificateInfo, EncryptedBy,
+        KeyEntryLoadBits, KeyMetaData, KeyMetaEntry, KEYSTORE_UUID,
+    };
+
+    let mut db = new_test_db()?;
+    let mut store_and_load = |alias: &str, exportable: bool, super_encrypted: bool| {
+        let key = KeyDescriptor {
+            domain: Domain::APP,
+            nspace: 1,
+            alias: Some(alias.to_string()),
+            blob: None,
+        };
+        let mut blob_metadata = BlobMetaData::new();
+        if super_encrypted {
+            blob_metadata.add(BlobMetaEntry::EncryptedBy(EncryptedBy::KeyId(1)));
+        }
+        // No creation flag makes a key exportable.
+        let mut metadata = KeyMetaData::new();
+        metadata.add(KeyMetaEntry::CreationFlags(-1));
+        db.store_new_key(
+            &key,
+            KeyType::Client,
+            &[],
+            &BlobInfo::new(b"encrypted blob", &blob_metadata),
+            &CertificateInfo::new(None, None),
+            &metadata,
+            &KEYSTORE_UUID,
+        )?;
+        // Marking a key exportable is subject to the permission check.
+        assert_eq!(
+            Some(&Error::perm()),
+            db.set_key_exportable(&key, KeyType::Client, 1, |_, _| Err(Error::perm().into()))
+                .unwrap_err()
+                .root_cause()
+                .downcast_ref::<Error>()
+        );
+        if exportable {
+            db.set_key_exportable(&key, KeyType::Client, 1, |_, _| Ok(()))?;
+        }
+        db.load_key_entry(&key, KeyType::Client, KeyEntryLoadBits::KM, 1, |_, _| Ok(()))
+            .map(|(_, key_entry)| key_entry)
+    };
+
+    let exportable = store_and_load("exportable", true, true)?;
+    assert_eq!(exportable_key_blob(&exportable)?, b"encrypted blob".to_vec());
+
+    for key_entry in [store_and_load("plain", false, true)?, store_and_load("raw", true, false)?] {
+        assert_eq!(
+            Some(&Error::perm()),
+            exportable_key_blob(&key_entry).unwrap_err().root_cause().downcast_ref::<Error>()
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_key_permission_denial_line() {
+    let key = KeyDescriptor {
+        domain: Domain::SELINUX,
+        nspace: 102,
+        alias: Some("my_key".to_string()),
+        blob: Some(vec![0xde, 0xad, 0xbe, 0xef]),
+    };
+    assert_eq!(
+        key_permission_denial_line(10_001, KeyPerm::Use, &key),
+        format!(
+            "keystore2 key permission denied: uid=10001 perm=use domain={:?} nspace=102 \
+            alias=my_key blob=redacted",
+            Domain::SELINUX
+        )
+    );
+}
+
+#[test]
+fn test_diff_key_params() {
+    use crate::key_parameter::{
+        Algorithm, Digest, KeyParameterValue as KsValue, KeyPurpose, SecurityLevel,
+    };
+
+    let tee = |value| KeyParameter::new(value, SecurityLevel::TRUSTED_ENVIRONMENT);
+    let before = vec![
+        tee(KsValue::Algorithm(Algorithm::EC)),
+        tee(KsValue::KeyPurpose(KeyPurpose::SIGN)),
+        tee(KsValue::KeyPurpose(KeyPurpose::VERIFY)),
+        tee(KsValue::Digest(Digest::SHA_2_256)),
+        tee(KsValue::OSPatchLevel(202301)),
+        tee(KsValue::UsageCountLimit(5)),
+    ];
+    let after = vec![
+        tee(KsValue::KeyPurpose(KeyPurpose::VERIFY)),
+        tee(KsValue::Algorithm(Algorithm::EC)),
+        tee(KsValue::KeyPurpose(KeyPurpose::SIGN)),
+        tee(KsValue::KeyPurpose(KeyPurpose::AGREE_KEY)),
+        tee(KsValue::Digest(Digest::SHA_2_512)),
+        tee(KsValue::OSPatchLevel(202401)),
+        tee(KsValue::BootPatchLevel(20240105)),
+    ];
+    assert_eq!(diff_key_params(&before, &before), (vec![], vec![], vec![]));
+
+    let (added, removed, changed) = diff_key_params(&before, &after);
+    // DIGEST is repeatable, so a replaced digest is reported as removed and added.
+    assert_eq!(
+        added,
+        vec![
+            tee(KsValue::KeyPurpose(KeyPurpose::AGREE_KEY)),
+            tee(KsValue::Digest(Digest::SHA_2_512)),
+            tee(KsValue::BootPatchLevel(20240105)),
+        ]
+    );
+    assert_eq!(
+        removed,
+        vec![tee(KsValue::Digest(Digest::SHA_2_256)), tee(KsValue::UsageCountLimit(5))]
+    );
+    assert_eq!(
+        changed,
+        vec![(tee(KsValue::OSPatchLevel(202301)), tee(KsValue::OSPatchLevel(202401)))]
+    );
+
+    // Duplicates of a repeatable value count individually.
+    let (added, removed, changed) = diff_key_params(
+        &[tee(KsValue::KeyPurpose(KeyPurpose::SIGN)), tee(KsValue::KeyPurpose(KeyPurpose::SIGN))],
+        &[tee(KsValue::KeyPurpose(KeyPurpose::SIGN))],
+    );
+    assert!(added.is_empty() && changed.is_empty());
+    assert_eq!(removed, vec![tee(KsValue::KeyPurpose(KeyPurpose::SIGN))]);
+
+    // A change of the security level alone is a change, too.
+    let keystore = KeyParameter::new(KsValue::UsageCountLimit(5), SecurityLevel::KEYSTORE);
+    let (_, _, changed) = diff_key_params(&before, &[&after[..], &[keystore.clone()]].concat());
+    assert!(changed.contains(&(tee(KsValue::UsageCountLimit(5)), keystore)));
+}
+
+// Self-signed Ed25519 root certificate with subject "CN=Root".
+const ROOT_CERT: &[u8] = &[
+    0x30, 0x82, 0x01, 0x21, 0x30, 0x81, 0xd4, 0xa0, 0x03, 0x02, 0x01, 0x02, 0x02, 0x01, 0x01, 0x30,
+    0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x30, 0x0f, 0x31, 0x0d, 0x30, 0x0b, 0x06, 0x03, 0x55, 0x04,
+    0x03, 0x0c, 0x04, 0x52, 0x6f, 0x6f, 0x74, 0x30, 0x20, 0x17, 0x0d, 0x32, 0x36, 0x31, 0x30, 0x31,
+    0x35, 0x30, 0x36, 0x32, 0x38, 0x34, 0x33, 0x5a, 0x18, 0x0f, 0x32, 0x31, 0x32, 0x36, 0x30, 0x39,
+    0x32, 0x31, 0x30, 0x36, 0x32, 0x38, 0x34, 0x33, 0x5a, 0x30, 0x0f, 0x31, 0x0d, 0x30, 0x0b, 0x06,
+    0x03, 0x55, 0x04, 0x03, 0x0c, 0x04, 0x52, 0x6f, 0x6f, 0x74, 0x30, 0x2a, 0x30, 0x05, 0x06, 0x03,
+    0x2b, 0x65, 0x70, 0x03, 0x21, 0x00, 0x2b, 0x1a, 0x3b, 0x83, 0xf5, 0x66, 0x68, 0xef, 0xa4, 0x37,
+    0x7c, 0x9b, 0x8c, 0x74, 0xcc, 0x78, 0x3d, 0x93, 0x06, 0x57, 0xe5, 0xa7, 0x87, 0x6b, 0x65, 0x2a,
+    0xc6, 0x06, 0x5c, 0x47, 0x00, 0xa3, 0xa3, 0x53, 0x30, 0x51, 0x30, 0x1d, 0x06, 0x03, 0x55, 0x1d,
+    0x0e, 0x04, 0x16, 0x04, 0x14, 0xfe, 0x68, 0x19, 0x5a, 0x27, 0x97, 0x9c, 0xea, 0x04, 0xfd, 0x9f,
+    0x91, 0x65, 0x1c, 0x51, 0xda, 0xfa, 0x12, 0xa7, 0x6b, 0x30, 0x1f, 0x06, 0x03, 0x55, 0x1d, 0x23,
+    0x04, 0x18, 0x30, 0x16, 0x80, 0x14, 0xfe, 0x68, 0x19, 0x5a, 0x27, 0x97, 0x9c, 0xea, 0x04, 0xfd,
+    0x9f, 0x91, 0x65, 0x1c, 0x51, 0xda, 0xfa, 0x12, 0xa7, 0x6b, 0x30, 0x0f, 0x06, 0x03, 0x55, 0x1d,
+    0x13, 0x01, 0x01, 0xff, 0x04, 0x05, 0x30, 0x03, 0x01, 0x01, 0xff, 0x30, 0x05, 0x06, 0x03, 0x2b,
+    0x65, 0x70, 0x03, 0x41, 0x00, 0xcd, 0x91, 0x9a, 0x6a, 0x67, 0x23, 0x7e, 0xfb, 0x92, 0x61, 0xf6,
+    0x11, 0xe5, 0xcc, 0xd6, 0xf4, 0xa6, 0x09, 0x06, 0x7b, 0x41, 0xdc, 0xb0, 0xed, 0x3c, 0x7b, 0x01,
+    0xec, 0xba, 0xfc, 0x00, 0x6f, 0x1e, 0xdf, 0xa0, 0xf5, 0xde, 0x2e, 0x83, 0x3e, 0xde, 0x47, 0x86,
+    0xaa, 0x50, 0x94, 0x74, 0x1a, 0x7a, 0x6c, 0xd2, 0xab, 0x99, 0xb0, 0x30, 0x5e, 0x22, 0xea, 0x7f,
+    0x12, 0x84, 0x73, 0xb9, 0x0b,
+];
+
+// Ed25519 certificate with subject "CN=Leaf", issued by `ROOT_CERT`.
+const LEAF_CERT: &[u8] = &[
+    0x30, 0x82, 0x01, 0x10, 0x30, 0x81, 0xc3, 0xa0, 0x03, 0x02, 0x01, 0x02, 0x02, 0x01, 0x02, 0x30,
+    0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x30, 0x0f, 0x31, 0x0d, 0x30, 0x0b, 0x06, 0x03, 0x55, 0x04,
+    0x03, 0x0c, 0x04, 0x52, 0x6f, 0x6f, 0x74, 0x30, 0x20, 0x17, 0x0d, 0x32, 0x36, 0x31, 0x30, 0x31,
+    0x35, 0x30, 0x36, 0x32, 0x38, 0x34, 0x33, 0x5a, 0x18, 0x0f, 0x32, 0x31, 0x32, 0x36, 0x30, 0x39,
+    0x32, 0x31, 0x30, 0x36, 0x32, 0x38, 0x34, 0x33, 0x5a, 0x30, 0x0f, 0x31, 0x0d, 0x30, 0x0b, 0x06,
+    0x03, 0x55, 0x04, 0x03, 0x0c, 0x04, 0x4c, 0x65, 0x61, 0x66, 0x30, 0x2a, 0x30, 0x05, 0x06, 0x03,
+    0x2b, 0x65, 0x70, 0x03, 0x21, 0x00, 0x15, 0x78, 0x62, 0xe3, 0xad, 0xec, 0x95, 0x8f, 0x8d, 0xae,
+    0xd4, 0xcd, 0xce, 0x50, 0xc1, 0x24, 0x24, 0xf5, 0x23, 0xf8, 0xa6, 0xe2, 0xd0, 0x86, 0xe2, 0x06,
+    0x1d, 0xd9, 0x5b, 0x48, 0x21, 0xdb, 0xa3, 0x42, 0x30, 0x40, 0x30, 0x1d, 0x06, 0x03, 0x55, 0x1d,
+    0x0e, 0x04, 0x16, 0x04, 0x14, 0xf5, 0x3a, 0xc1, 0x35, 0x0e, 0x70, 0xd9, 0x04, 0x47, 0xf0, 0xe8,
+    0x2a, 0xf2, 0x12, 0x92, 0xc9, 0x89, 0xe9, 0x6a, 0xc9, 0x30, 0x1f, 0x06, 0x03, 0x55, 0x1d, 0x23,
+    0x04, 0x18, 0x30, 0x16, 0x80, 0x14, 0xfe, 0x68, 0x19, 0x5a, 0x27, 0x97, 0x9c, 0xea, 0x04, 0xfd,
+    0x9f, 0x91, 0x65, 0x1c, 0x51, 0xda, 0xfa, 0x12, 0xa7, 0x6b, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65,
+    0x70, 0x03, 0x41, 0x00, 0x42, 0xe2, 0x8d, 0xf3, 0xd5, 0x18, 0xfa, 0xed, 0x18, 0x6c, 0xd1, 0xf3,
+    0xfe, 0x5e, 0x6c, 0x3f, 0xe3, 0x13, 0x01, 0x4a, 0xea, 0x7f, 0xe1, 0x40, 0xc9, 0xf4, 0xb0, 0x11,
+    0x90, 0xeb, 0xd1, 0x74, 0x6e, 0x7e, 0xd0, 0x63, 0x64, 0x96, 0x08, 0x6a, 0x3f, 0x16, 0x0b, 0x41,
+    0xff, 0xf7, 0x59, 0x5d, 0x39, 0x8e, 0xd9, 0x68, 0x60, 0x95, 0x26, 0x1d, 0x4f, 0xa3, 0x79, 0x7d,
+    0x6e, 0x6e, 0x33, 0x0f,
+];
+
+fn cert(der: &[u8]) -> Certificate {
+    Certificate { encodedCertificate: der.to_vec() }
+}
+
+#[test]
+fn test_normalize_cert_chain() {
+    let encoded = |chain: Vec<Certificate>| -> Vec<Vec<u8>> {
+        chain.into_iter().map(|c| c.encodedCertificate).collect()
+    };
+    let chain = vec![cert(LEAF_CERT), cert(ROOT_CERT), cert(ROOT_CERT)];
+
+    assert_eq!(
+        encoded(normalize_cert_chain(chain.clone(), false)),
+        vec![LEAF_CERT.to_vec(), ROOT_CERT.to_vec()]
+    );
+    assert_eq!(encoded(normalize_cert_chain(chain, true)), vec![LEAF_CERT.to_vec()]);
+
+    // A certificate that is not self-signed is kept, as is a leaf that happens to be self-signed.
+    let chain = vec![cert(ROOT_CERT), cert(LEAF_CERT)];
+    assert_eq!(encoded(normalize_cert_chain(chain, true)).len(), 2);
+    assert_eq!(encoded(normalize_cert_chain(vec![cert(ROOT_CERT)], true)).len(), 1);
+
+    // A concatenated chain as returned by RKP cannot be parsed as a single root.
+    let rkp_chain = vec![cert(LEAF_CERT), cert(&[ROOT_CERT, ROOT_CERT].concat())];
+    assert_eq!(encoded(normalize_cert_chain(rkp_chain, true)).len(), 2);
+    assert!(normalize_cert_chain(Vec::new(), true).is_empty());
+}
+
+#[test]
+fn test_fingerprint_key_descriptor() {
+    let key = KeyDescriptor {
+        domain: Domain::APP,
+        nspace: 10001,
+        alias: Some("my private alias".to_string()),
+        blob: None,
+    };
+    let fingerprint = fingerprint_key_descriptor(&key);
+    assert_eq!(fingerprint, fingerprint_key_descriptor(&key.clone()));
+    assert!(fingerprint.starts_with("APP/10001/"), "{fingerprint}");
+    assert!(!fingerprint.contains("alias"), "{fingerprint}");
+    let other = KeyDescriptor { alias: Some("other".to_string()), ..key.clone() };
+    assert_ne!(fingerprint, fingerprint_key_descriptor(&other));
+
+    // Blob bytes are never included.
+    let with_blob = KeyDescriptor { blob: Some(vec![0xab; 64]), ..key };
+    let fingerprint = fingerprint_key_descriptor(&with_blob);
+    assert!(fingerprint.ends_with("/blob"), "{fingerprint}");
+    assert_eq!(fingerprint.len(), "APP/10001/".len() + 8 + "/blob".len(), "{fingerprint}");
+    assert_eq!(
+        fingerprint_key_descriptor(&KeyDescriptor {
+            domain: Domain::BLOB,
+            nspace: 0,
+            alias: None,
+            blob: Some(vec![1, 2, 3]),
+        }),
+        "BLOB/0/-/blob"
+    );
+}
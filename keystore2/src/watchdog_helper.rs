@@ -19,8 +19,8 @@
 pub mod watchdog {
     use std::sync::{Arc, LazyLock};
     use std::time::Duration;
-    pub use watchdog_rs::WatchPoint;
     use watchdog_rs::Watchdog;
+    pub use watchdog_rs::{WatchPoint, WatchPointInfo};
 
     /// Default timeout interval, in milliseconds.
     pub const DEFAULT_TIMEOUT_MS: u64 = 500;
@@ -49,6 +49,11 @@ pub mod watchdog {
     ) -> Option<WatchPoint> {
         Watchdog::watch_with(&WD, id, Duration::from_millis(millis), context)
     }
+
+    /// Returns the armed watch points, the ones closest to their timeout first.
+    pub fn active_watch_points() -> Vec<WatchPointInfo> {
+        Watchdog::active_watch_points(&WD)
+    }
 }
 
 /// This module provides empty/noop implementations of the watch dog utility functions.
@@ -72,4 +77,24 @@ pub mod watchdog {
     ) -> Option<WatchPoint> {
         None
     }
+
+    /// Noop watch point info.
+    pub struct WatchPointInfo {
+        pub id: &'static str,
+        pub thread: String,
+        pub elapsed: std::time::Duration,
+        pub timeout: std::time::Duration,
+        pub context: Option<String>,
+    }
+
+    impl WatchPointInfo {
+        pub fn remaining(&self) -> std::time::Duration {
+            self.timeout.saturating_sub(self.elapsed)
+        }
+    }
+
+    /// There are no watch points without the watchdog.
+    pub fn active_watch_points() -> Vec<WatchPointInfo> {
+        Vec::new()
+    }
 }
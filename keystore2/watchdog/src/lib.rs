@@ -76,6 +76,28 @@ impl Record {
     }
 }
 
+/// A snapshot of an armed watch point, as returned by `Watchdog::active_watch_points`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchPointInfo {
+    /// The id the watch point was armed with.
+    pub id: &'static str,
+    /// Debug representation of the thread that armed the watch point.
+    pub thread: String,
+    /// Time since the watch point was armed.
+    pub elapsed: Duration,
+    /// The timeout the watch point was armed with.
+    pub timeout: Duration,
+    /// Debug representation of the context, if any.
+    pub context: Option<String>,
+}
+
+impl WatchPointInfo {
+    /// Time left before the watch point is overdue, zero if it already is.
+    pub fn remaining(&self) -> Duration {
+        self.timeout.saturating_sub(self.elapsed)
+    }
+}
+
 struct WatchdogState {
     state: State,
     thread: Option<thread::JoinHandle<()>>,
@@ -306,6 +328,27 @@ impl Watchdog {
         Self::watch_with_optional(wd.clone(), None, id, timeout)
     }
 
+    /// Returns all currently armed watch points, including overdue ones, ordered by the time
+    /// left before they are overdue, so that the ones closest to tripping come first.
+    pub fn active_watch_points(wd: &Arc<Self>) -> Vec<WatchPointInfo> {
+        let (_, ref state) = *wd.state;
+        let state = state.lock().unwrap();
+        let now = Instant::now();
+        let mut result: Vec<WatchPointInfo> = state
+            .records
+            .iter()
+            .map(|(i, r)| WatchPointInfo {
+                id: i.id,
+                thread: format!("{:?}", i.tid),
+                elapsed: now.saturating_duration_since(r.started),
+                timeout: r.deadline.saturating_duration_since(r.started),
+                context: r.context.as_ref().map(|ctx| format!("{:?}", ctx)),
+            })
+            .collect();
+        result.sort_by_key(|info| info.remaining());
+        result
+    }
+
     fn arm(
         &self,
         context: Option<Box<dyn std::fmt::Debug + Send + 'static>>,
@@ -84,3 +84,30 @@ fn test_watchdog_backoff() {
     thread::sleep(Duration::from_secs(4));
     assert_eq!(3, hit_counter.value());
 }
+
+#[test]
+fn test_active_watch_points() {
+    let wd = Watchdog::new(Duration::from_secs(3));
+    assert!(Watchdog::active_watch_points(&wd).is_empty());
+
+    let _idle = Watchdog::watch(&wd, "test_idle_call", Duration::from_secs(60));
+    let slow = Watchdog::watch_with(&wd, "test_slow_call", Duration::from_millis(300), "ctx");
+    // Simulate a slow call that gets close to its threshold.
+    thread::sleep(Duration::from_millis(250));
+
+    let active = Watchdog::active_watch_points(&wd);
+    assert_eq!(active.len(), 2);
+    let near_threshold = &active[0];
+    assert_eq!(near_threshold.id, "test_slow_call");
+    assert_eq!(near_threshold.timeout, Duration::from_millis(300));
+    assert!(near_threshold.elapsed >= Duration::from_millis(250));
+    assert!(near_threshold.remaining() <= Duration::from_millis(50));
+    assert_eq!(near_threshold.context.as_deref(), Some("\"ctx\""));
+    assert_eq!(active[1].id, "test_idle_call");
+    assert_eq!(active[1].context, None);
+
+    drop(slow);
+    let active = Watchdog::active_watch_points(&wd);
+    assert_eq!(active.len(), 1);
+    assert_eq!(active[0].id, "test_idle_call");
+}